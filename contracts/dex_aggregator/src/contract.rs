@@ -1,12 +1,22 @@
 use cosmwasm_std::{
-    entry_point, Binary, Deps, DepsMut, Env, Event, MessageInfo, Reply, Response, StdResult,
+    entry_point, Binary, Decimal, Deps, DepsMut, Empty, Env, Event, MessageInfo, Reply, Response,
+    StdResult,
 };
 use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQueryWrapper};
 
 use crate::error::ContractError;
-use crate::execute::{self, remove_fee, set_fee, update_fee_collector};
-use crate::msg::{amm, Cw20HookMsg, ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{Config, CONFIG};
+use crate::execute::{
+    self, check_fee_cap, remove_fee, set_fee, update_fee_collectors, validate_fee_collectors,
+    validate_parameters,
+};
+use crate::msg::{
+    amm, Cw20HookMsg, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, RouteOptions,
+};
+use crate::packed;
+use crate::state::{
+    record_admin_action, Config, FeeCollector, CONFIG, FEE_MAP, LEGACY_CONFIG, PARAMETERS,
+    POOL_ALLOWLIST,
+};
 use cw20::Cw20ReceiveMsg;
 
 pub const CONTRACT_NAME: &str = "crates.io:dex-aggregator";
@@ -23,21 +33,155 @@ pub fn instantiate(
 
     let admin_addr = deps.api.addr_validate(&msg.admin)?;
     let adapter_addr = deps.api.addr_validate(&msg.cw20_adapter_address)?;
-    let fee_collector_addr = deps.api.addr_validate(&msg.fee_collector_address)?;
+    let fee_collectors = validate_fee_collectors(deps.as_ref(), msg.fee_collectors)?;
 
     // Save the full config
     let config = Config {
         admin: admin_addr,
         cw20_adapter_address: adapter_addr,
-        fee_collector: fee_collector_addr,
+        fee_collectors,
     };
     CONFIG.save(deps.storage, &config)?;
 
-    Ok(Response::new().add_attribute("method", "instantiate"))
+    let parameters = msg.parameters.unwrap_or_default();
+    validate_parameters(&parameters)?;
+    PARAMETERS.save(deps.storage, &parameters)?;
+
+    // Optional seeding, so a deployment is usable without a series of admin transactions.
+    let initial_fees = msg.initial_fees.unwrap_or_default();
+    for fee in &initial_fees {
+        check_fee_cap(&parameters, fee.fee_percent)?;
+        let pool_addr = deps.api.addr_validate(&fee.pool_address)?;
+        FEE_MAP.save(deps.storage, &pool_addr, &fee.fee_percent)?;
+    }
+    let allowed_pools = msg.allowed_pools.unwrap_or_default();
+    for pool in &allowed_pools {
+        let pool_addr = deps.api.addr_validate(pool)?;
+        POOL_ALLOWLIST.save(deps.storage, &pool_addr, &Empty {})?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "instantiate")
+        .add_attribute("initial_fees", initial_fees.len().to_string())
+        .add_attribute("allowed_pools", allowed_pools.len().to_string()))
+}
+
+/// Upgrades the stored state of earlier deployments. A `Config` with a single fee collector
+/// becomes one whose only collector takes the whole fee.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    _env: Env,
+    _msg: MigrateMsg,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    cw2::set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let migrated_config = CONFIG.load(deps.storage).is_err();
+    if migrated_config {
+        let legacy = LEGACY_CONFIG.load(deps.storage)?;
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                admin: legacy.admin,
+                cw20_adapter_address: legacy.cw20_adapter_address,
+                fee_collectors: vec![FeeCollector {
+                    address: legacy.fee_collector,
+                    weight: Decimal::one(),
+                }],
+            },
+        )?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("migrated_config", migrated_config.to_string()))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let admin_action = admin_action_name(&msg);
+    let sender = info.sender.clone();
+    let response = dispatch_execute(deps.branch(), env.clone(), info, msg)?;
+    // Admin messages only succeed for the admin, so everything reaching here was authorised.
+    if let Some(action) = admin_action {
+        record_admin_action(
+            deps.storage,
+            env.block.height,
+            sender,
+            action,
+            &response.attributes,
+        )?;
+    }
+    Ok(response)
+}
+
+/// The admin-log name of an admin-only message, or `None` for any other message.
+fn admin_action_name(msg: &ExecuteMsg) -> Option<&'static str> {
+    let name = match msg {
+        ExecuteMsg::UpdateAdmin { .. } => "update_admin",
+        ExecuteMsg::SetFee { .. } => "set_fee",
+        ExecuteMsg::RemoveFee { .. } => "remove_fee",
+        ExecuteMsg::SetFees { .. } => "set_fees",
+        ExecuteMsg::RemoveFees { .. } => "remove_fees",
+        ExecuteMsg::UpdateFeeCollectors { .. } => "update_fee_collectors",
+        ExecuteMsg::EmergencyWithdraw { .. } => "emergency_withdraw",
+        ExecuteMsg::UpdateParameters { .. } => "update_parameters",
+        ExecuteMsg::SetDustThreshold { .. } => "set_dust_threshold",
+        ExecuteMsg::SweepDust {} => "sweep_dust",
+        ExecuteMsg::UpdatePoolAllowlist { .. } => "update_pool_allowlist",
+        ExecuteMsg::SetPoolRegistrationTerms { .. } => "set_pool_registration_terms",
+        ExecuteMsg::RevokePoolRegistration { .. } => "revoke_pool_registration",
+        ExecuteMsg::SetPoolQueryKind { .. } => "set_pool_query_kind",
+        ExecuteMsg::SetPoolEventFormat { .. } => "set_pool_event_format",
+        ExecuteMsg::SetSweepPool { .. } => "set_sweep_pool",
+        ExecuteMsg::SetWrappedInj { .. } => "set_wrapped_inj",
+        ExecuteMsg::SetBalanceCheckedToken { .. } => "set_balance_checked_token",
+        ExecuteMsg::SetPoolMetadata { .. } => "set_pool_metadata",
+        ExecuteMsg::SetMarket { .. } => "set_market",
+        ExecuteMsg::RegisterRouteTemplate { .. } => "register_route_template",
+        ExecuteMsg::RemoveRouteTemplate { .. } => "remove_route_template",
+        ExecuteMsg::SetDenomPolicy { .. } => "set_denom_policy",
+        ExecuteMsg::RemoveDenomPolicy {} => "remove_denom_policy",
+        ExecuteMsg::SetRateLimits { .. } => "set_rate_limits",
+        ExecuteMsg::RemoveRateLimits {} => "remove_rate_limits",
+        ExecuteMsg::SetRelayer { .. } => "set_relayer",
+        ExecuteMsg::SetUsdPriceFeed { .. } => "set_usd_price_feed",
+        ExecuteMsg::RegisterIntegrator { .. } => "register_integrator",
+        ExecuteMsg::RemoveIntegrator { .. } => "remove_integrator",
+        ExecuteMsg::SetNativeConverter { .. } => "set_native_converter",
+        ExecuteMsg::SetPoolMaxInput { .. } => "set_pool_max_input",
+        ExecuteMsg::SetPoolDisabled { .. } => "set_pool_disabled",
+        ExecuteMsg::ExecuteRoute { .. }
+        | ExecuteMsg::ExecuteRoutePacked { .. }
+        | ExecuteMsg::ExecuteTemplate { .. }
+        | ExecuteMsg::Receive(_)
+        | ExecuteMsg::ExecuteRouteWithAllowance { .. }
+        | ExecuteMsg::ExecuteRouteAtQuote { .. }
+        | ExecuteMsg::DepositForRelay {}
+        | ExecuteMsg::WithdrawRelayDeposit { .. }
+        | ExecuteMsg::SetRelayKey { .. }
+        | ExecuteMsg::ExecuteRouteFor { .. }
+        | ExecuteMsg::CancelRelayedRoutes { .. }
+        | ExecuteMsg::PlaceTwapOrder { .. }
+        | ExecuteMsg::ExecuteTwapSlice { .. }
+        | ExecuteMsg::CancelTwapOrder { .. }
+        | ExecuteMsg::ContinueRoute { .. }
+        | ExecuteMsg::AbortExecution { .. }
+        | ExecuteMsg::PruneHistory { .. }
+        | ExecuteMsg::ClaimFees { .. }
+        | ExecuteMsg::RegisterPool { .. }
+        | ExecuteMsg::Noop {}
+        | ExecuteMsg::VerifyInvariants { .. } => return None,
+    };
+    Some(name)
+}
+
+fn dispatch_execute(
     deps: DepsMut<InjectiveQueryWrapper>,
     env: Env,
     info: MessageInfo,
@@ -47,23 +191,47 @@ pub fn execute(
         ExecuteMsg::ExecuteRoute {
             stages,
             minimum_receive,
+            max_stages_per_tx,
+            max_quote_age,
+            tag,
+            pool_health_check,
+            split_expectations,
+            max_price_impact_bps,
+            deadline,
+            receive_hook,
+            final_assets,
+            post_action,
+            max_fee_bps,
+            minimum_receive_usd,
+            integrator,
+            recipients,
         } => {
             // This is the entry point for NATIVE token swaps
-            if info.funds.len() != 1 {
-                return Err(ContractError::InvalidFunds {});
-            }
-            let offer_asset = amm::Asset {
-                info: amm::AssetInfo::NativeToken {
-                    denom: info.funds[0].denom.clone(),
-                },
-                amount: info.funds[0].amount,
+            let offer_asset = native_offer_asset(&info)?;
+            let options = RouteOptions {
+                minimum_receive,
+                max_stages_per_tx,
+                max_quote_age,
+                tag,
+                pool_health_check,
+                split_expectations,
+                max_price_impact_bps,
+                deadline,
+                receive_hook,
+                final_assets,
+                post_action,
+                max_fee_bps,
+                minimum_receive_usd,
+                integrator,
+                recipients,
             };
             execute::execute_aggregate_swaps_internal(
                 deps,
                 env,
                 info.clone(),
                 stages,
-                minimum_receive,
+                options,
+                None,
                 offer_asset,
                 info.sender,
             )
@@ -74,11 +242,26 @@ pub fn execute(
             msg,
         }) => {
             if let Ok(hook_msg) = cosmwasm_std::from_json::<Cw20HookMsg>(&msg) {
-                // This is a user-initiated swap starting with a CW20 token.
+                // A user-initiated swap starting with a CW20 token, or a conversion
+                // result delivered by the adapter.
                 match hook_msg {
                     Cw20HookMsg::ExecuteRoute {
                         stages,
                         minimum_receive,
+                        max_stages_per_tx,
+                        max_quote_age,
+                        tag,
+                        pool_health_check,
+                        split_expectations,
+                        max_price_impact_bps,
+                        deadline,
+                        receive_hook,
+                        final_assets,
+                        post_action,
+                        max_fee_bps,
+                        minimum_receive_usd,
+                        integrator,
+                        recipients,
                     } => {
                         let offer_asset = amm::Asset {
                             info: amm::AssetInfo::Token {
@@ -87,16 +270,114 @@ pub fn execute(
                             amount,
                         };
                         let initiator = deps.api.addr_validate(&sender)?;
+                        let options = RouteOptions {
+                            minimum_receive,
+                            max_stages_per_tx,
+                            max_quote_age,
+                            tag,
+                            pool_health_check,
+                            split_expectations,
+                            max_price_impact_bps,
+                            deadline,
+                            receive_hook,
+                            final_assets,
+                            post_action,
+                            max_fee_bps,
+                            minimum_receive_usd,
+                            integrator,
+                            recipients,
+                        };
                         execute::execute_aggregate_swaps_internal(
                             deps,
                             env,
                             info,
                             stages,
+                            options,
+                            None,
+                            offer_asset,
+                            initiator,
+                        )
+                    }
+                    Cw20HookMsg::WithdrawAndRoute {
+                        pool_address,
+                        stages,
+                        minimum_receive,
+                        max_stages_per_tx,
+                        tag,
+                        deadline,
+                    } => {
+                        let offer_asset = amm::Asset {
+                            info: amm::AssetInfo::Token {
+                                contract_addr: info.sender.to_string(),
+                            },
+                            amount,
+                        };
+                        let initiator = deps.api.addr_validate(&sender)?;
+                        let options = RouteOptions {
+                            minimum_receive,
+                            max_stages_per_tx,
+                            tag,
+                            deadline,
+                            ..Default::default()
+                        };
+                        execute::execute_aggregate_swaps_internal(
+                            deps,
+                            env,
+                            info,
+                            stages,
+                            options,
+                            Some(pool_address),
+                            offer_asset,
+                            initiator,
+                        )
+                    }
+                    Cw20HookMsg::ExecuteRouteAtQuote {
+                        stages,
+                        quoted_out,
+                        max_deviation_bps,
+                    } => {
+                        let offer_asset = amm::Asset {
+                            info: amm::AssetInfo::Token {
+                                contract_addr: info.sender.to_string(),
+                            },
+                            amount,
+                        };
+                        let initiator = deps.api.addr_validate(&sender)?;
+                        execute::execute_route_at_quote(
+                            deps,
+                            env,
+                            info,
+                            stages,
+                            quoted_out,
+                            max_deviation_bps,
+                            offer_asset,
+                            initiator,
+                        )
+                    }
+                    Cw20HookMsg::ExecuteTemplate {
+                        name,
+                        minimum_receive,
+                    } => {
+                        let offer_asset = amm::Asset {
+                            info: amm::AssetInfo::Token {
+                                contract_addr: info.sender.to_string(),
+                            },
+                            amount,
+                        };
+                        let initiator = deps.api.addr_validate(&sender)?;
+                        execute::execute_template(
+                            deps,
+                            env,
+                            info,
+                            name,
                             minimum_receive,
                             offer_asset,
                             initiator,
                         )
                     }
+                    Cw20HookMsg::InternalConversion { route_id } => {
+                        execute::record_internal_conversion(deps, info, sender, amount, route_id)
+                    }
                 }
             } else {
                 Ok(Response::new()
@@ -109,6 +390,159 @@ pub fn execute(
                     .add_attribute("info", "cw20_received_for_normalization"))
             }
         }
+        ExecuteMsg::ExecuteRouteWithAllowance {
+            token,
+            amount,
+            stages,
+            minimum_receive,
+            max_stages_per_tx,
+            max_quote_age,
+            tag,
+            pool_health_check,
+            split_expectations,
+            max_price_impact_bps,
+            deadline,
+            receive_hook,
+            final_assets,
+            post_action,
+            max_fee_bps,
+            minimum_receive_usd,
+            integrator,
+            recipients,
+        } => {
+            let options = RouteOptions {
+                minimum_receive,
+                max_stages_per_tx,
+                max_quote_age,
+                tag,
+                pool_health_check,
+                split_expectations,
+                max_price_impact_bps,
+                deadline,
+                receive_hook,
+                final_assets,
+                post_action,
+                max_fee_bps,
+                minimum_receive_usd,
+                integrator,
+                recipients,
+            };
+            execute::execute_route_with_allowance(deps, env, info, token, amount, stages, options)
+        }
+        ExecuteMsg::ExecuteRouteAtQuote {
+            stages,
+            quoted_out,
+            max_deviation_bps,
+        } => {
+            let offer_asset = native_offer_asset(&info)?;
+            let initiator = info.sender.clone();
+            execute::execute_route_at_quote(
+                deps,
+                env,
+                info,
+                stages,
+                quoted_out,
+                max_deviation_bps,
+                offer_asset,
+                initiator,
+            )
+        }
+        ExecuteMsg::ExecuteRoutePacked { route } => {
+            let msg = packed::decode_route(&route)?;
+            dispatch_execute(deps, env, info, msg)
+        }
+        ExecuteMsg::DepositForRelay {} => execute::deposit_for_relay(deps, info),
+        ExecuteMsg::WithdrawRelayDeposit { denom, amount } => {
+            execute::withdraw_relay_deposit(deps, info, denom, amount)
+        }
+        ExecuteMsg::SetRelayKey { pubkey } => execute::set_relay_key(deps, info, pubkey),
+        ExecuteMsg::ExecuteRouteFor {
+            owner,
+            stages,
+            offer,
+            minimum_receive,
+            nonce,
+            expires_at,
+            signature,
+        } => execute::execute_route_for(
+            deps,
+            env,
+            info,
+            owner,
+            stages,
+            offer,
+            minimum_receive,
+            nonce,
+            expires_at,
+            signature,
+        ),
+        ExecuteMsg::CancelRelayedRoutes { next_nonce } => {
+            execute::cancel_relayed_routes(deps, info, next_nonce)
+        }
+        ExecuteMsg::PlaceTwapOrder {
+            stages,
+            total_amount,
+            n_slices,
+            interval,
+            max_slippage_bps,
+        } => crate::twap::place_twap_order(
+            deps,
+            env,
+            info,
+            stages,
+            total_amount,
+            n_slices,
+            interval,
+            max_slippage_bps,
+        ),
+        ExecuteMsg::ExecuteTwapSlice { order_id } => {
+            crate::twap::execute_twap_slice(deps, env, info, order_id)
+        }
+        ExecuteMsg::CancelTwapOrder { order_id } => {
+            crate::twap::cancel_twap_order(deps, info, order_id)
+        }
+        ExecuteMsg::ContinueRoute { route_id } => {
+            execute::continue_route(deps, env, info, route_id)
+        }
+        ExecuteMsg::AbortExecution { route_id } => {
+            execute::abort_execution(deps, env, info, route_id)
+        }
+        ExecuteMsg::PruneHistory {
+            older_than_blocks,
+            limit,
+        } => execute::prune_history(deps, env, info, older_than_blocks, limit),
+        ExecuteMsg::ClaimFees { recipient } => execute::claim_fees(deps, info, recipient),
+        ExecuteMsg::RegisterPool { pool_address } => {
+            execute::register_pool(deps, info, pool_address)
+        }
+        ExecuteMsg::ExecuteTemplate {
+            name,
+            minimum_receive,
+        } => {
+            let offer_asset = native_offer_asset(&info)?;
+            let initiator = info.sender.clone();
+            execute::execute_template(
+                deps,
+                env,
+                info,
+                name,
+                minimum_receive,
+                offer_asset,
+                initiator,
+            )
+        }
+        ExecuteMsg::Noop {} => {
+            if info.sender != env.contract.address {
+                return Err(ContractError::Unauthorized {});
+            }
+            Ok(Response::new())
+        }
+        ExecuteMsg::VerifyInvariants { minimum_balances } => {
+            if info.sender != env.contract.address {
+                return Err(ContractError::Unauthorized {});
+            }
+            execute::verify_invariants(deps.as_ref(), &env, minimum_balances)
+        }
         ExecuteMsg::UpdateAdmin { new_admin } => {
             crate::execute::update_admin(deps, info, new_admin)
         }
@@ -117,28 +551,195 @@ pub fn execute(
             fee_percent,
         } => set_fee(deps, info, pool_address, fee_percent),
         ExecuteMsg::RemoveFee { pool_address } => remove_fee(deps, info, pool_address),
-        ExecuteMsg::UpdateFeeCollector { new_fee_collector } => {
-            update_fee_collector(deps, info, new_fee_collector)
+        ExecuteMsg::SetFees {
+            entries,
+            all_or_nothing,
+        } => execute::set_fees(deps, info, entries, all_or_nothing),
+        ExecuteMsg::RemoveFees {
+            pool_addresses,
+            all_or_nothing,
+        } => execute::remove_fees(deps, info, pool_addresses, all_or_nothing),
+        ExecuteMsg::UpdateFeeCollectors { collectors } => {
+            update_fee_collectors(deps, info, collectors)
         }
         ExecuteMsg::EmergencyWithdraw { asset_info } => {
             crate::execute::emergency_withdraw(deps, env, info, asset_info)
         }
+        ExecuteMsg::UpdateParameters { parameters } => {
+            execute::update_parameters(deps, info, parameters)
+        }
+        ExecuteMsg::SetDustThreshold {
+            asset_info,
+            threshold,
+        } => execute::set_dust_threshold(deps, info, asset_info, threshold),
+        ExecuteMsg::SweepDust {} => execute::sweep_dust(deps, info),
+        ExecuteMsg::UpdatePoolAllowlist { add, remove } => {
+            execute::update_pool_allowlist(deps, info, add, remove)
+        }
+        ExecuteMsg::SetPoolRegistrationTerms { terms } => {
+            execute::set_pool_registration_terms(deps, info, terms)
+        }
+        ExecuteMsg::RevokePoolRegistration { pool_address } => {
+            execute::revoke_pool_registration(deps, info, pool_address)
+        }
+        ExecuteMsg::SetPoolQueryKind {
+            pool_address,
+            query_kind,
+        } => execute::set_pool_query_kind(deps, info, pool_address, query_kind),
+        ExecuteMsg::SetPoolEventFormat {
+            pool_address,
+            event_format,
+        } => execute::set_pool_event_format(deps, info, pool_address, event_format),
+        ExecuteMsg::SetSweepPool {
+            offer_asset_info,
+            ask_asset_info,
+            pool_address,
+        } => execute::set_sweep_pool(deps, info, offer_asset_info, ask_asset_info, pool_address),
+        ExecuteMsg::SetWrappedInj { address } => execute::set_wrapped_inj(deps, info, address),
+        ExecuteMsg::SetBalanceCheckedToken { token, enabled } => {
+            execute::set_balance_checked_token(deps, info, token, enabled)
+        }
+        ExecuteMsg::SetPoolMetadata {
+            pool_address,
+            metadata,
+        } => execute::set_pool_metadata(deps, info, pool_address, metadata),
+        ExecuteMsg::SetMarket {
+            swap_contract,
+            market,
+        } => execute::set_market(deps, info, swap_contract, market),
+        ExecuteMsg::RegisterRouteTemplate { name, stages } => {
+            execute::register_route_template(deps, info, name, stages)
+        }
+        ExecuteMsg::RemoveRouteTemplate { name } => {
+            execute::remove_route_template(deps, info, name)
+        }
+        ExecuteMsg::SetDenomPolicy { mode, denoms } => {
+            execute::set_denom_policy(deps, info, mode, denoms)
+        }
+        ExecuteMsg::RemoveDenomPolicy {} => execute::remove_denom_policy(deps, info),
+        ExecuteMsg::SetRateLimits { limits } => execute::set_rate_limits(deps, info, limits),
+        ExecuteMsg::RemoveRateLimits {} => execute::remove_rate_limits(deps, info),
+        ExecuteMsg::SetRelayer { relayer, allowed } => {
+            execute::set_relayer(deps, info, relayer, allowed)
+        }
+        ExecuteMsg::SetUsdPriceFeed { asset_info, feed } => {
+            execute::set_usd_price_feed(deps, info, asset_info, feed)
+        }
+        ExecuteMsg::RegisterIntegrator {
+            addr,
+            fee_bps,
+            collector,
+        } => execute::register_integrator(deps, info, addr, fee_bps, collector),
+        ExecuteMsg::RemoveIntegrator { addr } => execute::remove_integrator(deps, info, addr),
+        ExecuteMsg::SetNativeConverter { denom, converter } => {
+            execute::set_native_converter(deps, env, info, denom, converter)
+        }
+        ExecuteMsg::SetPoolMaxInput {
+            pool_address,
+            max_input,
+        } => execute::set_pool_max_input(deps, info, pool_address, max_input),
+        ExecuteMsg::SetPoolDisabled {
+            pool_address,
+            disabled,
+        } => execute::set_pool_disabled(deps, info, pool_address, disabled),
     }
 }
 
+/// Builds the offer asset of a native-token route from the single coin sent with the message.
+fn native_offer_asset(info: &MessageInfo) -> Result<amm::Asset, ContractError> {
+    if info.funds.len() != 1 {
+        return Err(ContractError::InvalidFunds {});
+    }
+    Ok((&info.funds[0]).into())
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
-        QueryMsg::SimulateRoute { stages, amount_in } => {
-            crate::query::simulate_route(deps, env, stages, amount_in)
+        QueryMsg::SimulateRoute {
+            stages,
+            amount_in,
+            lenient,
+        } => crate::query::simulate_route(deps, env, stages, amount_in, lenient),
+        QueryMsg::SimulateRouteFromAsset { stages, amount_in } => {
+            crate::query::simulate_route_from_asset(deps, env, stages, amount_in)
+        }
+        QueryMsg::SimulateRouteWithFees {
+            stages,
+            amount_in,
+            fee_overrides,
+        } => crate::query::simulate_route_with_fees(deps, env, stages, amount_in, fee_overrides),
+        QueryMsg::SimulateOperation {
+            operation,
+            offer_asset,
+        } => crate::query::simulate_operation(deps, env, operation, offer_asset),
+        QueryMsg::EstimateRouteComplexity { stages } => {
+            crate::query::estimate_route_complexity(stages)
         }
         QueryMsg::Config {} => crate::query::query_config(deps),
+        QueryMsg::Parameters {} => crate::query::query_parameters(deps),
+        QueryMsg::DustBucket {} => crate::query::query_dust_bucket(deps),
+        QueryMsg::UnclaimedFees { collector } => {
+            crate::query::query_unclaimed_fees(deps, collector)
+        }
         QueryMsg::FeeForPool { pool_address } => {
             crate::query::query_fee_for_pool(deps, pool_address)
         }
+        QueryMsg::FeesForPools { pool_addresses } => {
+            crate::query::query_fees_for_pools(deps, pool_addresses)
+        }
         QueryMsg::AllFees { start_after, limit } => {
             crate::query::query_all_fees(deps, start_after, limit)
         }
+        QueryMsg::Receipt { route_id } => crate::query::query_receipt(deps, env, route_id),
+        QueryMsg::Trace { route_id } => crate::query::query_trace(deps, env, route_id),
+        QueryMsg::RouteExecution { route_id } => {
+            crate::query::query_route_execution(deps, env, route_id)
+        }
+        QueryMsg::DenomPolicy {} => crate::query::query_denom_policy(deps),
+        QueryMsg::RateLimits {} => crate::query::query_rate_limits(deps),
+        QueryMsg::PoolStats { pool_address } => crate::query::query_pool_stats(deps, pool_address),
+        QueryMsg::AllPoolStats { start_after, limit } => {
+            crate::query::query_all_pool_stats(deps, start_after, limit)
+        }
+        QueryMsg::AllPools { start_after, limit } => {
+            crate::query::query_all_pools(deps, start_after, limit)
+        }
+        QueryMsg::Markets { start_after, limit } => {
+            crate::query::query_markets(deps, start_after, limit)
+        }
+        QueryMsg::BalanceCheckedTokens { start_after, limit } => {
+            crate::query::query_balance_checked_tokens(deps, start_after, limit)
+        }
+        QueryMsg::PoolAllowlist { start_after, limit } => {
+            crate::query::query_pool_allowlist(deps, start_after, limit)
+        }
+        QueryMsg::DisabledPools { start_after, limit } => {
+            crate::query::query_disabled_pools(deps, start_after, limit)
+        }
+        QueryMsg::FeeCollectors {} => crate::query::query_fee_collectors(deps),
+        QueryMsg::RouteTemplate { name } => crate::query::query_route_template(deps, name),
+        QueryMsg::RouteTemplates { start_after, limit } => {
+            crate::query::query_route_templates(deps, start_after, limit)
+        }
+        QueryMsg::Capabilities {} => crate::query::query_capabilities(deps),
+        QueryMsg::AdminLog { start_after, limit } => {
+            crate::query::query_admin_log(deps, start_after, limit)
+        }
+        QueryMsg::RelayAccount { owner } => crate::query::query_relay_account(deps, owner),
+        QueryMsg::UsdPriceFeed { asset_info } => {
+            crate::query::query_usd_price_feed(deps, asset_info)
+        }
+        QueryMsg::RelayNonce { owner } => crate::query::query_relay_nonce(deps, owner),
+        QueryMsg::Integrator { addr } => crate::query::query_integrator(deps, addr),
+        QueryMsg::NativeConverter { denom } => crate::query::query_native_converter(deps, denom),
+        QueryMsg::PoolMaxInput { pool_address } => {
+            crate::query::query_pool_max_input(deps, pool_address)
+        }
+        QueryMsg::TwapOrder { order_id } => crate::query::query_twap_order(deps, order_id),
+        QueryMsg::TwapOrders { start_after, limit } => {
+            crate::query::query_twap_orders(deps, start_after, limit)
+        }
     }
 }
 
@@ -150,3 +751,41 @@ pub fn reply(
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     crate::reply::handle_reply(deps, env, msg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::LegacyConfig;
+    use crate::testing::{mock_deps, MockContracts};
+    use cosmwasm_std::testing::mock_env;
+
+    #[test]
+    fn test_migrate_moves_a_single_fee_collector_into_the_collector_list() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let legacy = LegacyConfig {
+            admin: deps.api.addr_make("admin"),
+            cw20_adapter_address: deps.api.addr_make("adapter"),
+            fee_collector: deps.api.addr_make("treasury"),
+        };
+        LEGACY_CONFIG.save(&mut deps.storage, &legacy).unwrap();
+        assert!(CONFIG.load(&deps.storage).is_err());
+
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(res.attributes[1].value, "true");
+        assert_eq!(
+            CONFIG.load(&deps.storage).unwrap(),
+            Config {
+                admin: legacy.admin,
+                cw20_adapter_address: legacy.cw20_adapter_address,
+                fee_collectors: vec![FeeCollector {
+                    address: legacy.fee_collector,
+                    weight: Decimal::one(),
+                }],
+            }
+        );
+
+        // A deployment already on the current layout is left as it is.
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(res.attributes[1].value, "false");
+    }
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, Decimal, StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -9,8 +9,64 @@ pub enum ContractError {
     #[error("Unauthorized")]
     Unauthorized {},
 
-    #[error("Minimum receive amount not met")]
-    MinimumReceiveNotMet {},
+    #[error("Minimum receive amount not met: expected at least {expected}, received {actual}")]
+    MinimumReceiveNotMet { expected: Uint128, actual: Uint128 },
+
+    #[error("Minimum receive is declared in {expected} but the route pays out {actual}")]
+    MinimumReceiveAssetMismatch { expected: String, actual: String },
+
+    #[error("Receive hooks need a CW20 output, but the route pays out {denom}")]
+    ReceiveHookNeedsCw20 { denom: String },
+
+    #[error("Declared final assets cannot be combined with a minimum receive or a receive hook")]
+    FinalAssetsConflict {},
+
+    #[error("A post-route action cannot be combined with a receive hook or declared final assets")]
+    PostActionConflict {},
+
+    #[error("Output recipients cannot be combined with a receive hook or a post-route action")]
+    RecipientsConflict {},
+
+    #[error("Invalid output recipients: {reason}")]
+    InvalidRecipients { reason: String },
+
+    #[error(
+        "Delegating needs the route to pay out the bond denom {bond_denom}, but it pays out {actual}"
+    )]
+    DelegationNeedsBondDenom { bond_denom: String, actual: String },
+
+    #[error(
+        "Pool {pool} charges a fee of {fee_bps} bps, above the route's cap of {max_fee_bps} bps"
+    )]
+    FeeAboveUserCap {
+        pool: String,
+        fee_bps: Decimal,
+        max_fee_bps: u16,
+    },
+
+    #[error("Unknown validator {validator}")]
+    UnknownValidator { validator: String },
+
+    #[error("{granter} has not granted the aggregator an authz authorization for {msg_type_url}")]
+    MissingAuthzGrant {
+        granter: String,
+        msg_type_url: String,
+    },
+
+    #[error("Declared final assets {expected:?} do not match the route's final assets {actual:?}")]
+    FinalAssetSetMismatch {
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+
+    #[error(
+        "Minimum receive for {asset} not met: expected at least {expected}, received {actual}"
+    )]
+    FinalAssetMinimumNotMet {
+        asset: String,
+        expected: Uint128,
+        actual: Uint128,
+    },
 
     #[error("Route cannot be empty")]
     EmptyRoute {},
@@ -18,8 +74,29 @@ pub enum ContractError {
     #[error("Input amount must be greater than zero")]
     ZeroAmount {},
 
-    #[error("Percentages in a stage must sum to 100")]
-    InvalidPercentageSum {},
+    #[error("Percentages in a stage must sum to 100, but stage {stage_index} sums to {total}")]
+    InvalidPercentageSum { stage_index: usize, total: u32 },
+
+    #[error(
+        "Stage {stage_index} requires an input asset the route cannot produce at that point \
+         (split {split_index})"
+    )]
+    StageInputMismatch {
+        stage_index: usize,
+        split_index: usize,
+    },
+
+    #[error(
+        "Liquidity can only be provided by the last operation of a path in the final stage \
+         (stage {stage_index}, split {split_index})"
+    )]
+    ZapNotTerminal {
+        stage_index: usize,
+        split_index: usize,
+    },
+
+    #[error("Withdrawn asset {asset} is neither swapped by the first stage nor among its outputs")]
+    UnroutedWithdrawnAsset { asset: String },
 
     #[error("No stages provided for the swap")]
     NoStages {},
@@ -27,15 +104,305 @@ pub enum ContractError {
     #[error("Failed to parse submessage reply result: {error}")]
     SubmessageResultError { error: String },
 
-    #[error("Failed to parse reply: wasm event did not contain a return amount attribute")]
-    NoAmountInReply {},
+    #[error(
+        "Failed to parse reply for route {route_id}: event did not contain an amount attribute"
+    )]
+    NoAmountInReply { route_id: u64 },
 
-    #[error("Failed to parse reply: amount attribute has a malformed value '{value}'")]
-    MalformedAmountInReply { value: String },
+    #[error(
+        "Failed to parse reply for route {route_id}: amount attribute has a malformed value \
+         '{value}'"
+    )]
+    MalformedAmountInReply { route_id: u64, value: String },
 
-    #[error("Failed to parse conversion reply: could not find a valid 'transfer' or 'wasm' event")]
-    NoConversionEventInReply {},
+    #[error("Sub-aggregator {contract} replied to route {route_id} without completing its route")]
+    SubAggregatorIncomplete { route_id: u64, contract: String },
+
+    #[error(
+        "Failed to parse conversion reply for route {route_id}: could not find a valid \
+         'transfer' or 'wasm' event"
+    )]
+    NoConversionEventInReply { route_id: u64 },
+
+    #[error("Stage {stage_index} has no operation on replying contract {contract}")]
+    UnexpectedReplyingContract { stage_index: u64, contract: String },
 
     #[error("AggregateSwaps requires exactly one type of coin to be sent")]
     InvalidFunds {},
+
+    #[error("No in-flight route with id {route_id}")]
+    UnknownRoute { route_id: u64 },
+
+    #[error("max_stages_per_tx must be greater than zero")]
+    InvalidStageChunking {},
+
+    #[error("Route {route_id} is not awaiting continuation")]
+    RouteNotAwaitingContinuation { route_id: u64 },
+
+    #[error("Route {route_id} cannot be aborted by its sender before {available_at}")]
+    AbortNotYetAvailable { route_id: u64, available_at: u64 },
+
+    #[error("Denom {denom} is not permitted by the denom policy")]
+    DenomNotAllowed { denom: String },
+
+    #[error("Sender has already started the maximum of {max} routes in this block")]
+    RouteRateLimited { max: u32 },
+
+    #[error(
+        "Routing {requested} {asset} would exceed the hourly limit of {limit}, \
+        of which {used} is already used"
+    )]
+    NotionalRateLimited {
+        asset: String,
+        limit: Uint128,
+        used: Uint128,
+        requested: Uint128,
+    },
+
+    #[error("Fee collector weights must be positive and sum to 100%")]
+    InvalidFeeCollectorWeights {},
+
+    #[error("Duplicate fee collector {address}")]
+    DuplicateFeeCollector { address: String },
+
+    #[error("No route template named {name}")]
+    UnknownRouteTemplate { name: String },
+
+    #[error("Route template {name} takes {expected}, not {actual}")]
+    TemplateOfferMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Route exceeds the complexity limits")]
+    RouteTooComplex {},
+
+    #[error("Invalid parameters: {reason}")]
+    InvalidParameters { reason: String },
+
+    #[error("Pool {pool} is not on the allowlist")]
+    PoolNotAllowed { pool: String },
+
+    #[error("Pool {addr} is disabled")]
+    PoolDisabled { addr: String },
+
+    #[error("Split expectations need one output per split and a tolerance of at most 1")]
+    InvalidSplitExpectations {},
+
+    #[error("Maximum price impact of {max_bps} bps exceeds 10000 bps")]
+    InvalidMaxPriceImpact { max_bps: u16 },
+
+    #[error("Simulated price impact of {impact_bps} bps exceeds the maximum of {max_bps} bps")]
+    PriceImpactExceeded { impact_bps: u64, max_bps: u16 },
+
+    #[error("Price impact cannot be measured: a reference input of {reference_amount} simulates to nothing")]
+    PriceImpactUnmeasurable { reference_amount: Uint128 },
+
+    #[error("Maximum quote deviation of {max_deviation_bps} bps exceeds 10000 bps")]
+    InvalidMaxQuoteDeviation { max_deviation_bps: u16 },
+
+    #[error(
+        "Fresh quote of {fresh} is below the quoted {quoted} by more than {max_deviation_bps} bps"
+    )]
+    QuoteDeviationExceeded {
+        quoted: Uint128,
+        fresh: Uint128,
+        max_deviation_bps: u16,
+    },
+
+    #[error("A reply in stage {stage_index} reported no output and names no operation to check")]
+    UnattributedEmptyReply { stage_index: u64 },
+
+    #[error(
+        "Stage {stage_index} split {split_index} paid out {actual}, short of the expected {expected} \
+         beyond the tolerance"
+    )]
+    SplitOutputDeviation {
+        stage_index: u64,
+        split_index: usize,
+        expected: Uint128,
+        actual: Uint128,
+    },
+
+    #[error("Invalid pool metadata: {reason}")]
+    InvalidPoolMetadata { reason: String },
+
+    #[error("Invalid pool event format: {reason}")]
+    InvalidPoolEventFormat { reason: String },
+
+    #[error("Invalid market: {reason}")]
+    InvalidMarket { reason: String },
+
+    #[error(
+        "Orderbook swap on {swap_contract} is below the market's minimum notional: {notional} < \
+         {min_notional}"
+    )]
+    BelowMinNotional {
+        swap_contract: String,
+        notional: Uint128,
+        min_notional: Uint128,
+    },
+
+    #[error("Pool {pool} in stage {stage_index} failed its health check")]
+    PoolUnhealthy { pool: String, stage_index: u64 },
+
+    #[error("Invariant violation: balance of {asset} is {actual}, expected at least {expected}")]
+    InvariantViolation {
+        asset: String,
+        expected: Uint128,
+        actual: Uint128,
+    },
+
+    #[error("Tag exceeds {max} characters")]
+    TagTooLong { max: usize },
+
+    #[error("Route deadline {deadline} has passed at time {current_time}")]
+    DeadlineExceeded { deadline: u64, current_time: u64 },
+
+    #[error("Quote from height {quote_height} is too old at height {current_height}")]
+    StaleQuote {
+        quote_height: u64,
+        current_height: u64,
+    },
+
+    #[error("No unclaimed fees")]
+    NoUnclaimedFees {},
+
+    #[error(
+        "Operation {op_index} of split {split_index} in stage {stage_index} output {received}, below its minimum of {min_out}"
+    )]
+    OperationBelowMinOut {
+        stage_index: u64,
+        split_index: u64,
+        op_index: u64,
+        received: Uint128,
+        min_out: Uint128,
+    },
+
+    #[error(
+        "Pool {pool} reported an output of {reported}, more than {max_multiple} times its simulated {simulated}"
+    )]
+    SuspiciousOutputAmount {
+        pool: String,
+        reported: Uint128,
+        simulated: Uint128,
+        max_multiple: Decimal,
+    },
+
+    #[error("Cannot check the output of {contract} without a simulation of it: {error}")]
+    OutputSimulationFailed { contract: String, error: String },
+
+    #[error("Pool registration is closed")]
+    PoolRegistrationClosed {},
+
+    #[error("Pool registration requires a fee of exactly {fee}")]
+    PoolRegistrationFeeRequired { fee: Coin },
+
+    #[error("Pool {pool} is already on the allowlist")]
+    PoolAlreadyAllowed { pool: String },
+
+    #[error("Pool {pool} failed its registration check: {reason}")]
+    PoolRegistrationCheckFailed { pool: String, reason: String },
+
+    #[error("Pool {pool} was not registered by its operator")]
+    PoolNotSelfRegistered { pool: String },
+
+    #[error(
+        "Final asset {asset} is registered with {registered} decimals where {expected} were expected"
+    )]
+    FinalAssetDecimalsMismatch {
+        asset: String,
+        expected: u8,
+        registered: u8,
+    },
+
+    #[error("Token {asset} has {decimals} decimals, above the supported maximum of {max}")]
+    UnsupportedDecimals {
+        asset: String,
+        decimals: u8,
+        max: u8,
+    },
+
+    #[error("Amount overflows while {context}")]
+    AmountOverflow { context: String },
+
+    #[error(
+        "Pool {pool} paid out at a rate of {realized}, more than {max_deviation} away from its reference rate of {reference}"
+    )]
+    PoolRateDeviation {
+        pool: String,
+        realized: Decimal,
+        reference: Decimal,
+        max_deviation: Decimal,
+    },
+
+    #[error("{relayer} is not allowed to relay routes")]
+    RelayerNotAllowed { relayer: String },
+
+    #[error("Relay key must be a 33-byte compressed or 65-byte uncompressed secp256k1 key")]
+    InvalidRelayKey {},
+
+    #[error("{owner} has not registered a relay key")]
+    RelayKeyNotRegistered { owner: String },
+
+    #[error("Signature verification failed against the owner's relay key")]
+    SignatureVerificationFailed {},
+
+    #[error("Invalid nonce: expected {expected}, received {received}")]
+    InvalidNonce { expected: u64, received: u64 },
+
+    #[error("Relayed route expired at {expires_at}, it is now {current_time}")]
+    RelayedRouteExpired { expires_at: u64, current_time: u64 },
+
+    #[error("{owner} has deposited {available} {denom}, short of the {required} the route spends")]
+    InsufficientRelayDeposit {
+        owner: String,
+        denom: String,
+        available: Uint128,
+        required: Uint128,
+    },
+
+    #[error("No USD price feed is configured for {asset}")]
+    UsdPriceFeedMissing { asset: String },
+
+    #[error("Oracle price of {asset} is missing or not positive")]
+    InvalidOraclePrice { asset: String },
+
+    #[error(
+        "Oracle price of {asset} was published at {published_at}, more than {max_age_seconds} seconds ago"
+    )]
+    StaleOraclePrice {
+        asset: String,
+        published_at: i64,
+        max_age_seconds: u64,
+    },
+
+    #[error("Minimum receive of {expected} USD not met: the output is worth {actual} USD")]
+    MinimumReceiveUsdNotMet { expected: Decimal, actual: Decimal },
+
+    #[error("Invalid packed route: {reason}")]
+    InvalidPackedRoute { reason: String },
+
+    #[error("{integrator} is not a registered integrator")]
+    IntegratorNotRegistered { integrator: String },
+
+    #[error("No converter is registered between {from} and {to}")]
+    NativeConverterMissing { from: String, to: String },
+
+    #[error("Swap of {amount} into pool {pool} exceeds its maximum input of {max_input}")]
+    PoolMaxInputExceeded {
+        pool: String,
+        amount: Uint128,
+        max_input: Uint128,
+    },
+
+    #[error("Invalid TWAP order: {reason}")]
+    InvalidTwapOrder { reason: String },
+
+    #[error("TWAP order {order_id} not found")]
+    TwapOrderNotFound { order_id: u64 },
+
+    #[error("The next slice of TWAP order {order_id} is not due until {next_slice_at}")]
+    TwapSliceNotDue { order_id: u64, next_slice_at: u64 },
 }
@@ -1,17 +1,41 @@
 use cosmwasm_std::{
-    to_json_binary, Addr, BankMsg, Coin, CosmosMsg, Decimal, DepsMut, Env, MessageInfo, Response,
-    StdError, Uint128, WasmMsg,
+    to_json_binary, Addr, BankMsg, Binary, Coin, CosmosMsg, Decimal, Deps, DepsMut, Empty, Env,
+    Event, MessageInfo, Order, Response, StdError, StdResult, Storage, SubMsg, Uint128, WasmMsg,
 };
 use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg};
+use cw_storage_plus::Bound;
 use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQueryWrapper};
 use injective_math::FPDecimal;
 use std::str::FromStr;
 
 use crate::error::ContractError;
-use crate::msg::{self, amm, orderbook, Operation, Stage};
-use crate::reply::proceed_to_next_step;
+use crate::msg::{
+    self, amm, orderbook, NativeConverterInfo, Operation, OrderbookSwapOp, PostAction,
+    RouteOptions, SplitExpectations, Stage,
+};
+use crate::oracle::load_usd_price_feed;
+use crate::reply::{
+    create_fee_send_submsgs, create_send_msg, dispatch_withdrawal, encode_proto_field,
+    get_route_output_info, measure_inflows, proceed_to_next_step, route_result_data,
+    DELEGATE_MSG_TYPE_URL,
+};
+use crate::routing::{
+    adapter_counterpart, check_min_notional, check_stage_inputs, get_operation_address,
+    is_counterpart, orderbook_settlement_denom, path_start_info, round_to_tick,
+    simulate_route_output, simulate_single_operation, zap_swap_amount,
+};
 use crate::state::{
-    Awaiting, ExecutionState, RoutePlan, CONFIG, FEE_MAP, REPLY_ID_COUNTER, ROUTE_PLANS,
+    asset_id, effective_fee, load_parameters, load_pool_config, record_pool_swap,
+    remove_route_plan, route_fee, save_route_plan, update_pool_config, Awaiting, Config,
+    DenomPolicy, DenomPolicyMode, ExecutionState, FeeCollector, Integrator, LoadedPlan, MarketInfo,
+    NativeConverter, Parameters, PoolEventFormat, PoolMetadata, PoolProtocol, PoolQueryKind,
+    PoolRegistrationTerms, RateLimits, Recipient, RoutePlan, UsdPriceFeed, BALANCE_CHECKED_TOKENS,
+    CONFIG, CONVERSION_RESULTS, DENOM_POLICY, DISABLED_POOLS, DUST_BUCKET, DUST_THRESHOLDS,
+    EXECUTION_STATES, FEE_MAP, INTEGRATORS, MARKETS, NATIVE_CONVERTERS, PARAMETERS, POOL_ALLOWLIST,
+    POOL_REGISTRATION_TERMS, PRUNE_CURSORS, RATE_LIMITS, RATE_LIMIT_WINDOW_SECONDS, RECEIPTS,
+    RELAYERS, RELAY_DEPOSITS, RELAY_KEYS, RELAY_NONCES, REPLY_ID_COUNTER, ROUTE_PLANS,
+    ROUTE_TEMPLATES, SELF_REGISTERED_POOLS, SENDER_BLOCK_ROUTES, SENDER_NOTIONAL, SWEEP_POOLS,
+    TRACES, TWAP_ORDERS, UNCLAIMED_FEES, USD_PRICE_FEEDS, WRAPPED_INJ,
 };
 
 pub fn update_admin(
@@ -36,305 +60,3173 @@ pub fn update_admin(
         .add_attribute("new_admin", new_admin_addr.to_string()))
 }
 
+/// Longest accepted client tag, in characters.
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// Starts a route taking `offer_asset` from `initiator`. A route given `withdraw_from` first
+/// withdraws its offer, the pool's LP tokens, from that pool.
+#[allow(clippy::too_many_arguments)]
 pub fn execute_aggregate_swaps_internal(
     mut deps: DepsMut<InjectiveQueryWrapper>,
     env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     stages: Vec<Stage>,
-    minimum_receive_str: Option<String>,
+    options: RouteOptions,
+    withdraw_from: Option<String>,
     offer_asset: amm::Asset,
     initiator: Addr,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let RouteOptions {
+        minimum_receive,
+        max_stages_per_tx,
+        max_quote_age,
+        tag,
+        pool_health_check,
+        split_expectations,
+        max_price_impact_bps,
+        deadline,
+        receive_hook,
+        final_assets,
+        post_action,
+        max_fee_bps,
+        minimum_receive_usd,
+        integrator,
+        recipients,
+    } = options;
+    check_quote_age(&env, max_quote_age)?;
+    check_deadline(&env, deadline)?;
     if offer_asset.amount.is_zero() {
         return Err(ContractError::ZeroAmount {});
     }
     if stages.is_empty() {
         return Err(ContractError::NoStages {});
     }
+    if max_stages_per_tx == Some(0) {
+        return Err(ContractError::InvalidStageChunking {});
+    }
+    if tag
+        .as_ref()
+        .is_some_and(|tag| tag.chars().count() > MAX_TAG_LENGTH)
+    {
+        return Err(ContractError::TagTooLong {
+            max: MAX_TAG_LENGTH,
+        });
+    }
+
+    let params = load_parameters(deps.storage)?;
+    if let Some(limits) = RATE_LIMITS.may_load(deps.storage)? {
+        apply_rate_limits(deps.storage, &env, &limits, &initiator, &offer_asset)?;
+    }
+    let exceeds = |len: usize, limit: Option<u32>| limit.is_some_and(|limit| len > limit as usize);
+    if exceeds(stages.len(), params.max_stages)
+        || stages.iter().any(|stage| {
+            exceeds(stage.splits.len(), params.max_splits_per_stage)
+                || stage
+                    .splits
+                    .iter()
+                    .any(|split| exceeds(split.path.len(), params.max_path_length))
+        })
+    {
+        return Err(ContractError::RouteTooComplex {});
+    }
+    check_pool_allowlist(deps.as_ref(), &stages)?;
+    check_disabled_pools(deps.as_ref(), &stages)?;
+    let config = CONFIG.load(deps.storage)?;
+    // A route withdrawing liquidity starts from the pool's assets rather than its LP tokens.
+    let start_infos = match &withdraw_from {
+        Some(pool) => {
+            let pool_addr = deps.api.addr_validate(pool)?;
+            if !POOL_ALLOWLIST.is_empty(deps.storage)
+                && !POOL_ALLOWLIST.has(deps.storage, &pool_addr)
+            {
+                return Err(ContractError::PoolNotAllowed { pool: pool.clone() });
+            }
+            if DISABLED_POOLS.has(deps.storage, &pool_addr) {
+                return Err(ContractError::PoolDisabled { addr: pool.clone() });
+            }
+            query_pool_asset_infos(deps.as_ref(), &pool_addr)?
+        }
+        None => vec![offer_asset.info.clone()],
+    };
+    check_stage_inputs(deps.storage, &stages, &start_infos)?;
+    if let Some(expectations) = &split_expectations {
+        check_split_expectations(expectations, &stages)?;
+    }
+    if let Some(max_bps) = max_price_impact_bps {
+        check_price_impact(deps.as_ref(), &stages, &offer_asset, max_bps)?;
+    }
 
     let first_stage = stages.first().unwrap();
-    let total_percentage: u8 = first_stage.splits.iter().map(|s| s.percent).sum();
-    if total_percentage != 100 {
-        return Err(ContractError::InvalidPercentageSum {});
+    if withdraw_from.is_some() {
+        check_withdrawal_stage(deps.storage, first_stage, &start_infos)?;
+    } else {
+        let total_percentage: u32 = first_stage.splits.iter().map(|s| s.percent as u32).sum();
+        if total_percentage != 100 {
+            return Err(ContractError::InvalidPercentageSum {
+                stage_index: 0,
+                total: total_percentage,
+            });
+        }
     }
 
     let reply_id = REPLY_ID_COUNTER.may_load(deps.storage)?.unwrap_or(0) + 1;
     REPLY_ID_COUNTER.save(deps.storage, &reply_id)?;
 
-    let minimum_receive = match minimum_receive_str {
-        Some(s) => Uint128::from_str(&s)?,
-        None => Uint128::zero(),
+    // Native offers and CW20 `Send`s are already in the balance; allowance routes pull their
+    // offer after this point.
+    let offer_received = match &offer_asset.info {
+        amm::AssetInfo::NativeToken { .. } => true,
+        amm::AssetInfo::Token { contract_addr } => info.sender.as_str() == contract_addr,
+    };
+    let balance_baseline = if params.invariant_checks {
+        Some(query_balance_baseline(
+            deps.as_ref(),
+            &env,
+            &stages,
+            &offer_asset,
+            offer_received,
+        )?)
+    } else {
+        None
+    };
+
+    let integrator = match integrator {
+        Some(integrator) => {
+            let address = deps.api.addr_validate(&integrator)?;
+            Some(
+                INTEGRATORS
+                    .may_load(deps.storage, &address)?
+                    .ok_or(ContractError::IntegratorNotRegistered { integrator })?,
+            )
+        }
+        None => None,
     };
+    let recipients = recipients
+        .map(|recipients| validate_recipients(deps.as_ref(), recipients))
+        .transpose()?;
 
+    let fee_schedule = snapshot_fees(deps.storage, &stages)?;
+    let stage_count = stages.len() as u64;
     let plan = RoutePlan {
         sender: initiator.clone(),
         minimum_receive,
         stages,
+        stage_count,
+        offer_asset: offer_asset.clone(),
+        max_stages_per_tx,
+        tag,
+        pool_health_check,
+        split_expectations,
+        deadline,
+        receive_hook,
+        final_assets,
+        post_action,
+        max_fee_bps,
+        minimum_receive_usd,
+        integrator,
+        recipients,
+        withdraw_from,
+        fee_schedule,
     };
-    ROUTE_PLANS.save(deps.storage, reply_id, &plan)?;
 
+    let output_info = get_route_output_info(plan.stages.last())?;
+    if let Some(policy) = DENOM_POLICY.may_load(deps.storage)? {
+        check_denom_policy(deps.as_ref(), &config, &policy, &plan.offer_asset.info)?;
+        check_denom_policy(deps.as_ref(), &config, &policy, &output_info)?;
+    }
+    if let Some(minimum) = &plan.minimum_receive {
+        // The route may pay out in either form of its output token, but not in anything else.
+        if minimum.info != output_info
+            && !is_counterpart(deps.storage, &minimum.info, &output_info)?
+        {
+            return Err(ContractError::MinimumReceiveAssetMismatch {
+                expected: asset_id(&minimum.info).to_string(),
+                actual: asset_id(&output_info).to_string(),
+            });
+        }
+        check_final_asset_decimals(deps.storage, &plan.stages, &minimum.info, &output_info)?;
+    }
+    if plan.minimum_receive_usd.is_some() {
+        load_usd_price_feed(deps.as_ref(), &output_info)?;
+    }
+    if let Some(final_assets) = &plan.final_assets {
+        if plan.minimum_receive.is_some()
+            || plan.minimum_receive_usd.is_some()
+            || plan.receive_hook.is_some()
+        {
+            return Err(ContractError::FinalAssetsConflict {});
+        }
+        check_final_assets(final_assets, &plan.stages)?;
+        if let Some(policy) = DENOM_POLICY.may_load(deps.storage)? {
+            for asset in final_assets {
+                check_denom_policy(deps.as_ref(), &config, &policy, &asset.info)?;
+            }
+        }
+    }
+    if let Some(hook) = &plan.receive_hook {
+        deps.api.addr_validate(&hook.contract)?;
+        // Only a CW20 output, in either form, can be delivered to a `Receive` hook.
+        if adapter_counterpart(&config.cw20_adapter_address, &output_info).is_none() {
+            return Err(ContractError::ReceiveHookNeedsCw20 {
+                denom: asset_id(&output_info).to_string(),
+            });
+        }
+    }
+    if let Some(action) = &plan.post_action {
+        if plan.receive_hook.is_some() || plan.final_assets.is_some() {
+            return Err(ContractError::PostActionConflict {});
+        }
+        match action {
+            PostAction::Delegate { validator } => {
+                let bond_denom = deps.querier.query_bonded_denom()?;
+                if output_info
+                    != (amm::AssetInfo::NativeToken {
+                        denom: bond_denom.clone(),
+                    })
+                {
+                    return Err(ContractError::DelegationNeedsBondDenom {
+                        bond_denom,
+                        actual: asset_id(&output_info).to_string(),
+                    });
+                }
+                if deps.querier.query_validator(validator)?.is_none() {
+                    return Err(ContractError::UnknownValidator {
+                        validator: validator.clone(),
+                    });
+                }
+                check_authz_grant(deps.as_ref(), &env, &plan.sender, DELEGATE_MSG_TYPE_URL)?;
+            }
+        }
+    }
+
+    if plan.recipients.is_some() && (plan.receive_hook.is_some() || plan.post_action.is_some()) {
+        return Err(ContractError::RecipientsConflict {});
+    }
+
+    let balance_snapshots = query_balance_snapshots(
+        deps.as_ref(),
+        &env,
+        &plan.stages,
+        &plan.offer_asset,
+        offer_received,
+    )?;
+    let plan = save_route_plan(deps.storage, reply_id, plan)?;
     let mut initial_exec_state = ExecutionState {
         awaiting: Awaiting::Swaps,
         current_stage_index: 0,
+        tx_start_stage_index: 0,
         replies_expected: 0,
         accumulated_assets: vec![offer_asset],
         pending_swaps: vec![],
         pending_path_op: None,
+        pending_output_split: None,
+        fees_collected: vec![],
+        conversions_performed: 0,
+        skipped_assets: vec![],
+        submessages_dispatched: 0,
+        reply_depth: 0,
+        pending_replies_by_depth: vec![],
+        balance_snapshots,
+        conversion_shortfalls: vec![],
+        last_checkpoint_time: env.block.time.seconds(),
+        pending_zaps: vec![],
+        pending_withdrawal: vec![],
+        pending_offers: vec![],
+        executions: vec![],
+        pending_conversion_target: None,
+        balance_baseline,
+    };
+
+    let mut response = match &plan.withdraw_from {
+        Some(pool) => dispatch_withdrawal(
+            &mut deps,
+            &env,
+            &mut initial_exec_state,
+            &plan,
+            reply_id,
+            pool,
+            &start_infos,
+        )?,
+        None => proceed_to_next_step(&mut deps, env, &mut initial_exec_state, &plan, reply_id)?,
     };
+    if response.data.is_none() {
+        response = response.set_data(route_result_data(reply_id, None)?);
+    }
 
-    proceed_to_next_step(&mut deps, env, &mut initial_exec_state, &plan, reply_id)
+    Ok(response.add_attribute("route_id", reply_id.to_string()))
 }
 
-pub fn create_swap_cosmos_msg(
-    deps: &mut DepsMut<InjectiveQueryWrapper>,
-    operation: &Operation,
-    offer_asset_info: &amm::AssetInfo,
-    amount: Uint128,
+/// Re-measures a checkpointed route's balance baseline at the start of the transaction that
+/// continues it, so funds other routes moved in between are not charged to it. What the route
+/// holds is its own; the fees it already collected are added back, as the invariant check
+/// deducts all of the route's fees.
+fn rebase_balance_baseline(
+    deps: Deps<InjectiveQueryWrapper>,
     env: &Env,
-) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
-    let recipient = env.contract.address.to_string();
+    exec_state: &mut ExecutionState,
+) -> Result<(), ContractError> {
+    let Some(baseline) = exec_state.balance_baseline.as_mut() else {
+        return Ok(());
+    };
+    for asset in baseline.iter_mut() {
+        let owned = exec_state
+            .accumulated_assets
+            .iter()
+            .chain(&exec_state.skipped_assets)
+            .filter(|held| held.info == asset.info)
+            .map(|held| held.amount)
+            .sum::<Uint128>();
+        let fees = exec_state
+            .fees_collected
+            .iter()
+            .filter(|fee| fee.info == asset.info)
+            .map(|fee| fee.amount)
+            .sum::<Uint128>();
+        asset.amount = query_asset_balance(deps, env, &asset.info)?.saturating_sub(owned) + fees;
+    }
+    Ok(())
+}
 
-    let cosmos_msg = match operation {
-        Operation::AmmSwap(amm_op) => {
-            let amm_swap_msg = amm::AmmPairExecuteMsg::Swap {
-                offer_asset: amm::Asset {
-                    info: offer_asset_info.clone(),
-                    amount,
-                },
-                belief_price: None,
-                max_spread: None,
-                to: Some(recipient),
-                deadline: None,
-            };
+/// The contract's balance of every asset a route touches, with the offer itself excluded.
+fn query_balance_baseline(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    stages: &[Stage],
+    offer_asset: &amm::Asset,
+    offer_received: bool,
+) -> Result<Vec<amm::Asset>, ContractError> {
+    let mut infos = vec![offer_asset.info.clone()];
+    for op in stages
+        .iter()
+        .flat_map(|s| &s.splits)
+        .flat_map(|split| &split.path)
+    {
+        for info in [op.offer_asset_info()?, op.ask_asset_info()?] {
+            if !infos.contains(&info) {
+                infos.push(info);
+            }
+        }
+    }
 
-            match offer_asset_info {
-                amm::AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: amm_op.pool_address.clone(),
-                    msg: to_json_binary(&amm_swap_msg)?,
-                    funds: vec![Coin {
-                        denom: denom.clone(),
-                        amount,
-                    }],
-                }),
-                amm::AssetInfo::Token { contract_addr } => {
-                    let cw20_send_msg = Cw20ExecuteMsg::Send {
-                        contract: amm_op.pool_address.clone(),
-                        amount,
-                        msg: to_json_binary(&amm_swap_msg)?,
-                    };
+    infos
+        .into_iter()
+        .map(|info| {
+            let mut amount = query_asset_balance(deps, env, &info)?;
+            if offer_received && info == offer_asset.info {
+                amount = amount.saturating_sub(offer_asset.amount);
+            }
+            Ok(amm::Asset { info, amount })
+        })
+        .collect()
+}
 
-                    CosmosMsg::Wasm(WasmMsg::Execute {
-                        contract_addr: contract_addr.clone(),
-                        msg: to_json_binary(&cw20_send_msg)?,
-                        funds: vec![],
-                    })
-                }
+/// The contract's balance of every balance-checked token the route touches, and of every asset a
+/// self-registered pool along it pays out, including an offer that has yet to be pulled in.
+pub(crate) fn query_balance_snapshots(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    stages: &[Stage],
+    offer_asset: &amm::Asset,
+    offer_received: bool,
+) -> Result<Vec<amm::Asset>, ContractError> {
+    let mut snapshots: Vec<amm::Asset> = vec![];
+    let mut infos = vec![offer_asset.info.clone()];
+    // Nothing vouches for the amounts a self-registered pool reports, so whatever it pays out is
+    // measured, native or not.
+    let mut measured = vec![];
+    for op in stages
+        .iter()
+        .flat_map(|s| &s.splits)
+        .flat_map(|split| &split.path)
+    {
+        infos.push(op.offer_asset_info()?);
+        infos.push(op.ask_asset_info()?);
+        match op {
+            Operation::ProvideLiquidity(lp_op) => infos.push(lp_op.other_asset_info.clone()),
+            Operation::AmmSwap(swap)
+                if SELF_REGISTERED_POOLS
+                    .has(deps.storage, &deps.api.addr_validate(&swap.pool_address)?) =>
+            {
+                measured.push(swap.ask_asset_info.clone());
             }
+            _ => {}
         }
-        Operation::OrderbookSwap(ob_op) => {
-            let tick_size_atomic = ob_op.min_quantity_tick_size;
+    }
 
-            if tick_size_atomic.is_zero() {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "min_quantity_tick_size cannot be zero",
-                )));
+    for info in infos {
+        if snapshots.iter().any(|s| s.info == info) {
+            continue;
+        }
+        let balance_checked = match &info {
+            amm::AssetInfo::Token { contract_addr } => {
+                BALANCE_CHECKED_TOKENS.has(deps.storage, &deps.api.addr_validate(contract_addr)?)
             }
+            amm::AssetInfo::NativeToken { .. } => false,
+        };
+        if !balance_checked && !measured.contains(&info) {
+            continue;
+        }
+        let mut amount = query_asset_balance(deps, env, &info)?;
+        if !offer_received && info == offer_asset.info {
+            amount += offer_asset.amount;
+        }
+        snapshots.push(amm::Asset { info, amount });
+    }
+    Ok(snapshots)
+}
 
-            let ratio = amount / tick_size_atomic;
-            let rounded_atomic_amount = ratio * tick_size_atomic;
+pub(crate) fn query_asset_balance(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    info: &amm::AssetInfo,
+) -> StdResult<Uint128> {
+    match info {
+        amm::AssetInfo::NativeToken { denom } => Ok(deps
+            .querier
+            .query_balance(&env.contract.address, denom)?
+            .amount),
+        amm::AssetInfo::Token { contract_addr } => {
+            let response: BalanceResponse = deps.querier.query_wasm_smart(
+                contract_addr,
+                &Cw20QueryMsg::Balance {
+                    address: env.contract.address.to_string(),
+                },
+            )?;
+            Ok(response.balance)
+        }
+    }
+}
 
-            if rounded_atomic_amount.is_zero() {
-                return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: env.contract.address.to_string(),
-                    msg: to_json_binary(&{})?,
-                    funds: vec![],
-                }));
-            }
+/// Aborts the transaction if any balance fell below its minimum.
+pub fn verify_invariants(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    minimum_balances: Vec<amm::Asset>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    for minimum in &minimum_balances {
+        let actual = query_asset_balance(deps, env, &minimum.info)?;
+        if actual < minimum.amount {
+            return Err(ContractError::InvariantViolation {
+                asset: asset_id(&minimum.info).to_string(),
+                expected: minimum.amount,
+                actual,
+            });
+        }
+    }
+    Ok(Response::new()
+        .add_attribute("action", "invariants_verified")
+        .add_attribute("assets_checked", minimum_balances.len().to_string()))
+}
 
-            let quantity_for_query_fp = FPDecimal::from(rounded_atomic_amount);
+/// Rejects execution when the caller's quote is older than it allows.
+pub fn check_quote_age(
+    env: &Env,
+    max_quote_age: Option<msg::QuoteAge>,
+) -> Result<(), ContractError> {
+    if let Some(quote_age) = max_quote_age {
+        let current_height = env.block.height;
+        if current_height.saturating_sub(quote_age.quote_height) > quote_age.max_blocks {
+            return Err(ContractError::StaleQuote {
+                quote_height: quote_age.quote_height,
+                current_height,
+            });
+        }
+    }
+    Ok(())
+}
 
-            let offer_denom =
-                match &ob_op.offer_asset_info {
-                    amm::AssetInfo::NativeToken { denom } => denom.clone(),
-                    _ => return Err(ContractError::Std(StdError::generic_err(
-                        "This OrderbookSwapOp implementation only supports native token inputs.",
-                    ))),
-                };
+pub fn check_deadline(env: &Env, deadline: Option<u64>) -> Result<(), ContractError> {
+    if let Some(deadline) = deadline {
+        let current_time = env.block.time.seconds();
+        if current_time > deadline {
+            return Err(ContractError::DeadlineExceeded {
+                deadline,
+                current_time,
+            });
+        }
+    }
+    Ok(())
+}
 
-            let target_denom = match &ob_op.ask_asset_info {
-                amm::AssetInfo::NativeToken { denom } => denom.clone(),
-                _ => {
-                    return Err(ContractError::Std(StdError::generic_err(
-                        "Orderbook swaps only support native token (bank) outputs.",
-                    )))
-                }
-            };
+/// Executes the stages registered under `name` with the caller's offer asset as input.
+pub fn execute_template(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    name: String,
+    minimum_receive: Option<amm::Asset>,
+    offer_asset: amm::Asset,
+    initiator: Addr,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let stages = ROUTE_TEMPLATES
+        .may_load(deps.storage, &name)?
+        .ok_or(ContractError::UnknownRouteTemplate { name: name.clone() })?;
+    // The template was registered without knowing what it would be executed with, so its first
+    // operations are checked against the offer before anything is dispatched.
+    for split in stages
+        .first()
+        .map(|stage| &stage.splits[..])
+        .unwrap_or_default()
+    {
+        let Some(first_op) = split.path.first() else {
+            continue;
+        };
+        let required = first_op.offer_asset_info()?;
+        if required != offer_asset.info
+            && !is_counterpart(deps.storage, &required, &offer_asset.info)?
+        {
+            return Err(ContractError::TemplateOfferMismatch {
+                name,
+                expected: asset_id(&required).to_string(),
+                actual: asset_id(&offer_asset.info).to_string(),
+            });
+        }
+    }
 
-            let simulate_msg = msg::orderbook::QueryMsg::GetOutputQuantity {
-                from_quantity: quantity_for_query_fp,
-                source_denom: offer_denom,
-                target_denom: target_denom.clone(),
-            };
-            let simulation_response: msg::orderbook::SwapEstimationResult = deps
-                .querier
-                .query_wasm_smart(&ob_op.swap_contract, &simulate_msg)?;
-            let expected_output_fp = simulation_response.result_quantity;
-            let slippage = FPDecimal::from_str("0.005")?;
+    let options = RouteOptions {
+        minimum_receive,
+        ..Default::default()
+    };
+    let response = execute_aggregate_swaps_internal(
+        deps,
+        env,
+        info,
+        stages,
+        options,
+        None,
+        offer_asset,
+        initiator,
+    )?;
 
-            let min_output_with_slippage_fp = expected_output_fp * (FPDecimal::ONE - slippage);
-            let floored_min_output_fp = min_output_with_slippage_fp.int();
+    Ok(response.add_attribute("route_template", name))
+}
 
-            let swap_msg = orderbook::OrderbookExecuteMsg::SwapMinOutput {
-                target_denom,
-                min_output_quantity: floored_min_output_fp,
-            };
+/// Starts a route from a CW20 allowance. The `TransferFrom` pulling the input is placed ahead of
+/// the route's first messages so the tokens are held before any swap executes.
+pub fn execute_route_with_allowance(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    token: String,
+    amount: Uint128,
+    stages: Vec<Stage>,
+    options: RouteOptions,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if !info.funds.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err(
+            "ExecuteRouteWithAllowance does not accept native funds",
+        )));
+    }
 
-            let funds = vec![Coin {
-                denom: match &ob_op.offer_asset_info {
-                    amm::AssetInfo::NativeToken { denom } => denom.clone(),
-                    _ => unreachable!(),
-                },
-                amount: rounded_atomic_amount,
-            }];
+    let token_addr = deps.api.addr_validate(&token)?;
+    let transfer_msg = CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: token_addr.to_string(),
+        msg: to_json_binary(&Cw20ExecuteMsg::TransferFrom {
+            owner: info.sender.to_string(),
+            recipient: env.contract.address.to_string(),
+            amount,
+        })?,
+        funds: vec![],
+    });
 
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: ob_op.swap_contract.clone(),
-                msg: to_json_binary(&swap_msg)?,
-                funds,
-            })
-        }
+    let offer_asset = amm::Asset {
+        info: amm::AssetInfo::Token {
+            contract_addr: token_addr.to_string(),
+        },
+        amount,
     };
+    let initiator = info.sender.clone();
+    let mut response = execute_aggregate_swaps_internal(
+        deps,
+        env,
+        info,
+        stages,
+        options,
+        None,
+        offer_asset,
+        initiator,
+    )?;
+    response.messages.insert(0, SubMsg::new(transfer_msg));
 
-    Ok(cosmos_msg)
+    Ok(response)
 }
 
-/// Admin-only. Sets or updates the fee for a given pool address.
-pub fn set_fee(
+/// Starts a route only if simulating it now quotes no worse than `quoted_out` less
+/// `max_deviation_bps`, closing the window between a caller's quote and its execution. The same
+/// floor becomes the route's minimum receive.
+pub fn execute_route_at_quote(
     deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
     info: MessageInfo,
-    pool_address: String,
-    fee_percent: Decimal,
+    stages: Vec<Stage>,
+    quoted_out: Uint128,
+    max_deviation_bps: u16,
+    offer_asset: amm::Asset,
+    initiator: Addr,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
-    if info.sender != config.admin {
-        return Err(ContractError::Unauthorized {});
+    if max_deviation_bps > 10_000 {
+        return Err(ContractError::InvalidMaxQuoteDeviation { max_deviation_bps });
     }
+    let output_info = stages
+        .last()
+        .and_then(|stage| stage.splits.first())
+        .and_then(|split| split.path.last())
+        .ok_or(ContractError::NoStages {})?
+        .ask_asset_info()?;
 
-    // Validate that the fee is reasonable (e.g., less than 100%)
-    if fee_percent >= Decimal::one() {
-        return Err(ContractError::Std(StdError::generic_err(
-            "Fee percentage must be less than 100%",
-        )));
+    // Quoted net of the fees the route will be charged, the snapshot it is about to take.
+    let fees: Vec<(Addr, Decimal)> = snapshot_fees(deps.storage, &stages)?
+        .into_iter()
+        .map(|entry| (Addr::unchecked(entry.pool_address), entry.fee_percent))
+        .collect();
+    let querier_deps = deps.as_ref().into_empty();
+    let fresh = simulate_route_output(
+        querier_deps.api,
+        &querier_deps.querier,
+        querier_deps.storage,
+        &stages,
+        offer_asset.clone(),
+        Some(&fees),
+    )?;
+    let floor = quoted_out.multiply_ratio(10_000 - max_deviation_bps as u128, 10_000u128);
+    if fresh < floor {
+        return Err(ContractError::QuoteDeviationExceeded {
+            quoted: quoted_out,
+            fresh,
+            max_deviation_bps,
+        });
     }
 
-    let pool_addr = deps.api.addr_validate(&pool_address)?;
-    FEE_MAP.save(deps.storage, &pool_addr, &fee_percent)?;
+    let options = RouteOptions {
+        minimum_receive: Some(amm::Asset {
+            info: output_info,
+            amount: floor,
+        }),
+        ..Default::default()
+    };
+    let response = execute_aggregate_swaps_internal(
+        deps,
+        env,
+        info,
+        stages,
+        options,
+        None,
+        offer_asset,
+        initiator,
+    )?;
+
+    Ok(response
+        .add_attribute("quoted_out", quoted_out.to_string())
+        .add_attribute("fresh_quote", fresh.to_string()))
+}
+
+/// Credits the attached native funds to the sender's relay deposit.
+pub fn deposit_for_relay(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if info.funds.is_empty() {
+        return Err(ContractError::ZeroAmount {});
+    }
+    for coin in &info.funds {
+        RELAY_DEPOSITS.update(
+            deps.storage,
+            (&info.sender, &coin.denom),
+            |balance| -> StdResult<_> { Ok(balance.unwrap_or_default().checked_add(coin.amount)?) },
+        )?;
+    }
 
     Ok(Response::new()
-        .add_attribute("action", "set_fee")
-        .add_attribute("pool_address", pool_addr)
-        .add_attribute("fee_percent", fee_percent.to_string()))
+        .add_attribute("action", "deposit_for_relay")
+        .add_attribute("owner", info.sender)
+        .add_attribute(
+            "funds",
+            info.funds
+                .iter()
+                .map(Coin::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ))
 }
 
-/// Admin-only. Removes the fee for a given pool address.
-pub fn remove_fee(
+/// Takes `amount` of `denom` out of `owner`'s relay deposit.
+fn debit_relay_deposit(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let available = RELAY_DEPOSITS
+        .may_load(storage, (owner, denom))?
+        .unwrap_or_default();
+    if available < amount {
+        return Err(ContractError::InsufficientRelayDeposit {
+            owner: owner.to_string(),
+            denom: denom.to_string(),
+            available,
+            required: amount,
+        });
+    }
+    let remaining = available - amount;
+    if remaining.is_zero() {
+        RELAY_DEPOSITS.remove(storage, (owner, denom));
+    } else {
+        RELAY_DEPOSITS.save(storage, (owner, denom), &remaining)?;
+    }
+    Ok(())
+}
+
+pub fn withdraw_relay_deposit(
     deps: DepsMut<InjectiveQueryWrapper>,
     info: MessageInfo,
-    pool_address: String,
+    denom: String,
+    amount: Uint128,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
-    if info.sender != config.admin {
-        return Err(ContractError::Unauthorized {});
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
     }
+    debit_relay_deposit(deps.storage, &info.sender, &denom, amount)?;
 
-    let pool_addr = deps.api.addr_validate(&pool_address)?;
-    FEE_MAP.remove(deps.storage, &pool_addr);
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![Coin::new(amount, denom.clone())],
+        })
+        .add_attribute("action", "withdraw_relay_deposit")
+        .add_attribute("owner", info.sender)
+        .add_attribute("amount", Coin::new(amount, denom).to_string()))
+}
+
+/// Sets or clears the key the sender signs relayed routes with.
+pub fn set_relay_key(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pubkey: Option<Binary>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    match &pubkey {
+        Some(pubkey) => {
+            if pubkey.len() != 33 && pubkey.len() != 65 {
+                return Err(ContractError::InvalidRelayKey {});
+            }
+            RELAY_KEYS.save(deps.storage, &info.sender, pubkey)?;
+        }
+        None => RELAY_KEYS.remove(deps.storage, &info.sender),
+    }
 
     Ok(Response::new()
-        .add_attribute("action", "remove_fee")
-        .add_attribute("pool_address", pool_addr))
+        .add_attribute("action", "set_relay_key")
+        .add_attribute("owner", info.sender)
+        .add_attribute(
+            "pubkey",
+            pubkey.map_or_else(|| "none".to_string(), |pubkey| pubkey.to_base64()),
+        ))
 }
 
-/// Admin-only. Updates the fee collector address.
-pub fn update_fee_collector(
+/// Admin-only. Allows or disallows an address to relay routes.
+pub fn set_relayer(
     deps: DepsMut<InjectiveQueryWrapper>,
     info: MessageInfo,
-    new_fee_collector: String,
+    relayer: String,
+    allowed: bool,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let mut config = CONFIG.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
     if info.sender != config.admin {
         return Err(ContractError::Unauthorized {});
     }
 
-    let new_collector_addr = deps.api.addr_validate(&new_fee_collector)?;
-    config.fee_collector = new_collector_addr.clone();
-    CONFIG.save(deps.storage, &config)?;
+    let relayer_addr = deps.api.addr_validate(&relayer)?;
+    if allowed {
+        RELAYERS.save(deps.storage, &relayer_addr, &Empty {})?;
+    } else {
+        RELAYERS.remove(deps.storage, &relayer_addr);
+    }
 
     Ok(Response::new()
-        .add_attribute("action", "update_fee_collector")
-        .add_attribute("new_fee_collector", new_collector_addr))
+        .add_attribute("action", "set_relayer")
+        .add_attribute("relayer", relayer_addr)
+        .add_attribute("allowed", allowed.to_string()))
 }
 
-pub fn emergency_withdraw(
+/// Runs a route for `owner` out of their relay deposit, once an allowed relayer submits it with
+/// the owner's signature over the route and their next nonce, before the signature expires.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_route_for(
     deps: DepsMut<InjectiveQueryWrapper>,
     env: Env,
     info: MessageInfo,
-    asset_info: amm::AssetInfo,
+    owner: String,
+    stages: Vec<Stage>,
+    offer: Coin,
+    minimum_receive: Option<amm::Asset>,
+    nonce: u64,
+    expires_at: u64,
+    signature: Binary,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    // 1. Authorization Check
-    let config = CONFIG.load(deps.storage)?;
-    if info.sender != config.admin {
-        return Err(ContractError::Unauthorized {});
+    if !RELAYERS.has(deps.storage, &info.sender) {
+        return Err(ContractError::RelayerNotAllowed {
+            relayer: info.sender.to_string(),
+        });
+    }
+    if !info.funds.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err(
+            "ExecuteRouteFor does not accept funds; the route spends the owner's deposit",
+        )));
+    }
+    let current_time = env.block.time.seconds();
+    if current_time > expires_at {
+        return Err(ContractError::RelayedRouteExpired {
+            expires_at,
+            current_time,
+        });
     }
 
-    let (amount_to_withdraw, send_msg) = match asset_info.clone() {
-        amm::AssetInfo::NativeToken { denom } => {
-            // 2a. Query the contract's native token balance
-            let balance = deps.querier.query_balance(&env.contract.address, denom)?;
+    let owner_addr = deps.api.addr_validate(&owner)?;
+    let expected_nonce = RELAY_NONCES
+        .may_load(deps.storage, &owner_addr)?
+        .unwrap_or_default();
+    if nonce != expected_nonce {
+        return Err(ContractError::InvalidNonce {
+            expected: expected_nonce,
+            received: nonce,
+        });
+    }
+    let pubkey = RELAY_KEYS
+        .may_load(deps.storage, &owner_addr)?
+        .ok_or_else(|| ContractError::RelayKeyNotRegistered {
+            owner: owner_addr.to_string(),
+        })?;
 
-            if balance.amount.is_zero() {
-                // Return success but do nothing if balance is zero
-                (balance.amount, None)
-            } else {
-                // 3a. Create a BankMsg to send the full balance to the admin
-                let msg = CosmosMsg::Bank(BankMsg::Send {
-                    to_address: info.sender.to_string(),
-                    amount: vec![balance.clone()],
-                });
-                (balance.amount, Some(msg))
-            }
-        }
-        amm::AssetInfo::Token { contract_addr } => {
-            // 2b. Query the contract's CW20 token balance
-            let balance_response: BalanceResponse = deps.querier.query_wasm_smart(
-                contract_addr.clone(),
-                &Cw20QueryMsg::Balance {
-                    address: env.contract.address.to_string(),
-                },
-            )?;
+    let relayed = msg::RelayedRoute {
+        chain_id: env.block.chain_id.clone(),
+        contract: env.contract.address.to_string(),
+        owner: owner_addr.to_string(),
+        stages,
+        offer,
+        minimum_receive,
+        nonce,
+        expires_at,
+    };
+    let verified = deps
+        .api
+        .secp256k1_verify(&relayed.hash()?, &signature, &pubkey)
+        .unwrap_or(false);
+    if !verified {
+        return Err(ContractError::SignatureVerificationFailed {});
+    }
+
+    RELAY_NONCES.save(deps.storage, &owner_addr, &(nonce + 1))?;
+    debit_relay_deposit(
+        deps.storage,
+        &owner_addr,
+        &relayed.offer.denom,
+        relayed.offer.amount,
+    )?;
+
+    let relayer = info.sender.clone();
+    let options = RouteOptions {
+        minimum_receive: relayed.minimum_receive,
+        ..Default::default()
+    };
+    let response = execute_aggregate_swaps_internal(
+        deps,
+        env,
+        info,
+        relayed.stages,
+        options,
+        None,
+        relayed.offer.into(),
+        owner_addr,
+    )?;
+
+    Ok(response
+        .add_attribute("relayer", relayer)
+        .add_attribute("relay_nonce", nonce.to_string()))
+}
+
+/// Moves the sender's relay nonce up to `next_nonce`, so no route they signed with a lower nonce
+/// can be relayed.
+pub fn cancel_relayed_routes(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    next_nonce: u64,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let nonce = RELAY_NONCES
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    if next_nonce <= nonce {
+        return Err(ContractError::InvalidNonce {
+            expected: nonce + 1,
+            received: next_nonce,
+        });
+    }
+    RELAY_NONCES.save(deps.storage, &info.sender, &next_nonce)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_relayed_routes")
+        .add_attribute("owner", info.sender)
+        .add_attribute("next_nonce", next_nonce.to_string()))
+}
+
+/// The route is also simulated with its input divided by this, as the rate it is measured against.
+pub const PRICE_IMPACT_REFERENCE_DIVISOR: u128 = 1_000;
+
+/// Rejects a route whose simulated rate at its actual size falls short of its simulated rate at a
+/// small reference size by more than `max_bps` basis points. A reference size that simulates to
+/// nothing gives no rate to measure against, so the route is rejected rather than let through.
+fn check_price_impact(
+    deps: Deps<InjectiveQueryWrapper>,
+    stages: &[Stage],
+    offer_asset: &amm::Asset,
+    max_bps: u16,
+) -> Result<(), ContractError> {
+    if max_bps > 10_000 {
+        return Err(ContractError::InvalidMaxPriceImpact { max_bps });
+    }
+
+    let reference_asset = amm::Asset {
+        info: offer_asset.info.clone(),
+        amount: (offer_asset.amount / Uint128::new(PRICE_IMPACT_REFERENCE_DIVISOR))
+            .max(Uint128::one()),
+    };
+    let deps = deps.into_empty();
+    let actual_output = simulate_route_output(
+        deps.api,
+        &deps.querier,
+        deps.storage,
+        stages,
+        offer_asset.clone(),
+        None,
+    )?;
+    let reference_output = simulate_route_output(
+        deps.api,
+        &deps.querier,
+        deps.storage,
+        stages,
+        reference_asset.clone(),
+        None,
+    )?;
+
+    // Compare output / input at both sizes without dividing.
+    if reference_output.is_zero() {
+        return Err(ContractError::PriceImpactUnmeasurable {
+            reference_amount: reference_asset.amount,
+        });
+    }
+    let actual_rate = actual_output.full_mul(reference_asset.amount);
+    let reference_rate = reference_output.full_mul(offer_asset.amount);
+    if actual_rate >= reference_rate {
+        return Ok(());
+    }
+    let impact_bps = (reference_rate - actual_rate).multiply_ratio(10_000u128, reference_rate);
+    let impact_bps = Uint128::try_from(impact_bps).map_err(StdError::from)?;
+    if impact_bps > Uint128::from(max_bps) {
+        return Err(ContractError::PriceImpactExceeded {
+            impact_bps: impact_bps.u128() as u64,
+            max_bps,
+        });
+    }
+    Ok(())
+}
+
+/// Requires `granter` to have granted this contract an authz authorization for `msg_type_url`,
+/// so a post-route action running under it fails before any swap rather than after all of them.
+fn check_authz_grant(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    granter: &Addr,
+    msg_type_url: &str,
+) -> Result<(), ContractError> {
+    let mut request = vec![];
+    encode_proto_field(&mut request, 1, granter.as_bytes());
+    encode_proto_field(&mut request, 2, env.contract.address.as_bytes());
+    encode_proto_field(&mut request, 3, msg_type_url.as_bytes());
+    // The chain answers a missing grant with an error; a found one is the response's field 1.
+    let granted = deps
+        .querier
+        .query_grpc(
+            "/cosmos.authz.v1beta1.Query/Grants".to_string(),
+            Binary::new(request),
+        )
+        .is_ok_and(|response| response.first() == Some(&0x0a));
+    if !granted {
+        return Err(ContractError::MissingAuthzGrant {
+            granter: granter.to_string(),
+            msg_type_url: msg_type_url.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Requires the declared final assets of a multi-output route to be exactly the distinct assets
+/// its final stage produces, each declared once.
+fn check_final_assets(final_assets: &[amm::Asset], stages: &[Stage]) -> Result<(), ContractError> {
+    let mut expected: Vec<String> = final_assets
+        .iter()
+        .map(|asset| asset_id(&asset.info).to_string())
+        .collect();
+    expected.sort();
+
+    let mut actual = vec![];
+    if let Some(last_stage) = stages.last() {
+        for last_op in last_stage
+            .splits
+            .iter()
+            .filter_map(|split| split.path.last())
+        {
+            actual.push(asset_id(&last_op.ask_asset_info()?).to_string());
+        }
+    }
+    actual.sort();
+    actual.dedup();
+
+    if expected != actual {
+        return Err(ContractError::FinalAssetSetMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Validates the recipients a route's output is split between: addresses must be valid and
+/// unique, and the shares positive and summing to exactly 10,000 basis points.
+fn validate_recipients(
+    deps: Deps<InjectiveQueryWrapper>,
+    recipients: Vec<msg::RecipientInfo>,
+) -> Result<Vec<Recipient>, ContractError> {
+    let mut validated: Vec<Recipient> = vec![];
+    let mut total_bps = 0u32;
+    for recipient in recipients {
+        let address = deps.api.addr_validate(&recipient.address)?;
+        if recipient.share_bps == 0 {
+            return Err(ContractError::InvalidRecipients {
+                reason: format!("{address} has a zero share"),
+            });
+        }
+        if validated.iter().any(|r| r.address == address) {
+            return Err(ContractError::InvalidRecipients {
+                reason: format!("{address} is listed more than once"),
+            });
+        }
+        total_bps += u32::from(recipient.share_bps);
+        validated.push(Recipient {
+            address,
+            share_bps: recipient.share_bps,
+        });
+    }
+
+    if total_bps != 10_000 {
+        return Err(ContractError::InvalidRecipients {
+            reason: format!("shares sum to {total_bps} bps instead of 10000"),
+        });
+    }
+    Ok(validated)
+}
+
+fn check_split_expectations(
+    expectations: &SplitExpectations,
+    stages: &[Stage],
+) -> Result<(), ContractError> {
+    let shape_matches = expectations.expected_outputs.len() == stages.len()
+        && expectations
+            .expected_outputs
+            .iter()
+            .zip(stages)
+            .all(|(outputs, stage)| outputs.len() == stage.splits.len());
+    if !shape_matches || expectations.tolerance > Decimal::one() {
+        return Err(ContractError::InvalidSplitExpectations {});
+    }
+    Ok(())
+}
+
+fn check_denom_policy(
+    deps: Deps<InjectiveQueryWrapper>,
+    config: &Config,
+    policy: &DenomPolicy,
+    info: &amm::AssetInfo,
+) -> Result<(), ContractError> {
+    let denom = asset_id(info);
+    let mut listed = policy.lists(denom);
+    if !listed {
+        // The other form of an adapter-registered token is listed under its own identifier.
+        if let Some((counterpart, cw20_addr)) =
+            adapter_counterpart(&config.cw20_adapter_address, info)
+        {
+            listed = policy.lists(asset_id(&counterpart))
+                && is_registered_with_adapter(deps, config, &cw20_addr)?;
+        }
+    }
+    if !policy.permits_listed(listed) {
+        return Err(ContractError::DenomNotAllowed {
+            denom: denom.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Whether the admin has registered `cw20_addr` with the cw20 adapter, per its
+/// `RegisteredContracts` query.
+fn is_registered_with_adapter(
+    deps: Deps<InjectiveQueryWrapper>,
+    config: &Config,
+    cw20_addr: &str,
+) -> StdResult<bool> {
+    let registered: Vec<Addr> = deps.querier.query_wasm_smart(
+        &config.cw20_adapter_address,
+        &msg::cw20_adapter::QueryMsg::RegisteredContracts {},
+    )?;
+    Ok(registered.iter().any(|addr| addr.as_str() == cw20_addr))
+}
+
+/// Records the output of a Native -> CW20 conversion delivered by the adapter through the
+/// `InternalConversion` hook, so the pending reply can read it by route id.
+pub fn record_internal_conversion(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    sender: String,
+    amount: Uint128,
+    route_id: u64,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if deps.api.addr_validate(&sender)? != config.cw20_adapter_address {
+        return Err(ContractError::Unauthorized {});
+    }
+    let exec_state = EXECUTION_STATES
+        .may_load(deps.storage, route_id)?
+        .ok_or(ContractError::UnknownRoute { route_id })?;
+    // `sender` is only what the calling contract claims, so the call must also come from the CW20
+    // the route is converting into, while that conversion is in flight.
+    let converting = matches!(
+        exec_state.awaiting,
+        Awaiting::Conversions
+            | Awaiting::FinalConversions
+            | Awaiting::PathConversion
+            | Awaiting::OutputConversion
+    );
+    let expected_token = match &exec_state.pending_conversion_target {
+        Some(amm::AssetInfo::Token { contract_addr }) if converting => contract_addr,
+        _ => return Err(ContractError::Unauthorized {}),
+    };
+    if info.sender.as_str() != expected_token {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    CONVERSION_RESULTS.update(deps.storage, route_id, |existing| -> StdResult<_> {
+        Ok(existing.unwrap_or_default() + amount)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "internal_conversion_complete")
+        .add_attribute("route_id", route_id.to_string())
+        .add_attribute("amount", amount.to_string()))
+}
+
+/// Resumes a route that checkpointed after reaching its `max_stages_per_tx`. Only the route's
+/// initiator may continue it.
+pub fn continue_route(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    route_id: u64,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let mut exec_state = EXECUTION_STATES
+        .may_load(deps.storage, route_id)?
+        .ok_or(ContractError::UnknownRoute { route_id })?;
+    let plan = LoadedPlan::load(deps.storage, route_id)?;
+
+    if info.sender != plan.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    if exec_state.awaiting != Awaiting::Continuation {
+        return Err(ContractError::RouteNotAwaitingContinuation { route_id });
+    }
+
+    exec_state.tx_start_stage_index = exec_state.current_stage_index;
+    // Other transactions may have moved the balances since the checkpoint; the route's own
+    // holdings are already in `accumulated_assets`.
+    measure_inflows(deps.as_ref(), &env, &mut exec_state)?;
+    rebase_balance_baseline(deps.as_ref(), &env, &mut exec_state)?;
+
+    let mut response = proceed_to_next_step(&mut deps, env, &mut exec_state, &plan, route_id)?;
+    if response.data.is_none() {
+        response = response.set_data(route_result_data(route_id, None)?);
+    }
+
+    Ok(response
+        .add_attribute("action", "continue_route")
+        .add_attribute("route_id", route_id.to_string()))
+}
+
+/// How long a route must sit idle after starting or checkpointing before its sender may abort it.
+pub const ROUTE_ABORT_TTL_SECONDS: u64 = 86_400;
+
+/// Cancels an in-flight route, clearing its state and refunding the assets it holds to its
+/// sender. The admin may abort any route; the sender only once it has been idle for
+/// `ROUTE_ABORT_TTL_SECONDS`.
+pub fn abort_execution(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    route_id: u64,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let exec_state = EXECUTION_STATES
+        .may_load(deps.storage, route_id)?
+        .ok_or(ContractError::UnknownRoute { route_id })?;
+    let plan = ROUTE_PLANS.load(deps.storage, route_id)?;
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.admin {
+        if info.sender != plan.sender {
+            return Err(ContractError::Unauthorized {});
+        }
+        let available_at = exec_state.last_checkpoint_time + ROUTE_ABORT_TTL_SECONDS;
+        if env.block.time.seconds() < available_at {
+            return Err(ContractError::AbortNotYetAvailable {
+                route_id,
+                available_at,
+            });
+        }
+    }
+
+    let (messages, event) = clear_route(deps.storage, route_id, &plan, &exec_state, &info.sender)?;
+
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_event(event)
+        .add_attribute("action", "abort_execution")
+        .add_attribute("route_id", route_id.to_string()))
+}
+
+/// Clears an in-flight route's state, returning the messages refunding what it holds to its
+/// sender and the `execution_aborted` event listing them.
+fn clear_route(
+    storage: &mut dyn Storage,
+    route_id: u64,
+    plan: &RoutePlan,
+    exec_state: &ExecutionState,
+    aborted_by: &Addr,
+) -> Result<(Vec<CosmosMsg<InjectiveMsgWrapper>>, Event), ContractError> {
+    EXECUTION_STATES.remove(storage, route_id);
+    remove_route_plan(storage, route_id, plan);
+    CONVERSION_RESULTS.remove(storage, route_id);
+    TRACES.remove(storage, route_id);
+
+    let mut messages = vec![];
+    let mut event = Event::new("execution_aborted")
+        .add_attribute("route_id", route_id.to_string())
+        .add_attribute("sender", plan.sender.to_string())
+        .add_attribute("aborted_by", aborted_by.to_string());
+    for asset in exec_state
+        .accumulated_assets
+        .iter()
+        .chain(&exec_state.skipped_assets)
+        .filter(|asset| !asset.amount.is_zero())
+    {
+        messages.push(create_send_msg(&plan.sender, &asset.info, asset.amount)?);
+        event = event
+            .add_attribute("refunded_asset", asset_id(&asset.info))
+            .add_attribute("refunded_amount", asset.amount.to_string());
+    }
+    Ok((messages, event))
+}
+
+/// Most entries of each kind one `PruneHistory` call examines.
+pub const MAX_PRUNE_LIMIT: u32 = 100;
+const DEFAULT_PRUNE_LIMIT: u32 = 30;
+
+/// Permissionless. Deletes receipts past their retention and `older_than_blocks`, routes idle for
+/// `ROUTE_ABORT_TTL_SECONDS` after refunding their holdings to their senders, and rate-limit
+/// history no window still counts. Each kind is scanned in key order from where the previous call
+/// left it, at most `limit` entries of it per call, so entries that cannot be pruned yet do not
+/// hold back the ones after them.
+pub fn prune_history(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    older_than_blocks: u64,
+    limit: Option<u32>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_PRUNE_LIMIT).min(MAX_PRUNE_LIMIT) as usize;
+    let height = env.block.height;
+    let now = env.block.time.seconds();
+    let mut cursors = PRUNE_CURSORS.may_load(deps.storage)?.unwrap_or_default();
+
+    let receipts = RECEIPTS
+        .range(
+            deps.storage,
+            cursors.receipts.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    cursors.receipts = page_cursor(&receipts, limit, |(route_id, _)| *route_id);
+    let mut receipts_pruned = 0u64;
+    for (route_id, receipt) in receipts {
+        let prunable_at = receipt.expires_at_height.max(
+            receipt
+                .completed_at_height
+                .saturating_add(older_than_blocks),
+        );
+        if height >= prunable_at {
+            RECEIPTS.remove(deps.storage, route_id);
+            TRACES.remove(deps.storage, route_id);
+            receipts_pruned += 1;
+        }
+    }
+
+    let routes = EXECUTION_STATES
+        .range(
+            deps.storage,
+            cursors.routes.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    cursors.routes = page_cursor(&routes, limit, |(route_id, _)| *route_id);
+    let mut response = Response::new();
+    let mut routes_refunded = 0u64;
+    for (route_id, exec_state) in routes {
+        if now < exec_state.last_checkpoint_time + ROUTE_ABORT_TTL_SECONDS {
+            continue;
+        }
+        let plan = ROUTE_PLANS.load(deps.storage, route_id)?;
+        let (messages, event) =
+            clear_route(deps.storage, route_id, &plan, &exec_state, &info.sender)?;
+        response = response.add_messages(messages).add_event(event);
+        routes_refunded += 1;
+    }
+
+    // Block counts only matter within their block, and notional only within its window.
+    let block_routes = SENDER_BLOCK_ROUTES
+        .range(
+            deps.storage,
+            cursors.block_routes.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    cursors.block_routes = page_cursor(&block_routes, limit, |(sender, _)| sender.clone());
+    let mut rate_limit_entries_pruned = 0u64;
+    for (sender, (route_height, _)) in block_routes {
+        if route_height.saturating_add(older_than_blocks.max(1)) <= height {
+            SENDER_BLOCK_ROUTES.remove(deps.storage, &sender);
+            rate_limit_entries_pruned += 1;
+        }
+    }
+    let notional = SENDER_NOTIONAL
+        .range(
+            deps.storage,
+            cursors
+                .notional
+                .as_ref()
+                .map(|(sender, asset)| Bound::exclusive((sender, asset.as_str()))),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    cursors.notional = page_cursor(&notional, limit, |(key, _)| key.clone());
+    for ((sender, asset), entries) in notional {
+        let latest = entries.last().map_or(0, |(time, _)| *time);
+        if latest + RATE_LIMIT_WINDOW_SECONDS <= now {
+            SENDER_NOTIONAL.remove(deps.storage, (&sender, &asset));
+            rate_limit_entries_pruned += 1;
+        }
+    }
+    PRUNE_CURSORS.save(deps.storage, &cursors)?;
+
+    Ok(response
+        .add_attribute("action", "prune_history")
+        .add_attribute("receipts_pruned", receipts_pruned.to_string())
+        .add_attribute("routes_refunded", routes_refunded.to_string())
+        .add_attribute(
+            "rate_limit_entries_pruned",
+            rate_limit_entries_pruned.to_string(),
+        ))
+}
+
+/// The key a scan resumes after: the last of a full page, or none to start over once the page
+/// reached the end of its kind.
+fn page_cursor<E, K>(page: &[E], limit: usize, key: impl Fn(&E) -> K) -> Option<K> {
+    if page.len() < limit {
+        return None;
+    }
+    page.last().map(key)
+}
+
+/// Pays out the fees kept for the sender, a fee collector, after they could not be sent to it.
+/// They go to `recipient` instead when set, for a collector that cannot receive them itself.
+pub fn claim_fees(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    recipient: Option<String>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let fees = UNCLAIMED_FEES
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    if fees.is_empty() {
+        return Err(ContractError::NoUnclaimedFees {});
+    }
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => info.sender.clone(),
+    };
+    UNCLAIMED_FEES.remove(deps.storage, &info.sender);
+
+    let mut response = Response::new()
+        .add_attribute("action", "claim_fees")
+        .add_attribute("collector", info.sender.to_string())
+        .add_attribute("recipient", recipient.to_string());
+    for asset in fees.iter().filter(|asset| !asset.amount.is_zero()) {
+        response = response
+            .add_message(create_send_msg(&recipient, &asset.info, asset.amount)?)
+            .add_attribute("claimed_asset", asset_id(&asset.info))
+            .add_attribute("claimed_amount", asset.amount.to_string());
+    }
+    Ok(response)
+}
+
+/// The part of `amount` an orderbook swap leaves unspent after rounding down to the market's
+/// minimum quantity tick size. AMM swaps always consume the full amount.
+pub(crate) fn unswapped_remainder(
+    storage: &dyn Storage,
+    operation: &Operation,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let Operation::OrderbookSwap(ob_op) = operation else {
+        return Ok(Uint128::zero());
+    };
+    let tick_size = orderbook_tick_size(storage, ob_op)?;
+    if tick_size.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    Ok(amount % tick_size)
+}
+
+/// The quantity tick size of an orderbook swap: its registered market's, or else the route's.
+fn orderbook_tick_size(storage: &dyn Storage, ob_op: &OrderbookSwapOp) -> StdResult<Uint128> {
+    let market = MARKETS.may_load(storage, &Addr::unchecked(&ob_op.swap_contract))?;
+    Ok(market.map_or(ob_op.min_quantity_tick_size, |market| {
+        market.min_quantity_tick_size
+    }))
+}
+
+/// Fails if the final stage's pools register decimals for the route's output, in the form the
+/// minimum is declared in or the one the stage pays out, that disagree. The minimum is compared
+/// once the output is normalized into a single form, with the forms converted one to one, which
+/// only holds while they share decimals.
+fn check_final_asset_decimals(
+    storage: &dyn Storage,
+    stages: &[Stage],
+    minimum_info: &amm::AssetInfo,
+    output_info: &amm::AssetInfo,
+) -> Result<(), ContractError> {
+    let Some(final_stage) = stages.last() else {
+        return Ok(());
+    };
+    let mut expected: Option<u8> = None;
+    for op in final_stage
+        .splits
+        .iter()
+        .filter_map(|split| split.path.last())
+    {
+        let pool = Addr::unchecked(get_operation_address(op));
+        let Some(metadata) = load_pool_config(storage, &pool)?.metadata else {
+            continue;
+        };
+        let registered = metadata
+            .token_decimals
+            .iter()
+            .filter(|entry| entry.asset_info == *minimum_info || entry.asset_info == *output_info);
+        for entry in registered {
+            match expected {
+                None => expected = Some(entry.decimals),
+                Some(decimals) if decimals != entry.decimals => {
+                    return Err(ContractError::FinalAssetDecimalsMismatch {
+                        asset: asset_id(&entry.asset_info).to_string(),
+                        expected: decimals,
+                        registered: entry.decimals,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds the hops of `path` from `start` that can be swapped in a single router call: consecutive
+/// AMM swaps on pools registered with the same router, each taking the previous hop's output.
+/// Every hop but the last must be free of aggregator fees and minimum outputs, as the router does
+/// not expose the amounts in between, and every hop after the first must have no maximum input,
+/// as its input is only known once the router has run, and must not be disabled. Returns the router and the index of the
+/// last hop when two or more qualify.
+pub(crate) fn router_batch(
+    storage: &dyn Storage,
+    plan: &RoutePlan,
+    path: &[Operation],
+    start: usize,
+) -> StdResult<Option<(String, usize)>> {
+    let router_of = |op: &Operation| -> StdResult<Option<String>> {
+        let Operation::AmmSwap(amm_op) = op else {
+            return Ok(None);
+        };
+        let pool = load_pool_config(storage, &Addr::unchecked(&amm_op.pool_address))?;
+        Ok(pool.metadata.and_then(|metadata| metadata.router))
+    };
+
+    let Some(router) = path.get(start).map(router_of).transpose()?.flatten() else {
+        return Ok(None);
+    };
+    let mut end = start;
+    while let (Operation::AmmSwap(hop), Some(next)) = (&path[end], path.get(end + 1)) {
+        let Operation::AmmSwap(next_hop) = next else {
+            break;
+        };
+        if next_hop.offer_asset_info != hop.ask_asset_info
+            || router_of(next)?.as_ref() != Some(&router)
+            || !route_fee(storage, plan, &Addr::unchecked(&hop.pool_address))?.is_zero()
+            || hop.min_out.is_some()
+            || load_pool_config(storage, &Addr::unchecked(&next_hop.pool_address))?
+                .max_input
+                .is_some()
+            || DISABLED_POOLS.has(storage, &Addr::unchecked(&next_hop.pool_address))
+        {
+            break;
+        }
+        end += 1;
+    }
+    Ok((end > start).then_some((router, end)))
+}
+
+/// Swaps `amount` through `hops` with one `ExecuteSwapOperations` call on `router`. Each pool
+/// still emits its own swap event, so the reply is read from the last hop's as usual. Only the
+/// first pool's volume is recorded, as the amounts in between are not known.
+pub fn create_router_swap_msg(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    router: &str,
+    hops: &[Operation],
+    offer_asset_info: &amm::AssetInfo,
+    amount: Uint128,
+    env: &Env,
+) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
+    let operations = hops
+        .iter()
+        .map(|hop| match hop {
+            Operation::AmmSwap(amm_op) => Ok(amm::SwapOperation::AstroSwap {
+                offer_asset_info: amm_op.offer_asset_info.clone(),
+                ask_asset_info: amm_op.ask_asset_info.clone(),
+            }),
+            _ => Err(StdError::generic_err(
+                "router batches only contain AMM swaps",
+            )),
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    let router_msg = amm::RouterExecuteMsg::ExecuteSwapOperations {
+        operations,
+        minimum_receive: None,
+        to: Some(env.contract.address.to_string()),
+        max_spread: None,
+    };
+
+    let first_pool = deps.api.addr_validate(get_operation_address(&hops[0]))?;
+    record_pool_swap(deps.storage, &first_pool, offer_asset_info, amount)?;
+
+    let cosmos_msg = match offer_asset_info {
+        amm::AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: router.to_string(),
+            msg: to_json_binary(&router_msg)?,
+            funds: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        }),
+        // The router's `Receive` hook takes the same message.
+        amm::AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.clone(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Send {
+                contract: router.to_string(),
+                amount,
+                msg: to_json_binary(&router_msg)?,
+            })?,
+            funds: vec![],
+        }),
+    };
+    Ok(cosmos_msg)
+}
+
+pub fn create_swap_cosmos_msg(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    operation: &Operation,
+    offer_asset_info: &amm::AssetInfo,
+    amount: Uint128,
+    deadline: Option<u64>,
+    env: &Env,
+) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
+    let recipient = env.contract.address.to_string();
+
+    let cosmos_msg = match operation {
+        Operation::AmmSwap(amm_op) => {
+            let pool_addr = deps.api.addr_validate(&amm_op.pool_address)?;
+            record_pool_swap(deps.storage, &pool_addr, offer_asset_info, amount)?;
+            create_amm_swap_msg(&pool_addr, offer_asset_info, amount, recipient, deadline)?
+        }
+        Operation::ProvideLiquidity(lp_op) => {
+            // Only the swapped part goes out now; the reply provides it with the retained rest.
+            let swap_amount = zap_swap_amount(&deps.as_ref().into_empty().querier, lp_op, amount)?;
+            let pool_addr = deps.api.addr_validate(&lp_op.pool_address)?;
+            record_pool_swap(deps.storage, &pool_addr, offer_asset_info, swap_amount)?;
+            create_amm_swap_msg(
+                &pool_addr,
+                offer_asset_info,
+                swap_amount,
+                recipient,
+                deadline,
+            )?
+        }
+        Operation::OrderbookSwap(ob_op) => {
+            let pool_addr = deps.api.addr_validate(&ob_op.swap_contract)?;
+            let market = MARKETS.may_load(deps.storage, &pool_addr)?;
+            let tick_size_atomic = orderbook_tick_size(deps.storage, ob_op)?;
+
+            if tick_size_atomic.is_zero() {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "min_quantity_tick_size cannot be zero",
+                )));
+            }
+
+            let rounded_atomic_amount = round_to_tick(amount, tick_size_atomic);
+
+            if rounded_atomic_amount.is_zero() {
+                // Nothing can be swapped; the whole amount is refunded as unswapped input.
+                return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: env.contract.address.to_string(),
+                    msg: to_json_binary(&msg::ExecuteMsg::Noop {})?,
+                    funds: vec![],
+                }));
+            }
+
+            let quantity_for_query_fp = FPDecimal::from(rounded_atomic_amount);
+
+            let offer_denom =
+                match &ob_op.offer_asset_info {
+                    amm::AssetInfo::NativeToken { denom } => denom.clone(),
+                    _ => return Err(ContractError::Std(StdError::generic_err(
+                        "This OrderbookSwapOp implementation only supports native token inputs.",
+                    ))),
+                };
+
+            let target_denom = orderbook_settlement_denom(deps.storage, &ob_op.ask_asset_info)?;
+
+            let simulate_msg = msg::orderbook::QueryMsg::GetOutputQuantity {
+                from_quantity: quantity_for_query_fp,
+                source_denom: offer_denom.clone(),
+                target_denom: target_denom.clone(),
+            };
+            let simulation_response: msg::orderbook::SwapEstimationResult = deps
+                .querier
+                .query_wasm_smart(&ob_op.swap_contract, &simulate_msg)?;
+            let expected_output_fp = simulation_response.result_quantity;
+            if let Some(market) = &market {
+                check_min_notional(
+                    &ob_op.swap_contract,
+                    market,
+                    &offer_denom,
+                    &target_denom,
+                    rounded_atomic_amount,
+                    expected_output_fp.into(),
+                )?;
+            }
+            let params = load_parameters(deps.storage)?;
+            let slippage = FPDecimal::from_str(&params.orderbook_slippage.to_string())?;
+
+            let min_output_with_slippage_fp = expected_output_fp * (FPDecimal::ONE - slippage);
+            let floored_min_output_fp = min_output_with_slippage_fp.int();
+
+            let swap_msg = orderbook::OrderbookExecuteMsg::SwapMinOutput {
+                target_denom,
+                min_output_quantity: floored_min_output_fp,
+            };
+
+            record_pool_swap(
+                deps.storage,
+                &pool_addr,
+                offer_asset_info,
+                rounded_atomic_amount,
+            )?;
+
+            let funds = vec![Coin {
+                denom: match &ob_op.offer_asset_info {
+                    amm::AssetInfo::NativeToken { denom } => denom.clone(),
+                    _ => unreachable!(),
+                },
+                amount: rounded_atomic_amount,
+            }];
+
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: ob_op.swap_contract.clone(),
+                msg: to_json_binary(&swap_msg)?,
+                funds,
+            })
+        }
+        Operation::SubAggregator(sub_op) => {
+            let sub_addr = deps.api.addr_validate(&sub_op.contract)?;
+            record_pool_swap(deps.storage, &sub_addr, offer_asset_info, amount)?;
+
+            // The sub-aggregator pays its output to its caller, which is this contract.
+            match offer_asset_info {
+                amm::AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: sub_addr.to_string(),
+                    msg: to_json_binary(&msg::ExecuteMsg::ExecuteRoute {
+                        stages: sub_op.stages.clone(),
+                        minimum_receive: None,
+                        max_stages_per_tx: None,
+                        max_quote_age: None,
+                        tag: None,
+                        pool_health_check: None,
+                        split_expectations: None,
+                        max_price_impact_bps: None,
+                        deadline,
+                        receive_hook: None,
+                        final_assets: None,
+                        post_action: None,
+                        max_fee_bps: None,
+                        minimum_receive_usd: None,
+                        integrator: None,
+                        recipients: None,
+                    })?,
+                    funds: vec![Coin {
+                        denom: denom.clone(),
+                        amount,
+                    }],
+                }),
+                amm::AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: contract_addr.clone(),
+                    msg: to_json_binary(&Cw20ExecuteMsg::Send {
+                        contract: sub_addr.to_string(),
+                        amount,
+                        msg: to_json_binary(&msg::Cw20HookMsg::ExecuteRoute {
+                            stages: sub_op.stages.clone(),
+                            minimum_receive: None,
+                            max_stages_per_tx: None,
+                            max_quote_age: None,
+                            tag: None,
+                            pool_health_check: None,
+                            split_expectations: None,
+                            max_price_impact_bps: None,
+                            deadline,
+                            receive_hook: None,
+                            final_assets: None,
+                            post_action: None,
+                            max_fee_bps: None,
+                            minimum_receive_usd: None,
+                            integrator: None,
+                            recipients: None,
+                        })?,
+                    })?,
+                    funds: vec![],
+                }),
+            }
+        }
+    };
+
+    Ok(cosmos_msg)
+}
+
+/// Swaps `amount` on an AMM pool, paying the output to `recipient`.
+pub(crate) fn create_amm_swap_msg(
+    pool_addr: &Addr,
+    offer_asset_info: &amm::AssetInfo,
+    amount: Uint128,
+    recipient: String,
+    deadline: Option<u64>,
+) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
+    let amm_swap_msg = amm::AmmPairExecuteMsg::Swap {
+        offer_asset: amm::Asset {
+            info: offer_asset_info.clone(),
+            amount,
+        },
+        belief_price: None,
+        max_spread: None,
+        to: Some(recipient),
+        deadline,
+    };
+
+    Ok(match offer_asset_info {
+        amm::AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pool_addr.to_string(),
+            msg: to_json_binary(&amm_swap_msg)?,
+            funds: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        }),
+        amm::AssetInfo::Token { contract_addr } => {
+            let cw20_send_msg = Cw20ExecuteMsg::Send {
+                contract: pool_addr.to_string(),
+                amount,
+                msg: to_json_binary(&amm_swap_msg)?,
+            };
+
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: contract_addr.clone(),
+                msg: to_json_binary(&cw20_send_msg)?,
+                funds: vec![],
+            })
+        }
+    })
+}
+
+/// How far a zap's deposit may be off the pool's ratio when it is provided. The deposit is sized
+/// to the ratio in the same transaction, so only rounding should move it.
+pub const ZAP_SLIPPAGE_TOLERANCE: Decimal = Decimal::percent(1);
+
+/// Provides `assets` as liquidity to `pool_addr`, minting its LP tokens to `receiver`. Returns the
+/// allowances the pool draws CW20 assets through, which must go first, and the provision itself.
+
+pub(crate) fn create_provide_liquidity_msgs(
+    pool_addr: &Addr,
+    assets: Vec<amm::Asset>,
+    receiver: String,
+    deadline: Option<u64>,
+) -> Result<
+    (
+        Vec<CosmosMsg<InjectiveMsgWrapper>>,
+        CosmosMsg<InjectiveMsgWrapper>,
+    ),
+    ContractError,
+> {
+    let mut allowance_msgs = vec![];
+    let mut funds = vec![];
+    for asset in assets.iter().filter(|asset| !asset.amount.is_zero()) {
+        match &asset.info {
+            amm::AssetInfo::NativeToken { denom } => funds.push(Coin {
+                denom: denom.clone(),
+                amount: asset.amount,
+            }),
+            amm::AssetInfo::Token { contract_addr } => {
+                allowance_msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: contract_addr.clone(),
+                    msg: to_json_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                        spender: pool_addr.to_string(),
+                        amount: asset.amount,
+                        expires: None,
+                    })?,
+                    funds: vec![],
+                }))
+            }
+        }
+    }
+    funds.sort_by(|a, b| a.denom.cmp(&b.denom));
+
+    let provide_msg = CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: pool_addr.to_string(),
+        msg: to_json_binary(&amm::AmmPairExecuteMsg::ProvideLiquidity {
+            assets,
+            slippage_tolerance: Some(ZAP_SLIPPAGE_TOLERANCE),
+            receiver: Some(receiver),
+            deadline,
+        })?,
+        funds,
+    });
+    Ok((allowance_msgs, provide_msg))
+}
+
+/// Admin-only. Sets or updates the fee for a given pool address.
+pub fn set_fee(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+    fee_percent: Decimal,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    check_fee_cap(&load_parameters(deps.storage)?, fee_percent)?;
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    FEE_MAP.save(deps.storage, &pool_addr, &fee_percent)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_fee")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("fee_percent", fee_percent.to_string()))
+}
+
+/// The current fee of every pool `stages` pass through, for a route to be charged by until it
+/// completes.
+fn snapshot_fees(storage: &dyn Storage, stages: &[Stage]) -> StdResult<Vec<msg::FeeInfo>> {
+    let mut schedule: Vec<msg::FeeInfo> = vec![];
+    let operations = stages
+        .iter()
+        .flat_map(|stage| &stage.splits)
+        .flat_map(|split| &split.path);
+    for operation in operations {
+        let pool = get_operation_address(operation);
+        if schedule.iter().any(|entry| entry.pool_address == *pool) {
+            continue;
+        }
+        schedule.push(msg::FeeInfo {
+            pool_address: pool.clone(),
+            fee_percent: effective_fee(storage, &Addr::unchecked(pool))?,
+        });
+    }
+    Ok(schedule)
+}
+
+/// Rejects fees at or above the configured cap.
+pub fn check_fee_cap(params: &Parameters, fee_percent: Decimal) -> Result<(), ContractError> {
+    if fee_percent >= params.max_fee_percent {
+        return Err(ContractError::Std(StdError::generic_err(format!(
+            "Fee percentage must be less than {}%",
+            params.max_fee_percent * Decimal::percent(10_000)
+        ))));
+    }
+    Ok(())
+}
+
+/// Admin-only. Removes the fee for a given pool address.
+pub fn remove_fee(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    FEE_MAP.remove(deps.storage, &pool_addr);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_fee")
+        .add_attribute("pool_address", pool_addr))
+}
+
+/// Admin-only. Sets the fees of several pools. An entry with an invalid address or a fee at or
+/// above the cap fails the whole message when `all_or_nothing`, and is otherwise skipped.
+pub fn set_fees(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    entries: Vec<msg::FeeInfo>,
+    all_or_nothing: bool,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let params = load_parameters(deps.storage)?;
+    let mut response = Response::new().add_attribute("action", "set_fees");
+    let mut set = 0;
+    for entry in entries {
+        let validated = check_fee_cap(&params, entry.fee_percent)
+            .and_then(|_| Ok(deps.api.addr_validate(&entry.pool_address)?));
+        match validated {
+            Ok(pool_addr) => {
+                FEE_MAP.save(deps.storage, &pool_addr, &entry.fee_percent)?;
+                set += 1;
+            }
+            Err(err) if all_or_nothing => return Err(err),
+            Err(err) => {
+                response =
+                    response.add_attribute("skipped", format!("{}: {}", entry.pool_address, err));
+            }
+        }
+    }
+
+    Ok(response.add_attribute("set", set.to_string()))
+}
+
+/// Admin-only. Removes the fees of several pools, handling invalid addresses like `set_fees`.
+pub fn remove_fees(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_addresses: Vec<String>,
+    all_or_nothing: bool,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut response = Response::new().add_attribute("action", "remove_fees");
+    let mut removed = 0;
+    for pool_address in pool_addresses {
+        match deps.api.addr_validate(&pool_address) {
+            Ok(pool_addr) => {
+                FEE_MAP.remove(deps.storage, &pool_addr);
+                removed += 1;
+            }
+            Err(err) if all_or_nothing => return Err(err.into()),
+            Err(err) => {
+                response = response.add_attribute("skipped", format!("{}: {}", pool_address, err));
+            }
+        }
+    }
+
+    Ok(response.add_attribute("removed", removed.to_string()))
+}
+
+/// Validates a fee split table: addresses must be valid and unique, and the weights positive
+/// and summing to exactly 100%.
+pub fn validate_fee_collectors(
+    deps: Deps<InjectiveQueryWrapper>,
+    collectors: Vec<msg::FeeCollectorInfo>,
+) -> Result<Vec<FeeCollector>, ContractError> {
+    let mut validated: Vec<FeeCollector> = vec![];
+    let mut total_weight = Decimal::zero();
+    for collector in collectors {
+        let address = deps.api.addr_validate(&collector.address)?;
+        if collector.weight.is_zero() {
+            return Err(ContractError::InvalidFeeCollectorWeights {});
+        }
+        if validated.iter().any(|c| c.address == address) {
+            return Err(ContractError::DuplicateFeeCollector {
+                address: address.to_string(),
+            });
+        }
+        total_weight = total_weight
+            .checked_add(collector.weight)
+            .map_err(|_| ContractError::InvalidFeeCollectorWeights {})?;
+        validated.push(FeeCollector {
+            address,
+            weight: collector.weight,
+        });
+    }
+
+    if total_weight != Decimal::one() {
+        return Err(ContractError::InvalidFeeCollectorWeights {});
+    }
+
+    Ok(validated)
+}
+
+/// Admin-only. Replaces the weighted list of fee collectors.
+pub fn update_fee_collectors(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    collectors: Vec<msg::FeeCollectorInfo>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.fee_collectors = validate_fee_collectors(deps.as_ref(), collectors)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_fee_collectors")
+        .add_attribute(
+            "fee_collectors",
+            config
+                .fee_collectors
+                .iter()
+                .map(|c| format!("{}:{}", c.address, c.weight))
+                .collect::<Vec<_>>()
+                .join(","),
+        ))
+}
+
+/// Admin-only. Replaces the operational parameters.
+pub fn update_parameters(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    parameters: Parameters,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    validate_parameters(&parameters)?;
+    PARAMETERS.save(deps.storage, &parameters)?;
+
+    Ok(Response::new().add_attribute("action", "update_parameters"))
+}
+
+pub fn validate_parameters(parameters: &Parameters) -> Result<(), ContractError> {
+    let invalid = |reason: &str| ContractError::InvalidParameters {
+        reason: reason.to_string(),
+    };
+    if parameters.max_fee_percent > Decimal::one() {
+        return Err(invalid("max_fee_percent cannot exceed 100%"));
+    }
+    if parameters.orderbook_slippage >= Decimal::one() {
+        return Err(invalid("orderbook_slippage must be below 100%"));
+    }
+    if [
+        parameters.max_stages,
+        parameters.max_splits_per_stage,
+        parameters.max_path_length,
+    ]
+    .contains(&Some(0))
+    {
+        return Err(invalid("route complexity limits must be greater than zero"));
+    }
+    if parameters
+        .max_output_multiple
+        .is_some_and(|multiple| multiple < Decimal::one())
+    {
+        return Err(invalid("max_output_multiple must be at least 1"));
+    }
+    if parameters
+        .max_rate_deviation
+        .is_some_and(|deviation| deviation.is_zero())
+    {
+        return Err(invalid("max_rate_deviation must be above zero"));
+    }
+    Ok(())
+}
+
+/// Admin-only. Adds pools to and removes pools from the allowlist.
+pub fn update_pool_allowlist(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    for pool in &add {
+        let pool_addr = deps.api.addr_validate(pool)?;
+        POOL_ALLOWLIST.save(deps.storage, &pool_addr, &Empty {})?;
+    }
+    for pool in &remove {
+        let pool_addr = deps.api.addr_validate(pool)?;
+        POOL_ALLOWLIST.remove(deps.storage, &pool_addr);
+        SELF_REGISTERED_POOLS.remove(deps.storage, &pool_addr);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_pool_allowlist")
+        .add_attribute("added", add.len().to_string())
+        .add_attribute("removed", remove.len().to_string()))
+}
+
+/// Admin-only. Sets the terms of pool self-registration, or closes it.
+pub fn set_pool_registration_terms(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    terms: Option<PoolRegistrationTerms>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let Some(terms) = terms else {
+        POOL_REGISTRATION_TERMS.remove(deps.storage);
+        return Ok(Response::new().add_attribute("action", "close_pool_registration"));
+    };
+    if terms.canary_amount.is_zero() {
+        return Err(ContractError::InvalidParameters {
+            reason: "canary_amount must be greater than zero".to_string(),
+        });
+    }
+    if terms.code_ids.is_empty() {
+        return Err(ContractError::InvalidParameters {
+            reason: "code_ids must list at least one code ID".to_string(),
+        });
+    }
+    POOL_REGISTRATION_TERMS.save(deps.storage, &terms)?;
+
+    let code_ids: Vec<String> = terms.code_ids.iter().map(u64::to_string).collect();
+    Ok(Response::new()
+        .add_attribute("action", "set_pool_registration_terms")
+        .add_attribute("fee", terms.fee.to_string())
+        .add_attribute("canary_amount", terms.canary_amount.to_string())
+        .add_attribute("code_ids", code_ids.join(",")))
+}
+
+/// Adds the sender's pool to the allowlist for the registration fee, which goes to the fee
+/// collectors. The pool must quote a non-zero output for the canary amount of each of its two
+/// assets. While the allowlist is empty every pool is allowed, so registration is closed.
+pub fn register_pool(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let terms = POOL_REGISTRATION_TERMS
+        .may_load(deps.storage)?
+        .ok_or(ContractError::PoolRegistrationClosed {})?;
+    if POOL_ALLOWLIST.is_empty(deps.storage) {
+        return Err(ContractError::PoolRegistrationClosed {});
+    }
+    let paid = match info.funds.as_slice() {
+        [] => terms.fee.amount.is_zero(),
+        [coin] => *coin == terms.fee,
+        _ => false,
+    };
+    if !paid {
+        return Err(ContractError::PoolRegistrationFeeRequired { fee: terms.fee });
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    if POOL_ALLOWLIST.has(deps.storage, &pool_addr) {
+        return Err(ContractError::PoolAlreadyAllowed {
+            pool: pool_addr.to_string(),
+        });
+    }
+    check_registered_pool(deps.as_ref(), &pool_addr, &terms)?;
+
+    POOL_ALLOWLIST.save(deps.storage, &pool_addr, &Empty {})?;
+    SELF_REGISTERED_POOLS.save(deps.storage, &pool_addr, &info.sender)?;
+
+    let config = CONFIG.load(deps.storage)?;
+    let fee_msgs = create_fee_send_submsgs(
+        &config.fee_collectors,
+        &amm::AssetInfo::NativeToken {
+            denom: terms.fee.denom.clone(),
+        },
+        terms.fee.amount,
+        None,
+    )?;
+
+    Ok(Response::new()
+        .add_submessages(fee_msgs)
+        .add_attribute("action", "register_pool")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("operator", info.sender))
+}
+
+/// Checks that a pool was instantiated from one of the vetted code IDs, then quotes the canary
+/// amount of each of its two assets for the other, failing unless both quotes are non-zero.
+fn check_registered_pool(
+    deps: Deps<InjectiveQueryWrapper>,
+    pool: &Addr,
+    terms: &PoolRegistrationTerms,
+) -> Result<(), ContractError> {
+    let check_failed = |reason: String| ContractError::PoolRegistrationCheckFailed {
+        pool: pool.to_string(),
+        reason,
+    };
+    let code_id = deps
+        .querier
+        .query_wasm_contract_info(pool)
+        .map_err(|err| check_failed(err.to_string()))?
+        .code_id;
+    if !terms.code_ids.contains(&code_id) {
+        return Err(check_failed(format!(
+            "code ID {code_id} is not open to registration"
+        )));
+    }
+
+    let canary_amount = terms.canary_amount;
+    let querier = deps.into_empty().querier;
+    let pool_info: amm::PoolResponse = querier
+        .query_wasm_smart(pool, &amm::QueryMsg::Pool {})
+        .map_err(|err| check_failed(err.to_string()))?;
+    let [first, second] = pool_info.assets.as_slice() else {
+        return Err(check_failed(
+            "pool must hold exactly two assets".to_string(),
+        ));
+    };
+
+    for (offer, ask) in [(first, second), (second, first)] {
+        let operation = Operation::AmmSwap(msg::AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: offer.info.clone(),
+            ask_asset_info: ask.info.clone(),
+            min_out: None,
+        });
+        let canary = amm::Asset {
+            info: offer.info.clone(),
+            amount: canary_amount,
+        };
+        let output = simulate_single_operation(&querier, deps.storage, &operation, &canary)
+            .map_err(|err| check_failed(err.to_string()))?;
+        if output.amount.is_zero() {
+            return Err(check_failed(format!(
+                "quoted no output for {} {}",
+                canary_amount,
+                asset_id(&offer.info)
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Admin-only. Removes a pool its operator registered from the allowlist.
+pub fn revoke_pool_registration(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    let operator = SELF_REGISTERED_POOLS
+        .may_load(deps.storage, &pool_addr)?
+        .ok_or_else(|| ContractError::PoolNotSelfRegistered {
+            pool: pool_addr.to_string(),
+        })?;
+    SELF_REGISTERED_POOLS.remove(deps.storage, &pool_addr);
+    POOL_ALLOWLIST.remove(deps.storage, &pool_addr);
+
+    Ok(Response::new()
+        .add_attribute("action", "revoke_pool_registration")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("operator", operator))
+}
+
+/// Admin-only. Sets the simulation query shape of an AMM pool.
+pub fn set_pool_query_kind(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+    query_kind: PoolQueryKind,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    update_pool_config(deps.storage, &pool_addr, |pool| {
+        pool.query_kind = query_kind.clone()
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_pool_query_kind")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("query_kind", format!("{:?}", query_kind)))
+}
+
+/// Admin-only. Sets the event a pool's swap output is read from, or restores the default.
+pub fn set_pool_event_format(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+    event_format: Option<PoolEventFormat>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    let Some(event_format) = event_format else {
+        update_pool_config(deps.storage, &pool_addr, |pool| pool.event_format = None)?;
+        return Ok(Response::new()
+            .add_attribute("action", "remove_pool_event_format")
+            .add_attribute("pool_address", pool_addr));
+    };
+
+    // Contracts can only emit events of the `wasm-` types.
+    if !event_format.event_type.starts_with("wasm") {
+        return Err(ContractError::InvalidPoolEventFormat {
+            reason: "event_type must be a wasm event type".to_string(),
+        });
+    }
+    if event_format.amount_keys.is_empty() {
+        return Err(ContractError::InvalidPoolEventFormat {
+            reason: "amount_keys must not be empty".to_string(),
+        });
+    }
+    update_pool_config(deps.storage, &pool_addr, |pool| {
+        pool.event_format = Some(event_format.clone())
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_pool_event_format")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("event_type", event_format.event_type)
+        .add_attribute("amount_keys", event_format.amount_keys.join(",")))
+}
+
+/// Admin-only. Registers the pool leftovers of one asset are swept through into another, or
+/// removes it.
+pub fn set_sweep_pool(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    offer_asset_info: amm::AssetInfo,
+    ask_asset_info: amm::AssetInfo,
+    pool_address: Option<String>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let key = (asset_id(&offer_asset_info), asset_id(&ask_asset_info));
+    let Some(pool_address) = pool_address else {
+        SWEEP_POOLS.remove(deps.storage, key);
+        return Ok(Response::new()
+            .add_attribute("action", "remove_sweep_pool")
+            .add_attribute("offer_asset", key.0)
+            .add_attribute("ask_asset", key.1));
+    };
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    if !POOL_ALLOWLIST.is_empty(deps.storage) && !POOL_ALLOWLIST.has(deps.storage, &pool_addr) {
+        return Err(ContractError::PoolNotAllowed { pool: pool_address });
+    }
+    SWEEP_POOLS.save(deps.storage, key, &pool_addr)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_sweep_pool")
+        .add_attribute("offer_asset", key.0)
+        .add_attribute("ask_asset", key.1)
+        .add_attribute("pool_address", pool_addr))
+}
+
+/// Admin-only. Sets the wrapped-INJ CW20, or stops wrapping INJ.
+pub fn set_wrapped_inj(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    address: Option<String>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let Some(address) = address else {
+        WRAPPED_INJ.remove(deps.storage);
+        return Ok(Response::new().add_attribute("action", "remove_wrapped_inj"));
+    };
+    let wrapped_inj = deps.api.addr_validate(&address)?;
+    WRAPPED_INJ.save(deps.storage, &wrapped_inj)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_wrapped_inj")
+        .add_attribute("wrapped_inj", wrapped_inj))
+}
+
+/// Admin-only. Turns balance-checked accounting on or off for one CW20 token.
+pub fn set_balance_checked_token(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    token: String,
+    enabled: bool,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let token_addr = deps.api.addr_validate(&token)?;
+    if enabled {
+        BALANCE_CHECKED_TOKENS.save(deps.storage, &token_addr, &Empty {})?;
+    } else {
+        BALANCE_CHECKED_TOKENS.remove(deps.storage, &token_addr);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_balance_checked_token")
+        .add_attribute("token", token_addr)
+        .add_attribute("enabled", enabled.to_string()))
+}
+
+/// Longest accepted pool label, in characters.
+pub const MAX_POOL_LABEL_LENGTH: usize = 64;
+
+/// Admin-only. Sets or clears the display metadata of one pool.
+pub fn set_pool_metadata(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+    metadata: Option<PoolMetadata>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    let Some(metadata) = metadata else {
+        update_pool_config(deps.storage, &pool_addr, |pool| pool.metadata = None)?;
+        return Ok(Response::new()
+            .add_attribute("action", "remove_pool_metadata")
+            .add_attribute("pool_address", pool_addr));
+    };
+
+    let label_length = metadata.label.chars().count();
+    if label_length == 0 || label_length > MAX_POOL_LABEL_LENGTH {
+        return Err(ContractError::InvalidPoolMetadata {
+            reason: format!("label must be 1 to {MAX_POOL_LABEL_LENGTH} characters"),
+        });
+    }
+    if metadata.market_id.is_some() && metadata.protocol != PoolProtocol::Orderbook {
+        return Err(ContractError::InvalidPoolMetadata {
+            reason: "market_id is only valid for orderbook pools".to_string(),
+        });
+    }
+    if let Some(router) = &metadata.router {
+        if metadata.protocol != PoolProtocol::Amm {
+            return Err(ContractError::InvalidPoolMetadata {
+                reason: "router is only valid for AMM pools".to_string(),
+            });
+        }
+        deps.api.addr_validate(router)?;
+    }
+    if let Some(entry) = metadata
+        .token_decimals
+        .iter()
+        .find(|entry| entry.decimals > amm::MAX_DECIMALS)
+    {
+        return Err(ContractError::UnsupportedDecimals {
+            asset: asset_id(&entry.asset_info).to_string(),
+            decimals: entry.decimals,
+            max: amm::MAX_DECIMALS,
+        });
+    }
+    update_pool_config(deps.storage, &pool_addr, |pool| {
+        pool.metadata = Some(metadata.clone())
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_pool_metadata")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("label", metadata.label))
+}
+
+/// Admin-only. Sets the trading rules of the orderbook market behind a swap contract, or clears
+/// them with `None`.
+pub fn set_market(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    swap_contract: String,
+    market: Option<MarketInfo>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let swap_addr = deps.api.addr_validate(&swap_contract)?;
+    let Some(market) = market else {
+        MARKETS.remove(deps.storage, &swap_addr);
+        return Ok(Response::new()
+            .add_attribute("action", "remove_market")
+            .add_attribute("swap_contract", swap_addr));
+    };
+
+    let invalid = |reason: &str| ContractError::InvalidMarket {
+        reason: reason.to_string(),
+    };
+    if market.quote_denom.is_empty() {
+        return Err(invalid("quote_denom cannot be empty"));
+    }
+    if market.min_quantity_tick_size.is_zero() {
+        return Err(invalid("min_quantity_tick_size cannot be zero"));
+    }
+    if market.min_price_tick_size.is_zero() {
+        return Err(invalid("min_price_tick_size cannot be zero"));
+    }
+    if market.taker_fee_rate >= Decimal::one() {
+        return Err(invalid("taker_fee_rate must be below 1"));
+    }
+    MARKETS.save(deps.storage, &swap_addr, &market)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_market")
+        .add_attribute("swap_contract", swap_addr)
+        .add_attribute("min_notional", market.min_notional))
+}
+
+/// Admin-only. Sets the USD price feed of an asset, or clears it with `None`.
+pub fn set_usd_price_feed(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    asset_info: amm::AssetInfo,
+    feed: Option<UsdPriceFeed>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let asset = asset_id(&asset_info).to_string();
+    let Some(feed) = feed else {
+        USD_PRICE_FEEDS.remove(deps.storage, &asset);
+        return Ok(Response::new()
+            .add_attribute("action", "remove_usd_price_feed")
+            .add_attribute("asset", asset));
+    };
+
+    if feed.decimals > amm::MAX_DECIMALS {
+        return Err(ContractError::UnsupportedDecimals {
+            asset,
+            decimals: feed.decimals,
+            max: amm::MAX_DECIMALS,
+        });
+    }
+    USD_PRICE_FEEDS.save(deps.storage, &asset, &feed)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_usd_price_feed")
+        .add_attribute("asset", asset)
+        .add_attribute("max_age_seconds", feed.max_age_seconds.to_string()))
+}
+
+pub fn register_integrator(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    addr: String,
+    fee_bps: u16,
+    collector: String,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    check_fee_cap(
+        &load_parameters(deps.storage)?,
+        Decimal::from_ratio(fee_bps, 10_000u128),
+    )?;
+    let integrator = Integrator {
+        address: deps.api.addr_validate(&addr)?,
+        fee_bps,
+        collector: deps.api.addr_validate(&collector)?,
+    };
+    INTEGRATORS.save(deps.storage, &integrator.address, &integrator)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_integrator")
+        .add_attribute("integrator", integrator.address)
+        .add_attribute("fee_bps", fee_bps.to_string())
+        .add_attribute("collector", integrator.collector))
+}
+
+pub fn remove_integrator(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    addr: String,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&addr)?;
+    INTEGRATORS.remove(deps.storage, &address);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_integrator")
+        .add_attribute("integrator", address))
+}
+
+/// Admin-only. Sets or removes the converter between a native denom and its canonical denom.
+/// Canonical denoms are not themselves converted, so each token has at most two forms.
+pub fn set_native_converter(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    converter: Option<NativeConverterInfo>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
 
-            if balance_response.balance.is_zero() {
-                // Return success but do nothing if balance is zero
-                (balance_response.balance, None)
-            } else {
-                // 3b. Create a WasmMsg to transfer the full balance to the admin
-                let msg = CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr,
-                    msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
-                        recipient: info.sender.to_string(),
-                        amount: balance_response.balance,
-                    })?,
-                    funds: vec![],
+    let Some(converter) = converter else {
+        NATIVE_CONVERTERS.remove(deps.storage, &denom);
+        return Ok(Response::new()
+            .add_attribute("action", "remove_native_converter")
+            .add_attribute("denom", denom));
+    };
+    let invalid = |reason: &str| ContractError::InvalidParameters {
+        reason: reason.to_string(),
+    };
+    if converter.canonical_denom == denom {
+        return Err(invalid("a denom cannot be converted into itself"));
+    }
+    // Either denom having another form already would give the token three.
+    for native in [&denom, &converter.canonical_denom] {
+        let info = amm::AssetInfo::NativeToken {
+            denom: native.clone(),
+        };
+        if native == INJ_DENOM || adapter_counterpart(&config.cw20_adapter_address, &info).is_some()
+        {
+            return Err(invalid(
+                "INJ and adapter denoms are converted by their own contracts",
+            ));
+        }
+    }
+    if NATIVE_CONVERTERS.has(deps.storage, &converter.canonical_denom) {
+        return Err(invalid("the canonical denom has a converter of its own"));
+    }
+    for entry in NATIVE_CONVERTERS.range(deps.storage, None, None, Order::Ascending) {
+        let (other_denom, other) = entry?;
+        if other.canonical_denom == denom {
+            return Err(invalid(
+                "the denom is the canonical denom of another converter",
+            ));
+        }
+        if other.canonical_denom == converter.canonical_denom && other_denom != denom {
+            return Err(invalid(
+                "the canonical denom is converted from another denom",
+            ));
+        }
+    }
+    let converter = NativeConverter {
+        contract: deps.api.addr_validate(&converter.contract)?,
+        canonical_denom: converter.canonical_denom,
+        to_canonical_msg: converter.to_canonical_msg,
+        from_canonical_msg: converter.from_canonical_msg,
+    };
+    // Templates are checked up front rather than failing the routes that use them.
+    for to_canonical in [true, false] {
+        converter
+            .conversion_msg(to_canonical, Uint128::one(), &env.contract.address)
+            .map_err(|err| invalid(&err.to_string()))?;
+    }
+    NATIVE_CONVERTERS.save(deps.storage, &denom, &converter)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_native_converter")
+        .add_attribute("denom", denom)
+        .add_attribute("canonical_denom", converter.canonical_denom)
+        .add_attribute("converter", converter.contract))
+}
+
+/// Admin-only. Caps the amount a single swap may offer to a pool, or lifts the cap.
+pub fn set_pool_max_input(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+    max_input: Option<Uint128>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    update_pool_config(deps.storage, &pool_addr, |pool| pool.max_input = max_input)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_pool_max_input")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute(
+            "max_input",
+            max_input.map_or_else(|| "none".to_string(), |m| m.to_string()),
+        ))
+}
+
+/// Admin-only. Freezes a pool, so routes through it are rejected, or enables it again.
+pub fn set_pool_disabled(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    pool_address: String,
+    disabled: bool,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    if disabled {
+        DISABLED_POOLS.save(deps.storage, &pool_addr, &Empty {})?;
+    } else {
+        DISABLED_POOLS.remove(deps.storage, &pool_addr);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_pool_disabled")
+        .add_attribute("pool_address", pool_addr)
+        .add_attribute("disabled", disabled.to_string()))
+}
+
+/// The assets a pool holds, as listed by its `Pool` query.
+fn query_pool_asset_infos(
+    deps: Deps<InjectiveQueryWrapper>,
+    pool: &Addr,
+) -> StdResult<Vec<amm::AssetInfo>> {
+    let pool_response: amm::PoolResponse = deps
+        .querier
+        .query_wasm_smart(pool, &amm::QueryMsg::Pool {})?;
+    Ok(pool_response
+        .assets
+        .into_iter()
+        .map(|asset| asset.info)
+        .collect())
+}
+
+/// Checks the first stage of a route seeded by a liquidity withdrawal. Each split takes one of
+/// the pool's assets as it is, the splits taking an asset share all of it, and an asset no split
+/// takes must be one the stage produces, so it can join the stage's output.
+fn check_withdrawal_stage(
+    storage: &dyn Storage,
+    stage: &Stage,
+    pool_assets: &[amm::AssetInfo],
+) -> Result<(), ContractError> {
+    let mut produced = vec![];
+    for (split_index, split) in stage.splits.iter().enumerate() {
+        if !pool_assets.contains(&path_start_info(&split.path)?) {
+            return Err(ContractError::StageInputMismatch {
+                stage_index: 0,
+                split_index,
+            });
+        }
+        if let Some(last_op) = split.path.last() {
+            produced.push(last_op.ask_asset_info()?);
+        }
+    }
+
+    for asset in pool_assets {
+        let mut total_percentage = 0u32;
+        for split in &stage.splits {
+            if path_start_info(&split.path)? == *asset {
+                total_percentage += split.percent as u32;
+            }
+        }
+        if total_percentage == 0 {
+            let mut is_produced = false;
+            for info in &produced {
+                if info == asset || is_counterpart(storage, info, asset)? {
+                    is_produced = true;
+                    break;
+                }
+            }
+            if !is_produced {
+                return Err(ContractError::UnroutedWithdrawnAsset {
+                    asset: asset_id(asset).to_string(),
                 });
-                (balance_response.balance, Some(msg))
             }
+        } else if total_percentage != 100 {
+            return Err(ContractError::InvalidPercentageSum {
+                stage_index: 0,
+                total: total_percentage,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Rejects routes through pools missing from a non-empty allowlist.
+fn check_pool_allowlist(
+    deps: Deps<InjectiveQueryWrapper>,
+    stages: &[Stage],
+) -> Result<(), ContractError> {
+    if POOL_ALLOWLIST.is_empty(deps.storage) {
+        return Ok(());
+    }
+    for op in stages
+        .iter()
+        .flat_map(|s| &s.splits)
+        .flat_map(|split| &split.path)
+    {
+        let pool = get_operation_address(op);
+        if !POOL_ALLOWLIST.has(deps.storage, &deps.api.addr_validate(pool)?) {
+            return Err(ContractError::PoolNotAllowed { pool: pool.clone() });
+        }
+    }
+    Ok(())
+}
+
+/// Rejects routes through disabled pools.
+fn check_disabled_pools(
+    deps: Deps<InjectiveQueryWrapper>,
+    stages: &[Stage],
+) -> Result<(), ContractError> {
+    if DISABLED_POOLS.is_empty(deps.storage) {
+        return Ok(());
+    }
+    for op in stages
+        .iter()
+        .flat_map(|s| &s.splits)
+        .flat_map(|split| &split.path)
+    {
+        let pool = get_operation_address(op);
+        if DISABLED_POOLS.has(deps.storage, &deps.api.addr_validate(pool)?) {
+            return Err(ContractError::PoolDisabled { addr: pool.clone() });
+        }
+    }
+    Ok(())
+}
+
+/// Admin-only. Sets or clears the dust threshold override for one asset.
+pub fn set_dust_threshold(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    asset_info: amm::AssetInfo,
+    threshold: Option<Uint128>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let id = asset_id(&asset_info);
+    match threshold {
+        Some(threshold) => DUST_THRESHOLDS.save(deps.storage, id, &threshold)?,
+        None => DUST_THRESHOLDS.remove(deps.storage, id),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_dust_threshold")
+        .add_attribute("asset", id)
+        .add_attribute(
+            "threshold",
+            threshold.map_or_else(|| "default".to_string(), |t| t.to_string()),
+        ))
+}
+
+/// Admin-only. Sends the accrued dust to the fee collectors by weight and empties the bucket.
+pub fn sweep_dust(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let bucket = DUST_BUCKET.may_load(deps.storage)?.unwrap_or_default();
+    DUST_BUCKET.remove(deps.storage);
+
+    let mut response = Response::new().add_attribute("action", "sweep_dust");
+    for asset in bucket.iter().filter(|a| !a.amount.is_zero()) {
+        response = response
+            .add_submessages(create_fee_send_submsgs(
+                &config.fee_collectors,
+                &asset.info,
+                asset.amount,
+                None,
+            )?)
+            .add_attribute("swept_asset", asset_id(&asset.info))
+            .add_attribute("swept_amount", asset.amount.to_string());
+    }
+
+    Ok(response)
+}
+
+/// Admin-only. Registers or replaces a named route template.
+pub fn register_route_template(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    name: String,
+    stages: Vec<Stage>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if stages.is_empty() {
+        return Err(ContractError::NoStages {});
+    }
+    for (stage_index, stage) in stages.iter().enumerate() {
+        if stage.splits.iter().any(|split| split.path.is_empty()) {
+            return Err(ContractError::EmptyRoute {});
+        }
+        let total_percentage: u32 = stage.splits.iter().map(|s| s.percent as u32).sum();
+        if total_percentage != 100 {
+            return Err(ContractError::InvalidPercentageSum {
+                stage_index,
+                total: total_percentage,
+            });
+        }
+    }
+
+    ROUTE_TEMPLATES.save(deps.storage, &name, &stages)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_route_template")
+        .add_attribute("name", name)
+        .add_attribute("stage_count", stages.len().to_string()))
+}
+
+/// Admin-only. Removes a named route template.
+pub fn remove_route_template(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    ROUTE_TEMPLATES.remove(deps.storage, &name);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_route_template")
+        .add_attribute("name", name))
+}
+
+/// Admin-only. Replaces the denom policy applied to the offer and ask assets of new routes.
+pub fn set_denom_policy(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    mode: DenomPolicyMode,
+    denoms: Vec<String>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let policy = DenomPolicy { mode, denoms };
+    DENOM_POLICY.save(deps.storage, &policy)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_denom_policy")
+        .add_attribute("mode", format!("{:?}", policy.mode))
+        .add_attribute("denom_count", policy.denoms.len().to_string()))
+}
+
+/// Admin-only. Sets the per-sender rate limits, replacing any previous ones.
+pub fn set_rate_limits(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    limits: RateLimits,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    RATE_LIMITS.save(deps.storage, &limits)?;
+
+    let max_routes = limits
+        .max_routes_per_block
+        .map_or("none".to_string(), |max| max.to_string());
+    Ok(Response::new()
+        .add_attribute("action", "set_rate_limits")
+        .add_attribute("max_routes_per_block", max_routes)
+        .add_attribute(
+            "notional_limit_count",
+            limits.max_notional_per_hour.len().to_string(),
+        ))
+}
+
+/// Admin-only. Removes the rate limits. Counters already recorded are kept but no longer read.
+pub fn remove_rate_limits(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    RATE_LIMITS.remove(deps.storage);
+
+    Ok(Response::new().add_attribute("action", "remove_rate_limits"))
+}
+
+/// Counts a new route against the sender's limits, rejecting it if any would be exceeded.
+fn apply_rate_limits(
+    storage: &mut dyn Storage,
+    env: &Env,
+    limits: &RateLimits,
+    sender: &Addr,
+    offer_asset: &amm::Asset,
+) -> Result<(), ContractError> {
+    if let Some(max) = limits.max_routes_per_block {
+        let count = match SENDER_BLOCK_ROUTES.may_load(storage, sender)? {
+            Some((height, count)) if height == env.block.height => count,
+            _ => 0,
+        };
+        if count >= max {
+            return Err(ContractError::RouteRateLimited { max });
         }
+        SENDER_BLOCK_ROUTES.save(storage, sender, &(env.block.height, count + 1))?;
+    }
+
+    let Some(limit) = limits
+        .max_notional_per_hour
+        .iter()
+        .find(|limit| limit.info == offer_asset.info)
+    else {
+        return Ok(());
+    };
+    let asset = asset_id(&offer_asset.info);
+    let now = env.block.time.seconds();
+    let mut window = SENDER_NOTIONAL
+        .may_load(storage, (sender, asset))?
+        .unwrap_or_default();
+    window.retain(|(time, _)| time + RATE_LIMIT_WINDOW_SECONDS > now);
+    let used: Uint128 = window.iter().map(|(_, amount)| *amount).sum();
+    if used + offer_asset.amount > limit.amount {
+        return Err(ContractError::NotionalRateLimited {
+            asset: asset.to_string(),
+            limit: limit.amount,
+            used,
+            requested: offer_asset.amount,
+        });
+    }
+    window.push((now, offer_asset.amount));
+    SENDER_NOTIONAL.save(storage, (sender, asset), &window)?;
+    Ok(())
+}
+
+/// Admin-only. Removes the denom policy so routes may use any asset.
+pub fn remove_denom_policy(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    DENOM_POLICY.remove(deps.storage);
+
+    Ok(Response::new().add_attribute("action", "remove_denom_policy"))
+}
+
+/// Admin-only. Sends the admin the contract's balance of an asset, less what it holds for others:
+/// relay deposits, the unspent offers of TWAP orders and fees their collectors have yet to claim.
+pub fn emergency_withdraw(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    asset_info: amm::AssetInfo,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    // 1. Authorization Check
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // 2. Query the contract's balance and leave what is custodied in it
+    let balance = query_asset_balance(deps.as_ref(), &env, &asset_info)?;
+    let custodied = custodied_amount(deps.storage, &asset_info)?;
+    let amount_to_withdraw = balance.saturating_sub(custodied);
+
+    // 3. Send the rest to the admin, doing nothing if there is none
+    let send_msg = if amount_to_withdraw.is_zero() {
+        None
+    } else {
+        Some(match asset_info.clone() {
+            amm::AssetInfo::NativeToken { denom } => CosmosMsg::Bank(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: vec![Coin::new(amount_to_withdraw, denom)],
+            }),
+            amm::AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: info.sender.to_string(),
+                    amount: amount_to_withdraw,
+                })?,
+                funds: vec![],
+            }),
+        })
     };
 
     let mut response = Response::new()
         .add_attribute("action", "emergency_withdraw")
         .add_attribute("recipient", info.sender.to_string())
         .add_attribute("asset", format!("{:?}", asset_info))
+        .add_attribute("custodied_amount", custodied.to_string())
         .add_attribute("withdrawn_amount", amount_to_withdraw.to_string());
 
     if let Some(msg) = send_msg {
@@ -343,3 +3235,463 @@ pub fn emergency_withdraw(
 
     Ok(response)
 }
+
+/// How much of an asset the contract holds on others' behalf.
+fn custodied_amount(storage: &dyn Storage, asset_info: &amm::AssetInfo) -> StdResult<Uint128> {
+    let mut custodied = Uint128::zero();
+    if let amm::AssetInfo::NativeToken { denom } = asset_info {
+        for deposit in RELAY_DEPOSITS.range(storage, None, None, Order::Ascending) {
+            let ((_, deposit_denom), amount) = deposit?;
+            if deposit_denom == *denom {
+                custodied += amount;
+            }
+        }
+    }
+    for order in TWAP_ORDERS.range(storage, None, None, Order::Ascending) {
+        let (_, order) = order?;
+        if order.remaining.info == *asset_info {
+            custodied += order.remaining.amount;
+        }
+    }
+    for fees in UNCLAIMED_FEES.range(storage, None, None, Order::Ascending) {
+        let (_, assets) = fees?;
+        custodied += assets
+            .iter()
+            .filter(|asset| asset.info == *asset_info)
+            .map(|asset| asset.amount)
+            .sum::<Uint128>();
+    }
+    Ok(custodied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{AmmSwapOp, ExecuteMsg, Split};
+    use crate::state::{Receipt, TokenDecimals, TwapOrder};
+    use crate::testing::{mock_deps, MockContracts, MockDeps};
+    use cosmwasm_std::testing::{message_info, mock_env, MockApi};
+    use cosmwasm_std::to_json_vec;
+    use k256::ecdsa::{signature::hazmat::PrehashSigner, Signature, SigningKey};
+    use sha2::{Digest, Sha256};
+
+    fn single_pool_route(pool: &Addr) -> Vec<Stage> {
+        vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: pool.to_string(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }]
+    }
+
+    #[test]
+    fn test_price_impact_without_a_reference_rate_is_rejected() {
+        let pool = MockApi::default().addr_make("pool");
+        let deps = mock_deps(&[], MockContracts::new().with_amm_pool(pool.as_str(), 0));
+        let offer_asset = amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            amount: Uint128::new(50_000),
+        };
+
+        let err = check_price_impact(deps.as_ref(), &single_pool_route(&pool), &offer_asset, 100)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PriceImpactUnmeasurable {
+                reference_amount: Uint128::new(50),
+            }
+        );
+    }
+
+    #[test]
+    fn test_final_forms_registered_with_different_decimals_are_rejected() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let inj = amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        };
+        let native_shroom = amm::AssetInfo::NativeToken {
+            denom: "factory/creator/shroom".to_string(),
+        };
+        let cw20_shroom = amm::AssetInfo::Token {
+            contract_addr: deps.api.addr_make("shroom").to_string(),
+        };
+        let pools: Vec<Addr> = ["pool_a", "pool_b", "pool_c"]
+            .iter()
+            .map(|name| deps.api.addr_make(name))
+            .collect();
+        let stages = vec![Stage {
+            splits: pools
+                .iter()
+                .map(|pool| Split {
+                    percent: 33,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: pool.to_string(),
+                        offer_asset_info: inj.clone(),
+                        ask_asset_info: native_shroom.clone(),
+                        min_out: None,
+                    })],
+                })
+                .collect(),
+        }];
+        let register =
+            |storage: &mut dyn Storage, pool: &Addr, token_decimals: Vec<(amm::AssetInfo, u8)>| {
+                update_pool_config(storage, pool, |config| {
+                    config.metadata = Some(PoolMetadata {
+                        label: "SHROOM".to_string(),
+                        protocol: PoolProtocol::Amm,
+                        token_decimals: token_decimals
+                            .into_iter()
+                            .map(|(asset_info, decimals)| TokenDecimals {
+                                asset_info,
+                                decimals,
+                            })
+                            .collect(),
+                        market_id: None,
+                        router: None,
+                    })
+                })
+                .unwrap();
+            };
+        // Decimals of assets other than the output's forms are not compared, and the third pool
+        // registers none.
+        register(
+            &mut deps.storage,
+            &pools[0],
+            vec![(inj.clone(), 6), (native_shroom.clone(), 18)],
+        );
+        register(
+            &mut deps.storage,
+            &pools[1],
+            vec![(cw20_shroom.clone(), 18)],
+        );
+        check_final_asset_decimals(&deps.storage, &stages, &cw20_shroom, &native_shroom).unwrap();
+
+        register(&mut deps.storage, &pools[1], vec![(cw20_shroom.clone(), 6)]);
+        let err = check_final_asset_decimals(&deps.storage, &stages, &cw20_shroom, &native_shroom)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::FinalAssetDecimalsMismatch {
+                asset: asset_id(&cw20_shroom).to_string(),
+                expected: 18,
+                registered: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn test_emergency_withdraw_leaves_custodied_funds() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let admin = deps.api.addr_make("admin");
+        let owner = deps.api.addr_make("owner");
+        let inj = amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        };
+        deps.querier.bank.update_balance(
+            &env.contract.address,
+            vec![Coin::new(1_000u128, "inj"), Coin::new(80u128, "usdt")],
+        );
+        RELAY_DEPOSITS
+            .save(&mut deps.storage, (&owner, "inj"), &Uint128::new(300))
+            .unwrap();
+        RELAY_DEPOSITS
+            .save(&mut deps.storage, (&owner, "usdt"), &Uint128::new(80))
+            .unwrap();
+        TWAP_ORDERS
+            .save(
+                &mut deps.storage,
+                1,
+                &TwapOrder {
+                    id: 1,
+                    owner: owner.clone(),
+                    stages: single_pool_route(&deps.api.addr_make("pool")),
+                    remaining: amm::Asset {
+                        info: inj.clone(),
+                        amount: Uint128::new(200),
+                    },
+                    slices_left: 2,
+                    interval: 60,
+                    next_slice_at: 0,
+                    max_slippage_bps: 100,
+                },
+            )
+            .unwrap();
+        UNCLAIMED_FEES
+            .save(
+                &mut deps.storage,
+                &deps.api.addr_make("collector"),
+                &vec![amm::Asset {
+                    info: inj.clone(),
+                    amount: Uint128::new(50),
+                }],
+            )
+            .unwrap();
+
+        let res =
+            emergency_withdraw(deps.as_mut(), env.clone(), message_info(&admin, &[]), inj).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: admin.to_string(),
+                amount: vec![Coin::new(450u128, "inj")],
+            })
+        );
+
+        // Every USDT the contract holds is an owner's deposit.
+        let res = emergency_withdraw(
+            deps.as_mut(),
+            env,
+            message_info(&admin, &[]),
+            amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    /// An owner with a relay key and 1_000 INJ deposited, and a relayer allowed to run routes.
+    fn seed_relay(deps: &mut MockDeps, signing_key: &SigningKey) -> (Addr, Addr) {
+        let owner = deps.api.addr_make("owner");
+        let relayer = deps.api.addr_make("relayer");
+        let pubkey = signing_key.verifying_key().to_encoded_point(true);
+        RELAY_KEYS
+            .save(
+                &mut deps.storage,
+                &owner,
+                &pubkey.as_bytes().to_vec().into(),
+            )
+            .unwrap();
+        RELAY_DEPOSITS
+            .save(&mut deps.storage, (&owner, "inj"), &Uint128::new(1_000))
+            .unwrap();
+        RELAYERS
+            .save(&mut deps.storage, &relayer, &Empty {})
+            .unwrap();
+        (owner, relayer)
+    }
+
+    fn relayed_route(env: &Env, owner: &Addr, pool: &Addr, nonce: u64) -> msg::RelayedRoute {
+        msg::RelayedRoute {
+            chain_id: env.block.chain_id.clone(),
+            contract: env.contract.address.to_string(),
+            owner: owner.to_string(),
+            stages: single_pool_route(pool),
+            offer: Coin::new(100u128, "inj"),
+            minimum_receive: None,
+            nonce,
+            expires_at: env.block.time.seconds() + 60,
+        }
+    }
+
+    fn sign(signing_key: &SigningKey, digest: &[u8]) -> Binary {
+        let signature: Signature = signing_key.sign_prehash(digest).unwrap();
+        signature.to_bytes().to_vec().into()
+    }
+
+    fn relay(route: msg::RelayedRoute, signature: Binary) -> ExecuteMsg {
+        ExecuteMsg::ExecuteRouteFor {
+            owner: route.owner,
+            stages: route.stages,
+            offer: route.offer,
+            minimum_receive: route.minimum_receive,
+            nonce: route.nonce,
+            expires_at: route.expires_at,
+            signature,
+        }
+    }
+
+    #[test]
+    fn test_relayed_route_runs_once_with_the_owners_signature_over_its_domain_hash() {
+        let pool = MockApi::default().addr_make("pool");
+        let mut deps = mock_deps(
+            &[],
+            MockContracts::new().with_amm_pool(pool.as_str(), 1_000),
+        );
+        let env = mock_env();
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let (owner, relayer) = seed_relay(&mut deps, &signing_key);
+        let route = relayed_route(&env, &owner, &pool, 0);
+        let execute = |deps: &mut MockDeps, msg: ExecuteMsg| {
+            crate::contract::execute(deps.as_mut(), env.clone(), message_info(&relayer, &[]), msg)
+        };
+
+        // The digest is the route's JSON behind its length-prefixed domain.
+        let mut preimage = (msg::RELAYED_ROUTE_DOMAIN.len() as u64)
+            .to_be_bytes()
+            .to_vec();
+        preimage.extend(msg::RELAYED_ROUTE_DOMAIN.as_bytes());
+        preimage.extend(to_json_vec(&route).unwrap());
+        let digest: [u8; 32] = Sha256::digest(&preimage).into();
+        assert_eq!(route.hash().unwrap(), digest);
+
+        let other_key = SigningKey::from_slice(&[8u8; 32]).unwrap();
+        let undomained: [u8; 32] = Sha256::digest(to_json_vec(&route).unwrap()).into();
+        let other_chain = msg::RelayedRoute {
+            chain_id: "other-chain".to_string(),
+            ..route.clone()
+        };
+        for signature in [
+            sign(&other_key, &digest),
+            sign(&signing_key, &undomained),
+            sign(&signing_key, &other_chain.hash().unwrap()),
+        ] {
+            assert_eq!(
+                execute(&mut deps, relay(route.clone(), signature)).unwrap_err(),
+                ContractError::SignatureVerificationFailed {}
+            );
+        }
+
+        execute(&mut deps, relay(route.clone(), sign(&signing_key, &digest))).unwrap();
+        assert_eq!(RELAY_NONCES.load(&deps.storage, &owner).unwrap(), 1);
+        assert_eq!(
+            RELAY_DEPOSITS.load(&deps.storage, (&owner, "inj")).unwrap(),
+            Uint128::new(900)
+        );
+
+        // The same signature cannot be relayed twice.
+        assert_eq!(
+            execute(&mut deps, relay(route, sign(&signing_key, &digest))).unwrap_err(),
+            ContractError::InvalidNonce {
+                expected: 1,
+                received: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_relayed_route_is_rejected_once_expired_or_cancelled() {
+        let pool = MockApi::default().addr_make("pool");
+        let mut deps = mock_deps(
+            &[],
+            MockContracts::new().with_amm_pool(pool.as_str(), 1_000),
+        );
+        let mut env = mock_env();
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let (owner, relayer) = seed_relay(&mut deps, &signing_key);
+        let route = relayed_route(&env, &owner, &pool, 0);
+        let signature = sign(&signing_key, &route.hash().unwrap());
+
+        let mut expired_env = env.clone();
+        expired_env.block.time = env.block.time.plus_seconds(61);
+        let err = crate::contract::execute(
+            deps.as_mut(),
+            expired_env.clone(),
+            message_info(&relayer, &[]),
+            relay(route.clone(), signature.clone()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::RelayedRouteExpired {
+                expires_at: route.expires_at,
+                current_time: expired_env.block.time.seconds(),
+            }
+        );
+
+        // The owner cancels every route signed below nonce 3, and cannot move back.
+        let cancel = |deps: &mut MockDeps, next_nonce: u64| {
+            crate::contract::execute(
+                deps.as_mut(),
+                mock_env(),
+                message_info(&owner, &[]),
+                ExecuteMsg::CancelRelayedRoutes { next_nonce },
+            )
+        };
+        cancel(&mut deps, 3).unwrap();
+        assert_eq!(
+            cancel(&mut deps, 2).unwrap_err(),
+            ContractError::InvalidNonce {
+                expected: 4,
+                received: 2,
+            }
+        );
+        env.block.time = env.block.time.plus_seconds(1);
+        let err = crate::contract::execute(
+            deps.as_mut(),
+            env,
+            message_info(&relayer, &[]),
+            relay(route, signature),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidNonce {
+                expected: 3,
+                received: 0,
+            }
+        );
+        assert_eq!(
+            RELAY_DEPOSITS.load(&deps.storage, (&owner, "inj")).unwrap(),
+            Uint128::new(1_000)
+        );
+    }
+
+    #[test]
+    fn test_prune_history_moves_past_receipts_it_cannot_prune_yet() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let inj = amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            amount: Uint128::new(100),
+        };
+        // Routes 1 and 2 are still within their retention; route 3 is past it.
+        for (route_id, expires_at_height) in [(1, u64::MAX), (2, u64::MAX), (3, 0)] {
+            let receipt = Receipt {
+                input: inj.clone(),
+                output: inj.clone(),
+                additional_outputs: vec![],
+                fees: vec![],
+                stages_executed: 1,
+                conversions: 0,
+                completed_at_height: 0,
+                completed_at_time: 0,
+                expires_at_height,
+                executions: vec![],
+                fee_schedule: vec![],
+            };
+            RECEIPTS
+                .save(&mut deps.storage, route_id, &receipt)
+                .unwrap();
+        }
+        let mut prune = || {
+            let res = prune_history(
+                deps.as_mut(),
+                env.clone(),
+                message_info(&MockApi::default().addr_make("anyone"), &[]),
+                0,
+                Some(2),
+            )
+            .unwrap();
+            res.attributes
+                .iter()
+                .find(|a| a.key == "receipts_pruned")
+                .map(|a| a.value.clone())
+                .unwrap()
+        };
+
+        assert_eq!(prune(), "0");
+        assert_eq!(prune(), "1");
+        // Back at the start, the retained receipts are examined again.
+        assert_eq!(prune(), "0");
+        let remaining: Vec<u64> = RECEIPTS
+            .keys(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap();
+        assert_eq!(remaining, vec![1, 2]);
+    }
+}
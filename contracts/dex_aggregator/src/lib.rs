@@ -2,8 +2,21 @@ pub mod contract;
 pub mod error;
 pub mod execute;
 pub mod msg;
+mod oracle;
+pub mod packed;
+#[cfg(feature = "library")]
+pub mod planner;
+#[cfg(not(feature = "library"))]
+mod planner;
 pub mod query;
 pub mod reply;
+#[cfg(feature = "library")]
+pub mod routing;
+#[cfg(not(feature = "library"))]
+mod routing;
 pub mod state;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod twap;
 
 pub use crate::error::ContractError;
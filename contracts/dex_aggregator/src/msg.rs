@@ -1,12 +1,18 @@
 #[allow(unused_imports)]
-use crate::state::Config;
+use crate::state::{
+    AdminLogEntry, Config, DenomPolicy, DenomPolicyMode, FeeCollector, Integrator, MarketInfo,
+    NativeConverter, OperationExecution, Parameters, PoolEventFormat, PoolMetadata, PoolQueryKind,
+    PoolRegistrationTerms, PoolStats, RateLimits, Receipt, TraceEntry, TwapOrder, UsdPriceFeed,
+};
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Coin, Decimal, Uint128};
+use cosmwasm_std::{
+    to_json_vec, Addr, Binary, Coin, Decimal, StdError, StdResult, Timestamp, Uint128,
+};
 use cw20::Cw20ReceiveMsg;
+use sha2::{Digest, Sha256};
 
 pub mod cw20_adapter {
     use super::*;
-    use cosmwasm_std::Binary;
 
     #[cw_serde]
     pub struct InstantiateMsg {}
@@ -45,24 +51,72 @@ pub mod cw20_adapter {
     }
 }
 
+/// The wrapped-INJ contract: `Deposit` mints the attached INJ as its CW20 to the sender, and
+/// `Withdraw` burns the sender's CW20 and sends back the INJ.
+pub mod wrapped_inj {
+    use super::*;
+
+    #[cw_serde]
+    pub enum ExecuteMsg {
+        Deposit {},
+        Withdraw { amount: Uint128 },
+    }
+}
+
 pub mod amm {
     use super::*;
 
+    pub use aggregation_types::{Asset, AssetInfo, MAX_DECIMALS};
+
+    #[cw_serde]
+    pub enum QueryMsg {
+        Simulation { offer_asset: Asset },
+        Pool {},
+    }
+
     #[cw_serde]
-    pub enum AssetInfo {
-        Token { contract_addr: String },
-        NativeToken { denom: String },
+    pub struct PoolResponse {
+        pub assets: Vec<Asset>,
+        pub total_share: Uint128,
     }
 
+    /// Sent to a pair along with its LP tokens.
     #[cw_serde]
-    pub struct Asset {
-        pub info: AssetInfo,
-        pub amount: Uint128,
+    pub enum PairCw20HookMsg {
+        WithdrawLiquidity {},
     }
 
+    /// Router-style simulation, for pools that do not expose `Simulation`.
     #[cw_serde]
-    pub enum QueryMsg {
-        Simulation { offer_asset: Asset },
+    pub enum SwapOperationsQueryMsg {
+        SimulateSwapOperations {
+            offer_amount: Uint128,
+            operations: Vec<SwapOperation>,
+        },
+    }
+
+    #[cw_serde]
+    pub enum SwapOperation {
+        AstroSwap {
+            offer_asset_info: AssetInfo,
+            ask_asset_info: AssetInfo,
+        },
+    }
+
+    /// Router-style execution, swapping through several pairs in one call.
+    #[cw_serde]
+    pub enum RouterExecuteMsg {
+        ExecuteSwapOperations {
+            operations: Vec<SwapOperation>,
+            minimum_receive: Option<Uint128>,
+            to: Option<String>,
+            max_spread: Option<Decimal>,
+        },
+    }
+
+    #[cw_serde]
+    pub struct SimulateSwapOperationsResponse {
+        pub amount: Uint128,
     }
 
     #[cw_serde]
@@ -82,6 +136,12 @@ pub mod amm {
             to: Option<String>,
             deadline: Option<u64>,
         },
+        ProvideLiquidity {
+            assets: Vec<amm::Asset>,
+            slippage_tolerance: Option<Decimal>,
+            receiver: Option<String>,
+            deadline: Option<u64>,
+        },
     }
 }
 
@@ -124,6 +184,7 @@ pub struct AmmSwapOp {
     pub pool_address: String,
     pub offer_asset_info: amm::AssetInfo,
     pub ask_asset_info: amm::AssetInfo,
+    pub min_out: Option<Uint128>,
 }
 
 #[cw_serde]
@@ -132,12 +193,93 @@ pub struct OrderbookSwapOp {
     pub offer_asset_info: amm::AssetInfo,
     pub ask_asset_info: amm::AssetInfo,
     pub min_quantity_tick_size: Uint128,
+    pub min_out: Option<Uint128>,
+}
+
+/// Forwards the input to another aggregator instance, which runs `stages` and pays its output
+/// back to this contract.
+#[cw_serde]
+pub struct SubAggregatorOp {
+    pub contract: String,
+    pub stages: Vec<Stage>,
+    pub min_out: Option<Uint128>,
+}
+
+/// The pricing curve of a liquidity pool, which decides how a zap's LP output is estimated.
+#[cw_serde]
+pub enum PairType {
+    Xyk {},
+    Stable {},
+}
+
+/// Zaps the input into a pool: part of it is swapped through the pool for its other asset, and
+/// both are provided as liquidity, minting `lp_token` to this contract. A constant product pool
+/// swaps just enough for both sides to match its ratio after the swap, and whatever still does
+/// not fit is refunded; a stable pool swaps half. Only valid as the last operation of a path in
+/// the route's final stage.
+#[cw_serde]
+pub struct ProvideLiquidityOp {
+    pub pool_address: String,
+    pub pair_type: PairType,
+    pub offer_asset_info: amm::AssetInfo,
+    pub other_asset_info: amm::AssetInfo,
+    pub lp_token: amm::AssetInfo,
+    pub min_out: Option<Uint128>,
 }
 
 #[cw_serde]
 pub enum Operation {
     AmmSwap(AmmSwapOp),
     OrderbookSwap(OrderbookSwapOp),
+    SubAggregator(SubAggregatorOp),
+    ProvideLiquidity(ProvideLiquidityOp),
+}
+
+impl Operation {
+    /// The asset the operation consumes.
+    pub fn offer_asset_info(&self) -> StdResult<amm::AssetInfo> {
+        match self {
+            Operation::AmmSwap(op) => Ok(op.offer_asset_info.clone()),
+            Operation::OrderbookSwap(op) => Ok(op.offer_asset_info.clone()),
+            Operation::ProvideLiquidity(op) => Ok(op.offer_asset_info.clone()),
+            Operation::SubAggregator(op) => op
+                .stages
+                .first()
+                .and_then(|stage| stage.splits.first())
+                .and_then(|split| split.path.first())
+                .ok_or_else(|| StdError::generic_err("Sub-aggregator route cannot be empty"))?
+                .offer_asset_info(),
+        }
+    }
+
+    /// The asset the operation produces. For a sub-aggregator this is the declared output of
+    /// its route, which it may deliver in the other form (native or CW20).
+    pub fn ask_asset_info(&self) -> StdResult<amm::AssetInfo> {
+        match self {
+            Operation::AmmSwap(op) => Ok(op.ask_asset_info.clone()),
+            Operation::OrderbookSwap(op) => Ok(op.ask_asset_info.clone()),
+            Operation::ProvideLiquidity(op) => Ok(op.lp_token.clone()),
+            Operation::SubAggregator(op) => op
+                .stages
+                .last()
+                .and_then(|stage| stage.splits.first())
+                .and_then(|split| split.path.last())
+                .ok_or_else(|| StdError::generic_err("Sub-aggregator route cannot be empty"))?
+                .ask_asset_info(),
+        }
+    }
+
+    /// The least the operation may output, before the aggregator fee, or LP tokens mint for a
+    /// zap. Checked as its reply is processed, so one bad pool in a path fails the route even
+    /// when other splits make up the route's minimum receive.
+    pub fn min_out(&self) -> Option<Uint128> {
+        match self {
+            Operation::AmmSwap(op) => op.min_out,
+            Operation::OrderbookSwap(op) => op.min_out,
+            Operation::SubAggregator(op) => op.min_out,
+            Operation::ProvideLiquidity(op) => op.min_out,
+        }
+    }
 }
 
 #[cw_serde]
@@ -153,20 +295,168 @@ pub struct Stage {
 
 #[cw_serde]
 pub struct PlannedSwap {
+    /// The split whose path the swap starts.
+    pub split_index: u64,
     pub operation: Operation,
     pub amount: Uint128,
 }
 
-pub struct StagePlan {
-    pub swaps_to_execute: Vec<PlannedSwap>,
-    pub conversions_needed: Vec<(amm::Asset, amm::AssetInfo)>,
+/// The block height a route's quote was simulated at, and how many blocks it stays valid for.
+#[cw_serde]
+pub struct QuoteAge {
+    pub quote_height: u64,
+    pub max_blocks: u64,
+}
+
+/// The fields of an `ExecuteRoute` other than its stages, carried as JSON in a packed route and
+/// handed together to the route's execution. Fields left out are unset.
+#[cw_serde]
+#[derive(Default)]
+pub struct RouteOptions {
+    pub minimum_receive: Option<amm::Asset>,
+    pub max_stages_per_tx: Option<u64>,
+    pub max_quote_age: Option<QuoteAge>,
+    pub tag: Option<String>,
+    pub pool_health_check: Option<PoolHealthPolicy>,
+    pub split_expectations: Option<SplitExpectations>,
+    pub max_price_impact_bps: Option<u16>,
+    pub deadline: Option<u64>,
+    pub receive_hook: Option<ReceiveHook>,
+    pub final_assets: Option<Vec<amm::Asset>>,
+    pub post_action: Option<PostAction>,
+    pub max_fee_bps: Option<u16>,
+    pub minimum_receive_usd: Option<Decimal>,
+    pub integrator: Option<String>,
+    pub recipients: Option<Vec<RecipientInfo>>,
+}
+
+/// What to do with a stage's swap whose path has a pool that reverts or returns nothing when
+/// probed.
+#[cw_serde]
+pub enum PoolHealthPolicy {
+    /// Leave the split's input unswapped and refund it when the route completes.
+    Skip,
+    /// Move the split's input onto a healthy swap of the same stage taking the same asset.
+    Fallback,
+    /// Reject the route.
+    Abort,
+}
+
+/// Per-split outputs from the caller's off-chain simulation, guarding against pool state changed
+/// earlier in the same block.
+#[cw_serde]
+pub struct SplitExpectations {
+    /// The expected output of every split's path, indexed by stage and then split.
+    pub expected_outputs: Vec<Vec<Uint128>>,
+    /// Largest accepted shortfall below an expected output, as a fraction of it.
+    pub tolerance: Decimal,
+}
+
+/// Delivers a route's output to a contract through its CW20 `Receive` hook, for vaults, lockers
+/// and other contracts that act on tokens as they arrive.
+#[cw_serde]
+pub struct ReceiveHook {
+    /// The contract the output is sent to in place of the sender.
+    pub contract: String,
+    /// Passed to the contract's `Receive` hook.
+    pub msg: Binary,
+}
+
+/// An action taken on the sender's behalf with a route's output once it is paid out to them.
+#[cw_serde]
+pub enum PostAction {
+    /// Delegates the output to `validator` with an authz `MsgExec`, so the sender must have
+    /// granted the aggregator a `MsgDelegate` authorization, e.g. with
+    /// `injectived tx authz grant <aggregator> generic --msg-type
+    /// /cosmos.staking.v1beta1.MsgDelegate`. The grant is checked before the route runs, which
+    /// is rejected without it. The route must pay out in the bond denom.
+    Delegate { validator: String },
+}
+
+/// An address paid `share_bps` basis points of a route's output.
+#[cw_serde]
+pub struct RecipientInfo {
+    pub address: String,
+    pub share_bps: u16,
 }
 
 #[cw_serde]
 pub enum Cw20HookMsg {
     ExecuteRoute {
         stages: Vec<Stage>,
-        minimum_receive: Option<String>,
+        // The least the route must pay out, in the asset it is expected to pay out in.
+        minimum_receive: Option<amm::Asset>,
+        // When set, the route checkpoints after this many stages and must be resumed with
+        // `ContinueRoute`, letting routes exceed the gas limit of a single transaction.
+        max_stages_per_tx: Option<u64>,
+        // When set, execution is rejected if the quote the route was built from is too old.
+        max_quote_age: Option<QuoteAge>,
+        // Client or channel tag echoed into the completion attributes, at most 64 characters.
+        tag: Option<String>,
+        // When set, every pool along a stage's paths is simulated with the input it is expected
+        // to receive before the stage's swaps are dispatched, and a split whose path has a
+        // failing pool is handled by this policy.
+        pool_health_check: Option<PoolHealthPolicy>,
+        // When set, a route aborts as soon as any split pays out less than its quoted output,
+        // less the tolerance.
+        split_expectations: Option<SplitExpectations>,
+        // When set, execution is rejected if simulating the route at its actual size implies a
+        // worse rate than at a small reference size by more than this many basis points, or if
+        // the reference size simulates to nothing.
+        max_price_impact_bps: Option<u16>,
+        // When set, a unix time in seconds after which the route is rejected. It is also passed
+        // to every AMM pool swapped through, so stages run by `ContinueRoute` honour it too.
+        deadline: Option<u64>,
+        // When set, the output is delivered with a CW20 `Send` to the hook's contract, or through
+        // the adapter's `RedeemAndSend` when it is held in native form, instead of transferred
+        // to the sender. The route's output must be a CW20 token.
+        receive_hook: Option<ReceiveHook>,
+        // When set, the route runs in multi-output mode: its final holdings are paid out as they
+        // are, without normalization, and each declared asset's amount is the least that asset
+        // must pay out. Must list exactly the assets the final stage produces, and cannot be
+        // combined with `minimum_receive` or `receive_hook`.
+        final_assets: Option<Vec<amm::Asset>>,
+        // When set, the output is paid to the sender and the action is then taken with it on
+        // their behalf. Cannot be combined with `receive_hook` or `final_assets`.
+        post_action: Option<PostAction>,
+        // When set, the highest aggregator fee in basis points the sender agreed to. The route
+        // aborts if any hop is charged more, such as after a fee change made since it was quoted.
+        max_fee_bps: Option<u16>,
+        // When set, the least the output must be worth in USD, valued at settlement with the
+        // oracle feed configured for the asset it is paid out in.
+        minimum_receive_usd: Option<Decimal>,
+        // When set, a registered integrator whose fee is taken from the output and paid to its
+        // collector.
+        integrator: Option<String>,
+        // When set, the output is paid out to these addresses by their shares, which must sum to
+        // 10,000 basis points, instead of to the sender. Cannot be combined with `receive_hook`
+        // or `post_action`.
+        recipients: Option<Vec<RecipientInfo>>,
+    },
+    // `ExecuteMsg::ExecuteRouteAtQuote` for a route starting from the CW20 sent.
+    ExecuteRouteAtQuote {
+        stages: Vec<Stage>,
+        quoted_out: Uint128,
+        max_deviation_bps: u16,
+    },
+    ExecuteTemplate {
+        name: String,
+        minimum_receive: Option<amm::Asset>,
+    },
+    // Sent with the LP tokens of `pool_address`: the liquidity is withdrawn and the pool's assets
+    // seed the route. Each first-stage split takes one of them as it is, the splits taking an
+    // asset share all of it, and an asset no split takes is added to the first stage's output.
+    WithdrawAndRoute {
+        pool_address: String,
+        stages: Vec<Stage>,
+        minimum_receive: Option<amm::Asset>,
+        max_stages_per_tx: Option<u64>,
+        tag: Option<String>,
+        deadline: Option<u64>,
+    },
+    // Attached by the aggregator to adapter sends; only accepted from the cw20 adapter.
+    InternalConversion {
+        route_id: u64,
     },
 }
 
@@ -174,16 +464,197 @@ pub enum Cw20HookMsg {
 pub struct InstantiateMsg {
     pub admin: String,
     pub cw20_adapter_address: String,
-    pub fee_collector_address: String,
+    pub fee_collectors: Vec<FeeCollectorInfo>,
+    /// Per-pool fees to set at deployment, validated like `SetFee`.
+    pub initial_fees: Option<Vec<FeeInfo>>,
+    /// Pools to seed the allowlist with. Leaving it empty allows every pool.
+    pub allowed_pools: Option<Vec<String>>,
+    /// Defaults to `Parameters::default()`.
+    pub parameters: Option<Parameters>,
 }
 
 #[cw_serde]
 pub enum ExecuteMsg {
     ExecuteRoute {
         stages: Vec<Stage>,
-        minimum_receive: Option<String>,
+        // The least the route must pay out, in the asset it is expected to pay out in.
+        minimum_receive: Option<amm::Asset>,
+        // When set, the route checkpoints after this many stages and must be resumed with
+        // `ContinueRoute`, letting routes exceed the gas limit of a single transaction.
+        max_stages_per_tx: Option<u64>,
+        // When set, execution is rejected if the quote the route was built from is too old.
+        max_quote_age: Option<QuoteAge>,
+        // Client or channel tag echoed into the completion attributes, at most 64 characters.
+        tag: Option<String>,
+        // When set, every pool along a stage's paths is simulated with the input it is expected
+        // to receive before the stage's swaps are dispatched, and a split whose path has a
+        // failing pool is handled by this policy.
+        pool_health_check: Option<PoolHealthPolicy>,
+        // When set, a route aborts as soon as any split pays out less than its quoted output,
+        // less the tolerance.
+        split_expectations: Option<SplitExpectations>,
+        // When set, execution is rejected if simulating the route at its actual size implies a
+        // worse rate than at a small reference size by more than this many basis points, or if
+        // the reference size simulates to nothing.
+        max_price_impact_bps: Option<u16>,
+        // When set, a unix time in seconds after which the route is rejected. It is also passed
+        // to every AMM pool swapped through, so stages run by `ContinueRoute` honour it too.
+        deadline: Option<u64>,
+        // When set, the output is delivered with a CW20 `Send` to the hook's contract, or through
+        // the adapter's `RedeemAndSend` when it is held in native form, instead of transferred
+        // to the sender. The route's output must be a CW20 token.
+        receive_hook: Option<ReceiveHook>,
+        // When set, the route runs in multi-output mode: its final holdings are paid out as they
+        // are, without normalization, and each declared asset's amount is the least that asset
+        // must pay out. Must list exactly the assets the final stage produces, and cannot be
+        // combined with `minimum_receive` or `receive_hook`.
+        final_assets: Option<Vec<amm::Asset>>,
+        // When set, the output is paid to the sender and the action is then taken with it on
+        // their behalf. Cannot be combined with `receive_hook` or `final_assets`.
+        post_action: Option<PostAction>,
+        // When set, the highest aggregator fee in basis points the sender agreed to. The route
+        // aborts if any hop is charged more, such as after a fee change made since it was quoted.
+        max_fee_bps: Option<u16>,
+        // When set, the least the output must be worth in USD, valued at settlement with the
+        // oracle feed configured for the asset it is paid out in.
+        minimum_receive_usd: Option<Decimal>,
+        // When set, a registered integrator whose fee is taken from the output and paid to its
+        // collector.
+        integrator: Option<String>,
+        // When set, the output is paid out to these addresses by their shares, which must sum to
+        // 10,000 basis points, instead of to the sender. Cannot be combined with `receive_hook`
+        // or `post_action`.
+        recipients: Option<Vec<RecipientInfo>>,
+    },
+    // An `ExecuteRoute` in the compact encoding of the `packed` module, for bots submitting many
+    // large routes. It is decoded and then validated and executed exactly like `ExecuteRoute`.
+    ExecuteRoutePacked {
+        route: Binary,
     },
     Receive(Cw20ReceiveMsg),
+    // Pulls `amount` of the CW20 `token` from the sender with `TransferFrom`, for callers that
+    // can grant an allowance but cannot compose a `Send` hook.
+    ExecuteRouteWithAllowance {
+        token: String,
+        amount: Uint128,
+        stages: Vec<Stage>,
+        minimum_receive: Option<amm::Asset>,
+        max_stages_per_tx: Option<u64>,
+        max_quote_age: Option<QuoteAge>,
+        tag: Option<String>,
+        pool_health_check: Option<PoolHealthPolicy>,
+        split_expectations: Option<SplitExpectations>,
+        max_price_impact_bps: Option<u16>,
+        deadline: Option<u64>,
+        receive_hook: Option<ReceiveHook>,
+        final_assets: Option<Vec<amm::Asset>>,
+        post_action: Option<PostAction>,
+        max_fee_bps: Option<u16>,
+        minimum_receive_usd: Option<Decimal>,
+        integrator: Option<String>,
+        recipients: Option<Vec<RecipientInfo>>,
+    },
+    // Re-simulates the route right before executing it, net of the aggregator fees it will be
+    // charged, and aborts if the fresh quote falls short of `quoted_out`, the caller's earlier
+    // quote of its output, by more than `max_deviation_bps`. The route then runs with that floor
+    // as its minimum receive.
+    ExecuteRouteAtQuote {
+        stages: Vec<Stage>,
+        quoted_out: Uint128,
+        max_deviation_bps: u16,
+    },
+    // Credits the attached native funds to the sender's relay deposit, which routes relayed on
+    // their behalf spend.
+    DepositForRelay {},
+    WithdrawRelayDeposit {
+        denom: String,
+        amount: Uint128,
+    },
+    // Sets the compressed or uncompressed secp256k1 key the sender signs relayed routes with, or
+    // stops accepting relayed routes for them with `None`.
+    SetRelayKey {
+        pubkey: Option<Binary>,
+    },
+    // Submitted by an allowed relayer: runs a route for `owner`, spending `offer` from their relay
+    // deposit and paying them the output. `signature` is the owner's signature over the SHA-256
+    // hash of the route's `RelayedRoute`, and `nonce` must be the owner's next relay nonce, after
+    // which the signature cannot be replayed. The signature is rejected once the block time passes
+    // `expires_at`, in seconds.
+    ExecuteRouteFor {
+        owner: String,
+        stages: Vec<Stage>,
+        offer: Coin,
+        minimum_receive: Option<amm::Asset>,
+        nonce: u64,
+        expires_at: u64,
+        signature: Binary,
+    },
+    // Moves the sender's relay nonce to `next_nonce`, which must be above it, cancelling every
+    // relayed route they signed with a lower nonce.
+    CancelRelayedRoutes {
+        next_nonce: u64,
+    },
+    // Escrows the attached native funds, which must equal `total_amount`, to be routed through
+    // `stages` in `n_slices` equal slices at least `interval` seconds apart. Each slice must pay
+    // out no less than its oracle value, or its simulated output where the route's assets have no
+    // USD feeds, less `max_slippage_bps`.
+    PlaceTwapOrder {
+        stages: Vec<Stage>,
+        total_amount: Uint128,
+        n_slices: u32,
+        interval: u64,
+        max_slippage_bps: u16,
+    },
+    // Submitted by the order's owner or an allowed relayer: routes the order's next slice once it
+    // is due, paying the owner its output.
+    ExecuteTwapSlice {
+        order_id: u64,
+    },
+    // Closes an order and refunds its unrouted funds to its owner.
+    CancelTwapOrder {
+        order_id: u64,
+    },
+    ContinueRoute {
+        route_id: u64,
+    },
+    // Cancels an in-flight route and refunds the assets it holds to its sender. The admin may
+    // abort any route, the sender only one left idle for `ROUTE_ABORT_TTL_SECONDS`.
+    AbortExecution {
+        route_id: u64,
+    },
+    // Deletes receipts at least `older_than_blocks` old and past their retention, refunds routes
+    // left idle for `ROUTE_ABORT_TTL_SECONDS` to their senders, and drops rate-limit history no
+    // longer counted. Open to anyone; at most `limit` entries of each kind are examined per
+    // call, resuming after the last one the previous call examined.
+    PruneHistory {
+        older_than_blocks: u64,
+        limit: Option<u32>,
+    },
+    // Pays a fee collector the fees kept for it after they could not be sent, to `recipient`
+    // when set.
+    ClaimFees {
+        recipient: Option<String>,
+    },
+    // Adds an AMM pool to the allowlist for the registration fee, once it quotes a non-zero
+    // output for the canary amount of each of its assets. The pool must be an instance of one of
+    // the code IDs in the terms, and its output is always measured from the contract's balance.
+    // Open only while registration terms are set and the allowlist is in use.
+    RegisterPool {
+        pool_address: String,
+    },
+    // Runs a registered route template with the attached funds as input.
+    ExecuteTemplate {
+        name: String,
+        minimum_receive: Option<amm::Asset>,
+    },
+    // Internal: self-call standing in for an orderbook swap whose amount rounded to zero, so
+    // its reply completes the path with no output.
+    Noop {},
+    // Internal: self-call appended after a route's final transfers when invariant checks are
+    // enabled. Fails unless every balance is at least its minimum.
+    VerifyInvariants {
+        minimum_balances: Vec<amm::Asset>,
+    },
     // Admin-only
     UpdateAdmin {
         new_admin: String,
@@ -195,12 +666,175 @@ pub enum ExecuteMsg {
     RemoveFee {
         pool_address: String,
     },
-    UpdateFeeCollector {
-        new_fee_collector: String,
+    // Sets the fees of many pools at once. An invalid entry fails the whole message when
+    // `all_or_nothing`, and is otherwise skipped and reported in a `skipped` attribute.
+    SetFees {
+        entries: Vec<FeeInfo>,
+        all_or_nothing: bool,
+    },
+    // Removes the fees of many pools at once, with invalid addresses handled like `SetFees`.
+    RemoveFees {
+        pool_addresses: Vec<String>,
+        all_or_nothing: bool,
     },
+    UpdateFeeCollectors {
+        collectors: Vec<FeeCollectorInfo>,
+    },
+    // Sends the admin the contract's balance of an asset, less what it holds for others: relay
+    // deposits, the unspent offers of TWAP orders and unclaimed fees.
     EmergencyWithdraw {
         asset_info: amm::AssetInfo,
     },
+    UpdateParameters {
+        parameters: Parameters,
+    },
+    // Overrides the dust threshold for one asset; `None` falls back to the default.
+    SetDustThreshold {
+        asset_info: amm::AssetInfo,
+        threshold: Option<Uint128>,
+    },
+    SweepDust {},
+    UpdatePoolAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    // Sets the terms on which operators may register their own pools, or closes registration
+    // with `None`. Pools already registered stay on the allowlist.
+    SetPoolRegistrationTerms {
+        terms: Option<PoolRegistrationTerms>,
+    },
+    // Removes a pool registered by its operator from the allowlist.
+    RevokePoolRegistration {
+        pool_address: String,
+    },
+    SetPoolQueryKind {
+        pool_address: String,
+        query_kind: PoolQueryKind,
+    },
+    // Sets the event a pool's swap output is read from, or restores the default with `None`.
+    SetPoolEventFormat {
+        pool_address: String,
+        event_format: Option<PoolEventFormat>,
+    },
+    // Registers the AMM pool that leftovers of `offer_asset_info` are swapped through when a route
+    // pays out `ask_asset_info`, or removes it with `None`.
+    SetSweepPool {
+        offer_asset_info: amm::AssetInfo,
+        ask_asset_info: amm::AssetInfo,
+        pool_address: Option<String>,
+    },
+    // Sets the wrapped-INJ CW20 that routes wrap native INJ into, and unwrap it from, for pools
+    // that only take one of the two. `None` stops wrapping.
+    SetWrappedInj {
+        address: Option<String>,
+    },
+    // Marks a CW20 token as taxing transfers, so the amounts the route receives of it are
+    // measured from balances. The offer itself is still taken at its stated amount.
+    SetBalanceCheckedToken {
+        token: String,
+        enabled: bool,
+    },
+    // Sets a pool's display metadata, or clears it with `None`.
+    SetPoolMetadata {
+        pool_address: String,
+        metadata: Option<PoolMetadata>,
+    },
+    // Sets the trading rules of the orderbook market behind a swap contract, or clears them with
+    // `None`.
+    SetMarket {
+        swap_contract: String,
+        market: Option<MarketInfo>,
+    },
+    RegisterRouteTemplate {
+        name: String,
+        stages: Vec<Stage>,
+    },
+    RemoveRouteTemplate {
+        name: String,
+    },
+    SetDenomPolicy {
+        mode: DenomPolicyMode,
+        denoms: Vec<String>,
+    },
+    RemoveDenomPolicy {},
+    SetRateLimits {
+        limits: RateLimits,
+    },
+    RemoveRateLimits {},
+    SetRelayer {
+        relayer: String,
+        allowed: bool,
+    },
+    // Sets the oracle feed routes paying out `asset_info` are valued with for their
+    // `minimum_receive_usd`, or removes it with `None`.
+    SetUsdPriceFeed {
+        asset_info: amm::AssetInfo,
+        feed: Option<UsdPriceFeed>,
+    },
+    // Registers `addr` as an integrator, or updates its registration. Routes naming it pay
+    // `fee_bps` of their output to `collector`, in addition to the aggregator fees.
+    RegisterIntegrator {
+        addr: String,
+        fee_bps: u16,
+        collector: String,
+    },
+    RemoveIntegrator {
+        addr: String,
+    },
+    // Sets the converter between `denom` and its canonical denom, or removes it with `None`.
+    SetNativeConverter {
+        denom: String,
+        converter: Option<NativeConverterInfo>,
+    },
+    // Caps the amount a single swap may offer to a pool, or lifts the cap with `None`.
+    SetPoolMaxInput {
+        pool_address: String,
+        max_input: Option<Uint128>,
+    },
+    // Freezes a single pool, so routes through it are rejected, or enables it again.
+    SetPoolDisabled {
+        pool_address: String,
+        disabled: bool,
+    },
+}
+
+/// Domain tag the hash of a relayed route starts with, so an owner's signature over one cannot be
+/// passed off as their signature over any other message.
+pub const RELAYED_ROUTE_DOMAIN: &str = "choice-exchange/dex-aggregator/relayed-route/v1";
+
+/// SHA-256 over the length of `domain` as 8 big-endian bytes, then `domain`, then `payload`.
+/// Exported so client SDKs hash exactly what the contract verifies.
+pub fn domain_separated_hash(domain: &str, payload: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((domain.len() as u64).to_be_bytes());
+    hasher.update(domain.as_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+/// What an owner signs to have a relayer run a route for them, through `RelayedRoute::hash`.
+#[cw_serde]
+pub struct RelayedRoute {
+    /// The chain and aggregator the route is for, so a signature cannot be replayed elsewhere.
+    pub chain_id: String,
+    pub contract: String,
+    pub owner: String,
+    pub stages: Vec<Stage>,
+    pub offer: Coin,
+    pub minimum_receive: Option<amm::Asset>,
+    pub nonce: u64,
+    /// Block time, in seconds, after which the signature is no longer accepted.
+    pub expires_at: u64,
+}
+
+impl RelayedRoute {
+    /// The digest the owner signs: the route's JSON, hashed under `RELAYED_ROUTE_DOMAIN`.
+    pub fn hash(&self) -> StdResult<[u8; 32]> {
+        Ok(domain_separated_hash(
+            RELAYED_ROUTE_DOMAIN,
+            &to_json_vec(self)?,
+        ))
+    }
 }
 
 #[cw_serde]
@@ -209,11 +843,40 @@ pub struct FeeInfo {
     pub fee_percent: Decimal,
 }
 
+#[cw_serde]
+pub struct MigrateMsg {}
+
+#[cw_serde]
+pub struct FeeCollectorInfo {
+    pub address: String,
+    pub weight: Decimal,
+}
+
+/// A converter between a native denom and its canonical denom. See `NativeConverter`.
+#[cw_serde]
+pub struct NativeConverterInfo {
+    pub contract: String,
+    pub canonical_denom: String,
+    pub to_canonical_msg: String,
+    pub from_canonical_msg: String,
+}
+
+#[cw_serde]
+pub struct FeeCollectorsResponse {
+    pub collectors: Vec<FeeCollector>,
+}
+
 #[cw_serde]
 pub struct FeeResponse {
     pub fee: Option<Decimal>,
 }
 
+/// Fees in the same order as the queried pool addresses.
+#[cw_serde]
+pub struct FeesForPoolsResponse {
+    pub fees: Vec<Option<Decimal>>,
+}
+
 #[cw_serde]
 pub struct AllFeesResponse {
     pub fees: Vec<FeeInfo>,
@@ -222,20 +885,421 @@ pub struct AllFeesResponse {
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
+    /// The route's output after the aggregator fee of every hop, as `ExecuteRoute` charges it.
+    /// With `lenient`, a split whose pools cannot be simulated is reported in the response's
+    /// `failed_splits` and counted as producing nothing, instead of failing the whole quote.
+    #[returns(SimulateRouteResponse)]
+    SimulateRoute {
+        stages: Vec<Stage>,
+        amount_in: Coin,
+        lenient: bool,
+    },
+    /// Like `SimulateRoute`, but from any asset, so routes offering a CW20 can be simulated.
+    #[returns(SimulateRouteResponse)]
+    SimulateRouteFromAsset {
+        stages: Vec<Stage>,
+        amount_in: amm::Asset,
+    },
     #[returns(SimulateRouteResponse)]
-    SimulateRoute { stages: Vec<Stage>, amount_in: Coin },
+    SimulateRouteWithFees {
+        stages: Vec<Stage>,
+        amount_in: Coin,
+        fee_overrides: Vec<FeeInfo>,
+    },
+    /// Simulates a single operation as a route would execute it, with the aggregator fee taken
+    /// from its output.
+    #[returns(SimulateOperationResponse)]
+    SimulateOperation {
+        operation: Operation,
+        offer_asset: amm::Asset,
+    },
+    /// Counts the work a route dispatches and a rough gas estimate, without querying any pool.
+    #[returns(RouteComplexityResponse)]
+    EstimateRouteComplexity { stages: Vec<Stage> },
     #[returns(Config)]
     Config {},
+    #[returns(ParametersResponse)]
+    Parameters {},
+    #[returns(DustBucketResponse)]
+    DustBucket {},
+    /// Fees kept for a collector after they could not be sent to it, claimable with `ClaimFees`.
+    #[returns(UnclaimedFeesResponse)]
+    UnclaimedFees { collector: String },
     #[returns(FeeResponse)]
     FeeForPool { pool_address: String },
+    #[returns(FeesForPoolsResponse)]
+    FeesForPools { pool_addresses: Vec<String> },
     #[returns(AllFeesResponse)]
     AllFees {
         start_after: Option<String>,
         limit: Option<u32>,
     },
+    #[returns(ReceiptResponse)]
+    Receipt { route_id: u64 },
+    /// A completed route's executions with their prices and fees, by operation, stage and pool,
+    /// while its receipt is retained.
+    #[returns(RouteExecutionResponse)]
+    RouteExecution { route_id: u64 },
+    /// The replies a route processed while tracing was enabled, in order, while the route is in
+    /// flight or its receipt is retained.
+    #[returns(TraceResponse)]
+    Trace { route_id: u64 },
+    #[returns(DenomPolicyResponse)]
+    DenomPolicy {},
+    #[returns(RateLimitsResponse)]
+    RateLimits {},
+    #[returns(PoolStatsResponse)]
+    PoolStats { pool_address: String },
+    #[returns(AllPoolStatsResponse)]
+    AllPoolStats {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Every pool with registered metadata.
+    #[returns(AllPoolsResponse)]
+    AllPools {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Every orderbook market with registered trading rules.
+    #[returns(MarketsResponse)]
+    Markets {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(BalanceCheckedTokensResponse)]
+    BalanceCheckedTokens {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(PoolAllowlistResponse)]
+    PoolAllowlist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(DisabledPoolsResponse)]
+    DisabledPools {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(FeeCollectorsResponse)]
+    FeeCollectors {},
+    #[returns(RouteTemplateResponse)]
+    RouteTemplate { name: String },
+    #[returns(RouteTemplatesResponse)]
+    RouteTemplates {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// What routes on this deployment may use, for front-ends building routes for several
+    /// deployments.
+    #[returns(CapabilitiesResponse)]
+    Capabilities {},
+    /// Recent admin actions, oldest first.
+    #[returns(AdminLogResponse)]
+    AdminLog {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// An owner's relay key, next relay nonce and deposited balances.
+    #[returns(RelayAccountResponse)]
+    RelayAccount { owner: String },
+    #[returns(UsdPriceFeedResponse)]
+    UsdPriceFeed { asset_info: amm::AssetInfo },
+    #[returns(IntegratorResponse)]
+    Integrator { addr: String },
+    #[returns(NativeConverterResponse)]
+    NativeConverter { denom: String },
+    #[returns(PoolMaxInputResponse)]
+    PoolMaxInput { pool_address: String },
+    #[returns(TwapOrderResponse)]
+    TwapOrder { order_id: u64 },
+    /// Open TWAP orders by id, for keepers looking for slices that are due.
+    #[returns(TwapOrdersResponse)]
+    TwapOrders {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// The nonce an owner's next relayed route must be signed with.
+    #[returns(RelayNonceResponse)]
+    RelayNonce { owner: String },
+}
+
+#[cw_serde]
+pub struct UsdPriceFeedResponse {
+    pub feed: Option<UsdPriceFeed>,
+}
+
+#[cw_serde]
+pub struct IntegratorResponse {
+    pub integrator: Option<Integrator>,
+}
+
+#[cw_serde]
+pub struct NativeConverterResponse {
+    pub converter: Option<NativeConverter>,
+}
+
+#[cw_serde]
+pub struct PoolMaxInputResponse {
+    pub max_input: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct TwapOrderResponse {
+    pub order: Option<TwapOrder>,
+}
+
+#[cw_serde]
+pub struct TwapOrdersResponse {
+    pub orders: Vec<TwapOrder>,
+}
+
+#[cw_serde]
+pub struct RelayNonceResponse {
+    pub owner: String,
+    pub nonce: u64,
+}
+
+#[cw_serde]
+pub struct RelayAccountResponse {
+    pub pubkey: Option<Binary>,
+    /// The nonce the owner's next relayed route must be signed with.
+    pub nonce: u64,
+    pub deposits: Vec<Coin>,
+}
+
+/// Worst-case counts for a route, assuming every path produces output.
+#[cw_serde]
+pub struct RouteComplexityResponse {
+    /// Reply-tracked swaps and adapter conversions, each handled by its own `reply`.
+    pub submessages: u64,
+    pub conversions: u64,
+    pub storage_writes: u64,
+    /// Built from uncalibrated per-item figures, so it ranks routes rather than predicting the
+    /// gas a transaction needs.
+    pub estimated_gas: u64,
 }
 
 #[cw_serde]
 pub struct SimulateRouteResponse {
     pub output_amount: Uint128,
+    /// Block at which the simulation ran, letting callers judge quote staleness.
+    pub block_height: u64,
+    pub block_time: Timestamp,
+    /// Splits left out of a lenient simulation. Always empty otherwise.
+    pub failed_splits: Vec<SplitFailure>,
+    /// The input every split of every stage was allocated, in route order, so clients can check
+    /// the apportionment of each stage against its percentages.
+    pub allocations: Vec<SplitAllocation>,
+}
+
+#[cw_serde]
+pub struct SimulateOperationResponse {
+    /// What the operation pays out, less the aggregator fee.
+    pub return_asset: amm::Asset,
+    /// The aggregator fee the operation's pool would be charged.
+    pub fee_amount: Uint128,
+    pub block_height: u64,
+    pub block_time: Timestamp,
+}
+
+/// The input a simulation allocated to one split.
+#[cw_serde]
+pub struct SplitAllocation {
+    pub stage_index: u64,
+    pub split_index: u64,
+    pub input: amm::Asset,
+}
+
+/// A split a lenient simulation could not quote, and why.
+#[cw_serde]
+pub struct SplitFailure {
+    pub stage_index: u64,
+    pub split_index: u64,
+    pub error: String,
+}
+
+/// Set as the data of the call that starts or continues a route, and replaced by the final reply
+/// once the route completes, so calling contracts can read the result without parsing events.
+#[cw_serde]
+pub struct RouteResult {
+    pub route_id: u64,
+    /// The route's payout, or `None` while it is checkpointed awaiting `ContinueRoute`.
+    pub output: Option<amm::Asset>,
+    /// Further assets paid out by a multi-output route.
+    pub additional_outputs: Vec<amm::Asset>,
+}
+
+#[cw_serde]
+pub struct ReceiptResponse {
+    pub receipt: Option<Receipt>,
+}
+
+#[cw_serde]
+pub struct TraceResponse {
+    pub entries: Vec<TraceEntry>,
+}
+
+#[cw_serde]
+pub struct RouteExecutionResponse {
+    pub execution: Option<RouteExecution>,
+}
+
+/// Prices are in base units received, after the aggregator fee, per base unit offered.
+#[cw_serde]
+pub struct RouteExecution {
+    pub route_id: u64,
+    pub input: amm::Asset,
+    pub output: amm::Asset,
+    pub fees: Vec<amm::Asset>,
+    pub completed_at_height: u64,
+    pub completed_at_time: u64,
+    pub operations: Vec<ExecutedOperation>,
+    pub stages: Vec<StageExecution>,
+    pub pools: Vec<PoolExecution>,
+}
+
+#[cw_serde]
+pub struct ExecutedOperation {
+    pub execution: OperationExecution,
+    pub price: Option<Decimal>,
+}
+
+/// A stage's first operations' inputs and the outputs of the last operation of each split.
+#[cw_serde]
+pub struct StageExecution {
+    pub stage_index: u64,
+    pub totals: ExecutionTotals,
+    pub completed_at_height: u64,
+    pub completed_at_time: u64,
+}
+
+/// Everything swapped through one contract over the route.
+#[cw_serde]
+pub struct PoolExecution {
+    pub contract: Addr,
+    pub totals: ExecutionTotals,
+}
+
+#[cw_serde]
+pub struct ExecutionTotals {
+    pub offered: Vec<amm::Asset>,
+    /// Outputs after the aggregator fee.
+    pub received: Vec<amm::Asset>,
+    pub fees: Vec<amm::Asset>,
+    /// Set when `offered` and `received` are one asset each.
+    pub price: Option<Decimal>,
+}
+
+#[cw_serde]
+pub struct DenomPolicyResponse {
+    pub policy: Option<DenomPolicy>,
+}
+
+#[cw_serde]
+pub struct RateLimitsResponse {
+    pub limits: Option<RateLimits>,
+}
+
+#[cw_serde]
+pub struct PoolStatsResponse {
+    pub pool_address: String,
+    pub stats: PoolStats,
+}
+
+#[cw_serde]
+pub struct AllPoolStatsResponse {
+    pub pools: Vec<PoolStatsResponse>,
+}
+
+#[cw_serde]
+pub struct PoolInfoResponse {
+    pub pool_address: String,
+    pub metadata: PoolMetadata,
+}
+
+#[cw_serde]
+pub struct AllPoolsResponse {
+    pub pools: Vec<PoolInfoResponse>,
+}
+
+#[cw_serde]
+pub struct MarketResponse {
+    pub swap_contract: String,
+    pub market: MarketInfo,
+}
+
+#[cw_serde]
+pub struct MarketsResponse {
+    pub markets: Vec<MarketResponse>,
+}
+
+#[cw_serde]
+pub struct BalanceCheckedTokensResponse {
+    pub tokens: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct PoolAllowlistResponse {
+    pub pools: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct DisabledPoolsResponse {
+    pub pools: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct RouteTemplateResponse {
+    pub name: String,
+    pub stages: Vec<Stage>,
+}
+
+#[cw_serde]
+pub struct RouteTemplatesResponse {
+    pub templates: Vec<RouteTemplateResponse>,
+}
+
+#[cw_serde]
+pub struct AdminLogResponse {
+    pub entries: Vec<AdminLogEntry>,
+}
+
+/// Features are named as they are serialized, in snake case.
+#[cw_serde]
+pub struct CapabilitiesResponse {
+    /// `Operation` variants a route's paths may use.
+    pub operations: Vec<String>,
+    /// `PostAction` variants a route may request.
+    pub post_actions: Vec<String>,
+    /// How fees are charged and paid: `pool_fee` when some pools have their own, `fee_cap` as
+    /// routes may cap it, `integrator_fee` when integrators are registered, `split_collectors`
+    /// when the aggregator fee is split across several collectors, and `claimable` as a fee its
+    /// collector cannot receive is kept for `ClaimFees`.
+    pub fee_modes: Vec<String>,
+    /// Messages that start a route, with those sent through a CW20 `Send` as `receive.{hook}`,
+    /// and `register_pool` while pool operators may allowlist their own pools.
+    pub entry_points: Vec<String>,
+}
+
+/// Every deployment-specific setting in one response.
+#[cw_serde]
+pub struct ParametersResponse {
+    pub config: Config,
+    pub parameters: Parameters,
+    pub denom_policy: Option<DenomPolicy>,
+    pub wrapped_inj: Option<Addr>,
+    pub pool_registration_terms: Option<PoolRegistrationTerms>,
+    pub receipt_retention_blocks: u64,
+}
+
+#[cw_serde]
+pub struct DustBucketResponse {
+    pub assets: Vec<amm::Asset>,
+}
+
+#[cw_serde]
+pub struct UnclaimedFeesResponse {
+    pub assets: Vec<amm::Asset>,
 }
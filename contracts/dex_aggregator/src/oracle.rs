@@ -0,0 +1,90 @@
+//! USD valuation of route outputs, from the oracle feeds the admin configures per asset.
+
+use std::str::FromStr;
+
+use cosmwasm_std::{Decimal, Deps, Env};
+use injective_cosmwasm::{InjectiveQuerier, InjectiveQueryWrapper};
+
+use crate::error::ContractError;
+use crate::msg::amm;
+use crate::routing::adapter_counterpart;
+use crate::state::{asset_id, PriceSource, UsdPriceFeed, CONFIG, USD_PRICE_FEEDS};
+
+/// The feed pricing `info`, or that of its other form when `info` is one form of a CW20.
+pub fn load_usd_price_feed(
+    deps: Deps<InjectiveQueryWrapper>,
+    info: &amm::AssetInfo,
+) -> Result<UsdPriceFeed, ContractError> {
+    if let Some(feed) = USD_PRICE_FEEDS.may_load(deps.storage, asset_id(info))? {
+        return Ok(feed);
+    }
+    let config = CONFIG.load(deps.storage)?;
+    if let Some((counterpart, _)) = adapter_counterpart(&config.cw20_adapter_address, info) {
+        if let Some(feed) = USD_PRICE_FEEDS.may_load(deps.storage, asset_id(&counterpart))? {
+            return Ok(feed);
+        }
+    }
+    Err(ContractError::UsdPriceFeedMissing {
+        asset: asset_id(info).to_string(),
+    })
+}
+
+/// The USD value of `asset` at the current price of its feed.
+pub fn usd_value(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    asset: &amm::Asset,
+) -> Result<Decimal, ContractError> {
+    let feed = load_usd_price_feed(deps, &asset.info)?;
+    let asset_name = asset_id(&asset.info).to_string();
+    let querier = InjectiveQuerier::new(&deps.querier);
+
+    let (price, published_at) = match &feed.source {
+        PriceSource::Pyth { price_id } => {
+            let state = querier
+                .query_pyth_price(price_id)?
+                .price_state
+                .ok_or_else(|| ContractError::InvalidOraclePrice {
+                    asset: asset_name.clone(),
+                })?;
+            (state.price_state.price, state.publish_time)
+        }
+        PriceSource::Injective {
+            oracle_type,
+            base,
+            quote,
+        } => {
+            let state = querier
+                .query_oracle_price(oracle_type, base, quote, None)?
+                .price_pair_state
+                .ok_or_else(|| ContractError::InvalidOraclePrice {
+                    asset: asset_name.clone(),
+                })?;
+            (state.pair_price, state.base_timestamp)
+        }
+    };
+
+    if (env.block.time.seconds() as i64).saturating_sub(published_at) > feed.max_age_seconds as i64
+    {
+        return Err(ContractError::StaleOraclePrice {
+            asset: asset_name,
+            published_at,
+            max_age_seconds: feed.max_age_seconds,
+        });
+    }
+    // Negative and zero prices fail to parse or are refused here.
+    let price = Decimal::from_str(&price.to_string())
+        .ok()
+        .filter(|price| !price.is_zero())
+        .ok_or_else(|| ContractError::InvalidOraclePrice {
+            asset: asset_name.clone(),
+        })?;
+
+    let overflow = || ContractError::AmountOverflow {
+        context: format!("valuing {asset_name} in USD"),
+    };
+    Decimal::checked_from_ratio(asset.amount, 10u128.pow(feed.decimals as u32))
+        .map_err(|_| overflow())?
+        .checked_mul(price)
+        .map_err(|_| overflow())
+}
@@ -0,0 +1,596 @@
+//! The compact route encoding accepted by `ExecuteMsg::ExecuteRoutePacked`.
+//!
+//! A large route spells out every field name in JSON and repeats the same pool addresses and
+//! denoms across its splits. A packed route stores each distinct string once and refers to it by
+//! index, and encodes the stages as bytes and LEB128 varints:
+//!
+//! ```text
+//! route     = version:u8 strings stages options
+//! strings   = count:varint (len:varint utf8)*
+//! stages    = count:varint (splits:varint (percent:u8 ops:varint operation*)*)*
+//! operation = 0 pool:str offer:asset ask:asset min_out
+//!           | 1 contract:str offer:asset ask:asset tick_size:varint min_out
+//!           | 2 contract:str stages min_out
+//!           | 3 pool:str pair_type:u8 offer:asset other:asset lp_token:asset min_out
+//! asset     = 0 contract_addr:str | 1 denom:str
+//! str       = index into strings:varint
+//! min_out   = 0 | 1 amount:varint
+//! options   = len:varint json(RouteOptions)
+//! ```
+//!
+//! The route's other fields are few and small, so they stay JSON, and an empty `options` leaves
+//! them all unset. A packed route decodes to the `ExecuteRoute` it encodes, which is then executed
+//! exactly as if it had been sent as JSON.
+
+use crate::error::ContractError;
+use crate::msg::{
+    amm, AmmSwapOp, ExecuteMsg, Operation, OrderbookSwapOp, PairType, ProvideLiquidityOp,
+    RouteOptions, Split, Stage, SubAggregatorOp,
+};
+use cosmwasm_std::{from_json, to_json_vec, Binary, StdResult, Uint128};
+use std::collections::HashMap;
+
+/// The encoding version, the first byte of every packed route.
+pub const PACKED_ROUTE_VERSION: u8 = 1;
+
+/// Most sub-aggregator routes a packed route may nest, bounding the decoder's recursion.
+pub const MAX_PACKED_NESTING: usize = 4;
+
+const AMM_SWAP: u8 = 0;
+const ORDERBOOK_SWAP: u8 = 1;
+const SUB_AGGREGATOR: u8 = 2;
+const PROVIDE_LIQUIDITY: u8 = 3;
+
+const TOKEN: u8 = 0;
+const NATIVE_TOKEN: u8 = 1;
+
+const XYK: u8 = 0;
+const STABLE: u8 = 1;
+
+/// Packs a route's stages and options, for clients submitting `ExecuteRoutePacked`.
+pub fn encode_route(stages: &[Stage], options: &RouteOptions) -> StdResult<Binary> {
+    let mut encoder = Encoder::default();
+    encoder.stages(stages);
+
+    let mut packed = vec![PACKED_ROUTE_VERSION];
+    write_varint(&mut packed, encoder.strings.len() as u128);
+    for string in &encoder.strings {
+        write_varint(&mut packed, string.len() as u128);
+        packed.extend_from_slice(string.as_bytes());
+    }
+    packed.extend_from_slice(&encoder.body);
+    if *options == RouteOptions::default() {
+        write_varint(&mut packed, 0);
+    } else {
+        let json = to_json_vec(options)?;
+        write_varint(&mut packed, json.len() as u128);
+        packed.extend_from_slice(&json);
+    }
+    Ok(Binary::new(packed))
+}
+
+/// Unpacks a route into the `ExecuteRoute` it encodes.
+pub fn decode_route(packed: &[u8]) -> Result<ExecuteMsg, ContractError> {
+    let mut decoder = Decoder {
+        bytes: packed,
+        strings: vec![],
+    };
+    let version = decoder.byte()?;
+    if version != PACKED_ROUTE_VERSION {
+        return Err(invalid(format!("unsupported version {version}")));
+    }
+
+    let count = decoder.length()?;
+    for _ in 0..count {
+        let len = decoder.length()?;
+        let bytes = decoder.take(len)?;
+        let string = std::str::from_utf8(bytes)
+            .map_err(|_| invalid("string table entry is not UTF-8"))?
+            .to_string();
+        decoder.strings.push(string);
+    }
+
+    let stages = decoder.stages(0)?;
+    let options_len = decoder.length()?;
+    let options: RouteOptions = if options_len == 0 {
+        RouteOptions::default()
+    } else {
+        from_json(decoder.take(options_len)?)
+            .map_err(|err| invalid(format!("options are not valid JSON: {err}")))?
+    };
+    if !decoder.bytes.is_empty() {
+        return Err(invalid(format!(
+            "{} trailing bytes after the options",
+            decoder.bytes.len()
+        )));
+    }
+
+    Ok(ExecuteMsg::ExecuteRoute {
+        stages,
+        minimum_receive: options.minimum_receive,
+        max_stages_per_tx: options.max_stages_per_tx,
+        max_quote_age: options.max_quote_age,
+        tag: options.tag,
+        pool_health_check: options.pool_health_check,
+        split_expectations: options.split_expectations,
+        max_price_impact_bps: options.max_price_impact_bps,
+        deadline: options.deadline,
+        receive_hook: options.receive_hook,
+        final_assets: options.final_assets,
+        post_action: options.post_action,
+        max_fee_bps: options.max_fee_bps,
+        minimum_receive_usd: options.minimum_receive_usd,
+        integrator: options.integrator,
+        recipients: options.recipients,
+    })
+}
+
+fn invalid(reason: impl Into<String>) -> ContractError {
+    ContractError::InvalidPackedRoute {
+        reason: reason.into(),
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u128) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[derive(Default)]
+struct Encoder {
+    strings: Vec<String>,
+    indices: HashMap<String, usize>,
+    body: Vec<u8>,
+}
+
+impl Encoder {
+    fn string(&mut self, string: &str) {
+        let index = match self.indices.get(string) {
+            Some(index) => *index,
+            None => {
+                self.strings.push(string.to_string());
+                self.indices
+                    .insert(string.to_string(), self.strings.len() - 1);
+                self.strings.len() - 1
+            }
+        };
+        write_varint(&mut self.body, index as u128);
+    }
+
+    fn asset(&mut self, info: &amm::AssetInfo) {
+        match info {
+            amm::AssetInfo::Token { contract_addr } => {
+                self.body.push(TOKEN);
+                self.string(contract_addr);
+            }
+            amm::AssetInfo::NativeToken { denom } => {
+                self.body.push(NATIVE_TOKEN);
+                self.string(denom);
+            }
+        }
+    }
+
+    fn min_out(&mut self, min_out: Option<Uint128>) {
+        match min_out {
+            None => self.body.push(0),
+            Some(amount) => {
+                self.body.push(1);
+                write_varint(&mut self.body, amount.u128());
+            }
+        }
+    }
+
+    fn stages(&mut self, stages: &[Stage]) {
+        write_varint(&mut self.body, stages.len() as u128);
+        for stage in stages {
+            write_varint(&mut self.body, stage.splits.len() as u128);
+            for split in &stage.splits {
+                self.body.push(split.percent);
+                write_varint(&mut self.body, split.path.len() as u128);
+                for operation in &split.path {
+                    self.operation(operation);
+                }
+            }
+        }
+    }
+
+    fn operation(&mut self, operation: &Operation) {
+        match operation {
+            Operation::AmmSwap(op) => {
+                self.body.push(AMM_SWAP);
+                self.string(&op.pool_address);
+                self.asset(&op.offer_asset_info);
+                self.asset(&op.ask_asset_info);
+                self.min_out(op.min_out);
+            }
+            Operation::OrderbookSwap(op) => {
+                self.body.push(ORDERBOOK_SWAP);
+                self.string(&op.swap_contract);
+                self.asset(&op.offer_asset_info);
+                self.asset(&op.ask_asset_info);
+                write_varint(&mut self.body, op.min_quantity_tick_size.u128());
+                self.min_out(op.min_out);
+            }
+            Operation::SubAggregator(op) => {
+                self.body.push(SUB_AGGREGATOR);
+                self.string(&op.contract);
+                self.stages(&op.stages);
+                self.min_out(op.min_out);
+            }
+            Operation::ProvideLiquidity(op) => {
+                self.body.push(PROVIDE_LIQUIDITY);
+                self.string(&op.pool_address);
+                self.body.push(match op.pair_type {
+                    PairType::Xyk {} => XYK,
+                    PairType::Stable {} => STABLE,
+                });
+                self.asset(&op.offer_asset_info);
+                self.asset(&op.other_asset_info);
+                self.asset(&op.lp_token);
+                self.min_out(op.min_out);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    strings: Vec<String>,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, ContractError> {
+        let (first, rest) = self
+            .bytes
+            .split_first()
+            .ok_or_else(|| invalid("unexpected end of input"))?;
+        self.bytes = rest;
+        Ok(*first)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ContractError> {
+        if len > self.bytes.len() {
+            return Err(invalid("unexpected end of input"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// A LEB128 varint of at most 128 bits, rejecting encodings with bits past the 128th.
+    fn varint(&mut self) -> Result<u128, ContractError> {
+        let mut value = 0u128;
+        let mut shift = 0u32;
+        loop {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as u128;
+            if shift == 126 && bits > 0b11 {
+                return Err(invalid("varint overflows 128 bits"));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 126 {
+                return Err(invalid("varint overflows 128 bits"));
+            }
+        }
+    }
+
+    fn length(&mut self) -> Result<usize, ContractError> {
+        usize::try_from(self.varint()?).map_err(|_| invalid("length out of range"))
+    }
+
+    fn string(&mut self) -> Result<String, ContractError> {
+        let index = self.length()?;
+        self.strings
+            .get(index)
+            .cloned()
+            .ok_or_else(|| invalid(format!("string index {index} is out of range")))
+    }
+
+    fn asset(&mut self) -> Result<amm::AssetInfo, ContractError> {
+        match self.byte()? {
+            TOKEN => Ok(amm::AssetInfo::Token {
+                contract_addr: self.string()?,
+            }),
+            NATIVE_TOKEN => Ok(amm::AssetInfo::NativeToken {
+                denom: self.string()?,
+            }),
+            tag => Err(invalid(format!("unknown asset tag {tag}"))),
+        }
+    }
+
+    fn amount(&mut self) -> Result<Uint128, ContractError> {
+        Ok(Uint128::new(self.varint()?))
+    }
+
+    fn min_out(&mut self) -> Result<Option<Uint128>, ContractError> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.amount()?)),
+            tag => Err(invalid(format!("unknown min_out tag {tag}"))),
+        }
+    }
+
+    fn stages(&mut self, nesting: usize) -> Result<Vec<Stage>, ContractError> {
+        let mut stages = vec![];
+        for _ in 0..self.length()? {
+            let mut splits = vec![];
+            for _ in 0..self.length()? {
+                let percent = self.byte()?;
+                let mut path = vec![];
+                for _ in 0..self.length()? {
+                    path.push(self.operation(nesting)?);
+                }
+                splits.push(Split { path, percent });
+            }
+            stages.push(Stage { splits });
+        }
+        Ok(stages)
+    }
+
+    fn operation(&mut self, nesting: usize) -> Result<Operation, ContractError> {
+        match self.byte()? {
+            AMM_SWAP => Ok(Operation::AmmSwap(AmmSwapOp {
+                pool_address: self.string()?,
+                offer_asset_info: self.asset()?,
+                ask_asset_info: self.asset()?,
+                min_out: self.min_out()?,
+            })),
+            ORDERBOOK_SWAP => Ok(Operation::OrderbookSwap(OrderbookSwapOp {
+                swap_contract: self.string()?,
+                offer_asset_info: self.asset()?,
+                ask_asset_info: self.asset()?,
+                min_quantity_tick_size: self.amount()?,
+                min_out: self.min_out()?,
+            })),
+            SUB_AGGREGATOR => {
+                if nesting >= MAX_PACKED_NESTING {
+                    return Err(invalid(format!(
+                        "sub-aggregator routes nest more than {MAX_PACKED_NESTING} deep"
+                    )));
+                }
+                Ok(Operation::SubAggregator(SubAggregatorOp {
+                    contract: self.string()?,
+                    stages: self.stages(nesting + 1)?,
+                    min_out: self.min_out()?,
+                }))
+            }
+            PROVIDE_LIQUIDITY => Ok(Operation::ProvideLiquidity(ProvideLiquidityOp {
+                pool_address: self.string()?,
+                pair_type: match self.byte()? {
+                    XYK => PairType::Xyk {},
+                    STABLE => PairType::Stable {},
+                    tag => return Err(invalid(format!("unknown pair type {tag}"))),
+                },
+                offer_asset_info: self.asset()?,
+                other_asset_info: self.asset()?,
+                lp_token: self.asset()?,
+                min_out: self.min_out()?,
+            })),
+            tag => Err(invalid(format!("unknown operation tag {tag}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::QuoteAge;
+
+    fn amm_op(pool: &str, offer: &str, ask: &str) -> Operation {
+        Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: amm::AssetInfo::native(offer),
+            ask_asset_info: amm::AssetInfo::native(ask),
+            min_out: None,
+        })
+    }
+
+    fn every_operation() -> Vec<Stage> {
+        let inj_to_usdt = Stage {
+            splits: vec![Split {
+                path: vec![amm_op("pool", "inj", "usdt")],
+                percent: 100,
+            }],
+        };
+        vec![
+            Stage {
+                splits: vec![
+                    Split {
+                        path: vec![Operation::AmmSwap(AmmSwapOp {
+                            pool_address: "pool".to_string(),
+                            offer_asset_info: amm::AssetInfo::native("inj"),
+                            ask_asset_info: amm::AssetInfo::token("cw20"),
+                            min_out: Some(Uint128::MAX),
+                        })],
+                        percent: 40,
+                    },
+                    Split {
+                        path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                            swap_contract: "orderbook".to_string(),
+                            offer_asset_info: amm::AssetInfo::native("inj"),
+                            ask_asset_info: amm::AssetInfo::native("usdt"),
+                            min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                            min_out: Some(Uint128::new(7)),
+                        })],
+                        percent: 35,
+                    },
+                    Split {
+                        path: vec![Operation::SubAggregator(SubAggregatorOp {
+                            contract: "aggregator".to_string(),
+                            stages: vec![inj_to_usdt],
+                            min_out: None,
+                        })],
+                        percent: 25,
+                    },
+                ],
+            },
+            Stage {
+                splits: vec![Split {
+                    path: vec![Operation::ProvideLiquidity(ProvideLiquidityOp {
+                        pool_address: "pool".to_string(),
+                        pair_type: PairType::Stable {},
+                        offer_asset_info: amm::AssetInfo::native("usdt"),
+                        other_asset_info: amm::AssetInfo::native("inj"),
+                        lp_token: amm::AssetInfo::token("lp"),
+                        min_out: None,
+                    })],
+                    percent: 100,
+                }],
+            },
+        ]
+    }
+
+    fn stages_of(msg: ExecuteMsg) -> Vec<Stage> {
+        match msg {
+            ExecuteMsg::ExecuteRoute { stages, .. } => stages,
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_packed_route_round_trips() {
+        let stages = every_operation();
+        let options = RouteOptions {
+            minimum_receive: Some(amm::Asset {
+                info: amm::AssetInfo::token("lp"),
+                amount: Uint128::new(1),
+            }),
+            max_quote_age: Some(QuoteAge {
+                quote_height: 10,
+                max_blocks: 5,
+            }),
+            tag: Some("bot".to_string()),
+            ..Default::default()
+        };
+
+        let packed = encode_route(&stages, &options).unwrap();
+        match decode_route(&packed).unwrap() {
+            ExecuteMsg::ExecuteRoute {
+                stages: decoded,
+                minimum_receive,
+                max_quote_age,
+                tag,
+                deadline,
+                ..
+            } => {
+                assert_eq!(decoded, stages);
+                assert_eq!(minimum_receive, options.minimum_receive);
+                assert_eq!(max_quote_age, options.max_quote_age);
+                assert_eq!(tag, options.tag);
+                assert_eq!(deadline, None);
+            }
+            other => panic!("unexpected message {other:?}"),
+        }
+
+        // Without options the trailer is a single zero byte.
+        let bare = encode_route(&stages, &RouteOptions::default()).unwrap();
+        assert_eq!(bare.last(), Some(&0));
+        assert_eq!(stages_of(decode_route(&bare).unwrap()), stages);
+    }
+
+    #[test]
+    fn test_packed_route_is_smaller_than_json() {
+        let split = |pool: &str| Split {
+            path: vec![amm_op(
+                pool,
+                "inj",
+                "peggy0xdAC17F958D2ee523a2206206994597C13D831ec7",
+            )],
+            percent: 10,
+        };
+        let stages: Vec<Stage> = (0..4)
+            .map(|_| Stage {
+                splits: (0..10)
+                    .map(|i| split(&format!("inj1pool{}", i % 3)))
+                    .collect(),
+            })
+            .collect();
+
+        let packed = encode_route(&stages, &RouteOptions::default()).unwrap();
+        let json = to_json_vec(&stages).unwrap();
+        assert!(
+            packed.len() * 5 < json.len(),
+            "packed {} bytes, JSON {} bytes",
+            packed.len(),
+            json.len()
+        );
+    }
+
+    #[test]
+    fn test_malformed_packed_routes_are_rejected() {
+        let packed = encode_route(&every_operation(), &RouteOptions::default()).unwrap();
+        let reason = |bytes: &[u8]| match decode_route(bytes).unwrap_err() {
+            ContractError::InvalidPackedRoute { reason } => reason,
+            other => panic!("unexpected error {other}"),
+        };
+
+        let mut wrong_version = packed.to_vec();
+        wrong_version[0] = 2;
+        assert_eq!(reason(&wrong_version), "unsupported version 2");
+
+        assert_eq!(
+            reason(&packed[..packed.len() - 1]),
+            "unexpected end of input"
+        );
+
+        let mut trailing = packed.to_vec();
+        trailing.push(0);
+        assert_eq!(reason(&trailing), "1 trailing bytes after the options");
+
+        // One string, then one stage with one split whose only operation refers to string 1.
+        let out_of_range = [1, 1, 1, b'a', 1, 1, 100, 1, AMM_SWAP, 1];
+        assert_eq!(reason(&out_of_range), "string index 1 is out of range");
+
+        // A varint running past 128 bits.
+        let overlong = [
+            1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0x7f,
+        ];
+        assert_eq!(reason(&overlong), "varint overflows 128 bits");
+    }
+
+    #[test]
+    fn test_sub_aggregator_nesting_is_bounded() {
+        let mut stages = vec![Stage {
+            splits: vec![Split {
+                path: vec![amm_op("pool", "inj", "usdt")],
+                percent: 100,
+            }],
+        }];
+        for _ in 0..=MAX_PACKED_NESTING {
+            stages = vec![Stage {
+                splits: vec![Split {
+                    path: vec![Operation::SubAggregator(SubAggregatorOp {
+                        contract: "aggregator".to_string(),
+                        stages,
+                        min_out: None,
+                    })],
+                    percent: 100,
+                }],
+            }];
+        }
+        let packed = encode_route(&stages, &RouteOptions::default()).unwrap();
+        assert!(matches!(
+            decode_route(&packed).unwrap_err(),
+            ContractError::InvalidPackedRoute { .. }
+        ));
+
+        // One level fewer decodes.
+        let Operation::SubAggregator(outer) = &stages[0].splits[0].path[0] else {
+            unreachable!()
+        };
+        let packed = encode_route(&outer.stages, &RouteOptions::default()).unwrap();
+        assert_eq!(stages_of(decode_route(&packed).unwrap()), outer.stages);
+    }
+}
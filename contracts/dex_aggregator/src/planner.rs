@@ -0,0 +1,750 @@
+//! The stage planner: how a stage's input is allocated across its splits, and the fewest
+//! conversions that leave each form of the token holding what its splits need. A token has at most
+//! two forms: native and CW20 under the adapter or wrapped INJ, or two native denoms with a
+//! registered converter between them.
+//!
+//! Everything here is a pure function of the holdings and the stage, so it is tested directly
+//! rather than through test-tube.
+
+use crate::error::ContractError;
+use crate::msg::{amm, PlannedSwap, Stage};
+use cosmwasm_std::{StdError, StdResult, Uint128};
+
+/// A conversion between two forms of a token: `offer` is sent in, and arrives as `target`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedConversion {
+    pub offer: amm::Asset,
+    pub target: amm::AssetInfo,
+}
+
+/// How a stage is executed: the swap each split makes, preceded by at most one conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StagePlan {
+    pub swaps_to_execute: Vec<PlannedSwap>,
+    pub conversion: Option<PlannedConversion>,
+}
+
+/// Amounts held or needed in each form of the token, in the order the forms were first seen.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormAmounts {
+    pub forms: Vec<(amm::AssetInfo, Uint128)>,
+}
+
+impl FormAmounts {
+    /// The total of `assets` in each form. Piles of tokens with many decimals can be large
+    /// enough that their sum leaves `Uint128`, which fails rather than wrapping.
+    pub fn of(assets: &[amm::Asset]) -> Result<Self, ContractError> {
+        let mut amounts = Self::default();
+        for asset in assets {
+            amounts.add(&asset.info, asset.amount)?;
+        }
+        Ok(amounts)
+    }
+
+    pub fn total(&self) -> Result<Uint128, ContractError> {
+        self.forms
+            .iter()
+            .try_fold(Uint128::zero(), |total, (_, amount)| {
+                total.checked_add(*amount)
+            })
+            .map_err(|_| pile_overflow())
+    }
+
+    /// The amount in the form `info`, zero if there is none.
+    pub fn amount(&self, info: &amm::AssetInfo) -> Uint128 {
+        self.forms
+            .iter()
+            .find(|(form, _)| form == info)
+            .map_or(Uint128::zero(), |(_, amount)| *amount)
+    }
+
+    fn add(&mut self, info: &amm::AssetInfo, amount: Uint128) -> Result<(), ContractError> {
+        match self.forms.iter_mut().find(|(form, _)| form == info) {
+            Some((_, pile)) => *pile = pile.checked_add(amount).map_err(|_| pile_overflow())?,
+            None => self.forms.push((info.clone(), amount)),
+        }
+        Ok(())
+    }
+}
+
+fn pile_overflow() -> ContractError {
+    ContractError::AmountOverflow {
+        context: "summing a stage's input piles".to_string(),
+    }
+}
+
+/// Allocates `total` across splits in proportion to their percentages by largest remainder: every
+/// split takes its exact share rounded down, and the units left over go one each to the splits
+/// whose shares were rounded down the most, earlier splits first on ties. The amounts always sum
+/// to `total`, and no split is more than one unit away from its exact share.
+pub fn apportion(total: Uint128, percents: &[u8]) -> StdResult<Vec<Uint128>> {
+    let weight: u128 = percents.iter().map(|percent| *percent as u128).sum();
+    if weight == 0 {
+        if total.is_zero() {
+            return Ok(vec![Uint128::zero(); percents.len()]);
+        }
+        return Err(StdError::generic_err(format!(
+            "Cannot allocate {total} across splits of 0%"
+        )));
+    }
+
+    // total * percent = floor * weight + remainder, where remainder = (total % weight) * percent
+    // % weight, so the remainders never need the full product.
+    let spare = total.u128() % weight;
+    let mut amounts = vec![];
+    let mut remainders = vec![];
+    for (i, percent) in percents.iter().enumerate() {
+        amounts.push(total.multiply_ratio(*percent as u128, weight));
+        remainders.push((spare * *percent as u128 % weight, i));
+    }
+
+    let allocated: Uint128 = amounts.iter().sum();
+    let leftover = total.checked_sub(allocated)?.u128() as usize;
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, i) in remainders.into_iter().take(leftover) {
+        amounts[i] += Uint128::one();
+    }
+    Ok(amounts)
+}
+
+/// Plans a stage from the holdings left by the previous one: the amount each split swaps, and the
+/// conversion, if any, that leaves each form holding exactly what its splits need.
+pub fn plan_next_stage(
+    accumulated_assets: &[amm::Asset],
+    next_stage: &Stage,
+) -> Result<StagePlan, ContractError> {
+    let have = FormAmounts::of(accumulated_assets)?;
+    let total_logical_amount = have.total()?;
+
+    // Allocate the stage input across splits exactly as it will be swapped, by largest remainder.
+    // Needs per form are derived from the same allocation, so they always sum to the holdings.
+    let percents: Vec<u8> = next_stage
+        .splits
+        .iter()
+        .map(|split| split.percent)
+        .collect();
+    let allocation = apportion(total_logical_amount, &percents)?;
+    let mut swaps_to_execute: Vec<PlannedSwap> = vec![];
+    let mut needs = FormAmounts::default();
+    for (split_index, (split, amount_for_split)) in
+        next_stage.splits.iter().zip(allocation).enumerate()
+    {
+        let first_op = split.path.first().ok_or(ContractError::EmptyRoute {})?;
+        needs.add(&first_op.offer_asset_info()?, amount_for_split)?;
+        swaps_to_execute.push(PlannedSwap {
+            split_index: split_index as u64,
+            operation: first_op.clone(),
+            amount: amount_for_split,
+        });
+    }
+
+    Ok(StagePlan {
+        swaps_to_execute,
+        conversion: plan_conversion(&have, &needs),
+    })
+}
+
+/// Plans the first stage of a route seeded by a liquidity withdrawal, whose splits each take one
+/// of the withdrawn assets as it is. Each asset is allocated across the splits taking it like a
+/// stage input. Assets no split takes are returned, to be carried into the stage's output.
+pub fn plan_withdrawal_stage(
+    withdrawn: &[amm::Asset],
+    stage: &Stage,
+) -> Result<(Vec<PlannedSwap>, Vec<amm::Asset>), ContractError> {
+    let mut swaps = vec![];
+    let mut carried = vec![];
+    for asset in withdrawn {
+        let mut takers = vec![];
+        for (split_index, split) in stage.splits.iter().enumerate() {
+            let first_op = split.path.first().ok_or(ContractError::EmptyRoute {})?;
+            if first_op.offer_asset_info()? == asset.info {
+                takers.push((split.percent, split_index, first_op));
+            }
+        }
+        if takers.is_empty() {
+            carried.push(asset.clone());
+            continue;
+        }
+
+        let percents: Vec<u8> = takers.iter().map(|(percent, _, _)| *percent).collect();
+        let allocation = apportion(asset.amount, &percents)?;
+        for ((_, split_index, first_op), amount) in takers.iter().zip(allocation) {
+            swaps.push(PlannedSwap {
+                split_index: *split_index as u64,
+                operation: (*first_op).clone(),
+                amount,
+            });
+        }
+    }
+    Ok((swaps, carried))
+}
+
+/// Since needs and holdings have the same total and the token has at most two forms, at most one
+/// form has a surplus, and exactly that surplus is converted into the form short of it.
+fn plan_conversion(have: &FormAmounts, needs: &FormAmounts) -> Option<PlannedConversion> {
+    let (info, surplus) = have.forms.iter().find_map(|(info, held)| {
+        let needed = needs.amount(info);
+        (*held > needed).then(|| (info.clone(), *held - needed))
+    })?;
+    let (target, _) = needs
+        .forms
+        .iter()
+        .find(|(target, needed)| *needed > have.amount(target))?;
+    Some(PlannedConversion {
+        offer: amm::Asset {
+            info,
+            amount: surplus,
+        },
+        target: target.clone(),
+    })
+}
+
+/// Picks the form of the output asset to normalize into: the one already holding the largest
+/// amount, so the smallest balance is the one sent through the adapter. Ties keep the earlier one.
+pub fn select_normalization_target(assets: &[amm::Asset]) -> amm::AssetInfo {
+    let mut totals: Vec<(&amm::AssetInfo, Uint128)> = vec![];
+    for asset in assets {
+        if let Some((_, total)) = totals.iter_mut().find(|(info, _)| **info == asset.info) {
+            *total = total.saturating_add(asset.amount);
+        } else {
+            totals.push((&asset.info, asset.amount));
+        }
+    }
+
+    let mut best = totals[0];
+    for candidate in totals.into_iter().skip(1) {
+        if candidate.1 > best.1 {
+            best = candidate;
+        }
+    }
+    best.0.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{AmmSwapOp, Operation, Split};
+
+    fn native_shroom() -> amm::AssetInfo {
+        amm::AssetInfo::NativeToken {
+            denom: "factory/adapter/shroom".to_string(),
+        }
+    }
+
+    fn cw20_shroom() -> amm::AssetInfo {
+        amm::AssetInfo::Token {
+            contract_addr: "shroom".to_string(),
+        }
+    }
+
+    fn split(percent: u8, offer_asset_info: amm::AssetInfo) -> Split {
+        Split {
+            percent,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: "pool".to_string(),
+                offer_asset_info,
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        }
+    }
+
+    fn asset(info: amm::AssetInfo, amount: u128) -> amm::Asset {
+        amm::Asset {
+            info,
+            amount: Uint128::new(amount),
+        }
+    }
+
+    fn holdings(native: u128, cw20: u128) -> Vec<amm::Asset> {
+        let mut assets = vec![];
+        if native > 0 {
+            assets.push(asset(native_shroom(), native));
+        }
+        if cw20 > 0 {
+            assets.push(asset(cw20_shroom(), cw20));
+        }
+        assets
+    }
+
+    fn amounts(plan: &StagePlan) -> Vec<u128> {
+        plan.swaps_to_execute
+            .iter()
+            .map(|swap| swap.amount.u128())
+            .collect()
+    }
+
+    #[test]
+    fn test_apportion_hands_the_rounding_leftover_to_the_largest_remainders() {
+        let amounts = |total: u128, percents: &[u8]| -> Vec<u128> {
+            apportion(Uint128::new(total), percents)
+                .unwrap()
+                .into_iter()
+                .map(|amount| amount.u128())
+                .collect()
+        };
+
+        // 33.33, 33.33 and 33.34: the leftover unit goes to the largest fraction, the last split's.
+        assert_eq!(amounts(101, &[33, 33, 34]), vec![33, 33, 35]);
+        // 6.6, 3.3 and 0.1 of 10 units: the last split no longer takes every unit rounded away.
+        assert_eq!(amounts(10, &[66, 33, 1]), vec![7, 3, 0]);
+        // Equal fractions break ties in favour of the earlier split.
+        assert_eq!(amounts(3, &[25, 25, 25, 25]), vec![1, 1, 1, 0]);
+        // Percentages are weights, so splits of one asset among others need not sum to 100.
+        assert_eq!(amounts(5, &[30, 20]), vec![3, 2]);
+        // A split of 0% never takes a leftover unit.
+        assert_eq!(amounts(1, &[0, 50, 50]), vec![0, 1, 0]);
+
+        assert_eq!(amounts(0, &[0, 0]), vec![0, 0]);
+        assert!(apportion(Uint128::new(1), &[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_normalization_target_is_the_largest_holding() {
+        let assets = vec![
+            asset(cw20_shroom(), 2),
+            asset(native_shroom(), 600),
+            asset(cw20_shroom(), 500),
+        ];
+        // 502 CW20 across two paths still loses to 600 native.
+        assert_eq!(select_normalization_target(&assets), native_shroom());
+        assert_eq!(select_normalization_target(&assets[..1]), cw20_shroom());
+
+        // Ties keep the first asset seen.
+        let tied = holdings(5, 5);
+        assert_eq!(select_normalization_target(&tied), native_shroom());
+    }
+
+    #[test]
+    fn test_plan_next_stage_converts_only_the_shortfall() {
+        // 60% native / 40% CW20 needs from a pure native balance: only the CW20 share moves.
+        let stage = Stage {
+            splits: vec![split(40, cw20_shroom()), split(60, native_shroom())],
+        };
+        let plan = plan_next_stage(&holdings(1000, 0), &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![400, 600]);
+        assert_eq!(
+            plan.conversion,
+            Some(PlannedConversion {
+                offer: asset(native_shroom(), 400),
+                target: cw20_shroom(),
+            })
+        );
+
+        // Holdings already in the right proportions need no conversion at all.
+        let plan = plan_next_stage(&holdings(600, 400), &stage).unwrap();
+        assert_eq!(plan.conversion, None);
+    }
+
+    #[test]
+    fn test_withdrawal_stage_allocates_each_asset_and_carries_the_rest() {
+        let inj = amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        };
+        let usdt = amm::AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        };
+        let stage = Stage {
+            splits: vec![split(30, inj.clone()), split(70, inj.clone())],
+        };
+        let (swaps, carried) =
+            plan_withdrawal_stage(&[asset(inj, 101), asset(usdt.clone(), 500)], &stage).unwrap();
+        assert_eq!(
+            swaps.iter().map(|s| s.amount.u128()).collect::<Vec<_>>(),
+            vec![30, 71]
+        );
+        assert_eq!(carried, vec![asset(usdt, 500)]);
+    }
+
+    #[test]
+    fn test_plan_next_stage_converts_a_cw20_surplus_to_native() {
+        let stage = Stage {
+            splits: vec![split(75, native_shroom()), split(25, cw20_shroom())],
+        };
+        let plan = plan_next_stage(&holdings(100, 900), &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![750, 250]);
+        assert_eq!(
+            plan.conversion,
+            Some(PlannedConversion {
+                offer: asset(cw20_shroom(), 650),
+                target: native_shroom(),
+            })
+        );
+
+        // A stage wanting only native converts every CW20 unit.
+        let stage = Stage {
+            splits: vec![split(50, native_shroom()), split(50, native_shroom())],
+        };
+        let plan = plan_next_stage(&holdings(0, 999), &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![499, 500]);
+        assert_eq!(
+            plan.conversion,
+            Some(PlannedConversion {
+                offer: asset(cw20_shroom(), 999),
+                target: native_shroom(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan_next_stage_combines_holdings_of_each_form() {
+        // Three paths of the previous stage each left a pile; piles of one form are combined.
+        let accumulated = vec![
+            asset(cw20_shroom(), 300),
+            asset(native_shroom(), 100),
+            asset(cw20_shroom(), 200),
+        ];
+        assert_eq!(
+            FormAmounts::of(&accumulated).unwrap(),
+            FormAmounts {
+                forms: vec![
+                    (cw20_shroom(), Uint128::new(500)),
+                    (native_shroom(), Uint128::new(100)),
+                ],
+            }
+        );
+
+        let stage = Stage {
+            splits: vec![split(80, native_shroom()), split(20, cw20_shroom())],
+        };
+        let plan = plan_next_stage(&accumulated, &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![480, 120]);
+        assert_eq!(
+            plan.conversion,
+            Some(PlannedConversion {
+                offer: asset(cw20_shroom(), 380),
+                target: native_shroom(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan_next_stage_converts_between_two_native_forms() {
+        let bridged = amm::AssetInfo::NativeToken {
+            denom: "peggy0xusdt".to_string(),
+        };
+        let canonical = amm::AssetInfo::NativeToken {
+            denom: "factory/issuer/usdt".to_string(),
+        };
+        let stage = Stage {
+            splits: vec![split(30, canonical.clone()), split(70, bridged.clone())],
+        };
+        let plan = plan_next_stage(&[asset(bridged.clone(), 1000)], &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![300, 700]);
+        assert_eq!(
+            plan.conversion,
+            Some(PlannedConversion {
+                offer: asset(bridged, 300),
+                target: canonical,
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan_next_stage_with_zero_piles() {
+        let stage = Stage {
+            splits: vec![split(40, cw20_shroom()), split(60, native_shroom())],
+        };
+
+        // Nothing held: every split swaps zero and nothing is converted.
+        let plan = plan_next_stage(&[], &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![0, 0]);
+        assert_eq!(plan.conversion, None);
+
+        // Explicit zero-amount piles are the same as none.
+        let zero_piles = vec![asset(native_shroom(), 0), asset(cw20_shroom(), 0)];
+        let plan = plan_next_stage(&zero_piles, &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![0, 0]);
+        assert_eq!(plan.conversion, None);
+
+        // Shares of 0.8 and 1.2 units: the first split's larger fraction earns the leftover unit.
+        let plan = plan_next_stage(&holdings(0, 2), &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![1, 1]);
+        assert_eq!(
+            plan.conversion,
+            Some(PlannedConversion {
+                offer: asset(cw20_shroom(), 1),
+                target: native_shroom(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan_next_stage_with_extreme_decimals() {
+        let stage = Stage {
+            splits: vec![split(50, native_shroom()), split(50, cw20_shroom())],
+        };
+
+        // A token of 0 decimals: a handful of atomics, split without losing any.
+        let plan = plan_next_stage(&holdings(3, 0), &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![1, 2]);
+
+        // A token of the maximum decimals: piles of a hundred million whole tokens still add up.
+        let whole = 10u128.pow(amm::MAX_DECIMALS as u32);
+        let plan = plan_next_stage(&holdings(100_000_000 * whole, 0), &stage).unwrap();
+        assert_eq!(amounts(&plan), vec![50_000_000 * whole, 50_000_000 * whole]);
+
+        // Piles whose sum leaves Uint128 fail rather than wrap.
+        let piles = vec![asset(native_shroom(), u128::MAX), asset(cw20_shroom(), 1)];
+        assert_eq!(
+            plan_next_stage(&piles, &stage).unwrap_err(),
+            ContractError::AmountOverflow {
+                context: "summing a stage's input piles".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_plan_next_stage_rejects_an_empty_path() {
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![],
+            }],
+        };
+        assert_eq!(
+            plan_next_stage(&holdings(10, 0), &stage).unwrap_err(),
+            ContractError::EmptyRoute {}
+        );
+    }
+
+    #[test]
+    fn test_plan_next_stage_never_round_trips() {
+        let layouts: Vec<Vec<(u8, bool)>> = vec![
+            vec![(40, false), (60, true)],
+            vec![(60, true), (40, false)],
+            vec![(33, true), (33, false), (34, true)],
+            vec![(1, false), (98, true), (1, false)],
+            vec![(50, false), (50, false)],
+            vec![(100, true)],
+        ];
+        let amounts = [0u128, 1, 2, 3, 7, 99, 100, 101, 997, 1_000_003];
+
+        for layout in &layouts {
+            let stage = Stage {
+                splits: layout
+                    .iter()
+                    .map(|(percent, native)| {
+                        split(
+                            *percent,
+                            if *native {
+                                native_shroom()
+                            } else {
+                                cw20_shroom()
+                            },
+                        )
+                    })
+                    .collect(),
+            };
+            for native in amounts {
+                for cw20 in amounts {
+                    let plan = plan_next_stage(&holdings(native, cw20), &stage).unwrap();
+
+                    // Every unit is allocated to a split.
+                    let allocated: Uint128 = plan.swaps_to_execute.iter().map(|s| s.amount).sum();
+                    assert_eq!(allocated, Uint128::new(native + cw20));
+
+                    // After the conversion, each form holds exactly what its splits need.
+                    let (mut native_held, mut cw20_held) = (native, cw20);
+                    if let Some(conversion) = &plan.conversion {
+                        assert_ne!(conversion.offer.info, conversion.target);
+                        if conversion.offer.info == native_shroom() {
+                            native_held -= conversion.offer.amount.u128();
+                            cw20_held += conversion.offer.amount.u128();
+                        } else {
+                            cw20_held -= conversion.offer.amount.u128();
+                            native_held += conversion.offer.amount.u128();
+                        }
+                    }
+                    let need = |form: amm::AssetInfo| -> u128 {
+                        plan.swaps_to_execute
+                            .iter()
+                            .filter(|s| s.operation.offer_asset_info().unwrap() == form)
+                            .map(|s| s.amount.u128())
+                            .sum()
+                    };
+                    assert_eq!(native_held, need(native_shroom()));
+                    assert_eq!(cw20_held, need(cw20_shroom()));
+                }
+            }
+        }
+    }
+}
+
+/// Allocation properties over arbitrary amounts and splits, for both the planner and the route
+/// simulation, which allocate each stage the same way.
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use crate::msg::{AmmSwapOp, Operation, Split};
+    use crate::routing::simulate_route_output;
+    use cosmwasm_std::testing::mock_dependencies;
+    use cosmwasm_std::{from_json, to_json_binary, ContractResult, SystemResult, WasmQuery};
+    use proptest::prelude::*;
+
+    fn native(denom: &str) -> amm::AssetInfo {
+        amm::AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        }
+    }
+
+    fn cw20(contract_addr: &str) -> amm::AssetInfo {
+        amm::AssetInfo::Token {
+            contract_addr: contract_addr.to_string(),
+        }
+    }
+
+    fn split(percent: u8, offer: amm::AssetInfo, ask: amm::AssetInfo) -> Split {
+        Split {
+            percent,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: "pool".to_string(),
+                offer_asset_info: offer,
+                ask_asset_info: ask,
+                min_out: None,
+            })],
+        }
+    }
+
+    /// Percentages of a valid stage: between one and eight splits, summing to 100. Splits of 0%
+    /// are allowed, as nothing rejects them.
+    fn percentages() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(0u8..=100, 0..8).prop_map(|mut cuts| {
+            cuts.sort_unstable();
+            let mut percents = vec![];
+            let mut previous = 0;
+            for cut in cuts.into_iter().chain([100]) {
+                percents.push(cut - previous);
+                previous = cut;
+            }
+            percents
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn apportioned_amounts_allocate_exactly_the_total(
+            total in any::<u128>(),
+            percents in percentages(),
+        ) {
+            let total = Uint128::new(total);
+            let amounts = apportion(total, &percents).unwrap();
+            let allocated: Uint128 = amounts.iter().sum();
+            prop_assert_eq!(allocated, total);
+            // Every split is within one unit of its exact share.
+            for (amount, percent) in amounts.iter().zip(&percents) {
+                let exact = total.multiply_ratio(*percent as u128, 100u128);
+                prop_assert!(*amount == exact || *amount == exact + Uint128::one());
+            }
+        }
+
+        #[test]
+        fn planned_swaps_allocate_exactly_the_holdings(
+            native_held in 0u128..=u64::MAX as u128,
+            cw20_held in 0u128..=u64::MAX as u128,
+            percents in percentages(),
+            forms in prop::collection::vec(any::<bool>(), 8),
+        ) {
+            let stage = Stage {
+                splits: percents
+                    .iter()
+                    .zip(&forms)
+                    .map(|(percent, is_native)| {
+                        let offer = if *is_native {
+                            native("factory/adapter/shroom")
+                        } else {
+                            cw20("shroom")
+                        };
+                        split(*percent, offer, native("usdt"))
+                    })
+                    .collect(),
+            };
+            let holdings = vec![
+                amm::Asset {
+                    info: native("factory/adapter/shroom"),
+                    amount: Uint128::new(native_held),
+                },
+                amm::Asset {
+                    info: cw20("shroom"),
+                    amount: Uint128::new(cw20_held),
+                },
+            ];
+
+            let plan = plan_next_stage(&holdings, &stage);
+            prop_assert!(plan.is_ok(), "planning failed: {:?}", plan.unwrap_err());
+            let plan = plan.unwrap();
+            let allocated: Uint128 = plan.swaps_to_execute.iter().map(|swap| swap.amount).sum();
+            prop_assert_eq!(allocated.u128(), native_held + cw20_held);
+
+            // The conversion never moves more than is held in the form it draws on.
+            if let Some(conversion) = &plan.conversion {
+                let held = if conversion.offer.info == holdings[0].info {
+                    native_held
+                } else {
+                    cw20_held
+                };
+                prop_assert!(conversion.offer.amount.u128() <= held);
+            }
+        }
+
+        #[test]
+        fn simulated_routes_allocate_exactly_the_offer(
+            amount in 0u128..=u64::MAX as u128,
+            stages in prop::collection::vec(percentages(), 1..4),
+        ) {
+            // Every pool returns what it is offered, so any unit lost or invented by the
+            // allocation shows up in the output.
+            let mut deps = mock_dependencies();
+            deps.querier.update_wasm(|query| match query {
+                WasmQuery::Smart { msg, .. } => {
+                    let amm::QueryMsg::Simulation { offer_asset } = from_json(msg).unwrap() else {
+                        panic!("unexpected pool query");
+                    };
+                    SystemResult::Ok(ContractResult::Ok(
+                        to_json_binary(&amm::SimulationResponse {
+                            return_amount: offer_asset.amount,
+                            spread_amount: Uint128::zero(),
+                            commission_amount: Uint128::zero(),
+                        })
+                        .unwrap(),
+                    ))
+                }
+                _ => panic!("unexpected wasm query"),
+            });
+
+            // Stages alternate between the two tokens, so each consumes the previous one's output.
+            let tokens = [native("inj"), native("usdt")];
+            let stages: Vec<Stage> = stages
+                .iter()
+                .enumerate()
+                .map(|(index, percents)| Stage {
+                    splits: percents
+                        .iter()
+                        .map(|percent| {
+                            let offer = tokens[index % 2].clone();
+                            let ask = tokens[(index + 1) % 2].clone();
+                            split(*percent, offer, ask)
+                        })
+                        .collect(),
+                })
+                .collect();
+
+            let deps = deps.as_ref();
+            let output = simulate_route_output(
+                deps.api,
+                &deps.querier,
+                deps.storage,
+                &stages,
+                amm::Asset {
+                    info: native("inj"),
+                    amount: Uint128::new(amount),
+                },
+                None,
+            );
+            prop_assert!(output.is_ok(), "simulation failed: {:?}", output.unwrap_err());
+            prop_assert_eq!(output.unwrap(), Uint128::new(amount));
+        }
+    }
+}
@@ -1,10 +1,28 @@
 use crate::msg::{
-    amm, orderbook, AllFeesResponse, FeeInfo, FeeResponse, Operation, SimulateRouteResponse, Stage,
+    amm, AdminLogResponse, AllFeesResponse, AllPoolStatsResponse, AllPoolsResponse,
+    BalanceCheckedTokensResponse, CapabilitiesResponse, DenomPolicyResponse, DisabledPoolsResponse,
+    DustBucketResponse, ExecutedOperation, ExecutionTotals, FeeCollectorsResponse, FeeInfo,
+    FeeResponse, FeesForPoolsResponse, IntegratorResponse, MarketResponse, MarketsResponse,
+    NativeConverterResponse, Operation, ParametersResponse, PoolAllowlistResponse, PoolExecution,
+    PoolInfoResponse, PoolMaxInputResponse, PoolStatsResponse, RateLimitsResponse, ReceiptResponse,
+    RelayAccountResponse, RelayNonceResponse, RouteComplexityResponse, RouteExecution,
+    RouteExecutionResponse, RouteTemplateResponse, RouteTemplatesResponse,
+    SimulateOperationResponse, SimulateRouteResponse, Stage, StageExecution, TraceResponse,
+    TwapOrderResponse, TwapOrdersResponse, UnclaimedFeesResponse, UsdPriceFeedResponse,
+};
+use crate::routing::{
+    path_start_info, simulate_hop_fee, simulate_route_detailed, simulate_single_operation,
+};
+use crate::state::{
+    add_asset_amount, asset_id, load_parameters, load_pool_config, Config, OperationExecution,
+    Receipt, ADMIN_LOG, BALANCE_CHECKED_TOKENS, DENOM_POLICY, DISABLED_POOLS, DUST_BUCKET,
+    EXECUTION_STATES, FEE_MAP, INTEGRATORS, MARKETS, NATIVE_CONVERTERS, POOL_ALLOWLIST,
+    POOL_CONFIGS, POOL_REGISTRATION_TERMS, POOL_STATS, RATE_LIMITS, RECEIPTS,
+    RECEIPT_RETENTION_BLOCKS, RELAYERS, RELAY_DEPOSITS, RELAY_KEYS, RELAY_NONCES, ROUTE_TEMPLATES,
+    TRACES, TWAP_ORDERS, UNCLAIMED_FEES, USD_PRICE_FEEDS, WRAPPED_INJ,
 };
-use crate::state::{Config, FEE_MAP};
 use cosmwasm_std::{
-    to_json_binary, Binary, Coin, Deps, Env, Order, QuerierWrapper, StdError, StdResult, Uint128,
-    WasmQuery,
+    to_json_binary, Addr, Binary, Coin, Decimal, Deps, Env, Order, StdError, StdResult,
 };
 use cw_storage_plus::Bound;
 
@@ -13,174 +31,464 @@ pub fn query_config(deps: Deps) -> StdResult<Binary> {
     to_json_binary(&config)
 }
 
+pub fn query_fee_collectors(deps: Deps) -> StdResult<Binary> {
+    let config: Config = crate::state::CONFIG.load(deps.storage)?;
+    to_json_binary(&FeeCollectorsResponse {
+        collectors: config.fee_collectors,
+    })
+}
+
+pub fn query_denom_policy(deps: Deps) -> StdResult<Binary> {
+    let policy = DENOM_POLICY.may_load(deps.storage)?;
+    to_json_binary(&DenomPolicyResponse { policy })
+}
+
+pub fn query_rate_limits(deps: Deps) -> StdResult<Binary> {
+    let limits = RATE_LIMITS.may_load(deps.storage)?;
+    to_json_binary(&RateLimitsResponse { limits })
+}
+
+pub fn query_pool_stats(deps: Deps, pool_address: String) -> StdResult<Binary> {
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    let stats = POOL_STATS
+        .may_load(deps.storage, &pool_addr)?
+        .unwrap_or_default();
+    to_json_binary(&PoolStatsResponse {
+        pool_address: pool_addr.to_string(),
+        stats,
+    })
+}
+
+pub fn query_all_pool_stats(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let pools = POOL_STATS
+        .range(
+            deps.storage,
+            start_addr.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (pool_addr, stats) = item?;
+            Ok(PoolStatsResponse {
+                pool_address: pool_addr.to_string(),
+                stats,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&AllPoolStatsResponse { pools })
+}
+
+pub fn query_all_pools(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let pools = POOL_CONFIGS
+        .range(
+            deps.storage,
+            start_addr.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .filter_map(|item| match item {
+            Ok((pool_addr, config)) => config.metadata.map(|metadata| {
+                Ok(PoolInfoResponse {
+                    pool_address: pool_addr.to_string(),
+                    metadata,
+                })
+            }),
+            Err(err) => Some(Err(err)),
+        })
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&AllPoolsResponse { pools })
+}
+
+pub fn query_markets(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let markets = MARKETS
+        .range(
+            deps.storage,
+            start_addr.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (swap_contract, market) = item?;
+            Ok(MarketResponse {
+                swap_contract: swap_contract.to_string(),
+                market,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&MarketsResponse { markets })
+}
+
+pub fn query_balance_checked_tokens(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let tokens = BALANCE_CHECKED_TOKENS
+        .keys(
+            deps.storage,
+            start_addr.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&BalanceCheckedTokensResponse { tokens })
+}
+
+pub fn query_pool_allowlist(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let pools = POOL_ALLOWLIST
+        .keys(
+            deps.storage,
+            start_addr.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&PoolAllowlistResponse { pools })
+}
+
+pub fn query_disabled_pools(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+
+    let pools = DISABLED_POOLS
+        .keys(
+            deps.storage,
+            start_addr.as_ref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&DisabledPoolsResponse { pools })
+}
+
+pub fn query_parameters(deps: Deps) -> StdResult<Binary> {
+    to_json_binary(&ParametersResponse {
+        config: crate::state::CONFIG.load(deps.storage)?,
+        parameters: load_parameters(deps.storage)?,
+        denom_policy: DENOM_POLICY.may_load(deps.storage)?,
+        wrapped_inj: WRAPPED_INJ.may_load(deps.storage)?,
+        pool_registration_terms: POOL_REGISTRATION_TERMS.may_load(deps.storage)?,
+        receipt_retention_blocks: RECEIPT_RETENTION_BLOCKS,
+    })
+}
+
+pub fn query_dust_bucket(deps: Deps) -> StdResult<Binary> {
+    let assets = DUST_BUCKET.may_load(deps.storage)?.unwrap_or_default();
+    to_json_binary(&DustBucketResponse { assets })
+}
+
+pub fn query_unclaimed_fees(deps: Deps, collector: String) -> StdResult<Binary> {
+    let collector = deps.api.addr_validate(&collector)?;
+    let assets = UNCLAIMED_FEES
+        .may_load(deps.storage, &collector)?
+        .unwrap_or_default();
+    to_json_binary(&UnclaimedFeesResponse { assets })
+}
+
+/// Simulates a route, taking each hop's aggregator fee from its output as execution does.
 pub fn simulate_route(
     deps: Deps,
-    _env: Env,
+    env: Env,
     stages: Vec<Stage>,
     amount_in: Coin,
+    lenient: bool,
 ) -> StdResult<Binary> {
-    if stages.is_empty() {
-        return to_json_binary(&SimulateRouteResponse {
-            output_amount: Uint128::zero(),
-        });
-    }
+    let simulation = simulate_route_detailed(
+        deps.api,
+        &deps.querier,
+        deps.storage,
+        &stages,
+        native_asset(amount_in),
+        Some(&[]),
+        lenient,
+    )?;
 
-    let mut current_assets: Vec<amm::Asset> = vec![amm::Asset {
-        info: amm::AssetInfo::NativeToken {
-            denom: amount_in.denom,
-        },
-        amount: amount_in.amount,
-    }];
+    to_json_binary(&SimulateRouteResponse {
+        output_amount: simulation.output_amount,
+        block_height: env.block.height,
+        block_time: env.block.time,
+        failed_splits: simulation.failed_splits,
+        allocations: simulation.allocations,
+    })
+}
 
-    for stage in stages {
-        let mut next_stage_outputs: Vec<amm::Asset> = vec![];
-
-        // Group the current assets by their type to get the total for each pile.
-        let mut grouped_inputs: Vec<(amm::AssetInfo, Uint128)> = vec![];
-        for asset in current_assets {
-            if let Some((_, amount)) = grouped_inputs
-                .iter_mut()
-                .find(|(info, _)| *info == asset.info)
-            {
-                *amount += asset.amount;
-            } else {
-                grouped_inputs.push((asset.info, asset.amount));
-            }
-        }
+/// Simulates a route from any asset, including a CW20 offered through the `Cw20HookMsg` flow,
+/// with hop fees taken like `simulate_route`.
+pub fn simulate_route_from_asset(
+    deps: Deps,
+    env: Env,
+    stages: Vec<Stage>,
+    amount_in: amm::Asset,
+) -> StdResult<Binary> {
+    let simulation = simulate_route_detailed(
+        deps.api,
+        &deps.querier,
+        deps.storage,
+        &stages,
+        amount_in,
+        Some(&[]),
+        false,
+    )?;
 
-        let mut amounts_allocated: Vec<(amm::AssetInfo, Uint128)> = vec![];
+    to_json_binary(&SimulateRouteResponse {
+        output_amount: simulation.output_amount,
+        block_height: env.block.height,
+        block_time: env.block.time,
+        failed_splits: vec![],
+        allocations: simulation.allocations,
+    })
+}
 
-        for (i, split) in stage.splits.iter().enumerate() {
-            let path_input_info = get_path_start_info(&split.path)?;
+/// Simulates one operation through the same adapters a route uses, and takes the aggregator fee
+/// for its pool from the output.
+pub fn simulate_operation(
+    deps: Deps,
+    env: Env,
+    operation: Operation,
+    offer_asset: amm::Asset,
+) -> StdResult<Binary> {
+    let expected_offer = operation.offer_asset_info()?;
+    if offer_asset.info != expected_offer {
+        return Err(StdError::generic_err(format!(
+            "Offer asset {} does not match the operation's offer asset {}",
+            asset_id(&offer_asset.info),
+            asset_id(&expected_offer)
+        )));
+    }
 
-            let total_amount_for_type = grouped_inputs
-                .iter()
-                .find(|(info, _)| *info == path_input_info)
-                .map(|(_, amount)| *amount)
-                .unwrap_or_else(Uint128::zero);
+    let output = simulate_single_operation(&deps.querier, deps.storage, &operation, &offer_asset)?;
+    let (fee_amount, amount_after_fee) =
+        simulate_hop_fee(deps.api, deps.storage, &operation, &[], output.amount)?;
 
-            let amount_for_split = if i < stage.splits.len() - 1 {
-                total_amount_for_type.multiply_ratio(split.percent as u128, 100u128)
-            } else {
-                let already_allocated = amounts_allocated
-                    .iter()
-                    .find(|(info, _)| *info == path_input_info)
-                    .map(|(_, amount)| *amount)
-                    .unwrap_or_else(Uint128::zero);
-                total_amount_for_type
-                    .checked_sub(already_allocated)
-                    .map_err(StdError::from)?
-            };
+    to_json_binary(&SimulateOperationResponse {
+        return_asset: amm::Asset {
+            info: output.info,
+            amount: amount_after_fee,
+        },
+        fee_amount,
+        block_height: env.block.height,
+        block_time: env.block.time,
+    })
+}
 
-            if let Some((_, allocated)) = amounts_allocated
-                .iter_mut()
-                .find(|(info, _)| *info == path_input_info)
-            {
-                *allocated += amount_for_split;
-            } else {
-                amounts_allocated.push((path_input_info.clone(), amount_for_split));
-            }
+/// Simulates a route with the aggregator fee applied at every hop through a taxed pool, using
+/// `fee_overrides` in place of the stored fee for the listed pools. State is not modified.
+pub fn simulate_route_with_fees(
+    deps: Deps,
+    env: Env,
+    stages: Vec<Stage>,
+    amount_in: Coin,
+    fee_overrides: Vec<FeeInfo>,
+) -> StdResult<Binary> {
+    let params = load_parameters(deps.storage)?;
+    let mut overrides: Vec<(Addr, Decimal)> = vec![];
+    for fee_override in fee_overrides {
+        if fee_override.fee_percent >= params.max_fee_percent {
+            return Err(StdError::generic_err(format!(
+                "Fee percentage must be less than {}%",
+                params.max_fee_percent * Decimal::percent(10_000)
+            )));
+        }
+        let pool_addr = deps.api.addr_validate(&fee_override.pool_address)?;
+        overrides.push((pool_addr, fee_override.fee_percent));
+    }
 
-            let mut current_path_asset = amm::Asset {
-                info: path_input_info,
-                amount: amount_for_split,
-            };
+    let simulation = simulate_route_detailed(
+        deps.api,
+        &deps.querier,
+        deps.storage,
+        &stages,
+        native_asset(amount_in),
+        Some(&overrides),
+        false,
+    )?;
 
-            for operation in &split.path {
-                let output_asset =
-                    simulate_single_operation(&deps.querier, operation, &current_path_asset)?;
-                current_path_asset = output_asset;
-            }
+    to_json_binary(&SimulateRouteResponse {
+        output_amount: simulation.output_amount,
+        block_height: env.block.height,
+        block_time: env.block.time,
+        failed_splits: vec![],
+        allocations: simulation.allocations,
+    })
+}
 
-            next_stage_outputs.push(current_path_asset);
-        }
+// The `GAS_PER_*` figures below are uncalibrated round numbers chosen to err on the high side of
+// a typical Injective AMM swap, not measurements of this contract. Calibrating them means running
+// representative routes on a chain, e.g. the `injective-test-tube` gas benchmarks in
+// `tests/gas_benchmarks.rs`, and fitting each figure to the `gas_used` they report; until then
+// `estimated_gas` is only good for comparing routes with each other.
 
-        current_assets = next_stage_outputs;
-    }
+/// Gas charged before the first stage: message dispatch, validation and storing the route.
+pub const GAS_PER_ROUTE: u64 = 200_000;
+/// Gas per swap, covering the pool's execution and the reply that handles its output.
+pub const GAS_PER_SWAP: u64 = 180_000;
+/// Gas per adapter conversion and its reply.
+pub const GAS_PER_CONVERSION: u64 = 130_000;
+/// Gas per storage write of route state or pool statistics.
+pub const GAS_PER_STORAGE_WRITE: u64 = 25_000;
 
-    let total_output: Uint128 = current_assets.iter().map(|a| a.amount).sum();
+#[derive(Default)]
+struct RouteComplexity {
+    swaps: u64,
+    conversions: u64,
+    storage_writes: u64,
+}
 
-    let response = SimulateRouteResponse {
-        output_amount: total_output,
-    };
-    to_json_binary(&response)
-}
-
-/// Simulates a single swap operation.
-fn simulate_single_operation(
-    querier: &QuerierWrapper,
-    operation: &Operation,
-    offer_asset: &amm::Asset,
-) -> StdResult<amm::Asset> {
-    match operation {
-        Operation::AmmSwap(op) => {
-            let pair_query = amm::QueryMsg::Simulation {
-                offer_asset: offer_asset.clone(),
-            };
-            let contract_addr = op.pool_address.to_string();
+pub fn estimate_route_complexity(stages: Vec<Stage>) -> StdResult<Binary> {
+    let mut complexity = RouteComplexity::default();
+    count_route_work(&stages, &mut complexity)?;
 
-            let sim_response: amm::SimulationResponse = querier.query(
-                &WasmQuery::Smart {
-                    contract_addr,
-                    msg: to_json_binary(&pair_query)?,
-                }
-                .into(),
-            )?;
+    let submessages = complexity.swaps + complexity.conversions;
+    to_json_binary(&RouteComplexityResponse {
+        submessages,
+        conversions: complexity.conversions,
+        storage_writes: complexity.storage_writes,
+        estimated_gas: GAS_PER_ROUTE
+            + complexity.swaps * GAS_PER_SWAP
+            + complexity.conversions * GAS_PER_CONVERSION
+            + complexity.storage_writes * GAS_PER_STORAGE_WRITE,
+    })
+}
 
-            Ok(amm::Asset {
-                info: op.ask_asset_info.clone(),
-                amount: sim_response.return_amount,
-            })
-        }
-        Operation::OrderbookSwap(op) => {
-            let source_denom = match &offer_asset.info {
-                amm::AssetInfo::NativeToken { denom } => denom.clone(),
-                _ => {
-                    return Err(StdError::generic_err(
-                        "Orderbook simulation only supports native token inputs",
-                    ))
+/// Adds a route's swaps and conversions to `complexity`, including those of sub-aggregator
+/// routes. Every swap writes its pool's statistics and every reply the route's state.
+fn count_route_work(stages: &[Stage], complexity: &mut RouteComplexity) -> StdResult<()> {
+    // Starting stores the id counter, plan and state; completing stores the receipt and clears
+    // the plan, state and conversion result.
+    complexity.storage_writes += 7;
+    let is_native = |info: &amm::AssetInfo| matches!(info, amm::AssetInfo::NativeToken { .. });
+    // Forms (native, CW20) held going into each stage. The offer is assumed to be in a form the
+    // first stage asks for.
+    let mut held: Option<(bool, bool)> = None;
+    for stage in stages {
+        let mut needed = (false, false);
+        let mut produced = (false, false);
+        for split in &stage.splits {
+            let first = path_start_info(&split.path)?;
+            if is_native(&first) {
+                needed.0 = true;
+            } else {
+                needed.1 = true;
+            }
+            for (index, operation) in split.path.iter().enumerate() {
+                complexity.swaps += 1;
+                complexity.storage_writes += 2;
+                match operation {
+                    Operation::SubAggregator(sub_op) => {
+                        count_route_work(&sub_op.stages, complexity)?;
+                    }
+                    // Providing the liquidity costs about as much as another swap, and its reply
+                    // stores the route's state again.
+                    Operation::ProvideLiquidity(_) => {
+                        complexity.swaps += 1;
+                        complexity.storage_writes += 1;
+                    }
+                    _ => {}
                 }
-            };
-            let target_denom = match &op.ask_asset_info {
-                amm::AssetInfo::NativeToken { denom } => denom.clone(),
-                _ => {
-                    return Err(StdError::generic_err(
-                        "Orderbook simulation only supports native token outputs",
-                    ))
+                // An orderbook swap into a CW20 settles in the native form, converted unless the
+                // next hop takes that form.
+                let settles_native = matches!(
+                    operation,
+                    Operation::OrderbookSwap(op) if !is_native(&op.ask_asset_info)
+                );
+                let converts = match split.path.get(index + 1) {
+                    Some(next) if settles_native => !is_native(&next.offer_asset_info()?),
+                    Some(next) => operation.ask_asset_info()? != next.offer_asset_info()?,
+                    None => settles_native,
+                };
+                if converts {
+                    complexity.conversions += 1;
+                    complexity.storage_writes += 1;
                 }
-            };
-
-            let orderbook_query = orderbook::QueryMsg::GetOutputQuantity {
-                from_quantity: offer_asset.amount.into(),
-                source_denom,
-                target_denom,
-            };
-            let contract_addr = op.swap_contract.to_string();
-
-            let sim_response: orderbook::SwapEstimationResult = querier.query(
-                &WasmQuery::Smart {
-                    contract_addr,
-                    msg: to_json_binary(&orderbook_query)?,
+            }
+            if let Some(last) = split.path.last() {
+                if is_native(&last.ask_asset_info()?) {
+                    produced.0 = true;
+                } else {
+                    produced.1 = true;
                 }
-                .into(),
-            )?;
-
-            Ok(amm::Asset {
-                info: op.ask_asset_info.clone(),
-                amount: sim_response.result_quantity.into(),
-            })
+            }
+        }
+        // The planner converts at most once per stage, and only when the forms differ.
+        let stage_input_converts = match held {
+            Some(held) => held != needed || (needed.0 && needed.1),
+            None => needed.0 && needed.1,
+        };
+        if stage_input_converts {
+            complexity.conversions += 1;
+            complexity.storage_writes += 1;
         }
+        held = Some(produced);
+    }
+    // Final normalization converts the smaller holding when both forms come out.
+    if held == Some((true, true)) {
+        complexity.conversions += 1;
+        complexity.storage_writes += 1;
     }
+    Ok(())
 }
 
-fn get_path_start_info(path: &[Operation]) -> StdResult<amm::AssetInfo> {
-    let first_op = path
-        .first()
-        .ok_or_else(|| StdError::generic_err("Path cannot be empty"))?;
-    Ok(match first_op {
-        Operation::AmmSwap(op) => op.offer_asset_info.clone(),
-        Operation::OrderbookSwap(op) => op.offer_asset_info.clone(),
-    })
+fn native_asset(coin: Coin) -> amm::Asset {
+    coin.into()
 }
 
 /// Queries the fee percentage for a specific pool address.
@@ -191,6 +499,19 @@ pub fn query_fee_for_pool(deps: Deps, pool_address: String) -> StdResult<Binary>
     to_json_binary(&FeeResponse { fee })
 }
 
+/// Queries the fee percentages for several pools in one call.
+pub fn query_fees_for_pools(deps: Deps, pool_addresses: Vec<String>) -> StdResult<Binary> {
+    let fees = pool_addresses
+        .iter()
+        .map(|pool_address| {
+            let pool_addr = deps.api.addr_validate(pool_address)?;
+            FEE_MAP.may_load(deps.storage, &pool_addr)
+        })
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&FeesForPoolsResponse { fees })
+}
+
 // Pagination constants
 const DEFAULT_LIMIT: u32 = 10;
 const MAX_LIMIT: u32 = 30;
@@ -228,14 +549,335 @@ pub fn query_all_fees(
     to_json_binary(&AllFeesResponse { fees })
 }
 
+pub fn query_route_template(deps: Deps, name: String) -> StdResult<Binary> {
+    let stages = ROUTE_TEMPLATES.load(deps.storage, &name)?;
+    to_json_binary(&RouteTemplateResponse { name, stages })
+}
+
+/// Queries registered route templates by name with pagination.
+pub fn query_route_templates(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let templates: Vec<RouteTemplateResponse> = ROUTE_TEMPLATES
+        .range(
+            deps.storage,
+            start_after.as_deref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (name, stages) = item?;
+            Ok(RouteTemplateResponse { name, stages })
+        })
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&RouteTemplatesResponse { templates })
+}
+
+pub fn query_capabilities(deps: Deps) -> StdResult<Binary> {
+    let to_strings =
+        |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+
+    let mut fee_modes = vec![];
+    if !FEE_MAP.is_empty(deps.storage) {
+        fee_modes.push("pool_fee");
+    }
+    fee_modes.push("fee_cap");
+    if !INTEGRATORS.is_empty(deps.storage) {
+        fee_modes.push("integrator_fee");
+    }
+    let config = crate::state::CONFIG.may_load(deps.storage)?;
+    if config.is_some_and(|config| config.fee_collectors.len() > 1) {
+        fee_modes.push("split_collectors");
+    }
+    // A fee its collector cannot receive is always kept for `ClaimFees`.
+    fee_modes.push("claimable");
+
+    let mut entry_points = vec![
+        "execute_route",
+        "execute_route_packed",
+        "execute_route_with_allowance",
+        "execute_route_at_quote",
+        "place_twap_order",
+        "receive.execute_route",
+        "receive.execute_route_at_quote",
+        "receive.withdraw_and_route",
+    ];
+    if !ROUTE_TEMPLATES.is_empty(deps.storage) {
+        entry_points.extend(["execute_template", "receive.execute_template"]);
+    }
+    if !RELAYERS.is_empty(deps.storage) {
+        entry_points.push("execute_route_for");
+    }
+    entry_points.push("continue_route");
+    if POOL_REGISTRATION_TERMS.exists(deps.storage) {
+        entry_points.push("register_pool");
+    }
+
+    to_json_binary(&CapabilitiesResponse {
+        operations: to_strings(&[
+            "amm_swap",
+            "orderbook_swap",
+            "sub_aggregator",
+            "provide_liquidity",
+        ]),
+        post_actions: to_strings(&["delegate"]),
+        fee_modes: to_strings(&fee_modes),
+        entry_points: to_strings(&entry_points),
+    })
+}
+
+pub fn query_admin_log(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let entries = ADMIN_LOG
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, entry)| entry))
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&AdminLogResponse { entries })
+}
+
+pub fn query_relay_account(deps: Deps, owner: String) -> StdResult<Binary> {
+    let owner_addr = deps.api.addr_validate(&owner)?;
+    let deposits = RELAY_DEPOSITS
+        .prefix(&owner_addr)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin::new(amount, denom)))
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&RelayAccountResponse {
+        pubkey: RELAY_KEYS.may_load(deps.storage, &owner_addr)?,
+        nonce: RELAY_NONCES
+            .may_load(deps.storage, &owner_addr)?
+            .unwrap_or_default(),
+        deposits,
+    })
+}
+
+pub fn query_usd_price_feed(deps: Deps, asset_info: amm::AssetInfo) -> StdResult<Binary> {
+    let feed = USD_PRICE_FEEDS.may_load(deps.storage, asset_id(&asset_info))?;
+    to_json_binary(&UsdPriceFeedResponse { feed })
+}
+
+pub fn query_integrator(deps: Deps, addr: String) -> StdResult<Binary> {
+    let addr = deps.api.addr_validate(&addr)?;
+    let integrator = INTEGRATORS.may_load(deps.storage, &addr)?;
+    to_json_binary(&IntegratorResponse { integrator })
+}
+
+pub fn query_native_converter(deps: Deps, denom: String) -> StdResult<Binary> {
+    let converter = NATIVE_CONVERTERS.may_load(deps.storage, &denom)?;
+    to_json_binary(&NativeConverterResponse { converter })
+}
+
+pub fn query_pool_max_input(deps: Deps, pool_address: String) -> StdResult<Binary> {
+    let pool_addr = deps.api.addr_validate(&pool_address)?;
+    let max_input = load_pool_config(deps.storage, &pool_addr)?.max_input;
+    to_json_binary(&PoolMaxInputResponse { max_input })
+}
+
+pub fn query_twap_order(deps: Deps, order_id: u64) -> StdResult<Binary> {
+    let order = TWAP_ORDERS.may_load(deps.storage, order_id)?;
+    to_json_binary(&TwapOrderResponse { order })
+}
+
+pub fn query_twap_orders(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let orders = TWAP_ORDERS
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, order)| order))
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&TwapOrdersResponse { orders })
+}
+
+pub fn query_relay_nonce(deps: Deps, owner: String) -> StdResult<Binary> {
+    let owner_addr = deps.api.addr_validate(&owner)?;
+    let nonce = RELAY_NONCES
+        .may_load(deps.storage, &owner_addr)?
+        .unwrap_or_default();
+    to_json_binary(&RelayNonceResponse {
+        owner: owner_addr.to_string(),
+        nonce,
+    })
+}
+
+/// Queries the receipt of a completed route. Receipts past their retention window are
+/// treated as absent.
+pub fn query_receipt(deps: Deps, env: Env, route_id: u64) -> StdResult<Binary> {
+    let receipt = RECEIPTS
+        .may_load(deps.storage, route_id)?
+        .filter(|r| env.block.height <= r.expires_at_height);
+
+    to_json_binary(&ReceiptResponse { receipt })
+}
+
+pub fn query_trace(deps: Deps, env: Env, route_id: u64) -> StdResult<Binary> {
+    let retained = EXECUTION_STATES.has(deps.storage, route_id)
+        || RECEIPTS
+            .may_load(deps.storage, route_id)?
+            .is_some_and(|r| env.block.height <= r.expires_at_height);
+    let entries = if retained {
+        TRACES.may_load(deps.storage, route_id)?.unwrap_or_default()
+    } else {
+        vec![]
+    };
+
+    to_json_binary(&TraceResponse { entries })
+}
+
+pub fn query_route_execution(deps: Deps, env: Env, route_id: u64) -> StdResult<Binary> {
+    let execution = RECEIPTS
+        .may_load(deps.storage, route_id)?
+        .filter(|r| env.block.height <= r.expires_at_height)
+        .map(|receipt| route_execution(route_id, receipt));
+
+    to_json_binary(&RouteExecutionResponse { execution })
+}
+
+fn route_execution(route_id: u64, receipt: Receipt) -> RouteExecution {
+    let mut stages: Vec<StageExecution> = vec![];
+    for stage_index in receipt.executions.iter().map(|e| e.stage_index) {
+        if stages.iter().any(|s| s.stage_index == stage_index) {
+            continue;
+        }
+        let in_stage: Vec<&OperationExecution> = receipt
+            .executions
+            .iter()
+            .filter(|e| e.stage_index == stage_index)
+            .collect();
+        // Each split's path ends with the last of its operations that replied.
+        let outputs = in_stage.iter().filter(|e| {
+            !in_stage
+                .iter()
+                .any(|other| other.split_index == e.split_index && other.op_index > e.op_index)
+        });
+        let last = in_stage.iter().max_by_key(|e| e.height);
+        stages.push(StageExecution {
+            stage_index,
+            totals: execution_totals(
+                in_stage.iter().filter(|e| e.op_index == 0).copied(),
+                outputs.copied(),
+                in_stage.iter().copied(),
+            ),
+            completed_at_height: last.map_or(0, |e| e.height),
+            completed_at_time: last.map_or(0, |e| e.time),
+        });
+    }
+
+    let mut pools: Vec<PoolExecution> = vec![];
+    for contract in receipt.executions.iter().map(|e| &e.contract) {
+        if pools.iter().any(|p| p.contract == *contract) {
+            continue;
+        }
+        let through_pool = || {
+            receipt
+                .executions
+                .iter()
+                .filter(|e| e.contract == *contract)
+        };
+        pools.push(PoolExecution {
+            contract: contract.clone(),
+            totals: execution_totals(through_pool(), through_pool(), through_pool()),
+        });
+    }
+
+    let operations = receipt
+        .executions
+        .iter()
+        .map(|execution| ExecutedOperation {
+            execution: execution.clone(),
+            price: Decimal::checked_from_ratio(
+                execution.output.amount - execution.fee,
+                execution.offer.amount,
+            )
+            .ok(),
+        })
+        .collect();
+
+    RouteExecution {
+        route_id,
+        input: receipt.input,
+        output: receipt.output,
+        fees: receipt.fees,
+        completed_at_height: receipt.completed_at_height,
+        completed_at_time: receipt.completed_at_time,
+        operations,
+        stages,
+        pools,
+    }
+}
+
+/// Sums what `offers` were offered, what `outputs` received after fees and the fees `charged`.
+fn execution_totals<'a>(
+    offers: impl Iterator<Item = &'a OperationExecution>,
+    outputs: impl Iterator<Item = &'a OperationExecution>,
+    charged: impl Iterator<Item = &'a OperationExecution>,
+) -> ExecutionTotals {
+    let mut offered = vec![];
+    for e in offers {
+        add_asset_amount(&mut offered, &e.offer.info, e.offer.amount);
+    }
+    let mut received = vec![];
+    for e in outputs {
+        add_asset_amount(&mut received, &e.output.info, e.output.amount - e.fee);
+    }
+    let mut fees = vec![];
+    for e in charged.filter(|e| !e.fee.is_zero()) {
+        add_asset_amount(&mut fees, &e.output.info, e.fee);
+    }
+    let price = match (offered.as_slice(), received.as_slice()) {
+        ([offered], [received]) => {
+            Decimal::checked_from_ratio(received.amount, offered.amount).ok()
+        }
+        _ => None,
+    };
+    ExecutionTotals {
+        offered,
+        received,
+        fees,
+        price,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::contract::query;
     use crate::msg::{AmmSwapOp, QueryMsg, Split, Stage};
+    use crate::state::{update_pool_config, Integrator, PoolQueryKind, PoolRegistrationTerms};
     use amm::AssetInfo;
     use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier};
-    use cosmwasm_std::{from_json, ContractResult, Decimal, SystemResult};
+    use cosmwasm_std::{from_json, ContractResult, Decimal, SystemResult, Uint128, WasmQuery};
+    use std::cell::Cell;
+    use std::rc::Rc;
     use std::str::FromStr;
 
     const POOL_A_ADDR: &str = "inj1hkhdaj2ts42k2x53h3w0f26g2xvy3a52e0u4gp";
@@ -282,6 +924,7 @@ mod tests {
                     ask_asset_info: AssetInfo::NativeToken {
                         denom: "usdt".to_string(),
                     },
+                    min_out: None,
                 })],
             }],
         }];
@@ -291,12 +934,135 @@ mod tests {
             mock_env(),
             stages,
             Coin::new(1000u128, "inj"),
+            false,
         )
         .unwrap();
         let result: SimulateRouteResponse = from_json(&result_binary).unwrap();
         assert_eq!(result.output_amount, Uint128::new(50000));
     }
 
+    #[test]
+    fn test_simulate_takes_the_fee_of_every_hop() {
+        let mut querier = MockQuerier::new(&[]);
+        querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { msg, .. } => {
+                let amm::QueryMsg::Simulation { offer_asset } = from_json(msg).unwrap() else {
+                    panic!("expected a simulation query");
+                };
+                let response = amm::SimulationResponse {
+                    return_amount: offer_asset.amount * Uint128::new(10),
+                    spread_amount: Uint128::zero(),
+                    commission_amount: Uint128::zero(),
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+        let mut deps = mock_dependencies();
+        deps.querier = querier;
+        for pool in [POOL_A_ADDR, POOL_B_ADDR] {
+            FEE_MAP
+                .save(
+                    deps.as_mut().storage,
+                    &Addr::unchecked(pool),
+                    &Decimal::percent(1),
+                )
+                .unwrap();
+        }
+
+        let hop = |pool: &str, offer: &str, ask: &str| {
+            Operation::AmmSwap(AmmSwapOp {
+                pool_address: pool.to_string(),
+                offer_asset_info: AssetInfo::NativeToken {
+                    denom: offer.to_string(),
+                },
+                ask_asset_info: AssetInfo::NativeToken {
+                    denom: ask.to_string(),
+                },
+                min_out: None,
+            })
+        };
+        let stages = vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![
+                    hop(POOL_A_ADDR, "inj", "usdt"),
+                    hop(POOL_B_ADDR, "usdt", "atom"),
+                ],
+            }],
+        }];
+
+        // 1000 -> 10000, less 1% = 9900 -> 99000, less 1% = 98010.
+        let result: SimulateRouteResponse = from_json(
+            simulate_route(
+                deps.as_ref(),
+                mock_env(),
+                stages,
+                Coin::new(1000u128, "inj"),
+                false,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(result.output_amount, Uint128::new(98010));
+    }
+
+    #[test]
+    fn test_simulate_uses_registered_pool_query_kind() {
+        let mut querier = MockQuerier::new(&[]);
+        querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { msg, .. } => {
+                let amm::SwapOperationsQueryMsg::SimulateSwapOperations {
+                    offer_amount,
+                    operations,
+                } = from_json(msg).unwrap();
+                assert_eq!(offer_amount, Uint128::new(1000));
+                assert_eq!(operations.len(), 1);
+                let response = amm::SimulateSwapOperationsResponse {
+                    amount: Uint128::new(30000),
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+
+        let mut deps = mock_dependencies();
+        deps.querier = querier;
+        update_pool_config(
+            deps.as_mut().storage,
+            &Addr::unchecked(POOL_A_ADDR),
+            |pool| pool.query_kind = PoolQueryKind::SimulateSwapOperations,
+        )
+        .unwrap();
+
+        let stages = vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: POOL_A_ADDR.to_string(),
+                    offer_asset_info: AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }];
+
+        let result_binary = simulate_route(
+            deps.as_ref(),
+            mock_env(),
+            stages,
+            Coin::new(1000u128, "inj"),
+            false,
+        )
+        .unwrap();
+        let result: SimulateRouteResponse = from_json(&result_binary).unwrap();
+        assert_eq!(result.output_amount, Uint128::new(30000));
+    }
+
     #[test]
     fn test_simulate_multi_hop_path() {
         let mut querier = MockQuerier::new(&[]);
@@ -342,38 +1108,183 @@ mod tests {
         let stages = vec![Stage {
             splits: vec![Split {
                 percent: 100,
-                path: vec![
-                    Operation::AmmSwap(AmmSwapOp {
-                        pool_address: POOL_A_ADDR.to_string(),
-                        offer_asset_info: AssetInfo::NativeToken {
-                            denom: "inj".to_string(),
-                        },
-                        ask_asset_info: AssetInfo::NativeToken {
-                            denom: "usdt".to_string(),
-                        },
-                    }),
-                    Operation::AmmSwap(AmmSwapOp {
-                        pool_address: POOL_B_ADDR.to_string(),
-                        offer_asset_info: AssetInfo::NativeToken {
-                            denom: "usdt".to_string(),
-                        },
-                        ask_asset_info: AssetInfo::NativeToken {
-                            denom: "ausd".to_string(),
-                        },
-                    }),
-                ],
+                path: vec![
+                    Operation::AmmSwap(AmmSwapOp {
+                        pool_address: POOL_A_ADDR.to_string(),
+                        offer_asset_info: AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        ask_asset_info: AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        min_out: None,
+                    }),
+                    Operation::AmmSwap(AmmSwapOp {
+                        pool_address: POOL_B_ADDR.to_string(),
+                        offer_asset_info: AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        ask_asset_info: AssetInfo::NativeToken {
+                            denom: "ausd".to_string(),
+                        },
+                        min_out: None,
+                    }),
+                ],
+            }],
+        }];
+
+        let result_binary = simulate_route(
+            deps.as_ref(),
+            mock_env(),
+            stages,
+            Coin::new(1000u128, "inj"),
+            false,
+        )
+        .unwrap();
+        let result: SimulateRouteResponse = from_json(&result_binary).unwrap();
+        assert_eq!(result.output_amount, Uint128::new(5000));
+    }
+
+    #[test]
+    fn test_simulate_route_from_cw20_converts_through_the_adapter() {
+        const ADAPTER_ADDR: &str = "inj1adapter";
+        const SHROOM_ADDR: &str = "inj1shroom";
+        let native_shroom = format!("factory/{ADAPTER_ADDR}/{SHROOM_ADDR}");
+
+        let mut querier = MockQuerier::new(&[]);
+        let expected_offer = native_shroom.clone();
+        querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { contract_addr, msg } => {
+                assert_eq!(contract_addr, POOL_A_ADDR);
+                let amm::QueryMsg::Simulation { offer_asset } = from_json(msg).unwrap();
+                // The CW20 offer reaches the pool in the native form its split asks for.
+                assert_eq!(
+                    offer_asset.info,
+                    AssetInfo::NativeToken {
+                        denom: expected_offer.clone(),
+                    }
+                );
+                assert_eq!(offer_asset.amount, Uint128::new(1000));
+                let response = amm::SimulationResponse {
+                    return_amount: Uint128::new(50),
+                    ..Default::default()
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+        let mut deps = mock_dependencies();
+        deps.querier = querier;
+        crate::state::CONFIG
+            .save(
+                deps.as_mut().storage,
+                &Config {
+                    admin: Addr::unchecked("admin"),
+                    cw20_adapter_address: Addr::unchecked(ADAPTER_ADDR),
+                    fee_collectors: vec![],
+                },
+            )
+            .unwrap();
+
+        let stages = vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: POOL_A_ADDR.to_string(),
+                    offer_asset_info: AssetInfo::NativeToken {
+                        denom: native_shroom,
+                    },
+                    ask_asset_info: AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
             }],
         }];
 
-        let result_binary = simulate_route(
+        let result_binary = query(
             deps.as_ref(),
             mock_env(),
-            stages,
-            Coin::new(1000u128, "inj"),
+            QueryMsg::SimulateRouteFromAsset {
+                stages,
+                amount_in: amm::Asset {
+                    info: AssetInfo::Token {
+                        contract_addr: SHROOM_ADDR.to_string(),
+                    },
+                    amount: Uint128::new(1000),
+                },
+            },
         )
         .unwrap();
         let result: SimulateRouteResponse = from_json(&result_binary).unwrap();
-        assert_eq!(result.output_amount, Uint128::new(5000));
+        assert_eq!(result.output_amount, Uint128::new(50));
+    }
+
+    #[test]
+    fn test_estimate_route_complexity_counts_conversions() {
+        let native_shroom = || AssetInfo::NativeToken {
+            denom: "factory/adapter/shroom".to_string(),
+        };
+        let cw20 = |addr: &str| AssetInfo::Token {
+            contract_addr: addr.to_string(),
+        };
+        let op = |offer: AssetInfo, ask: AssetInfo| {
+            Operation::AmmSwap(AmmSwapOp {
+                pool_address: POOL_A_ADDR.to_string(),
+                offer_asset_info: offer,
+                ask_asset_info: ask,
+                min_out: None,
+            })
+        };
+        let inj = || AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        };
+
+        // Stage 1 pays out both forms of SHROOM; stage 2 takes only the CW20, so one conversion
+        // runs between the stages.
+        let stages = vec![
+            Stage {
+                splits: vec![
+                    Split {
+                        percent: 50,
+                        path: vec![op(inj(), native_shroom())],
+                    },
+                    Split {
+                        percent: 50,
+                        path: vec![op(inj(), cw20("shroom"))],
+                    },
+                ],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![op(cw20("shroom"), cw20("sai"))],
+                }],
+            },
+        ];
+        let deps = mock_dependencies();
+        let result: RouteComplexityResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::EstimateRouteComplexity { stages },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            RouteComplexityResponse {
+                submessages: 4,
+                conversions: 1,
+                // 7 to start and complete, 2 per swap, 1 per conversion.
+                storage_writes: 14,
+                estimated_gas: GAS_PER_ROUTE
+                    + 3 * GAS_PER_SWAP
+                    + GAS_PER_CONVERSION
+                    + 14 * GAS_PER_STORAGE_WRITE,
+            }
+        );
     }
 
     #[test]
@@ -426,6 +1337,7 @@ mod tests {
                             ask_asset_info: AssetInfo::NativeToken {
                                 denom: "usdt".to_string(),
                             },
+                            min_out: None,
                         })],
                     },
                     Split {
@@ -438,6 +1350,7 @@ mod tests {
                             ask_asset_info: AssetInfo::NativeToken {
                                 denom: "ausd".to_string(),
                             },
+                            min_out: None,
                         })],
                     },
                 ],
@@ -455,6 +1368,7 @@ mod tests {
                             ask_asset_info: AssetInfo::NativeToken {
                                 denom: "shroom".to_string(),
                             },
+                            min_out: None,
                         })],
                     },
                     Split {
@@ -467,6 +1381,7 @@ mod tests {
                             ask_asset_info: AssetInfo::NativeToken {
                                 denom: "shroom".to_string(),
                             },
+                            min_out: None,
                         })],
                     },
                 ],
@@ -478,6 +1393,7 @@ mod tests {
             mock_env(),
             stages,
             Coin::new(1000u128, "inj"),
+            false,
         )
         .unwrap();
         let result: SimulateRouteResponse = from_json(&result_binary).unwrap();
@@ -485,6 +1401,315 @@ mod tests {
         assert_eq!(result.output_amount, Uint128::new(5000 + 8000));
     }
 
+    #[test]
+    fn test_lenient_simulation_skips_failing_splits() {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { contract_addr, .. } if contract_addr == POOL_A_ADDR => {
+                let mock_response = amm::SimulationResponse {
+                    return_amount: Uint128::new(10000),
+                    ..Default::default()
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&mock_response).unwrap()))
+            }
+            WasmQuery::Smart { .. } => {
+                SystemResult::Ok(ContractResult::Err("pool is halted".to_string()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+
+        let split_for = |pool: &str| Split {
+            percent: 50,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: pool.to_string(),
+                offer_asset_info: AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        };
+        let stages = vec![Stage {
+            splits: vec![split_for(POOL_A_ADDR), split_for(POOL_B_ADDR)],
+        }];
+
+        let err = simulate_route(
+            deps.as_ref(),
+            mock_env(),
+            stages.clone(),
+            Coin::new(1000u128, "inj"),
+            false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("pool is halted"));
+
+        let result: SimulateRouteResponse = from_json(
+            simulate_route(
+                deps.as_ref(),
+                mock_env(),
+                stages,
+                Coin::new(1000u128, "inj"),
+                true,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(result.output_amount, Uint128::new(10000));
+        assert_eq!(result.failed_splits.len(), 1);
+        assert_eq!(result.failed_splits[0].stage_index, 0);
+        assert_eq!(result.failed_splits[0].split_index, 1);
+        assert!(result.failed_splits[0].error.contains("pool is halted"));
+    }
+
+    #[test]
+    fn test_simulation_reports_each_splits_allocation() {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(|q: &WasmQuery| match q {
+            WasmQuery::Smart { .. } => {
+                let mock_response = amm::SimulationResponse {
+                    return_amount: Uint128::new(100),
+                    ..Default::default()
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&mock_response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+
+        let split = |percent| Split {
+            percent,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: POOL_A_ADDR.to_string(),
+                offer_asset_info: AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        };
+        let stages = vec![Stage {
+            splits: vec![split(66), split(33), split(1)],
+        }];
+
+        let result: SimulateRouteResponse = from_json(
+            simulate_route(
+                deps.as_ref(),
+                mock_env(),
+                stages,
+                Coin::new(10u128, "inj"),
+                false,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // Shares of 6.6, 3.3 and 0.1: the unit rounding leaves over goes to the first split, not
+        // the last.
+        let allocated: Vec<(u64, u128)> = result
+            .allocations
+            .iter()
+            .map(|allocation| (allocation.split_index, allocation.input.amount.u128()))
+            .collect();
+        assert_eq!(allocated, vec![(0, 7), (1, 3), (2, 0)]);
+        assert!(result.allocations.iter().all(|a| a.stage_index == 0));
+    }
+
+    #[test]
+    fn test_simulation_queries_a_pool_once_per_offer() {
+        let queries = Rc::new(Cell::new(0u32));
+        let counted = queries.clone();
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { contract_addr, .. } if contract_addr == POOL_A_ADDR => {
+                counted.set(counted.get() + 1);
+                let mock_response = amm::SimulationResponse {
+                    return_amount: Uint128::new(4000),
+                    ..Default::default()
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&mock_response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+
+        // Both splits offer the pool the same half of the input.
+        let split = Split {
+            percent: 50,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: POOL_A_ADDR.to_string(),
+                offer_asset_info: AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        };
+        let stages = vec![Stage {
+            splits: vec![split.clone(), split],
+        }];
+
+        let result: SimulateRouteResponse = from_json(
+            simulate_route(
+                deps.as_ref(),
+                mock_env(),
+                stages,
+                Coin::new(1000u128, "inj"),
+                false,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(result.output_amount, Uint128::new(8000));
+        assert_eq!(queries.get(), 1);
+    }
+
+    #[test]
+    fn test_simulate_operation_takes_the_pool_fee() {
+        let mut deps = mock_dependencies();
+        deps.api = MockApi::default().with_prefix("inj");
+        let pool = deps.api.addr_make("pool");
+        deps.querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { .. } => {
+                let mock_response = amm::SimulationResponse {
+                    return_amount: Uint128::new(10000),
+                    ..Default::default()
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&mock_response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+        FEE_MAP
+            .save(deps.as_mut().storage, &pool, &Decimal::percent(1))
+            .unwrap();
+
+        let inj = AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        };
+        let usdt = AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        };
+        let operation = Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: inj.clone(),
+            ask_asset_info: usdt.clone(),
+            min_out: None,
+        });
+        let simulate = |offer_info: &AssetInfo| {
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::SimulateOperation {
+                    operation: operation.clone(),
+                    offer_asset: amm::Asset {
+                        info: offer_info.clone(),
+                        amount: Uint128::new(1000),
+                    },
+                },
+            )
+        };
+
+        let res: SimulateOperationResponse = from_json(simulate(&inj).unwrap()).unwrap();
+        assert_eq!(
+            res.return_asset,
+            amm::Asset {
+                info: usdt.clone(),
+                amount: Uint128::new(9900),
+            }
+        );
+        assert_eq!(res.fee_amount, Uint128::new(100));
+
+        // The offer has to be what the operation takes.
+        let err = simulate(&usdt).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Offer asset usdt does not match the operation's offer asset inj"));
+    }
+
+    #[test]
+    fn test_simulate_route_with_fee_overrides() {
+        let mut deps = mock_dependencies();
+        deps.api = MockApi::default().with_prefix("inj");
+
+        let pool_a_addr = deps.api.addr_make("pool_a");
+        let pool_b_addr = deps.api.addr_make("pool_b");
+
+        // Both pools return 10000 for any input.
+        deps.querier.update_wasm(move |q: &WasmQuery| match q {
+            WasmQuery::Smart { .. } => {
+                let mock_response = amm::SimulationResponse {
+                    return_amount: Uint128::new(10000),
+                    ..Default::default()
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&mock_response).unwrap()))
+            }
+            _ => panic!("Unsupported query type"),
+        });
+
+        // Stored fees: 1% on pool A, 2% on pool B.
+        FEE_MAP
+            .save(
+                deps.as_mut().storage,
+                &pool_a_addr,
+                &Decimal::from_str("0.01").unwrap(),
+            )
+            .unwrap();
+        FEE_MAP
+            .save(
+                deps.as_mut().storage,
+                &pool_b_addr,
+                &Decimal::from_str("0.02").unwrap(),
+            )
+            .unwrap();
+
+        let split_for = |pool: &cosmwasm_std::Addr| Split {
+            percent: 50,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: pool.to_string(),
+                offer_asset_info: AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        };
+        let stages = vec![Stage {
+            splits: vec![split_for(&pool_a_addr), split_for(&pool_b_addr)],
+        }];
+
+        // Override pool A to 5%; pool B keeps its stored 2%.
+        let msg = QueryMsg::SimulateRouteWithFees {
+            stages: stages.clone(),
+            amount_in: Coin::new(1000u128, "inj"),
+            fee_overrides: vec![FeeInfo {
+                pool_address: pool_a_addr.to_string(),
+                fee_percent: Decimal::from_str("0.05").unwrap(),
+            }],
+        };
+        let res: SimulateRouteResponse =
+            from_json(query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.output_amount, Uint128::new(9500 + 9800));
+
+        // Plain simulation ignores fees and leaves the stored schedule untouched.
+        let msg = QueryMsg::SimulateRoute {
+            stages,
+            amount_in: Coin::new(1000u128, "inj"),
+            lenient: false,
+        };
+        let res: SimulateRouteResponse =
+            from_json(query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.output_amount, Uint128::new(20000));
+        assert_eq!(
+            FEE_MAP.load(deps.as_ref().storage, &pool_a_addr).unwrap(),
+            Decimal::from_str("0.01").unwrap()
+        );
+    }
+
     #[test]
     fn test_query_fee_for_pool() {
         // --- Setup using the proven litmus test pattern ---
@@ -518,6 +1743,54 @@ mod tests {
         assert_eq!(res.fee, None);
     }
 
+    #[test]
+    fn test_query_fees_for_pools() {
+        let mut deps = mock_dependencies();
+        deps.api = MockApi::default().with_prefix("inj");
+
+        let pool_a_addr = deps.api.addr_make("pool_a");
+        let pool_b_addr = deps.api.addr_make("pool_b");
+        let pool_c_addr = deps.api.addr_make("pool_c_no_fee");
+        FEE_MAP
+            .save(
+                deps.as_mut().storage,
+                &pool_a_addr,
+                &Decimal::from_str("0.003").unwrap(),
+            )
+            .unwrap();
+        FEE_MAP
+            .save(
+                deps.as_mut().storage,
+                &pool_b_addr,
+                &Decimal::from_str("0.015").unwrap(),
+            )
+            .unwrap();
+
+        let msg = QueryMsg::FeesForPools {
+            pool_addresses: vec![
+                pool_b_addr.to_string(),
+                pool_c_addr.to_string(),
+                pool_a_addr.to_string(),
+            ],
+        };
+        let res: FeesForPoolsResponse =
+            from_json(query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(
+            res.fees,
+            vec![
+                Some(Decimal::from_str("0.015").unwrap()),
+                None,
+                Some(Decimal::from_str("0.003").unwrap()),
+            ]
+        );
+
+        // An invalid address fails the whole batch.
+        let msg = QueryMsg::FeesForPools {
+            pool_addresses: vec![pool_a_addr.to_string(), "not-an-address".to_string()],
+        };
+        assert!(query(deps.as_ref(), mock_env(), msg).is_err());
+    }
+
     #[test]
     fn test_query_all_fees_with_pagination() {
         // --- Setup using the proven litmus test pattern ---
@@ -573,4 +1846,252 @@ mod tests {
         let res: AllFeesResponse = from_json(&res_binary).unwrap();
         assert_eq!(res.fees.len(), 0);
     }
+
+    #[test]
+    fn test_query_receipt_respects_retention() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+
+        let receipt = crate::state::Receipt {
+            input: amm::Asset {
+                info: AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                amount: Uint128::new(1000),
+            },
+            output: amm::Asset {
+                info: AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                amount: Uint128::new(10000),
+            },
+            additional_outputs: vec![],
+            fees: vec![],
+            stages_executed: 1,
+            conversions: 0,
+            completed_at_height: env.block.height,
+            completed_at_time: env.block.time.seconds(),
+            expires_at_height: env.block.height + 10,
+            executions: vec![],
+            fee_schedule: vec![],
+        };
+        RECEIPTS.save(deps.as_mut().storage, 7, &receipt).unwrap();
+        let trace = vec![crate::state::TraceEntry {
+            height: env.block.height,
+            before: crate::state::TraceState {
+                awaiting: crate::state::Awaiting::Swaps,
+                stage_index: 0,
+            },
+            after: None,
+            executions: vec![],
+        }];
+        TRACES.save(deps.as_mut().storage, 7, &trace).unwrap();
+
+        // Within the retention window the receipt is returned, and so is the route's trace.
+        let res_binary = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Receipt { route_id: 7 },
+        )
+        .unwrap();
+        let res: ReceiptResponse = from_json(&res_binary).unwrap();
+        assert_eq!(res.receipt, Some(receipt));
+        let res: TraceResponse =
+            from_json(query(deps.as_ref(), env.clone(), QueryMsg::Trace { route_id: 7 }).unwrap())
+                .unwrap();
+        assert_eq!(res.entries, trace);
+
+        // Unknown route ids return nothing.
+        let res_binary = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Receipt { route_id: 8 },
+        )
+        .unwrap();
+        let res: ReceiptResponse = from_json(&res_binary).unwrap();
+        assert_eq!(res.receipt, None);
+
+        // Past the retention window the receipt is treated as expired.
+        let mut later_env = env;
+        later_env.block.height += 11;
+        let res_binary = query(
+            deps.as_ref(),
+            later_env.clone(),
+            QueryMsg::Receipt { route_id: 7 },
+        )
+        .unwrap();
+        let res: ReceiptResponse = from_json(&res_binary).unwrap();
+        assert_eq!(res.receipt, None);
+        let res: TraceResponse =
+            from_json(query(deps.as_ref(), later_env, QueryMsg::Trace { route_id: 7 }).unwrap())
+                .unwrap();
+        assert!(res.entries.is_empty());
+    }
+
+    #[test]
+    fn test_route_execution_totals_stages_and_pools() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let asset = |denom: &str, amount: u128| amm::Asset {
+            info: AssetInfo::NativeToken {
+                denom: denom.to_string(),
+            },
+            amount: Uint128::new(amount),
+        };
+        let execution =
+            |split_index, op_index, contract: &Addr, offer, output, fee| OperationExecution {
+                stage_index: 0,
+                split_index,
+                op_index,
+                contract: contract.clone(),
+                offer,
+                output,
+                fee: Uint128::new(fee),
+                height: env.block.height,
+                time: env.block.time.seconds(),
+            };
+
+        // INJ -> USDT -> ATOM through pools A and B, and INJ -> ATOM through pool B.
+        let receipt = Receipt {
+            input: asset("inj", 1000),
+            output: asset("atom", 199),
+            additional_outputs: vec![],
+            fees: vec![asset("usdt", 10)],
+            stages_executed: 1,
+            conversions: 0,
+            completed_at_height: env.block.height,
+            completed_at_time: env.block.time.seconds(),
+            expires_at_height: env.block.height + 10,
+            executions: vec![
+                execution(0, 0, &pool_a, asset("inj", 500), asset("usdt", 1000), 10),
+                execution(1, 0, &pool_b, asset("inj", 500), asset("atom", 100), 0),
+                execution(0, 1, &pool_b, asset("usdt", 990), asset("atom", 99), 0),
+            ],
+            fee_schedule: vec![],
+        };
+        RECEIPTS.save(deps.as_mut().storage, 7, &receipt).unwrap();
+
+        let res: RouteExecutionResponse =
+            from_json(query(deps.as_ref(), env, QueryMsg::RouteExecution { route_id: 7 }).unwrap())
+                .unwrap();
+        let execution = res.execution.unwrap();
+
+        assert_eq!(
+            execution.operations[0].price,
+            Some(Decimal::from_str("1.98").unwrap())
+        );
+        assert_eq!(
+            execution.stages[0].totals,
+            ExecutionTotals {
+                offered: vec![asset("inj", 1000)],
+                received: vec![asset("atom", 199)],
+                fees: vec![asset("usdt", 10)],
+                price: Some(Decimal::from_str("0.199").unwrap()),
+            }
+        );
+        // Pool B was offered two assets, so it has no single price.
+        assert_eq!(execution.pools[1].contract, pool_b);
+        assert_eq!(
+            execution.pools[1].totals.offered,
+            vec![asset("inj", 500), asset("usdt", 990)]
+        );
+        assert_eq!(execution.pools[1].totals.price, None);
+    }
+
+    #[test]
+    fn test_admin_log_keeps_only_the_latest_entries() {
+        let mut deps = mock_dependencies();
+        let admin = deps.api.addr_make("admin");
+        let total = crate::state::ADMIN_LOG_CAPACITY + 3;
+        for height in 0..total {
+            crate::state::record_admin_action(
+                deps.as_mut().storage,
+                height,
+                admin.clone(),
+                "set_fee",
+                &[cosmwasm_std::attr("action", "set_fee")],
+            )
+            .unwrap();
+        }
+
+        let admin_log = |start_after: Option<u64>| -> AdminLogResponse {
+            let msg = QueryMsg::AdminLog {
+                start_after,
+                limit: Some(MAX_LIMIT),
+            };
+            from_json(query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap()
+        };
+
+        // The three oldest entries were dropped to make room.
+        let entries = admin_log(None).entries;
+        assert_eq!(entries[0].id, 3);
+        assert_eq!(entries[0].height, 3);
+        assert_eq!(entries[0].sender, admin);
+        assert!(entries[0].attributes.is_empty());
+
+        let last_page = admin_log(Some(total - 3)).entries;
+        let ids: Vec<u64> = last_page.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![total - 2, total - 1]);
+    }
+
+    #[test]
+    fn test_capabilities_follow_the_deployment_config() {
+        let mut deps = mock_dependencies();
+        let capabilities = |deps: Deps| -> CapabilitiesResponse {
+            from_json(query(deps, mock_env(), QueryMsg::Capabilities {}).unwrap()).unwrap()
+        };
+
+        let defaults = capabilities(deps.as_ref());
+        assert!(defaults
+            .operations
+            .contains(&"provide_liquidity".to_string()));
+        assert_eq!(defaults.fee_modes, vec!["fee_cap", "claimable"]);
+        assert!(!defaults
+            .entry_points
+            .contains(&"execute_template".to_string()));
+        assert!(!defaults.entry_points.contains(&"register_pool".to_string()));
+
+        let pool = deps.api.addr_make("pool");
+        FEE_MAP
+            .save(deps.as_mut().storage, &pool, &Decimal::permille(3))
+            .unwrap();
+        ROUTE_TEMPLATES
+            .save(deps.as_mut().storage, "inj-usdt", &vec![])
+            .unwrap();
+        let integrator = deps.api.addr_make("wallet");
+        INTEGRATORS
+            .save(
+                deps.as_mut().storage,
+                &integrator,
+                &Integrator {
+                    address: integrator.clone(),
+                    fee_bps: 10,
+                    collector: integrator.clone(),
+                },
+            )
+            .unwrap();
+        POOL_REGISTRATION_TERMS
+            .save(
+                deps.as_mut().storage,
+                &PoolRegistrationTerms {
+                    fee: Coin::new(0u128, "inj"),
+                    canary_amount: Uint128::new(1),
+                    code_ids: vec![1],
+                },
+            )
+            .unwrap();
+        let configured = capabilities(deps.as_ref());
+        assert_eq!(
+            configured.fee_modes,
+            vec!["pool_fee", "fee_cap", "integrator_fee", "claimable"]
+        );
+        assert!(configured
+            .entry_points
+            .contains(&"receive.execute_template".to_string()));
+        assert!(configured
+            .entry_points
+            .contains(&"register_pool".to_string()));
+    }
 }
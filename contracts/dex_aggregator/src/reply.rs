@@ -1,84 +1,327 @@
 use crate::error::ContractError;
-use crate::execute::create_swap_cosmos_msg;
-use crate::msg::{amm, cw20_adapter, Operation, PlannedSwap, Stage, StagePlan};
+use crate::execute::{
+    create_amm_swap_msg, create_provide_liquidity_msgs, create_router_swap_msg,
+    create_swap_cosmos_msg, query_asset_balance, router_batch, unswapped_remainder,
+};
+use crate::msg::{
+    amm, cw20_adapter, wrapped_inj, Cw20HookMsg, ExecuteMsg, Operation, PairType, PlannedSwap,
+    PoolHealthPolicy, PostAction, ProvideLiquidityOp, RouteResult, Stage,
+};
+use crate::oracle::usd_value;
+use crate::planner::{plan_next_stage, plan_withdrawal_stage, select_normalization_target};
+use crate::routing::{
+    adapter_counterpart, deduct_fee, get_operation_address, orderbook_settlement_denom,
+    simulate_single_operation, zap_pool_reserve, zap_swap_amount,
+};
 use crate::state::{
-    Awaiting, Config, ExecutionState, PendingPathOp, RoutePlan, CONFIG, EXECUTION_STATES, FEE_MAP,
-    ROUTE_PLANS,
+    accrue_dust, add_asset_amount, asset_id, is_dust, load_parameters, load_pool_config,
+    record_pool_fee, remove_route_plan, route_fee, Awaiting, Config, ExecutionState, FeeCollector,
+    FeeDelivery, LoadedPlan, OperationExecution, PendingOffer, PendingPathOp, PendingZap,
+    PoolEventFormat, Receipt, Recipient, ReferenceRate, RoutePlan, TraceEntry, TraceState, CONFIG,
+    CONVERSION_RESULTS, DISABLED_POOLS, EXECUTION_STATES, FEE_DELIVERY_REPLY_ID, INJ_DENOM,
+    NATIVE_CONVERTERS, POOL_REFERENCE_RATES, RECEIPTS, RECEIPT_RETENTION_BLOCKS,
+    REFERENCE_RATE_CANARY_SHARE, REFERENCE_RATE_MAX_AGE_SECONDS, REFERENCE_RATE_WEIGHT,
+    SWEEP_POOLS, TRACES, UNCLAIMED_FEES, WRAPPED_INJ,
 };
 use cosmwasm_std::{
-    to_json_binary, Addr, Coin, CosmosMsg, DepsMut, Env, Reply, Response, StdError, SubMsg,
-    Uint128, WasmMsg,
+    from_json, to_json_binary, Addr, AnyMsg, Binary, Coin, CosmosMsg, Decimal, Deps, DepsMut, Env,
+    Event, Reply, Response, StdError, StdResult, Storage, SubMsg, Uint128, WasmMsg,
 };
 use cw20::Cw20ExecuteMsg;
 use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQueryWrapper};
+use std::str::FromStr;
 
 pub fn handle_reply(
-    deps: DepsMut<InjectiveQueryWrapper>,
+    mut deps: DepsMut<InjectiveQueryWrapper>,
     env: Env,
     msg: Reply,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if msg.id == FEE_DELIVERY_REPLY_ID {
+        return handle_fee_delivery_failure(deps, msg);
+    }
     let reply_id = msg.id;
     let mut exec_state = EXECUTION_STATES.load(deps.storage, reply_id)?;
-    let plan = ROUTE_PLANS.load(deps.storage, reply_id)?;
+    let plan = LoadedPlan::load(deps.storage, reply_id)?;
+    record_reply(&mut exec_state);
+    let inflows = measure_inflows(deps.as_ref(), &env, &mut exec_state)?;
+    if !load_parameters(deps.storage)?.tracing_enabled {
+        return dispatch_reply(deps, env, msg, &mut exec_state, &plan, &inflows);
+    }
+
+    let height = env.block.height;
+    let before = trace_state(&exec_state);
+    let executions_before = exec_state.executions.len();
+    let response = dispatch_reply(deps.branch(), env, msg, &mut exec_state, &plan, &inflows)?;
+    let entry = TraceEntry {
+        height,
+        before,
+        after: EXECUTION_STATES
+            .may_load(deps.storage, reply_id)?
+            .map(|exec_state| trace_state(&exec_state)),
+        executions: exec_state.executions[executions_before..].to_vec(),
+    };
+    let mut trace = TRACES.may_load(deps.storage, reply_id)?.unwrap_or_default();
+    trace.push(entry);
+    TRACES.save(deps.storage, reply_id, &trace)?;
+    Ok(response)
+}
+
+/// Where `exec_state` stands, as recorded in a trace.
+fn trace_state(exec_state: &ExecutionState) -> TraceState {
+    TraceState {
+        awaiting: exec_state.awaiting.clone(),
+        stage_index: exec_state.current_stage_index,
+    }
+}
 
+/// Hands a route's reply to the handler of what the route is awaiting.
+fn dispatch_reply(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     match exec_state.awaiting {
-        Awaiting::Swaps => handle_swap_reply(deps, env, msg, &mut exec_state, &plan),
-        Awaiting::Conversions => handle_conversion_reply(deps, env, msg, &mut exec_state, &plan),
+        Awaiting::Swaps => handle_swap_reply(deps, env, msg, exec_state, plan, inflows),
+        Awaiting::Conversions => handle_conversion_reply(deps, env, msg, exec_state, plan, inflows),
         Awaiting::FinalConversions => {
-            handle_final_conversion_reply(deps, env, msg, &mut exec_state, &plan)
+            handle_final_conversion_reply(deps, env, msg, exec_state, plan, inflows)
         }
         Awaiting::PathConversion => {
-            handle_path_conversion_reply(deps, env, msg, &mut exec_state, &plan)
+            handle_path_conversion_reply(deps, env, msg, exec_state, plan, inflows)
+        }
+        Awaiting::OutputConversion => {
+            handle_output_conversion_reply(deps, env, msg, exec_state, plan, inflows)
+        }
+        Awaiting::LiquidityProvision => {
+            handle_liquidity_reply(deps, env, msg, exec_state, plan, inflows)
+        }
+        Awaiting::Withdrawal => handle_withdrawal_reply(deps, env, msg, exec_state, plan),
+        Awaiting::Sweep => handle_sweep_reply(deps, env, msg, exec_state, plan, inflows),
+        Awaiting::Continuation => Err(ContractError::Std(StdError::generic_err(
+            "Unexpected reply for a route awaiting continuation",
+        ))),
+    }
+}
+
+/// Keeps a fee the collector could not be sent, such as when its contract rejects the funds, for
+/// it to claim with `ClaimFees` instead of failing the route that took it.
+fn handle_fee_delivery_failure(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    msg: Reply,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let delivery: FeeDelivery = from_json(&msg.payload)?;
+    let error = msg.result.into_result().err().unwrap_or_default();
+    let mut unclaimed = UNCLAIMED_FEES
+        .may_load(deps.storage, &delivery.collector)?
+        .unwrap_or_default();
+    add_asset_amount(&mut unclaimed, &delivery.asset.info, delivery.asset.amount);
+    UNCLAIMED_FEES.save(deps.storage, &delivery.collector, &unclaimed)?;
+
+    // The fee was taken out of the route's snapshot as sent, but it is still here and must not
+    // count as the output of the route's next swap.
+    if let Some(route_id) = delivery.route_id {
+        if let Some(mut exec_state) = EXECUTION_STATES.may_load(deps.storage, route_id)? {
+            if let Some(snapshot) = exec_state
+                .balance_snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.info == delivery.asset.info)
+            {
+                snapshot.amount += delivery.asset.amount;
+                EXECUTION_STATES.save(deps.storage, route_id, &exec_state)?;
+            }
         }
     }
+
+    Ok(Response::new()
+        .add_attribute("action", "fee_delivery_failed")
+        .add_attribute("collector", delivery.collector)
+        .add_attribute("asset", asset_id(&delivery.asset.info))
+        .add_attribute("amount", delivery.asset.amount.to_string())
+        .add_attribute("error", error))
 }
 
 pub(crate) fn proceed_to_next_step(
     deps: &mut DepsMut<InjectiveQueryWrapper>,
     env: Env,
     exec_state: &mut ExecutionState,
-    plan: &RoutePlan,
+    plan: &LoadedPlan,
     master_reply_id: u64,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    if exec_state.current_stage_index as usize >= plan.stages.len() {
-        return handle_final_stage(deps, env, master_reply_id, exec_state, plan);
+    // A zap whose swap paid out nothing never provides its retained half, which is refunded.
+    for zap in std::mem::take(&mut exec_state.pending_zaps) {
+        add_asset_amount(
+            &mut exec_state.skipped_assets,
+            &zap.retained.info,
+            zap.retained.amount,
+        );
     }
 
-    let next_stage_to_execute = plan
-        .stages
-        .get(exec_state.current_stage_index as usize)
-        .unwrap();
+    // Every operation of the stage has replied; an offer left over paid out nothing to record.
+    exec_state.pending_offers.clear();
+
+    let Some(next_stage_to_execute) = plan.stage(deps.storage, exec_state.current_stage_index)?
+    else {
+        return handle_final_stage(deps, env, master_reply_id, exec_state, plan);
+    };
+
+    if let Some(max_stages_per_tx) = plan.max_stages_per_tx {
+        let stages_this_tx = exec_state.current_stage_index - exec_state.tx_start_stage_index;
+        if stages_this_tx >= max_stages_per_tx {
+            // Checkpoint: the intermediate balances stay in `accumulated_assets` under the
+            // route id until the initiator sends `ContinueRoute`.
+            exec_state.awaiting = Awaiting::Continuation;
+            exec_state.replies_expected = 0;
+            exec_state.pending_replies_by_depth.clear();
+            exec_state.last_checkpoint_time = env.block.time.seconds();
+            EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+
+            return Ok(Response::new()
+                .add_attribute("action", "route_checkpointed")
+                .add_attribute("route_id", master_reply_id.to_string())
+                .add_attribute(
+                    "next_stage_index",
+                    exec_state.current_stage_index.to_string(),
+                ));
+        }
+    }
 
     let stage_plan = plan_next_stage(&exec_state.accumulated_assets, next_stage_to_execute)?;
     exec_state.accumulated_assets.clear();
 
-    if stage_plan.conversions_needed.is_empty() {
-        execute_planned_swaps(
+    let Some(conversion) = stage_plan.conversion else {
+        return execute_planned_swaps(
             deps,
             env,
             exec_state,
             plan,
             master_reply_id,
             stage_plan.swaps_to_execute,
-        )
-    } else {
-        let config = CONFIG.load(deps.storage)?;
-        let mut conversion_submsgs = vec![];
-        for (asset_to_convert, _target_info) in &stage_plan.conversions_needed {
-            let msg = create_conversion_msg(asset_to_convert, &config, &env)?;
-            conversion_submsgs.push(SubMsg::reply_on_success(msg, master_reply_id));
-        }
+        );
+    };
 
-        exec_state.awaiting = Awaiting::Conversions;
-        exec_state.replies_expected = conversion_submsgs.len() as u64;
-        exec_state.pending_swaps = stage_plan.swaps_to_execute;
+    let msg = create_conversion_msg(
+        deps.storage,
+        &conversion.offer,
+        &conversion.target,
+        &env,
+        master_reply_id,
+    )?;
+    if is_balance_checked(exec_state, &conversion.target) {
+        add_asset_amount(
+            &mut exec_state.conversion_shortfalls,
+            &conversion.target,
+            conversion.offer.amount,
+        );
+    }
 
-        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+    exec_state.awaiting = Awaiting::Conversions;
+    exec_state.pending_conversion_target = Some(conversion.target.clone());
+    exec_state.replies_expected = 1;
+    record_dispatch(exec_state, 1);
+    exec_state.conversions_performed += 1;
+    exec_state.pending_swaps = stage_plan.swaps_to_execute;
 
-        Ok(Response::new()
-            .add_submessages(conversion_submsgs)
-            .add_attribute("action", "performing_minimal_conversions"))
+    EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(msg, master_reply_id))
+        .add_event(conversion_event(
+            master_reply_id,
+            exec_state.current_stage_index,
+            &conversion.offer,
+            &conversion.target,
+            ConversionReason::SurplusRebalance,
+        ))
+        .add_attribute("action", "performing_minimal_conversions"))
+}
+
+/// Starts a route seeded by a liquidity withdrawal: the offered LP tokens are sent to `pool`, and
+/// the contract's balances of `pool_assets` are recorded so the reply can measure what it paid out.
+pub(crate) fn dispatch_withdrawal(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: &Env,
+    exec_state: &mut ExecutionState,
+    plan: &RoutePlan,
+    route_id: u64,
+    pool: &str,
+    pool_assets: &[amm::AssetInfo],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let amm::AssetInfo::Token { contract_addr } = &plan.offer_asset.info else {
+        return Err(ContractError::Std(StdError::generic_err(
+            "Liquidity can only be withdrawn with CW20 LP tokens",
+        )));
+    };
+
+    exec_state.pending_withdrawal = pool_assets
+        .iter()
+        .map(|info| {
+            Ok(amm::Asset {
+                info: info.clone(),
+                amount: query_asset_balance(deps.as_ref(), env, info)?,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    let withdraw_msg = CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: contract_addr.clone(),
+        msg: to_json_binary(&Cw20ExecuteMsg::Send {
+            contract: pool.to_string(),
+            amount: plan.offer_asset.amount,
+            msg: to_json_binary(&amm::PairCw20HookMsg::WithdrawLiquidity {})?,
+        })?,
+        funds: vec![],
+    });
+
+    exec_state.awaiting = Awaiting::Withdrawal;
+    exec_state.replies_expected = 1;
+    record_dispatch(exec_state, 1);
+    EXECUTION_STATES.save(deps.storage, route_id, exec_state)?;
+
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(withdraw_msg, route_id))
+        .add_attribute("action", "withdrawing_liquidity")
+        .add_attribute("pool", pool))
+}
+
+/// Handles the reply of a route's liquidity withdrawal. What it paid out is measured from the
+/// contract's balances and seeds the first stage.
+fn handle_withdrawal_reply(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let route_id = msg.id;
+
+    let mut withdrawn = vec![];
+    for before in std::mem::take(&mut exec_state.pending_withdrawal) {
+        let balance = query_asset_balance(deps.as_ref(), &env, &before.info)?;
+        let amount = balance.saturating_sub(before.amount);
+        if !amount.is_zero() {
+            withdrawn.push(amm::Asset {
+                info: before.info,
+                amount,
+            });
+        }
+    }
+
+    let first_stage = plan
+        .stage(deps.storage, 0)?
+        .ok_or(ContractError::EmptyRoute {})?;
+    let (swaps, carried) = plan_withdrawal_stage(&withdrawn, first_stage)?;
+    exec_state.accumulated_assets = carried;
+    exec_state.replies_expected = 0;
+
+    let mut response = execute_planned_swaps(&mut deps, env, exec_state, plan, route_id, swaps)?;
+    for asset in &withdrawn {
+        response = response
+            .add_attribute("withdrawn_asset", asset_id(&asset.info))
+            .add_attribute("withdrawn_amount", asset.amount.to_string());
     }
+    Ok(response)
 }
 
 fn handle_swap_reply(
@@ -86,7 +329,8 @@ fn handle_swap_reply(
     env: Env,
     msg: Reply,
     exec_state: &mut ExecutionState,
-    plan: &RoutePlan,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     let master_reply_id = msg.id;
 
@@ -97,16 +341,81 @@ fn handle_swap_reply(
         .map_err(|e| ContractError::SubmessageResultError { error: e })?
         .events;
 
-    // Find the specific DEX event. This is our source of truth for the amount.
-    let swap_event_opt = events.iter().rev().find(|e| {
-        e.ty.starts_with("wasm")
-            && (e.attributes.iter().any(|a| a.key == "return_amount")
-                || e.attributes.iter().any(|a| a.key == "swap_final_amount"))
-    });
+    let current_stage = plan
+        .stage(deps.storage, exec_state.current_stage_index)?
+        .ok_or(ContractError::EmptyRoute {})?;
+
+    // A sub-aggregator reports through its own completion event. Its nested pool swaps also
+    // emit swap events, so it has to be recognised before looking for one.
+    let sub_aggregator_result = find_sub_aggregator_result(events, current_stage, master_reply_id)?;
+
+    // Find the specific DEX events. These are our source of truth for the amount. A pool
+    // registered with its own event format is looked for first, as its events may come from
+    // another contract.
+    let registered_event = find_registered_swap_event(deps.storage, events, current_stage)?;
+    let swap_events = match &registered_event {
+        Some((_, swap_events, _)) => swap_events.clone(),
+        None => trailing_swap_events(events, |e| protocol_amount_keys(e).is_some()),
+    };
+
+    // Venues that settle by bank send emit no swap event, so their payout is read from the
+    // transfer instead.
+    let hop_output = match sub_aggregator_result {
+        None if swap_events.is_empty() => find_bank_settlement(
+            deps.storage,
+            events,
+            current_stage,
+            &env.contract.address,
+            master_reply_id,
+        )?,
+        result => result,
+    };
 
     // If there is no swap event, we assume the output was zero.
     // In this case, the path cannot continue, so we treat it as "complete" with a zero value.
-    if swap_event_opt.is_none() {
+    let zero_output = match &hop_output {
+        Some(output) => output.amount.is_zero(),
+        None => swap_events.is_empty(),
+    };
+    if zero_output {
+        // The path ends here with nothing, which the minimums left on it and the caller's
+        // expectation for its split have to allow. A reply with no output to read is attributed
+        // through its submessage's payload.
+        let position = match &hop_output {
+            Some(output) => Some(output.position),
+            None => dispatched_position(&msg)?,
+        };
+        let stage_index = exec_state.current_stage_index;
+        match position {
+            Some(position) => {
+                check_empty_path_end(plan, current_stage, stage_index, position)?;
+                // Paying nothing for an offer is as far as a pool can get from its reference.
+                let (split_index, op_index) = position;
+                let pool = get_operation_address(&current_stage.splits[split_index].path[op_index]);
+                check_rate_deviation(
+                    deps.storage,
+                    &env,
+                    exec_state,
+                    position,
+                    &deps.api.addr_validate(pool)?,
+                    Uint128::zero(),
+                )?;
+            }
+            None => check_unattributed_empty_reply(deps.storage, plan, current_stage, stage_index)?,
+        }
+        if let Some(output) = &hop_output {
+            record_execution(
+                &env,
+                exec_state,
+                output.position,
+                &deps.api.addr_validate(&output.contract)?,
+                amm::Asset {
+                    info: output.asset_info.clone(),
+                    amount: output.amount,
+                },
+                Uint128::zero(),
+            );
+        }
         exec_state.replies_expected -= 1; // Mutate exec_state
 
         let response = if exec_state.replies_expected > 0 {
@@ -122,120 +431,504 @@ fn handle_swap_reply(
         return Ok(response);
     }
 
-    let swap_event = swap_event_opt.unwrap();
+    let (replying_pool_addr, (split_index, op_index), received_amount, received_asset_info) =
+        match hop_output {
+            Some(output) => (
+                deps.api.addr_validate(&output.contract)?,
+                output.position,
+                output.amount,
+                output.asset_info,
+            ),
+            None => {
+                let swap_event = swap_events[swap_events.len() - 1];
 
-    // Get the address of the contract that emitted this specific event.
-    let replying_pool_addr_str = swap_event
-        .attributes
-        .iter()
-        .find(|a| a.key == "_contract_address")
-        .map(|a| a.value.clone())
-        .ok_or_else(|| StdError::generic_err("Swap result event is missing '_contract_address'"))?;
-
-    let replying_pool_addr = deps.api.addr_validate(&replying_pool_addr_str)?;
+                let replying_pool_addr = match &registered_event {
+                    Some((pool, _, _)) => pool.clone(),
+                    None => {
+                        // Get the address of the contract that emitted this specific event.
+                        let replying_pool_addr_str = swap_event
+                            .attributes
+                            .iter()
+                            .find(|a| a.key == "_contract_address")
+                            .map(|a| a.value.clone())
+                            .ok_or_else(|| {
+                                StdError::generic_err(
+                                    "Swap result event is missing '_contract_address'",
+                                )
+                            })?;
+                        deps.api.addr_validate(&replying_pool_addr_str)?
+                    }
+                };
 
-    let current_stage = plan
-        .stages
-        .get(exec_state.current_stage_index as usize)
-        .ok_or(ContractError::EmptyRoute {})?;
+                let (position, replied_op) = find_replying_operation(
+                    current_stage,
+                    dispatched_position(&msg)?,
+                    replying_pool_addr.as_str(),
+                )
+                .ok_or_else(|| ContractError::UnexpectedReplyingContract {
+                    stage_index: exec_state.current_stage_index,
+                    contract: replying_pool_addr.to_string(),
+                })?;
 
-    // Now, find the operation that matches this validated address.
-    let mut replied_path_info = None;
-    'outer: for (split_idx, split) in current_stage.splits.iter().enumerate() {
-        for (op_idx, op) in split.path.iter().enumerate() {
-            if get_operation_address(op) == replying_pool_addr.as_str() {
-                replied_path_info = Some(((split_idx, op_idx), op));
-                break 'outer;
+                // Since we know the events exist, we can now safely parse the amount from the
+                // original message.
+                let received_amount = sum_swap_event_amounts(
+                    master_reply_id,
+                    &swap_events,
+                    registered_event.as_ref().map(|(_, _, format)| format),
+                )?;
+                let received_asset_info = get_swap_output(deps.storage, replied_op)?;
+                (
+                    replying_pool_addr,
+                    position,
+                    received_amount,
+                    received_asset_info,
+                )
             }
-        }
-    }
-
-    let ((split_index, op_index), replied_op) = replied_path_info.ok_or_else(|| {
-        StdError::generic_err(format!(
-            "Could not find a split/operation matching the replying contract: {}",
-            replying_pool_addr
-        ))
-    })?;
+        };
 
-    // Since we know the event exists, we can now safely parse the amount from the original message.
-    let received_amount = parse_amount_from_swap_reply(&msg)?;
-    let received_asset_info = get_operation_output(replied_op)?;
+    let received_amount = measured_amount(inflows, &received_asset_info, received_amount);
 
     let replied_path = &current_stage.splits[split_index].path;
+    if let Operation::ProvideLiquidity(lp_op) = &replied_path[op_index] {
+        // A zap is charged on the LP tokens it mints rather than on its swap.
+        return provide_zap_liquidity(
+            &mut deps,
+            &env,
+            exec_state,
+            plan,
+            master_reply_id,
+            split_index,
+            lp_op,
+            received_amount,
+        );
+    }
+    check_operation_min_out(
+        current_stage,
+        exec_state.current_stage_index,
+        (split_index, op_index),
+        received_amount,
+    )?;
+    check_output_sanity(
+        deps.storage,
+        exec_state,
+        (split_index, op_index),
+        &replying_pool_addr,
+        received_amount,
+    )?;
+    check_rate_deviation(
+        deps.storage,
+        &env,
+        exec_state,
+        (split_index, op_index),
+        &replying_pool_addr,
+        received_amount,
+    )?;
+
+    // The aggregator fee applies at every hop through a taxed pool, not only at the end of a
+    // path, so multi-hop paths cannot route around it.
+    let (fee, amount_after_fee) = take_aggregator_fee(
+        deps.storage,
+        exec_state,
+        plan,
+        master_reply_id,
+        &replying_pool_addr,
+        &received_asset_info,
+        received_amount,
+    )?;
+    record_execution(
+        &env,
+        exec_state,
+        (split_index, op_index),
+        &replying_pool_addr,
+        amm::Asset {
+            info: received_asset_info.clone(),
+            amount: received_amount,
+        },
+        fee.amount,
+    );
 
-    if let Some(next_op) = replied_path.get(op_index + 1) {
+    let response = if let Some(next_op) = replied_path.get(op_index + 1) {
         let required_input_info = get_operation_input(next_op)?;
         let offer_asset_for_next_op = amm::Asset {
-            info: received_asset_info,
-            amount: received_amount,
+            info: received_asset_info.clone(),
+            amount: amount_after_fee,
         };
         if offer_asset_for_next_op.info != required_input_info {
             exec_state.awaiting = Awaiting::PathConversion; // Mutate exec_state
+            exec_state.pending_conversion_target = Some(required_input_info.clone());
             exec_state.pending_path_op = Some(PendingPathOp {
                 // Mutate exec_state
+                split_index: split_index as u64,
+                op_index: (op_index + 1) as u64,
                 operation: next_op.clone(),
-                amount: received_amount,
+                amount: amount_after_fee,
             });
-            let config = CONFIG.load(deps.storage)?;
-            let conversion_msg = create_conversion_msg(&offer_asset_for_next_op, &config, &env)?;
+            exec_state.conversions_performed += 1;
+            let conversion_msg = create_conversion_msg(
+                deps.storage,
+                &offer_asset_for_next_op,
+                &required_input_info,
+                &env,
+                master_reply_id,
+            )?;
             let sub_msg = SubMsg::reply_on_success(conversion_msg, master_reply_id);
+            record_dispatch(exec_state, 1);
             EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?; // Save exec_state
-            return Ok(Response::new()
+            Response::new()
+                .add_submessage(sub_msg)
+                .add_event(conversion_event(
+                    master_reply_id,
+                    exec_state.current_stage_index,
+                    &offer_asset_for_next_op,
+                    &required_input_info,
+                    ConversionReason::PathMismatch,
+                ))
+                .add_attribute("action", "performing_path_conversion")
+        } else {
+            let next_msg = create_path_swap_msg(
+                &mut deps,
+                plan,
+                current_stage,
+                next_op,
+                &offer_asset_for_next_op.info,
+                offer_asset_for_next_op.amount,
+                &env,
+            )?;
+            record_dispatched_input(
+                deps.as_ref(),
+                exec_state,
+                next_op,
+                &offer_asset_for_next_op.info,
+                offer_asset_for_next_op.amount,
+                (split_index, op_index + 1),
+            )?;
+            let sub_msg = swap_submsg(next_msg, master_reply_id, (split_index, op_index + 1))?;
+            record_dispatch(exec_state, 1);
+            EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?; // Save exec_state
+            Response::new()
                 .add_submessage(sub_msg)
-                .add_attribute("action", "performing_path_conversion"));
+                .add_attribute("action", "proceeding_to_next_op_in_path")
+                .add_attribute("split_index", split_index.to_string())
+                .add_attribute("op_index", (op_index + 1).to_string())
         }
-        let next_msg = create_swap_cosmos_msg(
-            &mut deps,
-            next_op,
-            &offer_asset_for_next_op.info,
-            offer_asset_for_next_op.amount,
-            &env,
-        )?;
-        let sub_msg = SubMsg::reply_on_success(next_msg, master_reply_id);
-        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?; // Save exec_state
-        Ok(Response::new()
-            .add_submessage(sub_msg)
-            .add_attribute("action", "proceeding_to_next_op_in_path")
+    } else if matches!(replied_path[op_index], Operation::OrderbookSwap(_))
+        && received_asset_info != get_operation_output(&replied_path[op_index])?
+    {
+        // An orderbook swap into a CW20 settled in its adapter denom, converted before the
+        // split's output is accumulated.
+        exec_state.awaiting = Awaiting::OutputConversion;
+        exec_state.pending_output_split = Some(split_index as u64);
+        exec_state.conversions_performed += 1;
+        let settled = amm::Asset {
+            info: received_asset_info.clone(),
+            amount: amount_after_fee,
+        };
+        let output_info = get_operation_output(&replied_path[op_index])?;
+        exec_state.pending_conversion_target = Some(output_info.clone());
+        let conversion_msg =
+            create_conversion_msg(deps.storage, &settled, &output_info, &env, master_reply_id)?;
+        record_dispatch(exec_state, 1);
+        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+        Response::new()
+            .add_submessage(SubMsg::reply_on_success(conversion_msg, master_reply_id))
+            .add_event(conversion_event(
+                master_reply_id,
+                exec_state.current_stage_index,
+                &settled,
+                &output_info,
+                ConversionReason::PathMismatch,
+            ))
+            .add_attribute("action", "converting_path_output")
             .add_attribute("split_index", split_index.to_string())
-            .add_attribute("op_index", (op_index + 1).to_string()))
     } else {
-        let fee = match FEE_MAP.may_load(deps.storage, &replying_pool_addr)? {
-            Some(fee_percent) => {
-                let numerator = fee_percent.atomics();
-                let denominator = Uint128::new(1_000_000_000_000_000_000u128);
-                received_amount.multiply_ratio(numerator, denominator)
-            }
-            None => Uint128::zero(),
-        };
-        let amount_after_fee = received_amount.checked_sub(fee).map_err(StdError::from)?;
+        check_split_output(
+            plan,
+            exec_state.current_stage_index,
+            split_index,
+            amount_after_fee,
+        )?;
         exec_state.accumulated_assets.push(amm::Asset {
             // Mutate exec_state
             info: received_asset_info.clone(),
             amount: amount_after_fee,
         });
         exec_state.replies_expected -= 1;
-        let mut response;
         if exec_state.replies_expected > 0 {
             EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?; // Save exec_state
-            response = Response::new().add_attribute("action", "accumulating_path_outputs");
+            Response::new().add_attribute("action", "accumulating_path_outputs")
         } else {
             exec_state.current_stage_index += 1; // Mutate exec_state
-            response = proceed_to_next_step(&mut deps, env, exec_state, plan, master_reply_id)?;
+            proceed_to_next_step(&mut deps, env, exec_state, plan, master_reply_id)?
         }
-        if !fee.is_zero() {
-            let config = CONFIG.load(deps.storage)?;
-            let fee_send_msg = create_send_msg(&config.fee_collector, &received_asset_info, fee)?;
-            response = response
-                .add_message(fee_send_msg)
-                .add_attribute("fee_collected", fee.to_string())
-                .add_attribute("fee_pool", replying_pool_addr.to_string());
+    };
+
+    Ok(fee.prepend_to(response))
+}
+
+/// An aggregator fee taken by `take_aggregator_fee`, with its sends to the fee collectors.
+#[derive(Debug)]
+struct TakenFee {
+    pool: Addr,
+    amount: Uint128,
+    sends: Vec<SubMsg<InjectiveMsgWrapper>>,
+}
+
+impl TakenFee {
+    /// Pays the fee out to the collectors ahead of everything else in `response`, so a later
+    /// reply never sees the sends as part of its own swap's effect on a balance-checked token.
+    fn prepend_to(
+        self,
+        mut response: Response<InjectiveMsgWrapper>,
+    ) -> Response<InjectiveMsgWrapper> {
+        if self.amount.is_zero() {
+            return response;
+        }
+        let mut messages = self.sends;
+        messages.append(&mut response.messages);
+        response.messages = messages;
+        response
+            .add_attribute("fee_collected", self.amount.to_string())
+            .add_attribute("fee_pool", self.pool.to_string())
+    }
+}
+
+/// Takes the aggregator fee for `pool` out of `received`: the fee is deducted as simulation
+/// deducts it, recorded against the route and the pool, and its sends to the collectors are
+/// built. Returns the fee and what is left of `received`, which is all a route may carry on with.
+/// Fails if the fee is above the cap the sender agreed to.
+fn take_aggregator_fee(
+    storage: &mut dyn Storage,
+    exec_state: &mut ExecutionState,
+    plan: &RoutePlan,
+    route_id: u64,
+    pool: &Addr,
+    info: &amm::AssetInfo,
+    received: Uint128,
+) -> Result<(TakenFee, Uint128), ContractError> {
+    let fee_percent = route_fee(storage, plan, pool)?;
+    if let Some(max_fee_bps) = plan.max_fee_bps {
+        let fee_bps = fee_percent * Decimal::from_ratio(10_000u128, 1u128);
+        if fee_bps > Decimal::from_ratio(max_fee_bps, 1u128) {
+            return Err(ContractError::FeeAboveUserCap {
+                pool: pool.to_string(),
+                fee_bps,
+                max_fee_bps,
+            });
+        }
+    }
+    let (fee, amount_after_fee) = deduct_fee(received, fee_percent)?;
+    let mut sends = vec![];
+    if !fee.is_zero() {
+        add_asset_amount(&mut exec_state.fees_collected, info, fee);
+        record_pool_fee(storage, pool, info, fee)?;
+        // The fee leaves the contract before anything dispatched from this reply runs.
+        deduct_asset_amount(&mut exec_state.balance_snapshots, info, fee);
+        let config = CONFIG.load(storage)?;
+        sends = create_fee_send_submsgs(&config.fee_collectors, info, fee, Some(route_id))?;
+    }
+    let taken = TakenFee {
+        pool: pool.clone(),
+        amount: fee,
+        sends,
+    };
+    Ok((taken, amount_after_fee))
+}
+
+/// Provides the output of the swap of the zap ending split `split_index` as liquidity, together
+/// with the part of its input held back. A constant product pool only takes both in its current
+/// ratio, so whatever exceeds it is refunded. The minted LP tokens come back to this contract and
+/// complete the path in the provision's own reply.
+#[allow(clippy::too_many_arguments)]
+fn provide_zap_liquidity(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: &Env,
+    exec_state: &mut ExecutionState,
+    plan: &RoutePlan,
+    route_id: u64,
+    split_index: usize,
+    lp_op: &ProvideLiquidityOp,
+    swapped_amount: Uint128,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let zap_index = exec_state
+        .pending_zaps
+        .iter()
+        .position(|zap| zap.split_index == split_index as u64)
+        .ok_or_else(|| StdError::generic_err("Zap state is invalid: no retained input found"))?;
+    let zap = exec_state.pending_zaps.remove(zap_index);
+
+    let pool_addr = deps.api.addr_validate(&lp_op.pool_address)?;
+    let mut deposits = (zap.retained.amount, swapped_amount);
+    if matches!(lp_op.pair_type, PairType::Xyk {}) {
+        let pool: amm::PoolResponse = deps
+            .querier
+            .query_wasm_smart(&pool_addr, &amm::QueryMsg::Pool {})?;
+        let reserves = (
+            zap_pool_reserve(&pool, &lp_op.offer_asset_info)?,
+            zap_pool_reserve(&pool, &lp_op.other_asset_info)?,
+        );
+        if !reserves.0.is_zero() && !reserves.1.is_zero() {
+            deposits = (
+                deposits
+                    .0
+                    .min(deposits.1.multiply_ratio(reserves.0, reserves.1)),
+                deposits
+                    .1
+                    .min(deposits.0.multiply_ratio(reserves.1, reserves.0)),
+            );
+        }
+    }
+    for (info, leftover) in [
+        (&zap.retained.info, zap.retained.amount - deposits.0),
+        (&lp_op.other_asset_info, swapped_amount - deposits.1),
+    ] {
+        if !leftover.is_zero() {
+            add_asset_amount(&mut exec_state.skipped_assets, info, leftover);
         }
-        Ok(response)
     }
+    let assets = vec![
+        amm::Asset {
+            info: zap.retained.info,
+            amount: deposits.0,
+        },
+        amm::Asset {
+            info: lp_op.other_asset_info.clone(),
+            amount: deposits.1,
+        },
+    ];
+    let (allowance_msgs, provide_msg) = create_provide_liquidity_msgs(
+        &pool_addr,
+        assets,
+        env.contract.address.to_string(),
+        plan.deadline,
+    )?;
+
+    exec_state.awaiting = Awaiting::LiquidityProvision;
+    record_dispatch(exec_state, 1);
+    EXECUTION_STATES.save(deps.storage, route_id, exec_state)?;
+
+    Ok(Response::new()
+        .add_messages(allowance_msgs)
+        .add_submessage(SubMsg::reply_on_success(provide_msg, route_id))
+        .add_attribute("action", "providing_zap_liquidity")
+        .add_attribute("pool", pool_addr.to_string()))
+}
+
+/// Handles the reply of a zap's liquidity provision. The LP tokens it minted, less the pool's
+/// aggregator fee, are the output of the zap's path.
+fn handle_liquidity_reply(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let master_reply_id = msg.id;
+
+    let events = &msg
+        .result
+        .clone()
+        .into_result()
+        .map_err(|e| ContractError::SubmessageResultError { error: e })?
+        .events;
+
+    let current_stage = plan
+        .stage(deps.storage, exec_state.current_stage_index)?
+        .ok_or(ContractError::EmptyRoute {})?;
+
+    let provide_event = events
+        .iter()
+        .rev()
+        .find(|e| e.ty.starts_with("wasm") && e.attributes.iter().any(|a| a.key == "share"))
+        .ok_or(ContractError::NoAmountInReply {
+            route_id: master_reply_id,
+        })?;
+    let event_attr = |key: &str| {
+        provide_event
+            .attributes
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    };
+
+    let pool_addr_str = event_attr("_contract_address").ok_or_else(|| {
+        StdError::generic_err("Liquidity provision event is missing '_contract_address'")
+    })?;
+    let pool_addr = deps.api.addr_validate(&pool_addr_str)?;
+    let (position, lp_op) =
+        find_stage_operation(current_stage, pool_addr.as_str()).ok_or_else(|| {
+            ContractError::UnexpectedReplyingContract {
+                stage_index: exec_state.current_stage_index,
+                contract: pool_addr.to_string(),
+            }
+        })?;
+
+    let share = event_attr("share").unwrap_or_default();
+    let share = share
+        .parse::<Uint128>()
+        .map_err(|_| ContractError::MalformedAmountInReply {
+            route_id: master_reply_id,
+            value: share,
+        })?;
+    let lp_token = get_operation_output(lp_op)?;
+    let minted = measured_amount(inflows, &lp_token, share);
+    check_operation_min_out(
+        current_stage,
+        exec_state.current_stage_index,
+        position,
+        minted,
+    )?;
+    let (split_index, _) = position;
+
+    let (fee, amount_after_fee) = take_aggregator_fee(
+        deps.storage,
+        exec_state,
+        plan,
+        master_reply_id,
+        &pool_addr,
+        &lp_token,
+        minted,
+    )?;
+    record_execution(
+        &env,
+        exec_state,
+        position,
+        &pool_addr,
+        amm::Asset {
+            info: lp_token.clone(),
+            amount: minted,
+        },
+        fee.amount,
+    );
+    check_split_output(
+        plan,
+        exec_state.current_stage_index,
+        split_index,
+        amount_after_fee,
+    )?;
+    exec_state.accumulated_assets.push(amm::Asset {
+        info: lp_token.clone(),
+        amount: amount_after_fee,
+    });
+    exec_state.awaiting = Awaiting::Swaps;
+    exec_state.replies_expected -= 1;
+
+    let response = if exec_state.replies_expected > 0 {
+        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+        Response::new().add_attribute("action", "accumulating_path_outputs")
+    } else {
+        exec_state.current_stage_index += 1;
+        proceed_to_next_step(&mut deps, env, exec_state, plan, master_reply_id)?
+    };
+
+    Ok(fee
+        .prepend_to(response)
+        .add_attribute("lp_minted", minted.to_string()))
 }
 
 // A helper to create the final transfer message.
-fn create_send_msg(
+pub(crate) fn create_send_msg(
     recipient: &Addr,
     asset_info: &amm::AssetInfo,
     amount: Uint128,
@@ -259,161 +952,1680 @@ fn create_send_msg(
     }
 }
 
-fn handle_final_stage(
-    deps: &mut DepsMut<InjectiveQueryWrapper>,
-    env: Env,
-    reply_id: u64,
-    exec_state: &mut ExecutionState,
+/// Adds the final payout of a route to `response`: transfers to its recipients by their shares,
+/// a transfer to the sender followed by its post-route action if it has one, or a delivery to the
+/// plan's receive hook. A hook is only accepted for CW20 outputs, so an output held in native form
+/// is the adapter's and can be redeemed and sent on by it.
+fn add_payout(
+    response: Response<InjectiveMsgWrapper>,
+    config: &Config,
+    env: &Env,
     plan: &RoutePlan,
+    asset_info: &amm::AssetInfo,
+    amount: Uint128,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    if exec_state.accumulated_assets.is_empty() {
-        if !plan.minimum_receive.is_zero() {
-            return Err(ContractError::MinimumReceiveNotMet {});
-        }
-        // CLEANUP HERE
-        EXECUTION_STATES.remove(deps.storage, reply_id);
-        ROUTE_PLANS.remove(deps.storage, reply_id);
-        return Ok(Response::new().add_attribute("action", "aggregate_swap_complete_empty"));
+    if let Some(recipients) = &plan.recipients {
+        return add_recipient_payouts(response, recipients, asset_info, amount);
     }
+    let Some(hook) = &plan.receive_hook else {
+        let response = response.add_message(create_send_msg(&plan.sender, asset_info, amount)?);
+        return match &plan.post_action {
+            Some(action) => Ok(response.add_message(create_post_action_msg(
+                env, plan, action, asset_info, amount,
+            )?)),
+            None => Ok(response),
+        };
+    };
+    let msg = match asset_info {
+        amm::AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.clone(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Send {
+                contract: hook.contract.clone(),
+                amount,
+                msg: hook.msg.clone(),
+            })?,
+            funds: vec![],
+        }),
+        amm::AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: config.cw20_adapter_address.to_string(),
+            msg: to_json_binary(&cw20_adapter::ExecuteMsg::RedeemAndSend {
+                recipient: hook.contract.clone(),
+                submsg: hook.msg.clone(),
+            })?,
+            funds: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        }),
+    };
+    Ok(response.add_message(msg))
+}
 
-    // The target asset for normalization is the type of the first asset in the final list.
-    let target_asset_info = exec_state.accumulated_assets[0].info.clone();
-
-    let mut conversion_submsgs = vec![];
-    let mut ready_amount = Uint128::zero();
-    let config = CONFIG.load(deps.storage)?;
-
-    for asset in &exec_state.accumulated_assets {
-        if asset.info == target_asset_info {
-            ready_amount += asset.amount;
+/// Splits the `amount` of `asset_info` paid out between `recipients` by their shares, with a
+/// `route_payout` event for each. Rounding leftovers go to the last recipient.
+fn add_recipient_payouts(
+    mut response: Response<InjectiveMsgWrapper>,
+    recipients: &[Recipient],
+    asset_info: &amm::AssetInfo,
+    amount: Uint128,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let mut remaining = amount;
+    for (index, recipient) in recipients.iter().enumerate() {
+        let share = if index + 1 == recipients.len() {
+            remaining
         } else {
-            let msg = create_conversion_msg(asset, &config, &env)?;
-            conversion_submsgs.push(SubMsg::reply_on_success(msg, reply_id));
+            amount.multiply_ratio(recipient.share_bps, 10_000u128)
+        };
+        remaining = remaining.checked_sub(share).map_err(StdError::from)?;
+        if share.is_zero() {
+            continue;
         }
+        response = response
+            .add_message(create_send_msg(&recipient.address, asset_info, share)?)
+            .add_event(
+                Event::new("route_payout")
+                    .add_attribute("recipient", recipient.address.to_string())
+                    .add_attribute("share_bps", recipient.share_bps.to_string())
+                    .add_attribute("asset", asset_id(asset_info))
+                    .add_attribute("amount", share.to_string()),
+            );
     }
+    Ok(response)
+}
 
-    if conversion_submsgs.is_empty() {
-        // SCENARIO A: All assets were already the same type. We are done.
-        let total_final_amount = ready_amount;
-        // Check against minimum_receive from the immutable plan
-        if total_final_amount < plan.minimum_receive {
-            return Err(ContractError::MinimumReceiveNotMet {});
+/// Takes the fee of the route's integrator, if it has one, out of the `amount` of `asset_info`
+/// about to be paid out, adding its send to the integrator's collector to `response`. Returns
+/// what is left for the sender, which is what the route's minimums are checked against.
+fn take_integrator_fee(
+    response: Response<InjectiveMsgWrapper>,
+    plan: &RoutePlan,
+    asset_info: &amm::AssetInfo,
+    amount: Uint128,
+) -> Result<(Response<InjectiveMsgWrapper>, Uint128), ContractError> {
+    let Some(integrator) = &plan.integrator else {
+        return Ok((response, amount));
+    };
+    let fee = amount.multiply_ratio(integrator.fee_bps, 10_000u128);
+    if fee.is_zero() {
+        return Ok((response, amount));
+    }
+    // Taken once the route has swapped its last stage, leaving no snapshot for a failed send to
+    // adjust.
+    let collector = FeeCollector {
+        address: integrator.collector.clone(),
+        weight: Decimal::one(),
+    };
+    let response = response
+        .add_submessages(create_fee_send_submsgs(
+            &[collector],
+            asset_info,
+            fee,
+            None,
+        )?)
+        .add_attribute("integrator", integrator.address.to_string())
+        .add_attribute("integrator_fee", fee.to_string())
+        .add_attribute("integrator_fee_asset", asset_id(asset_info));
+    Ok((response, amount.checked_sub(fee).map_err(StdError::from)?))
+}
+
+/// The message a `PostAction::Delegate` runs as the sender, who must have granted this contract
+/// an authz authorization for it.
+pub const DELEGATE_MSG_TYPE_URL: &str = "/cosmos.staking.v1beta1.MsgDelegate";
+
+/// Takes `action` with the `amount` of `asset_info` just paid out to the sender. The action runs
+/// as the sender through an authz `MsgExec`, under the authorization they granted this contract.
+fn create_post_action_msg(
+    env: &Env,
+    plan: &RoutePlan,
+    action: &PostAction,
+    asset_info: &amm::AssetInfo,
+    amount: Uint128,
+) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
+    match action {
+        PostAction::Delegate { validator } => {
+            // Execution only accepts a delegation for routes paying out the bond denom.
+            let amm::AssetInfo::NativeToken { denom } = asset_info else {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "Only a native output can be delegated",
+                )));
+            };
+            let mut coin = vec![];
+            encode_proto_field(&mut coin, 1, denom.as_bytes());
+            encode_proto_field(&mut coin, 2, amount.to_string().as_bytes());
+            let mut delegate = vec![];
+            encode_proto_field(&mut delegate, 1, plan.sender.as_bytes());
+            encode_proto_field(&mut delegate, 2, validator.as_bytes());
+            encode_proto_field(&mut delegate, 3, &coin);
+            Ok(create_authz_exec_msg(env, DELEGATE_MSG_TYPE_URL, &delegate))
         }
+    }
+}
 
-        let mut response = Response::new();
-        if !total_final_amount.is_zero() {
-            // Use the sender address from the immutable plan
-            let send_msg = create_send_msg(&plan.sender, &target_asset_info, total_final_amount)?;
-            response = response.add_message(send_msg);
+/// An authz `MsgExec` running the protobuf-encoded message `msg` of type `type_url`, with this
+/// contract as the grantee.
+fn create_authz_exec_msg(env: &Env, type_url: &str, msg: &[u8]) -> CosmosMsg<InjectiveMsgWrapper> {
+    let mut any = vec![];
+    encode_proto_field(&mut any, 1, type_url.as_bytes());
+    encode_proto_field(&mut any, 2, msg);
+    let mut exec = vec![];
+    encode_proto_field(&mut exec, 1, env.contract.address.as_bytes());
+    encode_proto_field(&mut exec, 2, &any);
+    CosmosMsg::Any(AnyMsg {
+        type_url: "/cosmos.authz.v1beta1.MsgExec".to_string(),
+        value: Binary::new(exec),
+    })
+}
+
+/// Appends a length-delimited protobuf field, the wire type of strings, bytes and messages.
+pub(crate) fn encode_proto_field(buf: &mut Vec<u8>, field_number: u64, value: &[u8]) {
+    encode_varint(buf, (field_number << 3) | 2);
+    encode_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Splits a fee across the collectors by weight. The last collector receives the rounding
+/// remainder so the full fee is always paid out. A send that fails is kept for its collector to
+/// claim rather than failing the transaction.
+pub(crate) fn create_fee_send_submsgs(
+    collectors: &[FeeCollector],
+    asset_info: &amm::AssetInfo,
+    fee: Uint128,
+    route_id: Option<u64>,
+) -> Result<Vec<SubMsg<InjectiveMsgWrapper>>, ContractError> {
+    let mut msgs = vec![];
+    let mut remaining = fee;
+    for (idx, collector) in collectors.iter().enumerate() {
+        let share = if idx + 1 == collectors.len() {
+            remaining
+        } else {
+            fee.multiply_ratio(
+                collector.weight.atomics(),
+                Uint128::new(1_000_000_000_000_000_000u128),
+            )
+        };
+        remaining = remaining.checked_sub(share).map_err(StdError::from)?;
+        if !share.is_zero() {
+            let send_msg = create_send_msg(&collector.address, asset_info, share)?;
+            let delivery = FeeDelivery {
+                route_id,
+                collector: collector.address.clone(),
+                asset: amm::Asset {
+                    info: asset_info.clone(),
+                    amount: share,
+                },
+            };
+            msgs.push(
+                SubMsg::reply_on_error(send_msg, FEE_DELIVERY_REPLY_ID)
+                    .with_payload(to_json_binary(&delivery)?),
+            );
         }
+    }
+    Ok(msgs)
+}
 
-        EXECUTION_STATES.remove(deps.storage, reply_id);
-        ROUTE_PLANS.remove(deps.storage, reply_id);
+/// The message swapping `amount` into `operation`, a hop of `stage`. When the hops after it go
+/// through the same router, they are swapped in the same call. Routes with a deadline are not
+/// batched, as router calls cannot carry one to the pools. Fails when the pool was disabled
+/// since the route started, or when `amount` exceeds its maximum input.
+fn create_path_swap_msg(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    plan: &RoutePlan,
+    stage: &Stage,
+    operation: &Operation,
+    offer_asset_info: &amm::AssetInfo,
+    amount: Uint128,
+    env: &Env,
+) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
+    let pool = get_operation_address(operation);
+    if DISABLED_POOLS.has(deps.storage, &Addr::unchecked(pool)) {
+        return Err(ContractError::PoolDisabled {
+            addr: pool.to_string(),
+        });
+    }
+    if let Some(max_input) = load_pool_config(deps.storage, &Addr::unchecked(pool))?.max_input {
+        if amount > max_input {
+            return Err(ContractError::PoolMaxInputExceeded {
+                pool: pool.to_string(),
+                amount,
+                max_input,
+            });
+        }
+    }
+    if plan.deadline.is_none() {
+        if let Some(((split_index, op_index), _)) =
+            find_stage_operation(stage, get_operation_address(operation))
+        {
+            let path = &stage.splits[split_index].path;
+            if let Some((router, last)) = router_batch(deps.storage, plan, path, op_index)? {
+                return create_router_swap_msg(
+                    deps,
+                    &router,
+                    &path[op_index..=last],
+                    offer_asset_info,
+                    amount,
+                    env,
+                );
+            }
+        }
+    }
+    create_swap_cosmos_msg(
+        deps,
+        operation,
+        offer_asset_info,
+        amount,
+        plan.deadline,
+        env,
+    )
+}
 
-        // State cleanup is now handled in the main `handle_reply` function
-        Ok(response
-            .add_attribute("action", "aggregate_swap_complete")
-            .add_attribute("final_received", total_final_amount.to_string()))
+/// The operation of `stage` run by the contract at `address`, with its (split, op) position.
+fn find_stage_operation<'a>(
+    stage: &'a Stage,
+    address: &str,
+) -> Option<((usize, usize), &'a Operation)> {
+    stage
+        .splits
+        .iter()
+        .enumerate()
+        .flat_map(|(split_idx, split)| {
+            split
+                .path
+                .iter()
+                .enumerate()
+                .map(move |(op_idx, op)| ((split_idx, op_idx), op))
+        })
+        .find(|(_, op)| get_operation_address(op) == address)
+}
+
+/// The operation of `stage` run by the contract at `address` that a swap reply answers. The
+/// position the swap was dispatched at narrows the search to that operation and the ones after
+/// it in its split, which a router call swaps through, so that splits sharing a pool are told
+/// apart.
+fn find_replying_operation<'a>(
+    stage: &'a Stage,
+    dispatched: Option<(usize, usize)>,
+    address: &str,
+) -> Option<((usize, usize), &'a Operation)> {
+    let Some((split_index, op_index)) = dispatched else {
+        return find_stage_operation(stage, address);
+    };
+    stage
+        .splits
+        .get(split_index)?
+        .path
+        .iter()
+        .enumerate()
+        .skip(op_index)
+        .find(|(_, op)| get_operation_address(op) == address)
+        .map(|(index, op)| ((split_index, index), op))
+}
+
+/// A submessage dispatching the swap at `position` in its stage, which it carries as its payload
+/// so that the reply can be attributed to it even when the pool reports nothing.
+fn swap_submsg(
+    msg: CosmosMsg<InjectiveMsgWrapper>,
+    reply_id: u64,
+    position: (usize, usize),
+) -> StdResult<SubMsg<InjectiveMsgWrapper>> {
+    Ok(SubMsg::reply_on_success(msg, reply_id).with_payload(to_json_binary(&position)?))
+}
+
+/// The position of the operation a swap reply answers, as dispatched by `swap_submsg`.
+fn dispatched_position(msg: &Reply) -> StdResult<Option<(usize, usize)>> {
+    if msg.payload.is_empty() {
+        return Ok(None);
+    }
+    from_json(&msg.payload).map(Some)
+}
+
+/// Checks a path that ended with nothing at `position` against the caller's expectations. The
+/// operation there, and every one after it, which never runs, output nothing.
+fn check_empty_path_end(
+    plan: &RoutePlan,
+    stage: &Stage,
+    stage_index: u64,
+    (split_index, op_index): (usize, usize),
+) -> Result<(), ContractError> {
+    for index in op_index..stage.splits[split_index].path.len() {
+        check_operation_min_out(stage, stage_index, (split_index, index), Uint128::zero())?;
+    }
+    check_split_output(plan, stage_index, split_index, Uint128::zero())
+}
+
+/// An empty reply that cannot be attributed to an operation is only taken as an ended path when
+/// no minimum or expectation of the stage could be broken by it, nor a pool's rate be checked.
+fn check_unattributed_empty_reply(
+    storage: &dyn Storage,
+    plan: &RoutePlan,
+    stage: &Stage,
+    stage_index: u64,
+) -> Result<(), ContractError> {
+    let has_min_out = stage
+        .splits
+        .iter()
+        .flat_map(|split| &split.path)
+        .any(|op| op.min_out().is_some_and(|min_out| !min_out.is_zero()));
+    let expects_output = plan
+        .split_expectations
+        .as_ref()
+        .is_some_and(|expectations| {
+            expectations
+                .expected_outputs
+                .get(stage_index as usize)
+                .is_some_and(|outputs| outputs.iter().any(|expected| !expected.is_zero()))
+        });
+    let rate_guarded = load_parameters(storage)?.max_rate_deviation.is_some();
+    if has_min_out || expects_output || rate_guarded {
+        return Err(ContractError::UnattributedEmptyReply { stage_index });
+    }
+    Ok(())
+}
+
+/// Records the execution of the operation at `position`, taking its input from the
+/// offers in flight. A path runs one operation at a time, so the offer of its split at or before
+/// this operation is this one's, or for a router call its first hop's.
+fn record_execution(
+    env: &Env,
+    exec_state: &mut ExecutionState,
+    (split_index, op_index): (usize, usize),
+    contract: &Addr,
+    output: amm::Asset,
+    fee: Uint128,
+) {
+    let Some(idx) = exec_state.pending_offers.iter().position(|pending| {
+        pending.split_index == split_index as u64 && pending.op_index <= op_index as u64
+    }) else {
+        return;
+    };
+    let pending = exec_state.pending_offers.remove(idx);
+    exec_state.executions.push(OperationExecution {
+        stage_index: exec_state.current_stage_index,
+        split_index: split_index as u64,
+        op_index: op_index as u64,
+        contract: contract.clone(),
+        offer: pending.offer,
+        output,
+        fee,
+        height: env.block.height,
+        time: env.block.time.seconds(),
+    });
+}
+
+/// Fails if the operation at `position` in `stage` output less than the minimum it declares.
+fn check_operation_min_out(
+    stage: &Stage,
+    stage_index: u64,
+    (split_index, op_index): (usize, usize),
+    received: Uint128,
+) -> Result<(), ContractError> {
+    match stage.splits[split_index].path[op_index].min_out() {
+        Some(min_out) if received < min_out => Err(ContractError::OperationBelowMinOut {
+            stage_index,
+            split_index: split_index as u64,
+            op_index: op_index as u64,
+            received,
+            min_out,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if the operation at `position` reports more than `max_output_multiple` times the output
+/// simulated at its dispatch. Operations without a simulation of their own, such as the later
+/// hops of a router call, are not checked.
+fn check_output_sanity(
+    storage: &dyn Storage,
+    exec_state: &ExecutionState,
+    (split_index, op_index): (usize, usize),
+    pool: &Addr,
+    reported: Uint128,
+) -> Result<(), ContractError> {
+    let Some(max_multiple) = load_parameters(storage)?.max_output_multiple else {
+        return Ok(());
+    };
+    let Some(simulated) = pending_offer(exec_state, (split_index, op_index))
+        .and_then(|pending| pending.expected_output)
+    else {
+        return Ok(());
+    };
+    if simulated
+        .checked_mul_floor(max_multiple)
+        .is_ok_and(|bound| reported > bound)
+    {
+        return Err(ContractError::SuspiciousOutputAmount {
+            pool: pool.to_string(),
+            reported,
+            simulated,
+            max_multiple,
+        });
+    }
+    Ok(())
+}
+
+/// The offer dispatched to the operation at `position` itself, if it is still in flight.
+fn pending_offer(
+    exec_state: &ExecutionState,
+    (split_index, op_index): (usize, usize),
+) -> Option<&PendingOffer> {
+    exec_state.pending_offers.iter().find(|pending| {
+        pending.split_index == split_index as u64 && pending.op_index == op_index as u64
+    })
+}
+
+/// While `max_rate_deviation` is set, fails if the marginal rate the operation at `position` paid
+/// out at is further than that from its pool's recent reference, and otherwise moves the
+/// reference towards it. That rate is the pool's quote for a canary-sized slice of the offer,
+/// scaled by how much of its quote for the whole offer it paid, so the price impact of a large
+/// offer is not taken for a deviation while a pool paying short of its quote is. Operations
+/// without a quote of their own, such as the later hops of a router call, are not tracked.
+fn check_rate_deviation(
+    storage: &mut dyn Storage,
+    env: &Env,
+    exec_state: &ExecutionState,
+    (split_index, op_index): (usize, usize),
+    pool: &Addr,
+    reported: Uint128,
+) -> Result<(), ContractError> {
+    let Some(max_deviation) = load_parameters(storage)?.max_rate_deviation else {
+        return Ok(());
+    };
+    let Some((offer, expected_output, marginal_rate)) =
+        pending_offer(exec_state, (split_index, op_index)).and_then(|pending| {
+            Some((
+                &pending.offer,
+                pending.expected_output?,
+                pending.marginal_rate?,
+            ))
+        })
+    else {
+        return Ok(());
+    };
+    // A pool that quoted nothing for the whole offer is only judged by its canary quote.
+    let paid_share = if expected_output.is_zero() {
+        Decimal::one()
     } else {
-        // SCENARIO B: Conversions are needed. Set up the exec_state for the final reply.
-        exec_state.awaiting = Awaiting::FinalConversions;
-        exec_state.replies_expected = conversion_submsgs.len() as u64;
-        exec_state.accumulated_assets = vec![amm::Asset {
-            info: target_asset_info,
-            amount: ready_amount,
-        }];
+        Decimal::checked_from_ratio(reported, expected_output).unwrap_or(Decimal::MAX)
+    };
+    let realized = marginal_rate.saturating_mul(paid_share);
 
-        // Save the small, mutated exec_state
-        EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
+    let key = (pool, asset_id(&offer.info));
+    let now = env.block.time.seconds();
+    let reference = POOL_REFERENCE_RATES
+        .may_load(storage, key)?
+        .filter(|reference| {
+            now.saturating_sub(reference.updated_at) < REFERENCE_RATE_MAX_AGE_SECONDS
+        });
+    let rate = match reference {
+        // A rate of zero cannot be deviated from by a fraction of it, so it never becomes one.
+        None if realized.is_zero() => return Ok(()),
+        None => realized,
+        Some(reference) => {
+            let deviation = realized.abs_diff(reference.rate);
+            if deviation > reference.rate.saturating_mul(max_deviation) {
+                return Err(ContractError::PoolRateDeviation {
+                    pool: pool.to_string(),
+                    realized,
+                    reference: reference.rate,
+                    max_deviation,
+                });
+            }
+            reference.rate * (Decimal::one() - REFERENCE_RATE_WEIGHT)
+                + realized * REFERENCE_RATE_WEIGHT
+        }
+    };
+    POOL_REFERENCE_RATES.save(
+        storage,
+        key,
+        &ReferenceRate {
+            rate,
+            updated_at: now,
+        },
+    )?;
+    Ok(())
+}
 
-        Ok(Response::new()
-            .add_submessages(conversion_submsgs)
-            .add_attribute("action", "final_asset_normalization_started"))
+/// The output of a hop whose reply carries no pool swap event: a sub-aggregator's, read from the
+/// completion event of the nested route, or a venue's that settles by bank send.
+struct HopOutput {
+    contract: String,
+    position: (usize, usize),
+    amount: Uint128,
+    asset_info: amm::AssetInfo,
+}
+
+/// Finds the completion of a sub-aggregator operation of `stage` in a reply's events. The
+/// sub-aggregator pays out in its normalization target, which is either the declared output
+/// of its route or the other form of that asset.
+fn find_sub_aggregator_result(
+    events: &[Event],
+    stage: &Stage,
+    route_id: u64,
+) -> Result<Option<HopOutput>, ContractError> {
+    let emitted_by = |event: &Event, contract: &str| {
+        event.ty.starts_with("wasm")
+            && event
+                .attributes
+                .iter()
+                .any(|a| a.key == "_contract_address" && a.value == contract)
+    };
+    let find_attr = |contract: &str, key: &str| {
+        events
+            .iter()
+            .filter(|e| emitted_by(e, contract))
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    };
+
+    for (split_idx, split) in stage.splits.iter().enumerate() {
+        for (op_idx, op) in split.path.iter().enumerate() {
+            let Operation::SubAggregator(sub_op) = op else {
+                continue;
+            };
+            if !events.iter().any(|e| emitted_by(e, &sub_op.contract)) {
+                continue;
+            }
+
+            // Every completed route reports what it paid out, even when it is nothing. Without it
+            // the nested route did not complete, e.g. because it checkpointed.
+            let amount = match find_attr(&sub_op.contract, "final_received") {
+                Some(value) => value
+                    .parse::<Uint128>()
+                    .map_err(|_| ContractError::MalformedAmountInReply { route_id, value })?,
+                None => {
+                    return Err(ContractError::SubAggregatorIncomplete {
+                        route_id,
+                        contract: sub_op.contract.clone(),
+                    })
+                }
+            };
+            let declared = get_operation_output(op)?;
+            let asset_info = match find_attr(&sub_op.contract, "normalization_target") {
+                Some(target) if target != asset_id(&declared) => match declared {
+                    amm::AssetInfo::Token { .. } => amm::AssetInfo::NativeToken { denom: target },
+                    amm::AssetInfo::NativeToken { .. } => amm::AssetInfo::Token {
+                        contract_addr: target,
+                    },
+                },
+                _ => declared,
+            };
+
+            return Ok(Some(HopOutput {
+                contract: sub_op.contract.clone(),
+                position: (split_idx, op_idx),
+                amount,
+                asset_info,
+            }));
+        }
     }
+    Ok(None)
 }
 
-fn handle_final_conversion_reply(
-    deps: DepsMut<InjectiveQueryWrapper>,
-    env: Env,
-    msg: Reply,
+/// Finds a bank payout to this contract from an operation of `stage`, for venues that settle by
+/// bank send without emitting swap attributes. The operation is the transfer's sender, and only
+/// coins in its ask denom count, so refunds of unspent input are not mistaken for output.
+fn find_bank_settlement(
+    storage: &dyn Storage,
+    events: &[Event],
+    stage: &Stage,
+    contract: &Addr,
+    route_id: u64,
+) -> Result<Option<HopOutput>, ContractError> {
+    let mut settlement: Option<HopOutput> = None;
+    for event in events.iter().filter(|e| e.ty == "transfer") {
+        let attr = |key: &str| {
+            event
+                .attributes
+                .iter()
+                .find(|a| a.key == key)
+                .map(|a| a.value.as_str())
+        };
+        if attr("recipient") != Some(contract.as_str()) {
+            continue;
+        }
+        let Some(sender) = attr("sender") else {
+            continue;
+        };
+        if settlement.as_ref().is_some_and(|s| s.contract != sender) {
+            continue;
+        }
+        let Some((position, op)) = find_stage_operation(stage, sender) else {
+            continue;
+        };
+        let amm::AssetInfo::NativeToken { denom } = get_swap_output(storage, op)? else {
+            continue;
+        };
+
+        let mut amount = Uint128::zero();
+        for coin in attr("amount").unwrap_or_default().split(',') {
+            let coin = Coin::from_str(coin).map_err(|_| ContractError::MalformedAmountInReply {
+                route_id,
+                value: coin.to_string(),
+            })?;
+            if coin.denom == denom {
+                amount += coin.amount;
+            }
+        }
+        if amount.is_zero() {
+            continue;
+        }
+        match &mut settlement {
+            Some(existing) => existing.amount += amount,
+            None => {
+                settlement = Some(HopOutput {
+                    contract: sender.to_string(),
+                    position,
+                    amount,
+                    asset_info: amm::AssetInfo::NativeToken { denom },
+                })
+            }
+        }
+    }
+    Ok(settlement)
+}
+
+/// Records what the operation at `position` dispatched with `amount` leaves behind: input left
+/// unswapped, to be refunded at the end, and the part of a zap's input held back to provide with
+/// its swap output.
+/// What it actually offers is kept for the execution its reply records, along with its simulated
+/// output while `max_output_multiple` or `max_rate_deviation` is set, and its pool's quote for a
+/// canary-sized slice of it while `max_rate_deviation` is set.
+fn record_dispatched_input(
+    deps: Deps<InjectiveQueryWrapper>,
     exec_state: &mut ExecutionState,
+    operation: &Operation,
+    offer_asset_info: &amm::AssetInfo,
+    amount: Uint128,
+    position: (usize, usize),
+) -> Result<(), ContractError> {
+    if let Operation::ProvideLiquidity(lp_op) = operation {
+        let swap_amount = zap_swap_amount(&deps.into_empty().querier, lp_op, amount)?;
+        exec_state.pending_zaps.push(PendingZap {
+            split_index: position.0 as u64,
+            retained: amm::Asset {
+                info: offer_asset_info.clone(),
+                amount: amount - swap_amount,
+            },
+        });
+        exec_state.pending_offers.push(PendingOffer {
+            split_index: position.0 as u64,
+            op_index: position.1 as u64,
+            offer: amm::Asset {
+                info: offer_asset_info.clone(),
+                amount,
+            },
+            expected_output: None,
+            marginal_rate: None,
+        });
+        return Ok(());
+    }
+    let remainder = unswapped_remainder(deps.storage, operation, amount)?;
+    if !remainder.is_zero() {
+        add_asset_amount(&mut exec_state.skipped_assets, offer_asset_info, remainder);
+    }
+    let offer = amm::Asset {
+        info: offer_asset_info.clone(),
+        amount: amount - remainder,
+    };
+    // An operation that cannot be simulated could not be checked, so it is not dispatched.
+    let simulate = |offer: &amm::Asset| {
+        simulate_single_operation(&deps.into_empty().querier, deps.storage, operation, offer)
+            .map(|output| output.amount)
+            .map_err(|err| ContractError::OutputSimulationFailed {
+                contract: get_operation_address(operation).to_string(),
+                error: err.to_string(),
+            })
+    };
+    let parameters = load_parameters(deps.storage)?;
+    let expected_output = match parameters
+        .max_output_multiple
+        .or(parameters.max_rate_deviation)
+    {
+        Some(_) => Some(simulate(&offer)?),
+        None => None,
+    };
+    let marginal_rate = match parameters.max_rate_deviation {
+        Some(_) => {
+            let canary = amm::Asset {
+                info: offer.info.clone(),
+                amount: offer
+                    .amount
+                    .mul_floor(REFERENCE_RATE_CANARY_SHARE)
+                    .max(Uint128::one()),
+            };
+            Decimal::checked_from_ratio(simulate(&canary)?, canary.amount).ok()
+        }
+        None => None,
+    };
+    exec_state.pending_offers.push(PendingOffer {
+        split_index: position.0 as u64,
+        op_index: position.1 as u64,
+        offer,
+        expected_output,
+        marginal_rate,
+    });
+    Ok(())
+}
+
+/// Refunds the inputs skipped by tick-size rounding to the route's sender. Amounts below the
+/// dust threshold go to the dust bucket instead.
+fn add_skipped_refunds(
+    storage: &mut dyn Storage,
+    mut response: Response<InjectiveMsgWrapper>,
+    exec_state: &ExecutionState,
     plan: &RoutePlan,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let reply_id = msg.id;
-    let converted_amount = parse_amount_from_conversion_reply(&msg, &env)?;
+    for asset in &exec_state.skipped_assets {
+        if is_dust(storage, asset)? {
+            accrue_dust(storage, asset)?;
+            response = response
+                .add_attribute("dust_asset", asset_id(&asset.info))
+                .add_attribute("dust_amount", asset.amount.to_string());
+            continue;
+        }
+        let refund_msg = create_send_msg(&plan.sender, &asset.info, asset.amount)?;
+        response = response
+            .add_message(refund_msg)
+            .add_attribute("refunded_asset", asset_id(&asset.info))
+            .add_attribute("refunded_amount", asset.amount.to_string());
+    }
+    Ok(response)
+}
 
-    let running_total_asset = exec_state.accumulated_assets.get_mut(0).ok_or_else(|| {
-        StdError::generic_err("Final conversion state is invalid: no accumulated asset found")
-    })?;
+/// With invariant checks enabled, appends the self-call verifying that no balance ended below
+/// its baseline less the fees collected in it. Must follow the route's final transfers.
+fn add_invariant_check(
+    response: Response<InjectiveMsgWrapper>,
+    env: &Env,
+    exec_state: &ExecutionState,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let Some(baseline) = &exec_state.balance_baseline else {
+        return Ok(response);
+    };
 
-    running_total_asset.amount += converted_amount;
-    exec_state.replies_expected -= 1;
+    let minimum_balances = baseline
+        .iter()
+        .map(|asset| {
+            let fees = exec_state
+                .fees_collected
+                .iter()
+                .filter(|fee| fee.info == asset.info)
+                .map(|fee| fee.amount)
+                .sum::<Uint128>();
+            amm::Asset {
+                info: asset.info.clone(),
+                amount: asset.amount.saturating_sub(fees),
+            }
+        })
+        .collect();
+    let verify_msg = WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_json_binary(&ExecuteMsg::VerifyInvariants { minimum_balances })?,
+        funds: vec![],
+    };
+    Ok(response.add_message(verify_msg))
+}
 
-    if exec_state.replies_expected > 0 {
-        // Still waiting for more conversions to finish. Save the updated exec_state.
-        EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
-        return Ok(Response::new().add_attribute("action", "accumulating_final_conversions"));
-    }
+/// Stores the receipt of a completed route and clears its in-flight state. Returns the
+/// `RouteResult` to set as the completing response's data.
+fn finalize_route(
+    storage: &mut dyn Storage,
+    env: &Env,
+    reply_id: u64,
+    exec_state: &ExecutionState,
+    plan: &RoutePlan,
+    output: amm::Asset,
+    additional_outputs: Vec<amm::Asset>,
+) -> StdResult<Binary> {
+    let receipt = Receipt {
+        input: plan.offer_asset.clone(),
+        output,
+        additional_outputs,
+        fees: exec_state.fees_collected.clone(),
+        stages_executed: exec_state.current_stage_index,
+        conversions: exec_state.conversions_performed,
+        completed_at_height: env.block.height,
+        completed_at_time: env.block.time.seconds(),
+        expires_at_height: env.block.height + RECEIPT_RETENTION_BLOCKS,
+        executions: exec_state.executions.clone(),
+        fee_schedule: plan.fee_schedule.clone(),
+    };
+    RECEIPTS.save(storage, reply_id, &receipt)?;
 
-    // All final conversions are complete.
-    let total_final_amount = running_total_asset.amount;
-    let final_asset_info = running_total_asset.info.clone();
+    EXECUTION_STATES.remove(storage, reply_id);
+    remove_route_plan(storage, reply_id, plan);
+    CONVERSION_RESULTS.remove(storage, reply_id);
+    route_result_data(reply_id, Some(&receipt))
+}
+
+/// The `RouteResult` of a route, without outputs unless it has completed with `receipt`.
+pub(crate) fn route_result_data(route_id: u64, receipt: Option<&Receipt>) -> StdResult<Binary> {
+    to_json_binary(&RouteResult {
+        route_id,
+        output: receipt.map(|receipt| receipt.output.clone()),
+        additional_outputs: receipt
+            .map(|receipt| receipt.additional_outputs.clone())
+            .unwrap_or_default(),
+    })
+}
+
+/// The asset produced by the last operation of the route's final stage.
+pub(crate) fn get_route_output_info(
+    last_stage: Option<&Stage>,
+) -> Result<amm::AssetInfo, ContractError> {
+    let last_op = last_stage
+        .and_then(|stage| stage.splits.first())
+        .and_then(|split| split.path.last())
+        .ok_or(ContractError::EmptyRoute {})?;
+    get_operation_output(last_op)
+}
 
-    if total_final_amount < plan.minimum_receive {
-        return Err(ContractError::MinimumReceiveNotMet {});
+fn handle_final_stage(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    reply_id: u64,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if let Some(final_assets) = &plan.final_assets {
+        return complete_multi_output_route(deps, &env, reply_id, exec_state, plan, final_assets);
     }
+    if let Some(response) = dispatch_sweeps(deps, &env, reply_id, exec_state, plan)? {
+        return Ok(response);
+    }
+    complete_route(deps, env, reply_id, exec_state, plan)
+}
 
-    let mut response = Response::new();
-    if !total_final_amount.is_zero() {
-        // Get the sender address from the immutable plan
-        let send_msg = create_send_msg(&plan.sender, &final_asset_info, total_final_amount)?;
-        response = response.add_message(send_msg);
+/// Swaps the leftover intermediate assets that have a sweep pool registered into the route's
+/// output, so they count towards it instead of being refunded. Sweeps are best-effort: a failed
+/// one leaves its asset to be refunded as it is. Returns `None` when there is nothing to sweep.
+fn dispatch_sweeps(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: &Env,
+    reply_id: u64,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+) -> Result<Option<Response<InjectiveMsgWrapper>>, ContractError> {
+    let output_info = get_route_output_info(plan.last_stage(deps.storage)?)?;
+    let mut sweep_submsgs = vec![];
+    let mut kept = vec![];
+    for asset in std::mem::take(&mut exec_state.skipped_assets) {
+        // Leftovers of the offer are the sender's own input and are always refunded.
+        let sweepable = asset.info != plan.offer_asset.info
+            && asset.info != output_info
+            && !is_dust(deps.storage, &asset)?;
+        let pool = if sweepable {
+            SWEEP_POOLS.may_load(
+                deps.storage,
+                (asset_id(&asset.info), asset_id(&output_info)),
+            )?
+        } else {
+            None
+        };
+        let Some(pool) = pool else {
+            kept.push(asset);
+            continue;
+        };
+        let msg = create_amm_swap_msg(
+            &pool,
+            &asset.info,
+            asset.amount,
+            env.contract.address.to_string(),
+            plan.deadline,
+        )?;
+        sweep_submsgs
+            .push(SubMsg::reply_always(msg, reply_id).with_payload(to_json_binary(&asset)?));
+    }
+    exec_state.skipped_assets = kept;
+    if sweep_submsgs.is_empty() {
+        return Ok(None);
     }
 
-    EXECUTION_STATES.remove(deps.storage, reply_id);
-    ROUTE_PLANS.remove(deps.storage, reply_id);
+    exec_state.awaiting = Awaiting::Sweep;
+    exec_state.replies_expected = sweep_submsgs.len() as u64;
+    record_dispatch(exec_state, sweep_submsgs.len());
+    EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
 
-    // State cleanup is now handled in the main `handle_reply` function
-    Ok(response
-        .add_attribute("action", "aggregate_swap_complete")
-        .add_attribute("final_received", total_final_amount.to_string()))
+    Ok(Some(
+        Response::new()
+            .add_attribute("action", "leftover_sweep_started")
+            .add_attribute("sweeps", sweep_submsgs.len().to_string())
+            .add_submessages(sweep_submsgs),
+    ))
 }
 
-fn handle_conversion_reply(
+/// Adds the output of a leftover sweep to the route's holdings, or returns the leftover to the
+/// refunds if the sweep failed. Once every sweep has replied, the route completes.
+fn handle_sweep_reply(
     mut deps: DepsMut<InjectiveQueryWrapper>,
     env: Env,
     msg: Reply,
     exec_state: &mut ExecutionState,
-    plan: &RoutePlan,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let master_reply_id = msg.id;
-    exec_state.replies_expected -= 1; // Mutate exec_state
+    let reply_id = msg.id;
+    let swept: amm::Asset = from_json(&msg.payload)?;
+    let output_info = get_route_output_info(plan.last_stage(deps.storage)?)?;
+    exec_state.replies_expected -= 1;
 
-    if exec_state.replies_expected > 0 {
-        // Save the small, mutated exec_state
-        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
-        return Ok(Response::new().add_attribute("action", "accumulating_conversion_outputs"));
+    let mut attrs = vec![("swept_asset", asset_id(&swept.info).to_string())];
+    let mut taken_fee = None;
+    match msg.result.into_result() {
+        Err(error) => {
+            add_asset_amount(&mut exec_state.skipped_assets, &swept.info, swept.amount);
+            attrs.push(("sweep_error", error));
+        }
+        Ok(result) => {
+            let pool = SWEEP_POOLS
+                .may_load(
+                    deps.storage,
+                    (asset_id(&swept.info), asset_id(&output_info)),
+                )?
+                .ok_or_else(|| StdError::generic_err("Sweep pool was removed mid-route"))?;
+            let format = load_pool_config(deps.storage, &pool)?.event_format;
+            let swap_events = trailing_swap_events(&result.events, |e| match &format {
+                Some(format) => is_registered_swap_event(e, format),
+                None => protocol_amount_keys(e).is_some(),
+            });
+            let reported = sum_swap_event_amounts(reply_id, &swap_events, format.as_ref())?;
+            let received = measured_amount(inflows, &output_info, reported);
+            let (fee, amount_after_fee) = take_aggregator_fee(
+                deps.storage,
+                exec_state,
+                plan,
+                reply_id,
+                &pool,
+                &output_info,
+                received,
+            )?;
+            add_asset_amount(
+                &mut exec_state.accumulated_assets,
+                &output_info,
+                amount_after_fee,
+            );
+            attrs.push(("swept_amount", swept.amount.to_string()));
+            attrs.push(("sweep_output", amount_after_fee.to_string()));
+            taken_fee = Some(fee);
+        }
     }
 
-    // Take pending_swaps from the mutated exec_state
-    let swaps_to_execute = std::mem::take(&mut exec_state.pending_swaps);
-
-    // Call the updated execute_planned_swaps with both state objects
-    execute_planned_swaps(
-        &mut deps,
-        env,
-        exec_state,
-        plan,
-        master_reply_id,
-        swaps_to_execute,
-    )
+    let response = if exec_state.replies_expected > 0 {
+        EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
+        Response::new().add_attribute("action", "accumulating_sweep_outputs")
+    } else {
+        complete_route(&mut deps, env, reply_id, exec_state, plan)?
+    };
+    let response = response.add_attributes(attrs);
+    match taken_fee {
+        Some(fee) => Ok(fee.prepend_to(response)),
+        None => Ok(response),
+    }
 }
 
-fn create_conversion_msg(
-    from: &amm::Asset,
-    config: &Config,
+/// Pays out a single-output route: its holdings are normalized into the asset its minimum is
+/// declared in, and its leftovers refunded.
+fn complete_route(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    reply_id: u64,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if exec_state.accumulated_assets.is_empty() {
+        if let Some(minimum) = plan
+            .minimum_receive
+            .as_ref()
+            .filter(|minimum| !minimum.amount.is_zero())
+        {
+            return Err(ContractError::MinimumReceiveNotMet {
+                expected: minimum.amount,
+                actual: Uint128::zero(),
+            });
+        }
+        if let Some(minimum) = plan
+            .minimum_receive_usd
+            .filter(|minimum| !minimum.is_zero())
+        {
+            return Err(ContractError::MinimumReceiveUsdNotMet {
+                expected: minimum,
+                actual: Decimal::zero(),
+            });
+        }
+        let output = amm::Asset {
+            info: get_route_output_info(plan.last_stage(deps.storage)?)?,
+            amount: Uint128::zero(),
+        };
+        let data = finalize_route(
+            deps.storage,
+            &env,
+            reply_id,
+            exec_state,
+            plan,
+            output,
+            vec![],
+        )?;
+        let response = add_skipped_refunds(deps.storage, Response::new(), exec_state, plan)?;
+        let response = add_invariant_check(response, &env, exec_state)?;
+        return Ok(response
+            .set_data(data)
+            .add_attribute("action", "aggregate_swap_complete_empty")
+            .add_attribute("final_received", Uint128::zero().to_string())
+            .add_attributes(execution_counters(exec_state))
+            .add_attributes(tag_attribute(plan)));
+    }
+
+    // Pay out in the asset the caller's minimum is declared in. Execution only accepts a minimum
+    // in the output token or its other form, so the holdings can always be normalized
+    // into it, even when none of them is in that form yet.
+    let target_asset_info = match &plan.minimum_receive {
+        Some(minimum) => minimum.info.clone(),
+        None => select_normalization_target(&exec_state.accumulated_assets),
+    };
+
+    let mut conversion_submsgs = vec![];
+    let mut conversion_events = vec![];
+    let mut ready_amount = Uint128::zero();
+    let config = CONFIG.load(deps.storage)?;
+
+    // Every holding is route output owed to the user, so none of it is kept as dust, however
+    // small.
+    for asset in &exec_state.accumulated_assets {
+        if asset.info == target_asset_info {
+            ready_amount += asset.amount;
+        } else {
+            let msg =
+                create_conversion_msg(deps.storage, asset, &target_asset_info, &env, reply_id)?;
+            conversion_submsgs.push(SubMsg::reply_on_success(msg, reply_id));
+            conversion_events.push(conversion_event(
+                reply_id,
+                exec_state.current_stage_index,
+                asset,
+                &target_asset_info,
+                ConversionReason::FinalNormalization,
+            ));
+        }
+    }
+
+    if conversion_submsgs.is_empty() {
+        // SCENARIO A: All assets were already the same type. We are done.
+        let (mut response, total_final_amount) =
+            take_integrator_fee(Response::new(), plan, &target_asset_info, ready_amount)?;
+        // Check against minimum_receive from the immutable plan
+        check_minimum_receive(plan, &target_asset_info, total_final_amount)?;
+        check_minimum_receive_usd(
+            deps.as_ref(),
+            &env,
+            plan,
+            &target_asset_info,
+            total_final_amount,
+        )?;
+
+        if !total_final_amount.is_zero() {
+            response = add_payout(
+                response,
+                &config,
+                &env,
+                plan,
+                &target_asset_info,
+                total_final_amount,
+            )?;
+        }
+
+        response = add_skipped_refunds(deps.storage, response, exec_state, plan)?;
+        response = add_invariant_check(response, &env, exec_state)?;
+
+        let output_info = target_asset_info.clone();
+        let output = amm::Asset {
+            info: target_asset_info,
+            amount: total_final_amount,
+        };
+        let data = finalize_route(
+            deps.storage,
+            &env,
+            reply_id,
+            exec_state,
+            plan,
+            output,
+            vec![],
+        )?;
+
+        // State cleanup is now handled in the main `handle_reply` function
+        Ok(response
+            .set_data(data)
+            .add_attribute("action", "aggregate_swap_complete")
+            .add_attribute("final_received", total_final_amount.to_string())
+            .add_attribute("normalization_target", asset_id(&output_info))
+            .add_attributes(execution_counters(exec_state))
+            .add_attributes(tag_attribute(plan)))
+    } else {
+        // SCENARIO B: Conversions are needed. Set up the exec_state for the final reply.
+        exec_state.awaiting = Awaiting::FinalConversions;
+        exec_state.pending_conversion_target = Some(target_asset_info.clone());
+        exec_state.replies_expected = conversion_submsgs.len() as u64;
+        record_dispatch(exec_state, conversion_submsgs.len());
+        exec_state.conversions_performed += conversion_submsgs.len() as u64;
+        let target_id = asset_id(&target_asset_info).to_string();
+        exec_state.accumulated_assets = vec![amm::Asset {
+            info: target_asset_info,
+            amount: ready_amount,
+        }];
+
+        // Save the small, mutated exec_state
+        EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
+
+        Ok(Response::new()
+            .add_submessages(conversion_submsgs)
+            .add_events(conversion_events)
+            .add_attribute("action", "final_asset_normalization_started")
+            .add_attribute("normalization_target", target_id))
+    }
+}
+
+/// Completes a multi-output route without normalizing: each held asset is paid out as it is, and
+/// each declared final asset must meet its own minimum. Holding an asset that was not declared
+/// fails the route.
+fn complete_multi_output_route(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: &Env,
+    reply_id: u64,
+    exec_state: &ExecutionState,
+    plan: &RoutePlan,
+    final_assets: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let mut outputs: Vec<amm::Asset> = final_assets
+        .iter()
+        .map(|declared| amm::Asset {
+            info: declared.info.clone(),
+            amount: Uint128::zero(),
+        })
+        .collect();
+    for asset in &exec_state.accumulated_assets {
+        let Some(output) = outputs.iter_mut().find(|output| output.info == asset.info) else {
+            let mut expected: Vec<String> = final_assets
+                .iter()
+                .map(|declared| asset_id(&declared.info).to_string())
+                .collect();
+            expected.sort();
+            let mut actual: Vec<String> = exec_state
+                .accumulated_assets
+                .iter()
+                .map(|held| asset_id(&held.info).to_string())
+                .collect();
+            actual.sort();
+            actual.dedup();
+            return Err(ContractError::FinalAssetSetMismatch { expected, actual });
+        };
+        output.amount += asset.amount;
+    }
+    let mut response = Response::new();
+    for output in outputs.iter_mut() {
+        (response, output.amount) =
+            take_integrator_fee(response, plan, &output.info, output.amount)?;
+    }
+    for (declared, output) in final_assets.iter().zip(&outputs) {
+        if output.amount < declared.amount {
+            return Err(ContractError::FinalAssetMinimumNotMet {
+                asset: asset_id(&declared.info).to_string(),
+                expected: declared.amount,
+                actual: output.amount,
+            });
+        }
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    for output in outputs.iter().filter(|output| !output.amount.is_zero()) {
+        response = add_payout(response, &config, env, plan, &output.info, output.amount)?
+            .add_attribute("final_asset", asset_id(&output.info))
+            .add_attribute("final_received", output.amount.to_string());
+    }
+    response = add_skipped_refunds(deps.storage, response, exec_state, plan)?;
+    response = add_invariant_check(response, env, exec_state)?;
+
+    let mut outputs = outputs.into_iter();
+    let output = outputs.next().ok_or(ContractError::EmptyRoute {})?;
+    let data = finalize_route(
+        deps.storage,
+        env,
+        reply_id,
+        exec_state,
+        plan,
+        output,
+        outputs.collect(),
+    )?;
+
+    Ok(response
+        .set_data(data)
+        .add_attribute("action", "aggregate_swap_complete_multi_output")
+        .add_attributes(execution_counters(exec_state))
+        .add_attributes(tag_attribute(plan)))
+}
+
+fn handle_final_conversion_reply(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let reply_id = msg.id;
+    let converted_amount = parse_amount_from_conversion_reply(deps.storage, &msg, &env)?;
+
+    let running_total_asset = exec_state.accumulated_assets.get_mut(0).ok_or_else(|| {
+        StdError::generic_err("Final conversion state is invalid: no accumulated asset found")
+    })?;
+
+    running_total_asset.amount +=
+        measured_amount(inflows, &running_total_asset.info, converted_amount);
+    exec_state.replies_expected -= 1;
+
+    if exec_state.replies_expected > 0 {
+        // Still waiting for more conversions to finish. Save the updated exec_state.
+        EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
+        return Ok(Response::new().add_attribute("action", "accumulating_final_conversions"));
+    }
+
+    // All final conversions are complete.
+    let final_asset_info = running_total_asset.info.clone();
+    let (mut response, total_final_amount) = take_integrator_fee(
+        Response::new(),
+        plan,
+        &final_asset_info,
+        running_total_asset.amount,
+    )?;
+
+    check_minimum_receive(plan, &final_asset_info, total_final_amount)?;
+    check_minimum_receive_usd(
+        deps.as_ref(),
+        &env,
+        plan,
+        &final_asset_info,
+        total_final_amount,
+    )?;
+
+    if !total_final_amount.is_zero() {
+        let config = CONFIG.load(deps.storage)?;
+        response = add_payout(
+            response,
+            &config,
+            &env,
+            plan,
+            &final_asset_info,
+            total_final_amount,
+        )?;
+    }
+
+    response = add_skipped_refunds(deps.storage, response, exec_state, plan)?;
+    response = add_invariant_check(response, &env, exec_state)?;
+
+    let output = amm::Asset {
+        info: final_asset_info,
+        amount: total_final_amount,
+    };
+    let data = finalize_route(
+        deps.storage,
+        &env,
+        reply_id,
+        exec_state,
+        plan,
+        output,
+        vec![],
+    )?;
+
+    // State cleanup is now handled in the main `handle_reply` function
+    Ok(response
+        .set_data(data)
+        .add_attribute("action", "aggregate_swap_complete")
+        .add_attribute("final_received", total_final_amount.to_string())
+        .add_attributes(execution_counters(exec_state))
+        .add_attributes(tag_attribute(plan)))
+}
+
+/// Enforces the caller's per-split expectation on a completed path's output.
+fn check_split_output(
+    plan: &RoutePlan,
+    stage_index: u64,
+    split_index: usize,
+    actual: Uint128,
+) -> Result<(), ContractError> {
+    let Some(expectations) = &plan.split_expectations else {
+        return Ok(());
+    };
+    let expected = expectations
+        .expected_outputs
+        .get(stage_index as usize)
+        .and_then(|outputs| outputs.get(split_index))
+        .copied()
+        .unwrap_or_default();
+    if actual < expected.mul_floor(Decimal::one() - expectations.tolerance) {
+        return Err(ContractError::SplitOutputDeviation {
+            stage_index,
+            split_index,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Enforces the caller's minimum on the route's final output. Only called once the holdings are
+/// normalized into the single asset the minimum is declared in, so native and CW20 forms of the
+/// output are never compared against it separately; any other asset is rejected.
+fn check_minimum_receive(
+    plan: &RoutePlan,
+    final_info: &amm::AssetInfo,
+    final_amount: Uint128,
+) -> Result<(), ContractError> {
+    let Some(minimum) = &plan.minimum_receive else {
+        return Ok(());
+    };
+    if minimum.info != *final_info {
+        return Err(ContractError::MinimumReceiveAssetMismatch {
+            expected: asset_id(&minimum.info).to_string(),
+            actual: asset_id(final_info).to_string(),
+        });
+    }
+    if final_amount < minimum.amount {
+        return Err(ContractError::MinimumReceiveNotMet {
+            expected: minimum.amount,
+            actual: final_amount,
+        });
+    }
+    Ok(())
+}
+
+/// Enforces the caller's USD minimum on the route's final output, valued at the price of the
+/// oracle feed configured for the asset it is paid out in.
+fn check_minimum_receive_usd(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    plan: &RoutePlan,
+    final_info: &amm::AssetInfo,
+    final_amount: Uint128,
+) -> Result<(), ContractError> {
+    let Some(minimum) = plan.minimum_receive_usd else {
+        return Ok(());
+    };
+    let value = usd_value(
+        deps,
+        env,
+        &amm::Asset {
+            info: final_info.clone(),
+            amount: final_amount,
+        },
+    )?;
+    if value < minimum {
+        return Err(ContractError::MinimumReceiveUsdNotMet {
+            expected: minimum,
+            actual: value,
+        });
+    }
+    Ok(())
+}
+
+/// Accounts for `count` reply-tracked submessages dispatched from the current nesting level.
+/// Their replies, and anything those dispatch, run before the remaining replies of the level
+/// above, so outstanding replies form a stack.
+fn record_dispatch(exec_state: &mut ExecutionState, count: usize) {
+    if count == 0 {
+        return;
+    }
+    exec_state.submessages_dispatched += count as u64;
+    exec_state.pending_replies_by_depth.push(count as u64);
+    exec_state.reply_depth = exec_state
+        .reply_depth
+        .max(exec_state.pending_replies_by_depth.len() as u64);
+}
+
+/// Accounts for a reply arriving at the innermost level that still has replies outstanding.
+fn record_reply(exec_state: &mut ExecutionState) {
+    while exec_state.pending_replies_by_depth.last() == Some(&0) {
+        exec_state.pending_replies_by_depth.pop();
+    }
+    if let Some(outstanding) = exec_state.pending_replies_by_depth.last_mut() {
+        *outstanding = outstanding.saturating_sub(1);
+    }
+}
+
+/// Profiling counters reported on the completion event.
+fn execution_counters(exec_state: &ExecutionState) -> Vec<(&str, String)> {
+    vec![
+        (
+            "submessages_dispatched",
+            exec_state.submessages_dispatched.to_string(),
+        ),
+        (
+            "conversions_performed",
+            exec_state.conversions_performed.to_string(),
+        ),
+        ("reply_depth", exec_state.reply_depth.to_string()),
+    ]
+}
+
+/// Brings the balance snapshots up to date and returns what each balance-checked token gained
+/// since the last one. A swap's own spend shows up as a drop, which only moves the snapshot.
+pub(crate) fn measure_inflows(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    exec_state: &mut ExecutionState,
+) -> Result<Vec<amm::Asset>, ContractError> {
+    let mut inflows = vec![];
+    for snapshot in exec_state.balance_snapshots.iter_mut() {
+        let balance = query_asset_balance(deps, env, &snapshot.info)?;
+        inflows.push(amm::Asset {
+            info: snapshot.info.clone(),
+            amount: balance.saturating_sub(snapshot.amount),
+        });
+        snapshot.amount = balance;
+    }
+    Ok(inflows)
+}
+
+fn is_balance_checked(exec_state: &ExecutionState, info: &amm::AssetInfo) -> bool {
+    exec_state
+        .balance_snapshots
+        .iter()
+        .any(|snapshot| snapshot.info == *info)
+}
+
+/// The measured inflow for a balance-checked token or the output of a self-registered pool, or
+/// the amount the reply reported for any other asset.
+fn measured_amount(inflows: &[amm::Asset], info: &amm::AssetInfo, reported: Uint128) -> Uint128 {
+    inflows
+        .iter()
+        .find(|inflow| inflow.info == *info)
+        .map_or(reported, |inflow| inflow.amount)
+}
+
+fn deduct_asset_amount(assets: &mut Vec<amm::Asset>, info: &amm::AssetInfo, amount: Uint128) {
+    if let Some(asset) = assets.iter_mut().find(|a| a.info == *info) {
+        asset.amount = asset.amount.saturating_sub(amount);
+    }
+    assets.retain(|a| !a.amount.is_zero());
+}
+
+/// Takes a stage conversion's missing output out of the swaps planned to spend it, starting with
+/// the last, so none of them offers more than the contract actually received.
+fn absorb_shortfall(swaps: &mut [PlannedSwap], mut shortfall: amm::Asset) {
+    for swap in swaps.iter_mut().rev() {
+        if shortfall.amount.is_zero() {
+            return;
+        }
+        if get_operation_input(&swap.operation).ok().as_ref() != Some(&shortfall.info) {
+            continue;
+        }
+        let taken = swap.amount.min(shortfall.amount);
+        swap.amount -= taken;
+        shortfall.amount -= taken;
+    }
+}
+
+/// The route's client tag as a completion attribute, if it has one.
+fn tag_attribute(plan: &RoutePlan) -> Option<(&str, String)> {
+    plan.tag.clone().map(|tag| ("tag", tag))
+}
+
+fn handle_conversion_reply(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let master_reply_id = msg.id;
+    // Stage conversions are sized by the planner, so any hook-recorded amount is not needed.
+    take_conversion_result(deps.storage, master_reply_id)?;
+    for inflow in inflows {
+        deduct_asset_amount(
+            &mut exec_state.conversion_shortfalls,
+            &inflow.info,
+            inflow.amount,
+        );
+    }
+    exec_state.replies_expected -= 1; // Mutate exec_state
+
+    if exec_state.replies_expected > 0 {
+        // Save the small, mutated exec_state
+        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+        return Ok(Response::new().add_attribute("action", "accumulating_conversion_outputs"));
+    }
+
+    // Take pending_swaps from the mutated exec_state
+    let mut swaps_to_execute = std::mem::take(&mut exec_state.pending_swaps);
+    for shortfall in std::mem::take(&mut exec_state.conversion_shortfalls) {
+        absorb_shortfall(&mut swaps_to_execute, shortfall);
+    }
+
+    // Call the updated execute_planned_swaps with both state objects
+    execute_planned_swaps(
+        &mut deps,
+        env,
+        exec_state,
+        plan,
+        master_reply_id,
+        swaps_to_execute,
+    )
+}
+
+/// Why a route sends an asset through the cw20 adapter, the wrapped-INJ contract or a native
+/// converter.
+#[derive(Clone, Copy)]
+enum ConversionReason {
+    /// The next stage's splits need more of one form of its input than the route holds.
+    SurplusRebalance,
+    /// A path's next operation, or its declared output, is the other form of what it was paid.
+    PathMismatch,
+    /// The route's holdings are normalized into the single asset it pays out.
+    FinalNormalization,
+}
+
+impl ConversionReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConversionReason::SurplusRebalance => "surplus-rebalance",
+            ConversionReason::PathMismatch => "path-mismatch",
+            ConversionReason::FinalNormalization => "final-normalization",
+        }
+    }
+}
+
+/// An `adapter_conversion` event for one conversion, so users can trace why their tokens went
+/// through the adapter and what the round-trip cost them.
+fn conversion_event(
+    route_id: u64,
+    stage_index: u64,
+    from: &amm::Asset,
+    to: &amm::AssetInfo,
+    reason: ConversionReason,
+) -> Event {
+    let direction = match (&from.info, to) {
+        (amm::AssetInfo::Token { .. }, _) => "cw20_to_native",
+        (_, amm::AssetInfo::Token { .. }) => "native_to_cw20",
+        _ => "native_to_native",
+    };
+    Event::new("adapter_conversion")
+        .add_attribute("route_id", route_id.to_string())
+        .add_attribute("stage_index", stage_index.to_string())
+        .add_attribute("direction", direction)
+        .add_attribute("asset", asset_id(&from.info))
+        .add_attribute("amount", from.amount.to_string())
+        .add_attribute("reason", reason.as_str())
+}
+
+/// The message converting `from` into `to`, its other form: through the registered converter
+/// between two native denoms, through the wrapped-INJ contract for INJ when one is set, and
+/// through the cw20 adapter for anything else.
+fn create_conversion_msg(
+    storage: &dyn Storage,
+    from: &amm::Asset,
+    to: &amm::AssetInfo,
     env: &Env,
+    route_id: u64,
 ) -> Result<CosmosMsg<InjectiveMsgWrapper>, ContractError> {
+    if let (amm::AssetInfo::NativeToken { denom }, amm::AssetInfo::NativeToken { denom: target }) =
+        (&from.info, to)
+    {
+        // The converter pays the other denom back by bank send, read from the transfer event.
+        let (converter, to_canonical) = match NATIVE_CONVERTERS.may_load(storage, denom)? {
+            Some(converter) if converter.canonical_denom == *target => (converter, true),
+            _ => match NATIVE_CONVERTERS.may_load(storage, target)? {
+                Some(converter) if converter.canonical_denom == *denom => (converter, false),
+                _ => {
+                    return Err(ContractError::NativeConverterMissing {
+                        from: denom.clone(),
+                        to: target.clone(),
+                    })
+                }
+            },
+        };
+        return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: converter.contract.to_string(),
+            msg: converter.conversion_msg(to_canonical, from.amount, &env.contract.address)?,
+            funds: vec![Coin {
+                denom: denom.clone(),
+                amount: from.amount,
+            }],
+        }));
+    }
+
+    if let Some(wrapper) = WRAPPED_INJ.may_load(storage)? {
+        match &from.info {
+            // Unwrap: the INJ comes back by bank send, read from the reply's transfer event.
+            amm::AssetInfo::Token { contract_addr } if contract_addr == wrapper.as_str() => {
+                return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: wrapper.to_string(),
+                    msg: to_json_binary(&wrapped_inj::ExecuteMsg::Withdraw {
+                        amount: from.amount,
+                    })?,
+                    funds: vec![],
+                }));
+            }
+            // Wrap: the CW20 is minted to this contract, read from the reply's mint event.
+            amm::AssetInfo::NativeToken { denom } if denom == INJ_DENOM => {
+                return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: wrapper.to_string(),
+                    msg: to_json_binary(&wrapped_inj::ExecuteMsg::Deposit {})?,
+                    funds: vec![Coin {
+                        denom: denom.clone(),
+                        amount: from.amount,
+                    }],
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    let config = CONFIG.load(storage)?;
     match &from.info {
         // Convert CW20 -> Native
         amm::AssetInfo::Token { contract_addr } => {
@@ -432,10 +2644,13 @@ fn create_conversion_msg(
             }))
         }
         // Convert Native -> CW20
+        // The adapter sends the CW20 back through our `Receive` hook, tagged with the route id,
+        // so the converted amount is recorded in state rather than parsed from events.
         amm::AssetInfo::NativeToken { denom } => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
             contract_addr: config.cw20_adapter_address.to_string(),
-            msg: to_json_binary(&cw20_adapter::ExecuteMsg::RedeemAndTransfer {
-                recipient: Some(env.contract.address.to_string()),
+            msg: to_json_binary(&cw20_adapter::ExecuteMsg::RedeemAndSend {
+                recipient: env.contract.address.to_string(),
+                submsg: to_json_binary(&Cw20HookMsg::InternalConversion { route_id })?,
             })?,
             funds: vec![Coin {
                 denom: denom.clone(),
@@ -445,311 +2660,3247 @@ fn create_conversion_msg(
     }
 }
 
-fn get_operation_output(op: &Operation) -> Result<amm::AssetInfo, ContractError> {
-    Ok(match op {
-        Operation::AmmSwap(o) => o.ask_asset_info.clone(),
-        Operation::OrderbookSwap(o) => o.ask_asset_info.clone(),
-    })
-}
+fn take_conversion_result(storage: &mut dyn Storage, route_id: u64) -> StdResult<Option<Uint128>> {
+    let amount = CONVERSION_RESULTS.may_load(storage, route_id)?;
+    if amount.is_some() {
+        CONVERSION_RESULTS.remove(storage, route_id);
+    }
+    Ok(amount)
+}
+
+fn get_operation_output(op: &Operation) -> Result<amm::AssetInfo, ContractError> {
+    Ok(op.ask_asset_info()?)
+}
+
+/// The asset the contract receives when `op` replies as a swap. A zap's swap pays out the pool's
+/// other asset, which only becomes the operation's output once provided. An orderbook swap into a
+/// CW20 pays out its adapter denom, which only becomes the CW20 once converted.
+fn get_swap_output(storage: &dyn Storage, op: &Operation) -> Result<amm::AssetInfo, ContractError> {
+    match op {
+        Operation::ProvideLiquidity(lp_op) => Ok(lp_op.other_asset_info.clone()),
+        Operation::OrderbookSwap(ob_op) => Ok(amm::AssetInfo::NativeToken {
+            denom: orderbook_settlement_denom(storage, &ob_op.ask_asset_info)?,
+        }),
+        op => get_operation_output(op),
+    }
+}
+
+/// Finds the swap events of a pool of `stage` registered with its own event format. The pool is
+/// the one the reply comes from if it ran as part of it, and the events may be emitted by any
+/// contract it called.
+fn find_registered_swap_event<'a>(
+    storage: &dyn Storage,
+    events: &'a [Event],
+    stage: &Stage,
+) -> StdResult<Option<(Addr, Vec<&'a Event>, PoolEventFormat)>> {
+    for split in &stage.splits {
+        for op in &split.path {
+            let pool = Addr::unchecked(get_operation_address(op));
+            let Some(format) = load_pool_config(storage, &pool)?.event_format else {
+                continue;
+            };
+            let ran = events.iter().any(|e| {
+                e.attributes
+                    .iter()
+                    .any(|a| a.key == "_contract_address" && a.value == pool.as_str())
+            });
+            if !ran {
+                continue;
+            }
+            let swap_events =
+                trailing_swap_events(events, |e| is_registered_swap_event(e, &format));
+            if !swap_events.is_empty() {
+                return Ok(Some((pool, swap_events, format)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn is_registered_swap_event(event: &Event, format: &PoolEventFormat) -> bool {
+    event.ty == format.event_type
+        && event
+            .attributes
+            .iter()
+            .any(|a| format.amount_keys.contains(&a.key))
+}
+
+/// The swap events a reply's output is read from: the last event `is_swap_event` accepts, and
+/// those of its type before it emitted by the same contract, back to the last swap event of
+/// another contract or type. A pool filling a swap in several parts emits an event per part,
+/// while a router call emits one per hop, of which only the last hop's pays the route. A pool
+/// reporting one swap in events of two types is read from the last type alone.
+fn trailing_swap_events(events: &[Event], is_swap_event: impl Fn(&Event) -> bool) -> Vec<&Event> {
+    let emitter = |event: &Event| {
+        event
+            .attributes
+            .iter()
+            .find(|a| a.key == "_contract_address")
+            .map(|a| a.value.clone())
+    };
+    let mut swap_events: Vec<&Event> = events.iter().rev().filter(|e| is_swap_event(e)).collect();
+    if let Some(last) = swap_events.first() {
+        let (last_emitter, last_type) = (emitter(last), last.ty.clone());
+        let run = swap_events
+            .iter()
+            .take_while(|e| emitter(e) == last_emitter && e.ty == last_type)
+            .count();
+        swap_events.truncate(run);
+        swap_events.reverse();
+    }
+    swap_events
+}
+
+/// The output reported across `swap_events`, read with the keys of `format` or, without one, of
+/// each event's protocol.
+fn sum_swap_event_amounts(
+    route_id: u64,
+    swap_events: &[&Event],
+    format: Option<&PoolEventFormat>,
+) -> Result<Uint128, ContractError> {
+    swap_events
+        .iter()
+        .try_fold(Uint128::zero(), |total, event| {
+            let amount = match format {
+                Some(format) => parse_amount_from_swap_event(route_id, event, &format.amount_keys)?,
+                None => parse_amount_from_swap_event(
+                    route_id,
+                    event,
+                    protocol_amount_keys(event).unwrap_or_default(),
+                )?,
+            };
+            total
+                .checked_add(amount)
+                .map_err(|_| ContractError::AmountOverflow {
+                    context: "summing a reply's swap events".to_string(),
+                })
+        })
+}
+
+/// The swap events of the protocols routes trade on, by protocol: the event type and the
+/// attributes that may hold the output amount, in order of preference. Pools reporting their
+/// output any other way are registered with `SetPoolEventFormat`.
+pub const PROTOCOL_SWAP_EVENTS: &[(&str, &str, &[&str])] = &[
+    ("choice", "wasm", &["return_amount"]),
+    ("dojoswap", "wasm", &["return_amount"]),
+    ("astroport", "wasm-astro_swap", &["return_amount"]),
+    (
+        "helix",
+        "wasm-atomic_swap_execution",
+        &["swap_final_amount"],
+    ),
+];
+
+/// The amount attributes of `event` if it is the swap event of a protocol in
+/// `PROTOCOL_SWAP_EVENTS`. A type matches as it is, or as the name of a custom event, which wasmd
+/// prefixes with `wasm-`.
+fn protocol_amount_keys(event: &Event) -> Option<&'static [&'static str]> {
+    PROTOCOL_SWAP_EVENTS
+        .iter()
+        .find(|(_, event_type, amount_keys)| {
+            (event.ty == *event_type || event.ty.strip_prefix("wasm-") == Some(*event_type))
+                && event
+                    .attributes
+                    .iter()
+                    .any(|attr| amount_keys.contains(&attr.key.as_str()))
+        })
+        .map(|(_, _, amount_keys)| *amount_keys)
+}
+
+/// Reads the output amount from a pool's swap event, from the first of `amount_keys` it has.
+fn parse_amount_from_swap_event<K: AsRef<str>>(
+    route_id: u64,
+    event: &Event,
+    amount_keys: &[K],
+) -> Result<Uint128, ContractError> {
+    let amount_str_opt = amount_keys.iter().find_map(|key| {
+        event
+            .attributes
+            .iter()
+            .find(|attr| attr.key == key.as_ref())
+            .map(|attr| attr.value.clone())
+    });
+
+    match amount_str_opt {
+        Some(amount_str) => {
+            let integer_part_str = if let Some(period_pos) = amount_str.find('.') {
+                &amount_str[..period_pos]
+            } else {
+                &amount_str
+            };
+
+            integer_part_str
+                .parse::<Uint128>()
+                .map_err(|_| ContractError::MalformedAmountInReply {
+                    route_id,
+                    value: amount_str,
+                })
+        }
+        None => Ok(Uint128::zero()),
+    }
+}
+
+fn parse_amount_from_conversion_reply(
+    storage: &mut dyn Storage,
+    msg: &Reply,
+    env: &Env,
+) -> Result<Uint128, ContractError> {
+    // Prefer the amount recorded by the `InternalConversion` hook for this route. Conversions
+    // that pay out via bank send (CW20 -> Native) have no hook, so fall back to their events.
+    if let Some(amount) = take_conversion_result(storage, msg.id)? {
+        return Ok(amount);
+    }
+
+    let events = &msg
+        .result
+        .clone()
+        .into_result()
+        .map_err(|e| ContractError::SubmessageResultError { error: e })?
+        .events;
+
+    if let Some(transfer_event) = events.iter().find(|e| {
+        e.ty == "transfer"
+            && e.attributes
+                .iter()
+                .any(|a| a.key == "recipient" && a.value == env.contract.address.to_string())
+    }) {
+        let amount_attr = transfer_event
+            .attributes
+            .iter()
+            .find(|a| a.key == "amount")
+            .ok_or(ContractError::NoAmountInReply { route_id: msg.id })?;
+
+        let numeric_part =
+            if let Some(first_non_digit) = amount_attr.value.find(|c: char| !c.is_ascii_digit()) {
+                &amount_attr.value[..first_non_digit]
+            } else {
+                &amount_attr.value
+            };
+
+        return numeric_part.parse::<Uint128>().map_err(|_| {
+            ContractError::MalformedAmountInReply {
+                route_id: msg.id,
+                value: amount_attr.value.clone(),
+            }
+        });
+    }
+
+    if let Some(wasm_event) = events.iter().find(|e| {
+        e.ty.starts_with("wasm")
+            && e.attributes
+                .iter()
+                .any(|a| a.key == "action" && a.value == "transfer")
+    }) {
+        let amount_attr = wasm_event
+            .attributes
+            .iter()
+            .find(|a| a.key == "amount")
+            .ok_or(ContractError::NoAmountInReply { route_id: msg.id })?;
+
+        return amount_attr.value.parse::<Uint128>().map_err(|_| {
+            ContractError::MalformedAmountInReply {
+                route_id: msg.id,
+                value: amount_attr.value.clone(),
+            }
+        });
+    }
+
+    // Wrapping INJ mints the CW20 straight to this contract.
+    if let Some(mint_event) = events.iter().find(|e| {
+        e.ty.starts_with("wasm")
+            && e.attributes
+                .iter()
+                .any(|a| a.key == "action" && a.value == "mint")
+            && e.attributes
+                .iter()
+                .any(|a| a.key == "to" && a.value == env.contract.address.as_str())
+    }) {
+        let amount_attr = mint_event
+            .attributes
+            .iter()
+            .find(|a| a.key == "amount")
+            .ok_or(ContractError::NoAmountInReply { route_id: msg.id })?;
+
+        return amount_attr.value.parse::<Uint128>().map_err(|_| {
+            ContractError::MalformedAmountInReply {
+                route_id: msg.id,
+                value: amount_attr.value.clone(),
+            }
+        });
+    }
+
+    Err(ContractError::NoConversionEventInReply { route_id: msg.id })
+}
+
+fn get_operation_input(op: &Operation) -> Result<amm::AssetInfo, ContractError> {
+    Ok(op.offer_asset_info()?)
+}
+
+fn execute_planned_swaps(
+    deps: &mut DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    reply_id: u64,
+    swaps: Vec<PlannedSwap>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let stage = plan
+        .stage(deps.storage, exec_state.current_stage_index)?
+        .ok_or(ContractError::EmptyRoute {})?;
+    let (swaps, health_attrs) = match &plan.pool_health_check {
+        Some(policy) => apply_pool_health_check(deps.as_ref(), exec_state, stage, policy, swaps)?,
+        None => (swaps, vec![]),
+    };
+    let mut submessages = vec![];
+
+    for swap in swaps.into_iter().filter(|s| !s.amount.is_zero()) {
+        let offer_asset_info = get_operation_input(&swap.operation)?;
+        let msg = create_path_swap_msg(
+            deps,
+            plan,
+            stage,
+            &swap.operation,
+            &offer_asset_info,
+            swap.amount,
+            &env,
+        )?;
+        record_dispatched_input(
+            deps.as_ref(),
+            exec_state,
+            &swap.operation,
+            &offer_asset_info,
+            swap.amount,
+            (swap.split_index as usize, 0),
+        )?;
+        submessages.push(swap_submsg(msg, reply_id, (swap.split_index as usize, 0))?);
+    }
+
+    if submessages.is_empty() {
+        exec_state.current_stage_index += 1;
+        return Ok(proceed_to_next_step(deps, env, exec_state, plan, reply_id)?
+            .add_attributes(health_attrs));
+    }
+
+    exec_state.awaiting = Awaiting::Swaps;
+    exec_state.replies_expected = submessages.len() as u64;
+    record_dispatch(exec_state, submessages.len());
+
+    EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
+
+    Ok(Response::new()
+        .add_submessages(submessages)
+        .add_attribute("action", "executing_planned_swaps")
+        .add_attribute("stage_index", exec_state.current_stage_index.to_string())
+        .add_attributes(health_attrs))
+}
+
+/// Probes the pools along the path of every planned swap and applies `policy` to the swaps whose
+/// path has a pool that reverts or returns nothing. Skipped inputs are refunded with the other
+/// unswapped inputs on completion.
+fn apply_pool_health_check(
+    deps: Deps<InjectiveQueryWrapper>,
+    exec_state: &mut ExecutionState,
+    stage: &Stage,
+    policy: &PoolHealthPolicy,
+    swaps: Vec<PlannedSwap>,
+) -> Result<(Vec<PlannedSwap>, Vec<(&'static str, String)>), ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let mut healthy = vec![];
+    let mut unhealthy = vec![];
+    for swap in swaps {
+        let unhealthy_pool = if swap.amount.is_zero() {
+            None
+        } else {
+            find_unhealthy_pool(deps, &config, stage, &swap)?
+        };
+        match unhealthy_pool {
+            Some(pool) => unhealthy.push((swap, pool)),
+            None => healthy.push(swap),
+        }
+    }
+
+    let stage_index = exec_state.current_stage_index;
+    let mut attrs = vec![];
+    for (swap, pool) in unhealthy {
+        let offer_asset_info = get_operation_input(&swap.operation)?;
+        match policy {
+            PoolHealthPolicy::Abort => {
+                return Err(ContractError::PoolUnhealthy { pool, stage_index })
+            }
+            PoolHealthPolicy::Skip => {
+                add_asset_amount(
+                    &mut exec_state.skipped_assets,
+                    &offer_asset_info,
+                    swap.amount,
+                );
+            }
+            PoolHealthPolicy::Fallback => {
+                let mut target = None;
+                for candidate in healthy.iter_mut().filter(|s| !s.amount.is_zero()) {
+                    if get_operation_input(&candidate.operation)? == offer_asset_info {
+                        target = Some(candidate);
+                        break;
+                    }
+                }
+                let target = target.ok_or(ContractError::PoolUnhealthy {
+                    pool: pool.clone(),
+                    stage_index,
+                })?;
+                target.amount += swap.amount;
+            }
+        }
+        attrs.push(("unhealthy_pool", pool));
+    }
+    Ok((healthy, attrs))
+}
+
+/// The first pool on the path of `swap` that reverts or returns nothing when simulated with the
+/// input the hops before it are simulated to deliver. An orderbook fills nothing for an
+/// input below its tick size, which ends the probe rather than failing it, and takes an adapter
+/// token in its native form. A nested aggregator also ends the probe, as its own route is checked
+/// when it runs.
+fn find_unhealthy_pool(
+    deps: Deps<InjectiveQueryWrapper>,
+    config: &Config,
+    stage: &Stage,
+    swap: &PlannedSwap,
+) -> Result<Option<String>, ContractError> {
+    let querier = deps.into_empty().querier;
+    let path = &stage.splits[swap.split_index as usize].path;
+    let mut input = amm::Asset {
+        info: get_operation_input(&swap.operation)?,
+        amount: swap.amount,
+    };
+    for op in path {
+        let is_orderbook = match op {
+            Operation::SubAggregator(_) => break,
+            Operation::OrderbookSwap(_) => true,
+            _ => false,
+        };
+        if is_orderbook && matches!(input.info, amm::AssetInfo::Token { .. }) {
+            if let Some((native, _)) =
+                adapter_counterpart(&config.cw20_adapter_address, &input.info)
+            {
+                input.info = native;
+            }
+        }
+        match simulate_single_operation(&querier, deps.storage, op, &input) {
+            Ok(output) if !output.amount.is_zero() => input = output,
+            Ok(_) if is_orderbook => break,
+            _ => return Ok(Some(get_operation_address(op).clone())),
+        }
+    }
+    Ok(None)
+}
+
+fn handle_path_conversion_reply(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let master_reply_id = msg.id;
+
+    let converted_amount = parse_amount_from_conversion_reply(deps.storage, &msg, &env)?;
+
+    let pending_op_details = exec_state.pending_path_op.take().ok_or_else(|| {
+        StdError::generic_err("Path conversion state is invalid: no pending operation found")
+    })?;
+
+    let converted_asset_info = get_operation_input(&pending_op_details.operation)?;
+    let converted_amount = measured_amount(inflows, &converted_asset_info, converted_amount);
+
+    let current_stage = plan
+        .stage(deps.storage, exec_state.current_stage_index)?
+        .ok_or(ContractError::EmptyRoute {})?;
+    let position = (
+        pending_op_details.split_index as usize,
+        pending_op_details.op_index as usize,
+    );
+    let swap_msg = create_path_swap_msg(
+        &mut deps,
+        plan,
+        current_stage,
+        &pending_op_details.operation,
+        &converted_asset_info,
+        converted_amount,
+        &env,
+    )?;
+    record_dispatched_input(
+        deps.as_ref(),
+        exec_state,
+        &pending_op_details.operation,
+        &converted_asset_info,
+        converted_amount,
+        position,
+    )?;
+    let sub_msg = swap_submsg(swap_msg, master_reply_id, position)?;
+    record_dispatch(exec_state, 1);
+
+    exec_state.awaiting = Awaiting::Swaps;
+
+    EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+
+    Ok(Response::new()
+        .add_submessage(sub_msg)
+        .add_attribute("action", "resuming_path_after_conversion"))
+}
+
+/// Accumulates the output of a split whose orderbook swap into a CW20 has been converted from
+/// its adapter denom.
+fn handle_output_conversion_reply(
+    mut deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    msg: Reply,
+    exec_state: &mut ExecutionState,
+    plan: &LoadedPlan,
+    inflows: &[amm::Asset],
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let master_reply_id = msg.id;
+
+    let converted_amount = parse_amount_from_conversion_reply(deps.storage, &msg, &env)?;
+
+    let split_index = exec_state.pending_output_split.take().ok_or_else(|| {
+        StdError::generic_err("Output conversion state is invalid: no pending split found")
+    })? as usize;
+    let current_stage = plan
+        .stage(deps.storage, exec_state.current_stage_index)?
+        .ok_or(ContractError::EmptyRoute {})?;
+    let last_op = current_stage
+        .splits
+        .get(split_index)
+        .and_then(|split| split.path.last())
+        .ok_or(ContractError::EmptyRoute {})?;
+    let output_info = get_operation_output(last_op)?;
+    let converted_amount = measured_amount(inflows, &output_info, converted_amount);
+
+    check_split_output(
+        plan,
+        exec_state.current_stage_index,
+        split_index,
+        converted_amount,
+    )?;
+    exec_state.accumulated_assets.push(amm::Asset {
+        info: output_info,
+        amount: converted_amount,
+    });
+    exec_state.awaiting = Awaiting::Swaps;
+    exec_state.replies_expected -= 1;
+    if exec_state.replies_expected > 0 {
+        EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+        Ok(Response::new().add_attribute("action", "accumulating_path_outputs"))
+    } else {
+        exec_state.current_stage_index += 1;
+        proceed_to_next_step(&mut deps, env, exec_state, plan, master_reply_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{
+        AmmSwapOp, FeeInfo, OrderbookSwapOp, PairType, Split, SplitExpectations, SubAggregatorOp,
+    };
+    use crate::state::{
+        update_pool_config, Integrator, NativeConverter, Parameters, PoolMetadata, PoolProtocol,
+        PriceSource, UsdPriceFeed, DUST_BUCKET, DUST_THRESHOLDS, FEE_MAP, PARAMETERS, ROUTE_PLANS,
+        ROUTE_STAGES, SELF_REGISTERED_POOLS, USD_PRICE_FEEDS,
+    };
+    use crate::testing::{
+        adapter_bank_transfer_reply, adapter_cw20_reply, amm_swap_reply, astroport_swap_reply,
+        bank_settlement_reply, empty_reply, execution_state, mock_deps, nested_swap_reply,
+        provide_liquidity_reply, route_plan, router_swap_reply, seed_route, set_pyth_prices,
+        sub_aggregator_reply, wrapped_inj_mint_reply, CountingStorage, MockContracts, MockDeps,
+    };
+    use cosmwasm_std::testing::{message_info, mock_env, MockApi};
+    use cosmwasm_std::{BankMsg, QuerierWrapper, ReplyOn, SubMsgResult};
+
+    const ROUTE_ID: u64 = 7;
+
+    fn native(denom: &str) -> amm::AssetInfo {
+        amm::AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        }
+    }
+
+    fn amm_op(pool: &Addr, offer: amm::AssetInfo, ask: amm::AssetInfo) -> Operation {
+        Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: offer,
+            ask_asset_info: ask,
+            min_out: None,
+        })
+    }
+
+    fn attr(response: &Response<InjectiveMsgWrapper>, key: &str) -> Option<String> {
+        response
+            .attributes
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    }
+
+    /// Seeds a route whose single stage has one INJ -> USDT split per pool, all dispatched.
+    fn seed_parallel_swaps(deps: &mut MockDeps, pools: &[&Addr]) -> Addr {
+        let sender = deps.api.addr_make("user");
+        let stage = Stage {
+            splits: pools
+                .iter()
+                .map(|pool| Split {
+                    percent: (100 / pools.len()) as u8,
+                    path: vec![amm_op(pool, native("inj"), native("usdt"))],
+                })
+                .collect(),
+        };
+        let plan = route_plan(
+            sender.clone(),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        let exec_state = execution_state(Awaiting::Swaps, 0, pools.len() as u64);
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        sender
+    }
+
+    #[test]
+    fn test_zero_output_path_completes_without_output() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let sender = seed_parallel_swaps(&mut deps, &[&pool_a, &pool_b]);
+
+        let res = handle_reply(deps.as_mut(), mock_env(), empty_reply(ROUTE_ID)).unwrap();
+        assert_eq!(
+            attr(&res, "info").as_deref(),
+            Some("zero_value_path_completed")
+        );
+        assert!(res.messages.is_empty());
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.replies_expected, 1);
+        assert!(exec_state.accumulated_assets.is_empty());
+
+        // Only the surviving path's output is paid out.
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 500),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("500"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: sender.to_string(),
+                amount: vec![Coin::new(500u128, "usdt")],
+            })
+        );
+        assert!(!EXECUTION_STATES.has(&deps.storage, ROUTE_ID));
+        assert!(RECEIPTS.has(&deps.storage, ROUTE_ID));
+    }
+
+    /// Seeds a route whose single split is an INJ -> USDT route run by `sub_aggregator`.
+    fn seed_sub_aggregator_route(deps: &mut MockDeps, sub_aggregator: &Addr) {
+        let pool = deps.api.addr_make("pool");
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::SubAggregator(SubAggregatorOp {
+                    contract: sub_aggregator.to_string(),
+                    stages: vec![Stage {
+                        splits: vec![Split {
+                            percent: 100,
+                            path: vec![amm_op(&pool, native("inj"), native("usdt"))],
+                        }],
+                    }],
+                    min_out: None,
+                })],
+            }],
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        let exec_state = execution_state(Awaiting::Swaps, 0, 1);
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+    }
+
+    #[test]
+    fn test_sub_aggregator_output_is_its_final_received() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let sub_aggregator = deps.api.addr_make("sub_aggregator");
+        seed_sub_aggregator_route(&mut deps, &sub_aggregator);
+
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            sub_aggregator_reply(ROUTE_ID, sub_aggregator.as_str(), Some(700)),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("700"));
+    }
+
+    #[test]
+    fn test_sub_aggregator_that_did_not_complete_fails_the_route() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let sub_aggregator = deps.api.addr_make("sub_aggregator");
+        seed_sub_aggregator_route(&mut deps, &sub_aggregator);
+
+        // A nested route that checkpointed reports no `final_received`, which is not zero output.
+        let err = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            sub_aggregator_reply(ROUTE_ID, sub_aggregator.as_str(), None),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SubAggregatorIncomplete {
+                route_id: ROUTE_ID,
+                contract: sub_aggregator.to_string(),
+            }
+        );
+
+        // An empty completion still reports it, as zero.
+        seed_sub_aggregator_route(&mut deps, &sub_aggregator);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            sub_aggregator_reply(ROUTE_ID, sub_aggregator.as_str(), Some(0)),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("aggregate_swap_complete_empty")
+        );
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn test_silent_reply_is_checked_against_its_splits_expectation() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let stage = Stage {
+            splits: [&pool_a, &pool_b]
+                .iter()
+                .map(|pool| Split {
+                    percent: 50,
+                    path: vec![amm_op(pool, native("inj"), native("usdt"))],
+                })
+                .collect(),
+        };
+        let mut plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        plan.split_expectations = Some(SplitExpectations {
+            expected_outputs: vec![vec![Uint128::zero(), Uint128::new(500)]],
+            tolerance: Decimal::percent(1),
+        });
+        let seed = |deps: &mut MockDeps| {
+            seed_route(
+                &mut deps.storage,
+                ROUTE_ID,
+                &plan,
+                &execution_state(Awaiting::Swaps, 0, 2),
+            )
+            .unwrap();
+        };
+
+        // Nothing names the pool that replied, so the stage's expectations cannot be checked.
+        seed(&mut deps);
+        let err = handle_reply(deps.as_mut(), mock_env(), empty_reply(ROUTE_ID)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnattributedEmptyReply { stage_index: 0 }
+        );
+
+        // The payload names the split, whose path cannot end with nothing when 500 is expected.
+        let silent_reply = |position: (usize, usize)| {
+            let mut reply = empty_reply(ROUTE_ID);
+            reply.payload = to_json_binary(&position).unwrap();
+            reply
+        };
+        seed(&mut deps);
+        let err = handle_reply(deps.as_mut(), mock_env(), silent_reply((1, 0))).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SplitOutputDeviation {
+                stage_index: 0,
+                split_index: 1,
+                expected: Uint128::new(500),
+                actual: Uint128::zero(),
+            }
+        );
+
+        // The split expected to output nothing may.
+        seed(&mut deps);
+        let res = handle_reply(deps.as_mut(), mock_env(), silent_reply((0, 0))).unwrap();
+        assert_eq!(
+            attr(&res, "info").as_deref(),
+            Some("zero_value_path_completed")
+        );
+    }
+
+    #[test]
+    fn test_bank_settled_swap_output_is_read_from_the_transfer() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let sender = seed_parallel_swaps(&mut deps, &[&pool_a, &pool_b]);
+        let env = mock_env();
+
+        // A refund of unspent input alongside the payout is not counted as output.
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            bank_settlement_reply(ROUTE_ID, &pool_a, &env.contract.address, "300usdt,5inj"),
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(
+            exec_state.accumulated_assets,
+            vec![amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(300),
+            }]
+        );
+
+        // A transfer to anyone else is not a settlement, so the path has no output.
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            bank_settlement_reply(ROUTE_ID, &pool_b, &sender, "200usdt"),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("300"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: sender.to_string(),
+                amount: vec![Coin::new(300u128, "usdt")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_multi_output_route_pays_out_each_final_asset() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let sender = deps.api.addr_make("user");
+        let seed = |deps: &mut MockDeps, atom_minimum: u128| {
+            let stage = Stage {
+                splits: vec![
+                    Split {
+                        percent: 50,
+                        path: vec![amm_op(&pool_a, native("inj"), native("usdt"))],
+                    },
+                    Split {
+                        percent: 50,
+                        path: vec![amm_op(&pool_b, native("inj"), native("atom"))],
+                    },
+                ],
+            };
+            let mut plan = route_plan(
+                sender.clone(),
+                vec![stage],
+                amm::Asset {
+                    info: native("inj"),
+                    amount: Uint128::new(100),
+                },
+                None,
+            );
+            plan.final_assets = Some(vec![
+                amm::Asset {
+                    info: native("usdt"),
+                    amount: Uint128::new(300),
+                },
+                amm::Asset {
+                    info: native("atom"),
+                    amount: Uint128::new(atom_minimum),
+                },
+            ]);
+            let exec_state = execution_state(Awaiting::Swaps, 0, 2);
+            seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        };
+        let run = |deps: &mut MockDeps| {
+            handle_reply(
+                deps.as_mut(),
+                mock_env(),
+                amm_swap_reply(ROUTE_ID, pool_a.as_str(), 300),
+            )
+            .unwrap();
+            handle_reply(
+                deps.as_mut(),
+                mock_env(),
+                amm_swap_reply(ROUTE_ID, pool_b.as_str(), 40),
+            )
+        };
+
+        // Each declared asset is held to its own minimum.
+        seed(&mut deps, 41);
+        assert_eq!(
+            run(&mut deps).unwrap_err(),
+            ContractError::FinalAssetMinimumNotMet {
+                asset: "atom".to_string(),
+                expected: Uint128::new(41),
+                actual: Uint128::new(40),
+            }
+        );
+
+        // Neither output is converted into the other; both are paid out as they are.
+        seed(&mut deps, 40);
+        let res = run(&mut deps).unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("aggregate_swap_complete_multi_output")
+        );
+        assert_eq!(
+            res.messages
+                .iter()
+                .map(|sub| sub.msg.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: sender.to_string(),
+                    amount: vec![Coin::new(300u128, "usdt")],
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: sender.to_string(),
+                    amount: vec![Coin::new(40u128, "atom")],
+                }),
+            ]
+        );
+        let receipt = RECEIPTS.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(receipt.output.amount, Uint128::new(300));
+        assert_eq!(
+            receipt.additional_outputs,
+            vec![amm::Asset {
+                info: native("atom"),
+                amount: Uint128::new(40),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_zap_provides_the_swap_output_with_the_retained_input() {
+        let pool = MockApi::default().addr_make("pool");
+        // After the swap the pool holds 10 USDT per INJ.
+        let pool_assets = vec![
+            amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(1000),
+            },
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+        ];
+        let mut deps = mock_deps(
+            &[],
+            MockContracts::new().with_contract(pool.as_str(), move |msg| match from_json(msg)? {
+                amm::QueryMsg::Pool {} => to_json_binary(&amm::PoolResponse {
+                    assets: pool_assets.clone(),
+                    total_share: Uint128::new(1000),
+                }),
+                _ => Err(StdError::generic_err("mock pool only answers Pool")),
+            }),
+        );
+        let lp_token = deps.api.addr_make("lp_token");
+        let sender = deps.api.addr_make("user");
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::ProvideLiquidity(ProvideLiquidityOp {
+                    pool_address: pool.to_string(),
+                    pair_type: PairType::Xyk {},
+                    offer_asset_info: native("usdt"),
+                    other_asset_info: native("inj"),
+                    lp_token: amm::AssetInfo::Token {
+                        contract_addr: lp_token.to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        };
+        let plan = route_plan(
+            sender.clone(),
+            vec![stage],
+            amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(101),
+            },
+            None,
+        );
+        let mut exec_state = execution_state(Awaiting::Swaps, 0, 1);
+        exec_state.pending_zaps = vec![PendingZap {
+            split_index: 0,
+            retained: amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(51),
+            },
+        }];
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::percent(10))
+            .unwrap();
+
+        // The swap is not charged; its output is provided with as much of the retained input as
+        // the pool's ratio takes.
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 5),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            msg,
+            funds,
+        }) = &res.messages[0].msg
+        else {
+            panic!("expected a liquidity provision");
+        };
+        assert_eq!(contract_addr, pool.as_str());
+        assert_eq!(
+            funds,
+            &vec![Coin::new(5u128, "inj"), Coin::new(50u128, "usdt")]
+        );
+        let amm::AmmPairExecuteMsg::ProvideLiquidity {
+            receiver,
+            slippage_tolerance,
+            ..
+        } = cosmwasm_std::from_json(msg).unwrap()
+        else {
+            panic!("expected a liquidity provision");
+        };
+        assert_eq!(receiver, Some(mock_env().contract.address.to_string()));
+        assert_eq!(
+            slippage_tolerance,
+            Some(crate::execute::ZAP_SLIPPAGE_TOLERANCE)
+        );
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.awaiting, Awaiting::LiquidityProvision);
+        assert!(exec_state.pending_zaps.is_empty());
+        // The USDT the ratio leaves over is refunded at the end.
+        assert_eq!(
+            exec_state.skipped_assets,
+            vec![amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(1),
+            }]
+        );
+
+        // The minted LP tokens, less the pool's fee, are paid out.
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            provide_liquidity_reply(ROUTE_ID, pool.as_str(), 1000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "fee_collected").as_deref(), Some("100"));
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("900"));
+        let receipt = RECEIPTS.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(
+            receipt.output,
+            amm::Asset {
+                info: amm::AssetInfo::Token {
+                    contract_addr: lp_token.to_string(),
+                },
+                amount: Uint128::new(900),
+            }
+        );
+    }
+
+    #[test]
+    fn test_delegation_is_encoded_as_an_authz_exec() {
+        let plan = route_plan(
+            Addr::unchecked("inj1sender"),
+            vec![],
+            amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(1000),
+            },
+            None,
+        );
+        let mut env = mock_env();
+        env.contract.address = Addr::unchecked("inj1aggregator");
+        let action = PostAction::Delegate {
+            validator: "injvaloper1".to_string(),
+        };
+
+        let msg = create_post_action_msg(&env, &plan, &action, &native("inj"), Uint128::new(100))
+            .unwrap();
+        // Each field is its tag, (field number << 3) | 2, its length and its bytes.
+        let expected = [
+            // MsgExec.grantee
+            &[0x0a, 0x0e][..],
+            b"inj1aggregator",
+            // MsgExec.msgs[0], an Any of 2 + 35 + 2 + 37 bytes
+            &[0x12, 0x4c],
+            &[0x0a, 0x23],
+            b"/cosmos.staking.v1beta1.MsgDelegate",
+            &[0x12, 0x25],
+            // MsgDelegate.delegator_address and validator_address
+            &[0x0a, 0x0a],
+            b"inj1sender",
+            &[0x12, 0x0b],
+            b"injvaloper1",
+            // MsgDelegate.amount, a Coin of its denom and decimal amount
+            &[0x1a, 0x0a],
+            &[0x0a, 0x03],
+            b"inj",
+            &[0x12, 0x03],
+            b"100",
+        ]
+        .concat();
+        assert_eq!(
+            msg,
+            CosmosMsg::Any(AnyMsg {
+                type_url: "/cosmos.authz.v1beta1.MsgExec".to_string(),
+                value: Binary::new(expected),
+            })
+        );
+
+        // Lengths of 128 and more take several bytes, 300 being the protobuf spec's example.
+        let mut buf = vec![];
+        encode_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_fee_truncates_to_zero_on_small_outputs() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::permille(3))
+            .unwrap();
+
+        // 0.3% of 333 is 0.999, which rounds down to no fee at all.
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 333),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("333"));
+        assert_eq!(attr(&res, "fee_collected"), None);
+        assert_eq!(res.messages.len(), 1);
+
+        // 0.3% of 1,000 is 3. The fee send goes out ahead of the payout.
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("997"));
+        assert_eq!(attr(&res, "fee_collected").as_deref(), Some("3"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: deps.api.addr_make("collector").to_string(),
+                amount: vec![Coin::new(3u128, "usdt")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_fee_deduction_is_capped_at_the_whole_amount() {
+        let amount = Uint128::new(1_000);
+        assert_eq!(
+            deduct_fee(amount, Decimal::one()).unwrap(),
+            (amount, Uint128::zero())
+        );
+        assert_eq!(
+            deduct_fee(amount, Decimal::zero()).unwrap(),
+            (Uint128::zero(), amount)
+        );
+        assert!(deduct_fee(amount, Decimal::percent(101)).is_err());
+    }
+
+    #[test]
+    fn test_fee_is_paid_out_in_the_asset_it_was_taken_in() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        let collector = deps.api.addr_make("collector");
+        let token = deps.api.addr_make("token");
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::permille(3))
+            .unwrap();
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let plan = ROUTE_PLANS.load(&deps.storage, ROUTE_ID).unwrap();
+        let mut exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+
+        let (fee, left) = take_aggregator_fee(
+            &mut deps.storage,
+            &mut exec_state,
+            &plan,
+            ROUTE_ID,
+            &pool,
+            &native("usdt"),
+            Uint128::new(1_000),
+        )
+        .unwrap();
+        assert_eq!((fee.amount, left), (Uint128::new(3), Uint128::new(997)));
+        assert_eq!(fee.sends[0].id, FEE_DELIVERY_REPLY_ID);
+        assert_eq!(
+            fee.sends[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: collector.to_string(),
+                amount: vec![Coin::new(3u128, "usdt")],
+            })
+        );
+
+        let cw20 = amm::AssetInfo::Token {
+            contract_addr: token.to_string(),
+        };
+        let (fee, left) = take_aggregator_fee(
+            &mut deps.storage,
+            &mut exec_state,
+            &plan,
+            ROUTE_ID,
+            &pool,
+            &cw20,
+            Uint128::new(1_000),
+        )
+        .unwrap();
+        assert_eq!((fee.amount, left), (Uint128::new(3), Uint128::new(997)));
+        assert_eq!(
+            fee.sends[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: token.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: collector.to_string(),
+                    amount: Uint128::new(3),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        // Both fees are recorded against the route in the asset they were taken in.
+        assert_eq!(exec_state.fees_collected.len(), 2);
+
+        // A fee that truncates to zero sends nothing and leaves the response as it was.
+        let (fee, left) = take_aggregator_fee(
+            &mut deps.storage,
+            &mut exec_state,
+            &plan,
+            ROUTE_ID,
+            &pool,
+            &cw20,
+            Uint128::new(333),
+        )
+        .unwrap();
+        assert_eq!(left, Uint128::new(333));
+        assert!(fee.sends.is_empty());
+        let response = fee.prepend_to(Response::new().add_attribute("action", "swap"));
+        assert_eq!(response.attributes.len(), 1);
+        assert!(response.messages.is_empty());
+    }
+
+    #[test]
+    fn test_fee_on_outputs_of_a_token_with_the_maximum_decimals() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::permille(3))
+            .unwrap();
+
+        // A hundred million whole tokens of 30 decimals is close to the top of Uint128.
+        let whole = 10u128.pow(amm::MAX_DECIMALS as u32);
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 100_000_000 * whole),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "fee_collected"),
+            Some((300_000 * whole).to_string())
+        );
+        assert_eq!(
+            attr(&res, "final_received"),
+            Some((99_700_000 * whole).to_string())
+        );
+    }
+
+    #[test]
+    fn test_output_below_its_usd_minimum_aborts_the_route() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        let env = mock_env();
+        let now = env.block.time.seconds() as i64;
+        USD_PRICE_FEEDS
+            .save(
+                &mut deps.storage,
+                "usdt",
+                &UsdPriceFeed {
+                    source: PriceSource::Pyth {
+                        price_id: "usdt-usd".to_string(),
+                    },
+                    decimals: 6,
+                    max_age_seconds: 60,
+                },
+            )
+            .unwrap();
+        let seed_with_minimum = |deps: &mut MockDeps, minimum: &str| {
+            seed_parallel_swaps(deps, &[&pool]);
+            let mut plan = ROUTE_PLANS.load(&deps.storage, ROUTE_ID).unwrap();
+            plan.minimum_receive_usd = Some(Decimal::from_str(minimum).unwrap());
+            ROUTE_PLANS
+                .save(&mut deps.storage, ROUTE_ID, &plan)
+                .unwrap();
+        };
+
+        // 10 USDT at 0.98 USD are worth 9.8 USD.
+        set_pyth_prices(&mut deps, &[("usdt-usd", "0.98", now - 10)]);
+        seed_with_minimum(&mut deps, "9.9");
+        let err = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 10_000_000),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::MinimumReceiveUsdNotMet {
+                expected: Decimal::from_str("9.9").unwrap(),
+                actual: Decimal::from_str("9.8").unwrap(),
+            }
+        );
+
+        seed_with_minimum(&mut deps, "9.7");
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 10_000_000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("10000000"));
+
+        // A price older than the feed's maximum age is refused rather than trusted.
+        set_pyth_prices(&mut deps, &[("usdt-usd", "0.98", now - 61)]);
+        seed_with_minimum(&mut deps, "9.7");
+        let err = handle_reply(
+            deps.as_mut(),
+            env,
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 10_000_000),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::StaleOraclePrice { .. }));
+    }
+
+    #[test]
+    fn test_integrator_fee_is_paid_out_of_the_output_before_the_minimum() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        let integrator = Integrator {
+            address: deps.api.addr_make("wallet"),
+            fee_bps: 30,
+            collector: deps.api.addr_make("wallet_collector"),
+        };
+        let seed_with_minimum = |deps: &mut MockDeps, minimum: u128| {
+            seed_parallel_swaps(deps, &[&pool]);
+            let mut plan = ROUTE_PLANS.load(&deps.storage, ROUTE_ID).unwrap();
+            plan.integrator = Some(integrator.clone());
+            plan.minimum_receive = Some(amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(minimum),
+            });
+            ROUTE_PLANS
+                .save(&mut deps.storage, ROUTE_ID, &plan)
+                .unwrap();
+        };
+
+        // 0.3% of 10 USDT is 0.03 USDT, leaving 9.97 USDT for the sender.
+        seed_with_minimum(&mut deps, 9_970_000);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 10_000_000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("9970000"));
+        assert_eq!(attr(&res, "integrator_fee").as_deref(), Some("30000"));
+        let fee_send = res
+            .messages
+            .iter()
+            .find(|sub| {
+                sub.msg
+                    == CosmosMsg::Bank(BankMsg::Send {
+                        to_address: integrator.collector.to_string(),
+                        amount: vec![Coin::new(30_000u128, "usdt")],
+                    })
+            })
+            .unwrap();
+        assert_eq!(fee_send.id, FEE_DELIVERY_REPLY_ID);
+        assert_eq!(fee_send.reply_on, ReplyOn::Error);
+
+        // A collector rejecting the send leaves the fee for it to claim.
+        handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: FEE_DELIVERY_REPLY_ID,
+                payload: fee_send.payload.clone(),
+                gas_used: 0,
+                result: SubMsgResult::Err("collector rejects funds".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            UNCLAIMED_FEES
+                .load(&deps.storage, &integrator.collector)
+                .unwrap(),
+            vec![amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(30_000),
+            }]
+        );
+
+        // The sender's minimum applies to what is left after the integrator's fee.
+        seed_with_minimum(&mut deps, 10_000_000);
+        let err = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 10_000_000),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::MinimumReceiveNotMet {
+                expected: Uint128::new(10_000_000),
+                actual: Uint128::new(9_970_000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_known_protocol_swap_events_are_read_without_registration() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            astroport_swap_reply(ROUTE_ID, pool.as_str(), 420),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("420"));
+
+        // A custom event named as a protocol's event type is read like it.
+        let mut reply = amm_swap_reply(ROUTE_ID, pool.as_str(), 300);
+        if let SubMsgResult::Ok(response) = &mut reply.result {
+            response.events[0].ty = "wasm-wasm".to_string();
+        }
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(deps.as_mut(), mock_env(), reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("300"));
+    }
+
+    #[test]
+    fn test_registered_event_format_reads_nested_swap_events() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        let engine = deps.api.addr_make("engine");
+        let reply = || {
+            nested_swap_reply(
+                ROUTE_ID,
+                pool.as_str(),
+                engine.as_str(),
+                "wasm-swap_executed",
+                "amount_out",
+                500,
+            )
+        };
+
+        // Unregistered, the pool's nested event goes unnoticed and the path counts as empty.
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(deps.as_mut(), mock_env(), reply()).unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("aggregate_swap_complete_empty")
+        );
+
+        update_pool_config(&mut deps.storage, &pool, |pool| {
+            pool.event_format = Some(PoolEventFormat {
+                event_type: "wasm-swap_executed".to_string(),
+                amount_keys: vec!["return_amount".to_string(), "amount_out".to_string()],
+            })
+        })
+        .unwrap();
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let res = handle_reply(deps.as_mut(), mock_env(), reply()).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("500"));
+    }
+
+    #[test]
+    fn test_leftovers_are_swept_into_the_output_or_refunded() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        let sweep_pool = deps.api.addr_make("sweep_pool");
+        SWEEP_POOLS
+            .save(&mut deps.storage, ("atom", "usdt"), &sweep_pool)
+            .unwrap();
+        let leftover = amm::Asset {
+            info: native("atom"),
+            amount: Uint128::new(20),
+        };
+        let seed_with_leftover = |deps: &mut MockDeps| {
+            let sender = seed_parallel_swaps(deps, &[&pool]);
+            let mut exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+            exec_state.skipped_assets = vec![leftover.clone()];
+            EXECUTION_STATES
+                .save(&mut deps.storage, ROUTE_ID, &exec_state)
+                .unwrap();
+            sender
+        };
+
+        // The route's last swap dispatches the sweep, and the route completes on its reply.
+        seed_with_leftover(&mut deps);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 500),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("leftover_sweep_started")
+        );
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Always);
+        let mut sweep_reply = amm_swap_reply(ROUTE_ID, sweep_pool.as_str(), 40);
+        sweep_reply.payload = res.messages[0].payload.clone();
+        let res = handle_reply(deps.as_mut(), mock_env(), sweep_reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("540"));
+        assert_eq!(attr(&res, "refunded_asset"), None);
+
+        // A failed sweep refunds the leftover as it is.
+        let sender = seed_with_leftover(&mut deps);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 500),
+        )
+        .unwrap();
+        let mut sweep_reply = empty_reply(ROUTE_ID);
+        sweep_reply.result = SubMsgResult::Err("pool is paused".to_string());
+        sweep_reply.payload = res.messages[0].payload.clone();
+        let res = handle_reply(deps.as_mut(), mock_env(), sweep_reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("500"));
+        assert_eq!(attr(&res, "sweep_error").as_deref(), Some("pool is paused"));
+        assert!(res.messages.iter().any(|msg| msg.msg
+            == CosmosMsg::Bank(BankMsg::Send {
+                to_address: sender.to_string(),
+                amount: vec![Coin::new(20u128, "atom")],
+            })));
+    }
+
+    #[test]
+    fn test_fee_above_the_senders_cap_aborts_the_route() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::permille(3))
+            .unwrap();
+
+        let seed_capped_route = |deps: &mut MockDeps, max_fee_bps: u16| {
+            seed_parallel_swaps(deps, &[&pool]);
+            let mut plan = ROUTE_PLANS.load(&deps.storage, ROUTE_ID).unwrap();
+            plan.max_fee_bps = Some(max_fee_bps);
+            ROUTE_PLANS
+                .save(&mut deps.storage, ROUTE_ID, &plan)
+                .unwrap();
+        };
+
+        // The pool charges 30 bps, which a cap of 30 bps accepts.
+        seed_capped_route(&mut deps, 30);
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "fee_collected").as_deref(), Some("3"));
+
+        seed_capped_route(&mut deps, 25);
+        let err = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::FeeAboveUserCap {
+                pool: pool.to_string(),
+                fee_bps: Decimal::from_ratio(30u128, 1u128),
+                max_fee_bps: 25,
+            }
+        );
+    }
+
+    #[test]
+    fn test_inj_is_wrapped_for_pools_taking_wrapped_inj() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool = deps.api.addr_make("pool");
+        let wrapper = deps.api.addr_make("winj");
+        WRAPPED_INJ.save(&mut deps.storage, &wrapper).unwrap();
+        let winj = amm::AssetInfo::Token {
+            contract_addr: wrapper.to_string(),
+        };
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![amm_op(&pool, winj.clone(), native("usdt"))],
+            }],
+        };
+        let offer = amm::Asset {
+            info: native("inj"),
+            amount: Uint128::new(100),
+        };
+        let plan = route_plan(deps.api.addr_make("user"), vec![stage], offer.clone(), None);
+        let mut exec_state = execution_state(Awaiting::Swaps, 0, 0);
+        exec_state.accumulated_assets = vec![offer];
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        let plan = LoadedPlan::load(&deps.storage, ROUTE_ID).unwrap();
+
+        let res = proceed_to_next_step(
+            &mut deps.as_mut(),
+            env.clone(),
+            &mut exec_state,
+            &plan,
+            ROUTE_ID,
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: wrapper.to_string(),
+                msg: to_json_binary(&wrapped_inj::ExecuteMsg::Deposit {}).unwrap(),
+                funds: vec![Coin::new(100u128, "inj")],
+            })
+        );
+
+        // The minted CW20 is swapped by the stage.
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            wrapped_inj_mint_reply(ROUTE_ID, &wrapper, &env.contract.address, 100),
+        )
+        .unwrap();
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) = &res.messages[0].msg else {
+            panic!("expected the stage's CW20 swap");
+        };
+        assert_eq!(*contract_addr, wrapper.to_string());
+
+        // Paying out native INJ from wrapped INJ unwraps it.
+        let unwrap = create_conversion_msg(
+            &deps.storage,
+            &amm::Asset {
+                info: winj,
+                amount: Uint128::new(50),
+            },
+            &native("inj"),
+            &env,
+            ROUTE_ID,
+        )
+        .unwrap();
+        assert_eq!(
+            unwrap,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: wrapper.to_string(),
+                msg: to_json_binary(&wrapped_inj::ExecuteMsg::Withdraw {
+                    amount: Uint128::new(50),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_native_forms_are_converted_through_their_registered_converter() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let converter = deps.api.addr_make("converter");
+        NATIVE_CONVERTERS
+            .save(
+                &mut deps.storage,
+                "peggy0xusdt",
+                &NativeConverter {
+                    contract: converter.clone(),
+                    canonical_denom: "usdt".to_string(),
+                    to_canonical_msg: r#"{"convert":{}}"#.to_string(),
+                    from_canonical_msg:
+                        r#"{"redeem":{"amount":"{amount}","recipient":"{recipient}"}}"#.to_string(),
+                },
+            )
+            .unwrap();
+        let asset = |denom: &str| amm::Asset {
+            info: native(denom),
+            amount: Uint128::new(50),
+        };
+
+        let msg = create_conversion_msg(
+            &deps.storage,
+            &asset("peggy0xusdt"),
+            &native("usdt"),
+            &env,
+            ROUTE_ID,
+        )
+        .unwrap();
+        assert_eq!(
+            msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: converter.to_string(),
+                msg: Binary::from(br#"{"convert":{}}"#.to_vec()),
+                funds: vec![Coin::new(50u128, "peggy0xusdt")],
+            })
+        );
+
+        // Out of the canonical denom, with the template filled in.
+        let msg = create_conversion_msg(
+            &deps.storage,
+            &asset("usdt"),
+            &native("peggy0xusdt"),
+            &env,
+            ROUTE_ID,
+        )
+        .unwrap();
+        let redeem = format!(
+            r#"{{"redeem":{{"amount":"50","recipient":"{}"}}}}"#,
+            env.contract.address
+        );
+        assert_eq!(
+            msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: converter.to_string(),
+                msg: Binary::from(redeem.into_bytes()),
+                funds: vec![Coin::new(50u128, "usdt")],
+            })
+        );
+
+        assert_eq!(
+            create_conversion_msg(
+                &deps.storage,
+                &asset("usdt"),
+                &native("usdc"),
+                &env,
+                ROUTE_ID
+            )
+            .unwrap_err(),
+            ContractError::NativeConverterMissing {
+                from: "usdt".to_string(),
+                to: "usdc".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_completed_route_receipt_records_its_executions() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool = deps.api.addr_make("pool");
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::permille(3))
+            .unwrap();
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let mut exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        exec_state.pending_offers.push(PendingOffer {
+            split_index: 0,
+            op_index: 0,
+            offer: amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            expected_output: None,
+            marginal_rate: None,
+        });
+        EXECUTION_STATES
+            .save(&mut deps.storage, ROUTE_ID, &exec_state)
+            .unwrap();
+
+        handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap();
+
+        let receipt = RECEIPTS.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(
+            receipt.executions,
+            vec![OperationExecution {
+                stage_index: 0,
+                split_index: 0,
+                op_index: 0,
+                contract: pool,
+                offer: amm::Asset {
+                    info: native("inj"),
+                    amount: Uint128::new(100),
+                },
+                output: amm::Asset {
+                    info: native("usdt"),
+                    amount: Uint128::new(1_000),
+                },
+                fee: Uint128::new(3),
+                height: env.block.height,
+                time: env.block.time.seconds(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_traced_route_records_each_reply_and_its_transition() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+
+        // Untraced by default.
+        seed_parallel_swaps(&mut deps, &[&pool_a]);
+        handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 1_000),
+        )
+        .unwrap();
+        assert!(!TRACES.has(&deps.storage, ROUTE_ID));
+
+        PARAMETERS
+            .save(
+                &mut deps.storage,
+                &Parameters {
+                    tracing_enabled: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        seed_parallel_swaps(&mut deps, &[&pool_a, &pool_b]);
+        let mut exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        exec_state.pending_offers.push(PendingOffer {
+            split_index: 0,
+            op_index: 0,
+            offer: amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(50),
+            },
+            expected_output: None,
+            marginal_rate: None,
+        });
+        EXECUTION_STATES
+            .save(&mut deps.storage, ROUTE_ID, &exec_state)
+            .unwrap();
+
+        handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 500),
+        )
+        .unwrap();
+        handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 400),
+        )
+        .unwrap();
+
+        let swaps = TraceState {
+            awaiting: Awaiting::Swaps,
+            stage_index: 0,
+        };
+        let trace = TRACES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0].before, swaps);
+        assert_eq!(trace[0].after, Some(swaps.clone()));
+        assert_eq!(trace[0].executions.len(), 1);
+        assert_eq!(trace[0].executions[0].contract, pool_a);
+        assert_eq!(trace[0].executions[0].output.amount, Uint128::new(500));
+        // The last reply completes the route.
+        assert_eq!(trace[1].before, swaps);
+        assert_eq!(trace[1].after, None);
+        assert_eq!(trace[1].height, env.block.height);
+    }
+
+    #[test]
+    fn test_operation_below_its_min_out_fails_the_route() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        seed_parallel_swaps(&mut deps, &[&pool_a, &pool_b]);
+        let mut stage = ROUTE_STAGES.load(&deps.storage, (ROUTE_ID, 0)).unwrap();
+        let Operation::AmmSwap(op) = &mut stage.splits[1].path[0] else {
+            unreachable!()
+        };
+        op.min_out = Some(Uint128::new(900));
+        ROUTE_STAGES
+            .save(&mut deps.storage, (ROUTE_ID, 0), &stage)
+            .unwrap();
+
+        // The other split has no minimum of its own.
+        handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 100),
+        )
+        .unwrap();
+
+        let err = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 899),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::OperationBelowMinOut {
+                stage_index: 0,
+                split_index: 1,
+                op_index: 0,
+                received: Uint128::new(899),
+                min_out: Uint128::new(900),
+            }
+        );
+    }
+
+    #[test]
+    fn test_silent_reply_below_its_operations_min_out_fails_the_route() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        seed_parallel_swaps(&mut deps, &[&pool_a, &pool_b]);
+        let mut stage = ROUTE_STAGES.load(&deps.storage, (ROUTE_ID, 0)).unwrap();
+        let Operation::AmmSwap(op) = &mut stage.splits[1].path[0] else {
+            unreachable!()
+        };
+        op.min_out = Some(Uint128::new(10));
+        ROUTE_STAGES
+            .save(&mut deps.storage, (ROUTE_ID, 0), &stage)
+            .unwrap();
+
+        // The pool reports no swap at all; its dispatch position still names the hop.
+        let mut reply = empty_reply(ROUTE_ID);
+        reply.payload = to_json_binary(&(1usize, 0usize)).unwrap();
+        let err = handle_reply(deps.as_mut(), mock_env(), reply).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::OperationBelowMinOut {
+                stage_index: 0,
+                split_index: 1,
+                op_index: 0,
+                received: Uint128::zero(),
+                min_out: Uint128::new(10),
+            }
+        );
+    }
+
+    #[test]
+    fn test_self_registered_pool_is_credited_only_what_it_paid() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool = deps.api.addr_make("pool");
+        let sender = deps.api.addr_make("user");
+        SELF_REGISTERED_POOLS
+            .save(&mut deps.storage, &pool, &deps.api.addr_make("operator"))
+            .unwrap();
+        let plan = route_plan(
+            sender.clone(),
+            vec![Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![amm_op(&pool, native("inj"), native("usdt"))],
+                }],
+            }],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        // USDT is not a balance-checked token, yet the pool's payout of it is snapshotted.
+        let mut exec_state = execution_state(Awaiting::Swaps, 0, 1);
+        exec_state.balance_snapshots = crate::execute::query_balance_snapshots(
+            deps.as_ref(),
+            &env,
+            &plan.stages,
+            &plan.offer_asset,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            exec_state.balance_snapshots,
+            vec![amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::zero(),
+            }]
+        );
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+
+        // The pool pays out 250 USDT but reports 1_000.
+        deps.querier
+            .bank
+            .update_balance(&env.contract.address, vec![Coin::new(250u128, "usdt")]);
+        let res = handle_reply(
+            deps.as_mut(),
+            env,
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("250"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: sender.to_string(),
+                amount: vec![Coin::new(250u128, "usdt")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_output_far_above_its_simulation_is_rejected() {
+        let pool_b = MockApi::default().addr_make("pool_b");
+        let mut deps = mock_deps(
+            &[],
+            MockContracts::new().with_amm_pool(pool_b.as_str(), 1_000),
+        );
+        let pool_a = deps.api.addr_make("pool_a");
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![
+                    amm_op(&pool_a, native("inj"), native("usdt")),
+                    amm_op(&pool_b, native("usdt"), native("atom")),
+                ],
+            }],
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        let exec_state = execution_state(Awaiting::Swaps, 0, 1);
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        PARAMETERS
+            .save(
+                &mut deps.storage,
+                &Parameters {
+                    max_output_multiple: Some(Decimal::percent(1_000)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // The first hop's pool was dispatched before the check was enabled and is not checked.
+        handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 500),
+        )
+        .unwrap();
+
+        // The second hop simulated 1_000 when dispatched, but reports six more decimals.
+        let err = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 1_000_000_000),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SuspiciousOutputAmount {
+                pool: pool_b.to_string(),
+                reported: Uint128::new(1_000_000_000),
+                simulated: Uint128::new(1_000),
+                max_multiple: Decimal::percent(1_000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_operation_that_cannot_be_simulated_is_not_dispatched() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        PARAMETERS
+            .save(
+                &mut deps.storage,
+                &Parameters {
+                    max_output_multiple: Some(Decimal::percent(1_000)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let mut exec_state = execution_state(Awaiting::Swaps, 0, 1);
+
+        // The pool answers no simulation, so its output could not be checked.
+        let err = record_dispatched_input(
+            deps.as_ref(),
+            &mut exec_state,
+            &amm_op(&pool, native("inj"), native("usdt")),
+            &native("inj"),
+            Uint128::new(100),
+            (0, 0),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err, ContractError::OutputSimulationFailed { contract, .. } if *contract == pool.to_string()),
+            "unexpected error: {err}"
+        );
+        assert!(exec_state.pending_offers.is_empty());
+    }
+
+    #[test]
+    fn test_splits_sharing_a_pool_are_told_apart_by_their_dispatch() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        seed_parallel_swaps(&mut deps, &[&pool, &pool]);
+        let mut exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        exec_state.pending_offers = [30, 70]
+            .into_iter()
+            .enumerate()
+            .map(|(split_index, amount)| PendingOffer {
+                split_index: split_index as u64,
+                op_index: 0,
+                offer: amm::Asset {
+                    info: native("inj"),
+                    amount: Uint128::new(amount),
+                },
+                expected_output: None,
+                marginal_rate: None,
+            })
+            .collect();
+        EXECUTION_STATES
+            .save(&mut deps.storage, ROUTE_ID, &exec_state)
+            .unwrap();
+
+        // The second split's swap replies first; its dispatch position names it.
+        let mut reply = amm_swap_reply(ROUTE_ID, pool.as_str(), 700);
+        reply.payload = to_json_binary(&(1usize, 0usize)).unwrap();
+        handle_reply(deps.as_mut(), mock_env(), reply).unwrap();
+
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.executions.len(), 1);
+        assert_eq!(exec_state.executions[0].split_index, 1);
+        assert_eq!(exec_state.executions[0].offer.amount, Uint128::new(70));
+        assert_eq!(exec_state.pending_offers.len(), 1);
+        assert_eq!(exec_state.pending_offers[0].split_index, 0);
+    }
+
+    #[test]
+    fn test_rate_far_from_the_pools_reference_aborts_the_route() {
+        // The pool quotes 12 USDT per INJ at the margin, and less per INJ the more it is offered.
+        let pool = MockApi::default().addr_make("pool");
+        let mut deps = mock_deps(
+            &[],
+            MockContracts::new().with_contract(pool.as_str(), |msg| {
+                let amm::QueryMsg::Simulation { offer_asset } = from_json(msg)? else {
+                    return Err(StdError::generic_err("mock pool only answers Simulation"));
+                };
+                let offered = offer_asset.amount.u128();
+                to_json_binary(&amm::SimulationResponse {
+                    return_amount: Uint128::new(offered * 12 - offered * offered / 100),
+                    spread_amount: Uint128::zero(),
+                    commission_amount: Uint128::zero(),
+                })
+            }),
+        );
+        let mut env = mock_env();
+        // One swap of `offered` INJ through the pool, dispatched and awaiting its reply.
+        let seed = |deps: &mut MockDeps, offered: u128| {
+            seed_parallel_swaps(deps, &[&pool]);
+            let mut exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+            record_dispatched_input(
+                deps.as_ref(),
+                &mut exec_state,
+                &amm_op(&pool, native("inj"), native("usdt")),
+                &native("inj"),
+                Uint128::new(offered),
+                (0, 0),
+            )
+            .unwrap();
+            EXECUTION_STATES
+                .save(&mut deps.storage, ROUTE_ID, &exec_state)
+                .unwrap();
+        };
+        let reference = |deps: &MockDeps| {
+            POOL_REFERENCE_RATES
+                .load(&deps.storage, (&pool, "inj"))
+                .unwrap()
+                .rate
+        };
+
+        // Without the guard nothing is simulated or tracked.
+        seed(&mut deps, 100);
+        handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_100),
+        )
+        .unwrap();
+        assert!(POOL_REFERENCE_RATES.is_empty(&deps.storage));
+
+        PARAMETERS
+            .save(
+                &mut deps.storage,
+                &Parameters {
+                    max_rate_deviation: Some(Decimal::percent(10)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // Paying out its quote, the pool swapped at its marginal rate of 12 whatever the size,
+        // though 500 INJ only fetch 7 each.
+        for (offered, quoted) in [(100, 1_100), (500, 3_500)] {
+            seed(&mut deps, offered);
+            handle_reply(
+                deps.as_mut(),
+                env.clone(),
+                amm_swap_reply(ROUTE_ID, pool.as_str(), quoted),
+            )
+            .unwrap();
+            assert_eq!(reference(&deps), Decimal::percent(1_200));
+        }
+
+        // Paying 5% above its quote moves the reference a fifth of the way.
+        seed(&mut deps, 100);
+        handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_155),
+        )
+        .unwrap();
+        assert_eq!(reference(&deps), Decimal::percent(1_212));
+
+        // Paying out half its quote is far outside 10% of the reference.
+        seed(&mut deps, 100);
+        let err = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 550),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PoolRateDeviation {
+                pool: pool.to_string(),
+                realized: Decimal::percent(600),
+                reference: Decimal::percent(1_212),
+                max_deviation: Decimal::percent(10),
+            }
+        );
+
+        // So is paying nothing, even in a reply with no event to read.
+        let mut reply = empty_reply(ROUTE_ID);
+        reply.payload = to_json_binary(&(0usize, 0usize)).unwrap();
+        let err = handle_reply(deps.as_mut(), env.clone(), reply).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PoolRateDeviation {
+                pool: pool.to_string(),
+                realized: Decimal::zero(),
+                reference: Decimal::percent(1_212),
+                max_deviation: Decimal::percent(10),
+            }
+        );
+        // A reply naming no operation cannot have its pool's rate checked.
+        let err = handle_reply(deps.as_mut(), env.clone(), empty_reply(ROUTE_ID)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnattributedEmptyReply { stage_index: 0 }
+        );
+
+        // Once the reference is stale, the pool's new rate replaces it.
+        env.block.time = env.block.time.plus_seconds(REFERENCE_RATE_MAX_AGE_SECONDS);
+        handle_reply(
+            deps.as_mut(),
+            env,
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 550),
+        )
+        .unwrap();
+        assert_eq!(reference(&deps), Decimal::percent(600));
+    }
+
+    #[test]
+    fn test_route_is_charged_the_fees_of_its_start() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        seed_parallel_swaps(&mut deps, &[&pool]);
+        let mut plan = ROUTE_PLANS.load(&deps.storage, ROUTE_ID).unwrap();
+        plan.fee_schedule = vec![FeeInfo {
+            pool_address: pool.to_string(),
+            fee_percent: Decimal::permille(3),
+        }];
+        ROUTE_PLANS
+            .save(&mut deps.storage, ROUTE_ID, &plan)
+            .unwrap();
+        // Raised by the admin while the route is in flight.
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::percent(5))
+            .unwrap();
+
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "fee_collected").as_deref(), Some("3"));
+        let receipt = RECEIPTS.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(receipt.fee_schedule, plan.fee_schedule);
+    }
+
+    #[test]
+    fn test_undeliverable_fee_is_kept_for_the_collector_to_claim() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool = deps.api.addr_make("pool");
+        let collector = deps.api.addr_make("collector");
+        FEE_MAP
+            .save(&mut deps.storage, &pool, &Decimal::permille(3))
+            .unwrap();
+        seed_parallel_swaps(&mut deps, &[&pool]);
+
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool.as_str(), 1_000),
+        )
+        .unwrap();
+        let fee_send = &res.messages[0];
+        assert_eq!(fee_send.id, FEE_DELIVERY_REPLY_ID);
+        assert_eq!(fee_send.reply_on, ReplyOn::Error);
+
+        // The collector rejects the send: the fee stays here instead of failing the route.
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: FEE_DELIVERY_REPLY_ID,
+                payload: fee_send.payload.clone(),
+                gas_used: 0,
+                result: SubMsgResult::Err("collector rejects funds".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "action").as_deref(), Some("fee_delivery_failed"));
+        assert_eq!(
+            UNCLAIMED_FEES.load(&deps.storage, &collector).unwrap(),
+            vec![amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(3),
+            }]
+        );
 
-fn parse_amount_from_swap_reply(msg: &Reply) -> Result<Uint128, ContractError> {
-    let events = msg
-        .result
-        .clone()
-        .into_result()
-        .map_err(|e| ContractError::SubmessageResultError { error: e })?
-        .events;
+        let recipient = deps.api.addr_make("treasury");
+        let claim = ExecuteMsg::ClaimFees {
+            recipient: Some(recipient.to_string()),
+        };
+        let res = crate::contract::execute(
+            deps.as_mut(),
+            mock_env(),
+            message_info(&collector, &[]),
+            claim.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: recipient.to_string(),
+                amount: vec![Coin::new(3u128, "usdt")],
+            })
+        );
+        let err = crate::contract::execute(
+            deps.as_mut(),
+            mock_env(),
+            message_info(&collector, &[]),
+            claim,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoUnclaimedFees {});
+    }
 
-    let amount_str_opt = events.iter().find_map(|event| {
-        if !event.ty.starts_with("wasm") {
-            return None;
-        }
-        let key = if event.ty == "wasm-atomic_swap_execution" {
-            "swap_final_amount"
-        } else {
-            "return_amount"
+    #[test]
+    fn test_path_conversion_resumes_with_the_converted_amount() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let shroom = deps.api.addr_make("shroom");
+        let adapter = deps.api.addr_make("adapter");
+        let cw20 = amm::AssetInfo::Token {
+            contract_addr: shroom.to_string(),
         };
-        event
-            .attributes
-            .iter()
-            .find(|attr| attr.key == key)
-            .map(|attr| attr.value.clone())
-    });
+        let native_denom = format!("factory/{adapter}/{shroom}");
 
-    match amount_str_opt {
-        Some(amount_str) => {
-            let integer_part_str = if let Some(period_pos) = amount_str.find('.') {
-                &amount_str[..period_pos]
-            } else {
-                &amount_str
-            };
+        // INJ -> CW20 SHROOM, then native SHROOM -> USDT: the hop outputs the wrong form.
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![
+                    amm_op(&pool_a, native("inj"), cw20.clone()),
+                    amm_op(&pool_b, native(&native_denom), native("usdt")),
+                ],
+            }],
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        seed_route(
+            &mut deps.storage,
+            ROUTE_ID,
+            &plan,
+            &execution_state(Awaiting::Swaps, 0, 1),
+        )
+        .unwrap();
 
-            integer_part_str
-                .parse::<Uint128>()
-                .map_err(|_| ContractError::MalformedAmountInReply { value: amount_str })
-        }
-        None => Ok(Uint128::zero()),
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 400),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("performing_path_conversion")
+        );
+        assert_eq!(
+            res.events,
+            vec![Event::new("adapter_conversion")
+                .add_attribute("route_id", ROUTE_ID.to_string())
+                .add_attribute("stage_index", "0")
+                .add_attribute("direction", "cw20_to_native")
+                .add_attribute("asset", shroom.to_string())
+                .add_attribute("amount", "400")
+                .add_attribute("reason", "path-mismatch")]
+        );
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.awaiting, Awaiting::PathConversion);
+        assert_eq!(
+            exec_state.pending_path_op.unwrap().amount,
+            Uint128::new(400)
+        );
+
+        // The adapter pays out by bank transfer; the next hop swaps exactly that.
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            adapter_bank_transfer_reply(ROUTE_ID, &env.contract.address, 400, &native_denom),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("resuming_path_after_conversion")
+        );
+        let CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            funds,
+            ..
+        }) = &res.messages[0].msg
+        else {
+            panic!("expected a swap on the next pool");
+        };
+        assert_eq!(*contract_addr, pool_b.to_string());
+        assert_eq!(*funds, vec![Coin::new(400u128, native_denom)]);
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.awaiting, Awaiting::Swaps);
+        assert_eq!(exec_state.conversions_performed, 1);
+
+        let res = handle_reply(
+            deps.as_mut(),
+            env,
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 40),
+        )
+        .unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("40"));
     }
-}
 
-fn parse_amount_from_conversion_reply(msg: &Reply, env: &Env) -> Result<Uint128, ContractError> {
-    let events = &msg
-        .result
-        .clone()
-        .into_result()
-        .map_err(|e| ContractError::SubmessageResultError { error: e })?
-        .events;
+    #[test]
+    fn test_orderbook_output_is_converted_to_the_cw20_its_path_ends_in() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let orderbook = deps.api.addr_make("orderbook");
+        let shroom = deps.api.addr_make("shroom");
+        let adapter = deps.api.addr_make("adapter");
+        let cw20 = amm::AssetInfo::Token {
+            contract_addr: shroom.to_string(),
+        };
+        let native_denom = format!("factory/{adapter}/{shroom}");
 
-    if let Some(transfer_event) = events.iter().find(|e| {
-        e.ty == "transfer"
-            && e.attributes
-                .iter()
-                .any(|a| a.key == "recipient" && a.value == env.contract.address.to_string())
-    }) {
-        let amount_attr = transfer_event
-            .attributes
-            .iter()
-            .find(|a| a.key == "amount")
-            .ok_or(ContractError::NoAmountInReply {})?;
+        let stage = Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                    swap_contract: orderbook.to_string(),
+                    offer_asset_info: native("inj"),
+                    ask_asset_info: cw20.clone(),
+                    min_quantity_tick_size: Uint128::one(),
+                    min_out: None,
+                })],
+            }],
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        seed_route(
+            &mut deps.storage,
+            ROUTE_ID,
+            &plan,
+            &execution_state(Awaiting::Swaps, 0, 1),
+        )
+        .unwrap();
 
-        let numeric_part =
-            if let Some(first_non_digit) = amount_attr.value.find(|c: char| !c.is_ascii_digit()) {
-                &amount_attr.value[..first_non_digit]
-            } else {
-                &amount_attr.value
-            };
+        // The orderbook settles in the adapter denom, which is sent to the adapter to redeem.
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            bank_settlement_reply(
+                ROUTE_ID,
+                &orderbook,
+                &env.contract.address,
+                &format!("500{native_denom}"),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("converting_path_output")
+        );
+        let CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            funds,
+            ..
+        }) = &res.messages[0].msg
+        else {
+            panic!("expected a conversion through the adapter");
+        };
+        assert_eq!(*contract_addr, adapter.to_string());
+        assert_eq!(*funds, vec![Coin::new(500u128, native_denom)]);
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.awaiting, Awaiting::OutputConversion);
+        assert_eq!(exec_state.pending_output_split, Some(0));
 
-        return numeric_part.parse::<Uint128>().map_err(|_| {
-            ContractError::MalformedAmountInReply {
-                value: amount_attr.value.clone(),
-            }
-        });
+        // The converted CW20 is the split's output, and the route pays it out.
+        let reply = adapter_cw20_reply(&mut deps.storage, ROUTE_ID, 500);
+        let res = handle_reply(deps.as_mut(), env, reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("500"));
+        assert_eq!(attr(&res, "conversions_performed").as_deref(), Some("1"));
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) = &res.messages[0].msg else {
+            panic!("expected a CW20 payout");
+        };
+        assert_eq!(*contract_addr, shroom.to_string());
     }
 
-    if let Some(wasm_event) = events.iter().find(|e| {
-        e.ty.starts_with("wasm")
-            && e.attributes
-                .iter()
-                .any(|a| a.key == "action" && a.value == "transfer")
-    }) {
-        let amount_attr = wasm_event
-            .attributes
-            .iter()
-            .find(|a| a.key == "amount")
-            .ok_or(ContractError::NoAmountInReply {})?;
-
-        return amount_attr.value.parse::<Uint128>().map_err(|_| {
-            ContractError::MalformedAmountInReply {
-                value: amount_attr.value.clone(),
-            }
+    #[test]
+    fn test_conversion_hook_is_accepted_only_from_the_cw20_being_converted_into() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let adapter = deps.api.addr_make("adapter");
+        let shroom = deps.api.addr_make("shroom");
+        let mut exec_state = execution_state(Awaiting::OutputConversion, 0, 1);
+        exec_state.pending_conversion_target = Some(amm::AssetInfo::Token {
+            contract_addr: shroom.to_string(),
         });
-    }
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        let hook = ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+            sender: adapter.to_string(),
+            amount: Uint128::new(1_000_000),
+            msg: to_json_binary(&Cw20HookMsg::InternalConversion { route_id: ROUTE_ID }).unwrap(),
+        });
+        let send_hook = |deps: &mut MockDeps, caller: &Addr| {
+            crate::contract::execute(
+                deps.as_mut(),
+                mock_env(),
+                message_info(caller, &[]),
+                hook.clone(),
+            )
+        };
 
-    Err(ContractError::NoConversionEventInReply {})
-}
+        // Naming the adapter as the sender is not enough: the call itself must come from the
+        // CW20 the route is converting into.
+        for caller in [adapter.clone(), deps.api.addr_make("other_cw20")] {
+            let err = send_hook(&mut deps, &caller).unwrap_err();
+            assert_eq!(err, ContractError::Unauthorized {});
+        }
+        assert!(!CONVERSION_RESULTS.has(&deps.storage, ROUTE_ID));
 
-fn plan_next_stage(
-    accumulated_assets: &[amm::Asset],
-    next_stage: &Stage,
-) -> Result<StagePlan, ContractError> {
-    let mut native_info: Option<amm::AssetInfo> = None;
-    let mut cw20_info: Option<amm::AssetInfo> = None;
+        send_hook(&mut deps, &shroom).unwrap();
+        assert_eq!(
+            CONVERSION_RESULTS.load(&deps.storage, ROUTE_ID).unwrap(),
+            Uint128::new(1_000_000)
+        );
 
-    for split in &next_stage.splits {
-        let first_op = split.path.first().ok_or(ContractError::EmptyRoute {})?;
-        let offer_info = get_operation_input(first_op)?;
-        match offer_info {
-            amm::AssetInfo::NativeToken { .. } => {
-                if native_info.is_none() {
-                    native_info = Some(offer_info);
-                }
-            }
-            amm::AssetInfo::Token { .. } => {
-                if cw20_info.is_none() {
-                    cw20_info = Some(offer_info);
-                }
-            }
-        }
+        // Once the route is no longer converting, the token cannot report results either.
+        exec_state.awaiting = Awaiting::Swaps;
+        EXECUTION_STATES
+            .save(&mut deps.storage, ROUTE_ID, &exec_state)
+            .unwrap();
+        let err = send_hook(&mut deps, &shroom).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
     }
 
-    let mut native_have = Uint128::zero();
-    let mut cw20_have = Uint128::zero();
-    for asset in accumulated_assets {
-        match &asset.info {
-            amm::AssetInfo::NativeToken { .. } => {
-                native_have += asset.amount;
-            }
-            amm::AssetInfo::Token { .. } => {
-                cw20_have += asset.amount;
-            }
-        }
-    }
-    let total_logical_amount = native_have + cw20_have;
+    #[test]
+    fn test_final_normalization_emits_a_conversion_event_per_asset() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let shroom = deps.api.addr_make("shroom");
+        let adapter = deps.api.addr_make("adapter");
+        let cw20 = amm::AssetInfo::Token {
+            contract_addr: shroom.to_string(),
+        };
+        let native_denom = format!("factory/{adapter}/{shroom}");
 
-    let mut total_native_needs = Uint128::zero();
-    let mut total_cw20_needs = Uint128::zero();
-    for split in &next_stage.splits {
-        let amount_for_split = total_logical_amount.multiply_ratio(split.percent as u128, 100u128);
-        let first_op = split.path.first().ok_or(ContractError::EmptyRoute {})?;
-        let offer_info = get_operation_input(first_op)?;
-        match offer_info {
-            amm::AssetInfo::NativeToken { .. } => total_native_needs += amount_for_split,
-            amm::AssetInfo::Token { .. } => total_cw20_needs += amount_for_split,
-        }
+        // One split pays out the CW20 and the other its adapter denom, normalized into the CW20.
+        let stage = Stage {
+            splits: vec![
+                Split {
+                    percent: 50,
+                    path: vec![amm_op(&pool_a, native("inj"), cw20.clone())],
+                },
+                Split {
+                    percent: 50,
+                    path: vec![amm_op(&pool_b, native("inj"), native(&native_denom))],
+                },
+            ],
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            Some(amm::Asset {
+                info: cw20.clone(),
+                amount: Uint128::zero(),
+            }),
+        );
+        seed_route(
+            &mut deps.storage,
+            ROUTE_ID,
+            &plan,
+            &execution_state(Awaiting::Swaps, 0, 2),
+        )
+        .unwrap();
+
+        handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 300),
+        )
+        .unwrap();
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 200),
+        )
+        .unwrap();
+        assert_eq!(
+            attr(&res, "action").as_deref(),
+            Some("final_asset_normalization_started")
+        );
+        assert_eq!(
+            res.events,
+            vec![Event::new("adapter_conversion")
+                .add_attribute("route_id", ROUTE_ID.to_string())
+                .add_attribute("stage_index", "1")
+                .add_attribute("direction", "native_to_cw20")
+                .add_attribute("asset", native_denom)
+                .add_attribute("amount", "200")
+                .add_attribute("reason", "final-normalization")]
+        );
     }
 
-    let mut conversions_needed: Vec<(amm::Asset, amm::AssetInfo)> = vec![];
-    if native_have > total_native_needs {
-        if let Some(target_info) = &cw20_info {
-            let native_asset_to_convert_info = accumulated_assets
-                .iter()
-                .find(|a| matches!(a.info, amm::AssetInfo::NativeToken { .. }))
-                .map(|a| a.info.clone())
-                .ok_or_else(|| {
-                    StdError::generic_err(
-                        "State inconsistency: have native amount but no native asset info found",
-                    )
-                })?;
+    #[test]
+    fn test_final_output_below_the_dust_threshold_is_still_converted() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let shroom = deps.api.addr_make("shroom");
+        let adapter = deps.api.addr_make("adapter");
+        let cw20 = amm::AssetInfo::Token {
+            contract_addr: shroom.to_string(),
+        };
+        let native_denom = format!("factory/{adapter}/{shroom}");
+        DUST_THRESHOLDS
+            .save(&mut deps.storage, &native_denom, &Uint128::new(1_000))
+            .unwrap();
 
-            conversions_needed.push((
-                amm::Asset {
-                    info: native_asset_to_convert_info,
-                    amount: native_have - total_native_needs,
+        let stage = Stage {
+            splits: vec![
+                Split {
+                    percent: 50,
+                    path: vec![amm_op(&pool_a, native("inj"), cw20.clone())],
                 },
-                target_info.clone(),
-            ));
-        }
+                Split {
+                    percent: 50,
+                    path: vec![amm_op(&pool_b, native("inj"), native(&native_denom))],
+                },
+            ],
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![stage],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            Some(amm::Asset {
+                info: cw20,
+                amount: Uint128::zero(),
+            }),
+        );
+        seed_route(
+            &mut deps.storage,
+            ROUTE_ID,
+            &plan,
+            &execution_state(Awaiting::Swaps, 0, 2),
+        )
+        .unwrap();
+
+        handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 300),
+        )
+        .unwrap();
+        let res = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_b.as_str(), 2),
+        )
+        .unwrap();
+
+        // The 2 units of the adapter denom are output owed to the user, not dust.
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(attr(&res, "dust_asset"), None);
+        assert_eq!(DUST_BUCKET.may_load(&deps.storage).unwrap(), None);
     }
-    if cw20_have > total_cw20_needs {
-        if let Some(target_info) = &native_info {
-            let cw20_asset_to_convert_info = accumulated_assets
-                .iter()
-                .find(|a| matches!(a.info, amm::AssetInfo::Token { .. }))
-                .map(|a| a.info.clone())
-                .ok_or_else(|| {
-                    StdError::generic_err(
-                        "State inconsistency: have cw20 amount but no cw20 asset info found",
-                    )
-                })?;
 
-            conversions_needed.push((
+    #[test]
+    fn test_final_conversions_pay_out_the_combined_amount() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let sender = deps.api.addr_make("user");
+        let shroom = deps.api.addr_make("shroom");
+        let cw20 = amm::AssetInfo::Token {
+            contract_addr: shroom.to_string(),
+        };
+        let seed = |deps: &mut MockDeps, minimum: u128| {
+            let plan = route_plan(
+                sender.clone(),
+                vec![],
                 amm::Asset {
-                    info: cw20_asset_to_convert_info,
-                    amount: cw20_have - total_cw20_needs,
+                    info: native("inj"),
+                    amount: Uint128::new(100),
                 },
-                target_info.clone(),
-            ));
-        }
-    }
+                Some(amm::Asset {
+                    info: cw20.clone(),
+                    amount: Uint128::new(minimum),
+                }),
+            );
+            let mut exec_state = execution_state(Awaiting::FinalConversions, 1, 1);
+            exec_state.accumulated_assets = vec![amm::Asset {
+                info: cw20.clone(),
+                amount: Uint128::new(600),
+            }];
+            seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        };
 
-    let mut swaps_to_execute: Vec<PlannedSwap> = vec![];
-    let mut native_allocated = Uint128::zero();
-    let mut cw20_allocated = Uint128::zero();
-    for (i, split) in next_stage.splits.iter().enumerate() {
-        let first_op = split.path.first().ok_or(ContractError::EmptyRoute {})?;
-        let offer_info = get_operation_input(first_op)?;
-        let amount_for_split = if i < next_stage.splits.len() - 1 {
-            total_logical_amount.multiply_ratio(split.percent as u128, 100u128)
-        } else {
-            let already_allocated = native_allocated + cw20_allocated;
-            total_logical_amount
-                .checked_sub(already_allocated)
-                .map_err(StdError::from)?
-        };
-        match offer_info {
-            amm::AssetInfo::NativeToken { .. } => native_allocated += amount_for_split,
-            amm::AssetInfo::Token { .. } => cw20_allocated += amount_for_split,
-        }
-        swaps_to_execute.push(PlannedSwap {
-            operation: first_op.clone(),
-            amount: amount_for_split,
-        });
+        // 600 already held plus 400 converted falls one short of the minimum.
+        seed(&mut deps, 1_001);
+        let reply = adapter_cw20_reply(&mut deps.storage, ROUTE_ID, 400);
+        let err = handle_reply(deps.as_mut(), mock_env(), reply).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::MinimumReceiveNotMet {
+                expected: Uint128::new(1_001),
+                actual: Uint128::new(1_000),
+            }
+        );
+
+        seed(&mut deps, 1_000);
+        let reply = adapter_cw20_reply(&mut deps.storage, ROUTE_ID, 400);
+        let res = handle_reply(deps.as_mut(), mock_env(), reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("1000"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: shroom.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: sender.to_string(),
+                    amount: Uint128::new(1_000),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        assert!(!CONVERSION_RESULTS.has(&deps.storage, ROUTE_ID));
     }
 
-    Ok(StagePlan {
-        swaps_to_execute,
-        conversions_needed,
-    })
-}
+    #[test]
+    fn test_hops_sharing_a_router_are_swapped_in_one_call() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let pool_c = deps.api.addr_make("pool_c");
+        let router = deps.api.addr_make("router");
+        for pool in [&pool_b, &pool_c] {
+            let metadata = PoolMetadata {
+                label: "routed".to_string(),
+                protocol: PoolProtocol::Amm,
+                token_decimals: vec![],
+                market_id: None,
+                router: Some(router.to_string()),
+            };
+            update_pool_config(&mut deps.storage, pool, |pool| {
+                pool.metadata = Some(metadata)
+            })
+            .unwrap();
+        }
 
-fn get_operation_input(op: &Operation) -> Result<amm::AssetInfo, ContractError> {
-    Ok(match op {
-        Operation::AmmSwap(o) => o.offer_asset_info.clone(),
-        Operation::OrderbookSwap(o) => o.offer_asset_info.clone(),
-    })
-}
+        // INJ -> USDT -> ATOM -> SHROOM, where only the last two pools share a router.
+        let path = vec![
+            amm_op(&pool_a, native("inj"), native("usdt")),
+            amm_op(&pool_b, native("usdt"), native("atom")),
+            amm_op(&pool_c, native("atom"), native("shroom")),
+        ];
+        let seed = |deps: &mut MockDeps| {
+            let plan = route_plan(
+                deps.api.addr_make("user"),
+                vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: path.clone(),
+                    }],
+                }],
+                amm::Asset {
+                    info: native("inj"),
+                    amount: Uint128::new(100),
+                },
+                None,
+            );
+            let exec_state = execution_state(Awaiting::Swaps, 0, 1);
+            seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        };
 
-fn execute_planned_swaps(
-    deps: &mut DepsMut<InjectiveQueryWrapper>,
-    env: Env,
-    exec_state: &mut ExecutionState,
-    plan: &RoutePlan,
-    reply_id: u64,
-    swaps: Vec<PlannedSwap>,
-) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let mut submessages = vec![];
+        seed(&mut deps);
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 400),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: router.to_string(),
+                msg: to_json_binary(&amm::RouterExecuteMsg::ExecuteSwapOperations {
+                    operations: vec![
+                        amm::SwapOperation::AstroSwap {
+                            offer_asset_info: native("usdt"),
+                            ask_asset_info: native("atom"),
+                        },
+                        amm::SwapOperation::AstroSwap {
+                            offer_asset_info: native("atom"),
+                            ask_asset_info: native("shroom"),
+                        },
+                    ],
+                    minimum_receive: None,
+                    to: Some(env.contract.address.to_string()),
+                    max_spread: None,
+                })
+                .unwrap(),
+                funds: vec![Coin::new(400u128, "usdt")],
+            })
+        );
 
-    for swap in swaps.into_iter().filter(|s| !s.amount.is_zero()) {
-        let offer_asset_info = get_operation_input(&swap.operation)?;
-        let msg =
-            create_swap_cosmos_msg(deps, &swap.operation, &offer_asset_info, swap.amount, &env)?;
-        submessages.push(SubMsg::reply_on_success(msg, reply_id));
+        // The router's reply carries each pool's swap event; the last hop's output completes
+        // the path.
+        let reply = router_swap_reply(ROUTE_ID, &[(pool_b.as_str(), 90), (pool_c.as_str(), 250)]);
+        let res = handle_reply(deps.as_mut(), env.clone(), reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("250"));
+
+        // A fee on an intermediate pool would be lost inside the router, so the hops go separately.
+        FEE_MAP
+            .save(&mut deps.storage, &pool_b, &Decimal::permille(3))
+            .unwrap();
+        seed(&mut deps);
+        let res = handle_reply(
+            deps.as_mut(),
+            env,
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 400),
+        )
+        .unwrap();
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) = &res.messages[0].msg else {
+            panic!("expected a swap on the next pool");
+        };
+        assert_eq!(*contract_addr, pool_b.to_string());
     }
 
-    if submessages.is_empty() {
-        exec_state.current_stage_index += 1;
-        return proceed_to_next_step(deps, env, exec_state, plan, reply_id);
+    #[test]
+    fn test_swaps_above_a_pools_max_input_are_rejected() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let pool_c = deps.api.addr_make("pool_c");
+        let router = deps.api.addr_make("router");
+        for pool in [&pool_b, &pool_c] {
+            let metadata = PoolMetadata {
+                label: "routed".to_string(),
+                protocol: PoolProtocol::Amm,
+                token_decimals: vec![],
+                market_id: None,
+                router: Some(router.to_string()),
+            };
+            update_pool_config(&mut deps.storage, pool, |pool| {
+                pool.metadata = Some(metadata)
+            })
+            .unwrap();
+        }
+        update_pool_config(&mut deps.storage, &pool_b, |pool| {
+            pool.max_input = Some(Uint128::new(300))
+        })
+        .unwrap();
+
+        let path = vec![
+            amm_op(&pool_a, native("inj"), native("usdt")),
+            amm_op(&pool_b, native("usdt"), native("atom")),
+            amm_op(&pool_c, native("atom"), native("shroom")),
+        ];
+        let seed = |deps: &mut MockDeps| {
+            let plan = route_plan(
+                deps.api.addr_make("user"),
+                vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: path.clone(),
+                    }],
+                }],
+                amm::Asset {
+                    info: native("inj"),
+                    amount: Uint128::new(100),
+                },
+                None,
+            );
+            let exec_state = execution_state(Awaiting::Swaps, 0, 1);
+            seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
+        };
+
+        seed(&mut deps);
+        let err = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 400),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PoolMaxInputExceeded {
+                pool: pool_b.to_string(),
+                amount: Uint128::new(400),
+                max_input: Uint128::new(300),
+            }
+        );
+
+        // At the ceiling the swap goes ahead, still batched with the next hop through the router.
+        seed(&mut deps);
+        let res = handle_reply(
+            deps.as_mut(),
+            env.clone(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 300),
+        )
+        .unwrap();
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) = &res.messages[0].msg else {
+            panic!("expected a router swap");
+        };
+        assert_eq!(*contract_addr, router.to_string());
+
+        // A capped pool later in the batch only learns its input once the router has run, so it
+        // is swapped on its own.
+        update_pool_config(&mut deps.storage, &pool_c, |pool| {
+            pool.max_input = Some(Uint128::new(1_000))
+        })
+        .unwrap();
+        seed(&mut deps);
+        let res = handle_reply(
+            deps.as_mut(),
+            env,
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 300),
+        )
+        .unwrap();
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) = &res.messages[0].msg else {
+            panic!("expected a swap on the next pool");
+        };
+        assert_eq!(*contract_addr, pool_b.to_string());
     }
 
-    exec_state.awaiting = Awaiting::Swaps;
-    exec_state.replies_expected = submessages.len() as u64;
+    #[test]
+    fn test_swap_events_of_the_replying_pool_are_summed() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
 
-    EXECUTION_STATES.save(deps.storage, reply_id, exec_state)?;
+        // A pool filling its swap in two parts reports each of them.
+        seed_parallel_swaps(&mut deps, &[&pool_a]);
+        let reply = router_swap_reply(ROUTE_ID, &[(pool_a.as_str(), 300), (pool_a.as_str(), 200)]);
+        let res = handle_reply(deps.as_mut(), mock_env(), reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("500"));
 
-    Ok(Response::new()
-        .add_submessages(submessages)
-        .add_attribute("action", "executing_planned_swaps")
-        .add_attribute("stage_index", exec_state.current_stage_index.to_string()))
-}
+        // The events of an earlier hop, here of a pool swapped through by a router, are not the
+        // route's, even when the last hop's pool emits several.
+        seed_parallel_swaps(&mut deps, &[&pool_a]);
+        let reply = router_swap_reply(
+            ROUTE_ID,
+            &[
+                (pool_b.as_str(), 90),
+                (pool_a.as_str(), 100),
+                (pool_a.as_str(), 150),
+            ],
+        );
+        let res = handle_reply(deps.as_mut(), mock_env(), reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("250"));
 
-fn get_operation_address(op: &Operation) -> &String {
-    match op {
-        Operation::AmmSwap(o) => &o.pool_address,
-        Operation::OrderbookSwap(o) => &o.swap_contract,
+        // A pool reporting one swap in a generic and a custom event is read once.
+        seed_parallel_swaps(&mut deps, &[&pool_a]);
+        let mut reply = amm_swap_reply(ROUTE_ID, pool_a.as_str(), 400);
+        let SubMsgResult::Ok(result) = &mut reply.result else {
+            panic!("expected a successful reply");
+        };
+        result.events.push(
+            Event::new("wasm-astro_swap")
+                .add_attribute("_contract_address", pool_a.as_str())
+                .add_attribute("return_amount", "400"),
+        );
+        let res = handle_reply(deps.as_mut(), mock_env(), reply).unwrap();
+        assert_eq!(attr(&res, "final_received").as_deref(), Some("400"));
     }
-}
 
-fn handle_path_conversion_reply(
-    mut deps: DepsMut<InjectiveQueryWrapper>,
-    env: Env,
-    msg: Reply,
-    exec_state: &mut ExecutionState,
-    _plan: &RoutePlan,
-) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
-    let master_reply_id = msg.id;
+    #[test]
+    fn test_continued_route_rebases_its_balance_baseline_on_what_it_holds() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let env = mock_env();
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let user = deps.api.addr_make("user");
+        let plan = route_plan(
+            user.clone(),
+            vec![
+                Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![amm_op(&pool_a, native("inj"), native("usdt"))],
+                    }],
+                },
+                Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![amm_op(&pool_b, native("usdt"), native("atom"))],
+                    }],
+                },
+            ],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        let mut exec_state = execution_state(Awaiting::Continuation, 1, 0);
+        exec_state.accumulated_assets = vec![amm::Asset {
+            info: native("usdt"),
+            amount: Uint128::new(500),
+        }];
+        exec_state.fees_collected = vec![amm::Asset {
+            info: native("usdt"),
+            amount: Uint128::new(20),
+        }];
+        exec_state.balance_baseline = Some(vec![amm::Asset {
+            info: native("usdt"),
+            amount: Uint128::zero(),
+        }]);
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
 
-    let converted_amount = parse_amount_from_conversion_reply(&msg, &env)?;
+        // Another route left 300 USDT in the contract while this one was checkpointed.
+        deps.querier
+            .bank
+            .update_balance(&env.contract.address, vec![Coin::new(800u128, "usdt")]);
+        crate::contract::execute(
+            deps.as_mut(),
+            env,
+            message_info(&user, &[]),
+            ExecuteMsg::ContinueRoute { route_id: ROUTE_ID },
+        )
+        .unwrap();
 
-    let pending_op_details = exec_state.pending_path_op.take().ok_or_else(|| {
-        StdError::generic_err("Path conversion state is invalid: no pending operation found")
-    })?;
+        // The 500 USDT the route holds are its own; the 20 it already paid in fees are added back
+        // as the final check deducts every fee of the route.
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(
+            exec_state.balance_baseline,
+            Some(vec![amm::Asset {
+                info: native("usdt"),
+                amount: Uint128::new(320),
+            }])
+        );
+    }
 
-    let converted_asset_info = get_operation_input(&pending_op_details.operation)?;
+    #[test]
+    fn test_health_check_probes_every_hop_of_a_path() {
+        let pool_b = MockApi::default().addr_make("pool_b");
+        let mut deps = mock_deps(
+            &[],
+            MockContracts::new().with_amm_pool(pool_b.as_str(), 300),
+        );
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_c = deps.api.addr_make("pool_c");
+        let mut plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![
+                Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![amm_op(&pool_a, native("inj"), native("usdt"))],
+                    }],
+                },
+                Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![
+                            amm_op(&pool_b, native("usdt"), native("atom")),
+                            amm_op(&pool_c, native("atom"), native("shroom")),
+                        ],
+                    }],
+                },
+            ],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        plan.pool_health_check = Some(PoolHealthPolicy::Abort);
+        let exec_state = execution_state(Awaiting::Swaps, 0, 1);
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
 
-    let swap_msg = create_swap_cosmos_msg(
-        &mut deps,
-        &pending_op_details.operation,
-        &converted_asset_info,
-        converted_amount,
-        &env,
-    )?;
-    let sub_msg = SubMsg::reply_on_success(swap_msg, master_reply_id);
+        // The first hop's pool quotes fine, but the second one, probed with the first hop's
+        // simulated output, does not answer.
+        let err = handle_reply(
+            deps.as_mut(),
+            mock_env(),
+            amm_swap_reply(ROUTE_ID, pool_a.as_str(), 400),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::PoolUnhealthy {
+                pool: pool_c.to_string(),
+                stage_index: 1,
+            }
+        );
+    }
 
-    exec_state.awaiting = Awaiting::Swaps;
+    #[test]
+    fn test_replies_load_only_the_stages_they_concern() {
+        let mut deps = mock_deps(&[], MockContracts::new());
+        let pool_a = deps.api.addr_make("pool_a");
+        let pool_b = deps.api.addr_make("pool_b");
+        let pool_c = deps.api.addr_make("pool_c");
+        let stage = |pools: &[&Addr], offer: &str, ask: &str| Stage {
+            splits: pools
+                .iter()
+                .map(|pool| Split {
+                    percent: (100 / pools.len()) as u8,
+                    path: vec![amm_op(pool, native(offer), native(ask))],
+                })
+                .collect(),
+        };
+        let plan = route_plan(
+            deps.api.addr_make("user"),
+            vec![
+                stage(&[&pool_a, &pool_b], "inj", "usdt"),
+                stage(&[&pool_c], "usdt", "inj"),
+                stage(&[&pool_a], "inj", "usdt"),
+            ],
+            amm::Asset {
+                info: native("inj"),
+                amount: Uint128::new(100),
+            },
+            None,
+        );
+        let exec_state = execution_state(Awaiting::Swaps, 0, 2);
+        seed_route(&mut deps.storage, ROUTE_ID, &plan, &exec_state).unwrap();
 
-    EXECUTION_STATES.save(deps.storage, master_reply_id, exec_state)?;
+        let mut reply = |reply: Reply| {
+            let mut storage = CountingStorage::new(&mut deps.storage);
+            let counted = DepsMut {
+                storage: &mut storage,
+                api: &deps.api,
+                querier: QuerierWrapper::new(&deps.querier),
+            };
+            handle_reply(counted, mock_env(), reply).unwrap();
+            (
+                storage.reads_of("route_plans"),
+                storage.reads_of("route_stages"),
+            )
+        };
 
-    Ok(Response::new()
-        .add_submessage(sub_msg)
-        .add_attribute("action", "resuming_path_after_conversion"))
+        // A reply that leaves the stage waiting on another reads only the stage it is about.
+        assert_eq!(reply(amm_swap_reply(ROUTE_ID, pool_a.as_str(), 50)), (1, 1));
+        // The stage's last reply also reads the stage it dispatches, but none after it.
+        assert_eq!(reply(amm_swap_reply(ROUTE_ID, pool_b.as_str(), 50)), (1, 2));
+        let exec_state = EXECUTION_STATES.load(&deps.storage, ROUTE_ID).unwrap();
+        assert_eq!(exec_state.current_stage_index, 1);
+    }
 }
@@ -0,0 +1,821 @@
+//! The route math shared by execution and simulation: validating a route's stages and quoting
+//! routes against pools. Stage inputs are allocated across splits by the `planner`.
+//!
+//! Nothing here touches contract storage directly. Pool settings are read through a
+//! [`PoolRegistry`], which the contract answers from its own storage, so other contracts and
+//! off-chain routers built with the `library` feature get exactly the numbers the aggregator uses.
+
+use crate::error::ContractError;
+use crate::msg::{
+    amm, orderbook, AmmSwapOp, Operation, PairType, ProvideLiquidityOp, SimulateRouteResponse,
+    SplitAllocation, SplitFailure, Stage,
+};
+use crate::planner::apportion;
+use crate::state::{
+    asset_id, effective_fee, load_pool_config, MarketInfo, PoolQueryKind, CONFIG, INJ_DENOM,
+    MARKETS, NATIVE_CONVERTERS, WRAPPED_INJ,
+};
+use cosmwasm_std::{
+    to_json_binary, Addr, Api, Decimal, Isqrt, QuerierWrapper, StdError, StdResult, Storage,
+    Uint128, Uint256, WasmQuery,
+};
+use std::collections::HashMap;
+
+/// The aggregator settings a route simulation depends on.
+pub trait PoolRegistry {
+    /// The cw20 adapter, whose native and CW20 forms of a token draw on one balance.
+    fn adapter(&self) -> StdResult<Option<Addr>>;
+    /// The wrapped-INJ CW20, which draws on one balance with native INJ. None by default.
+    fn wrapped_inj(&self) -> StdResult<Option<Addr>> {
+        Ok(None)
+    }
+    /// The canonical denom a registered native converter converts `denom` to and from, which
+    /// draws on one balance with it. None by default.
+    fn canonical_denom(&self, _denom: &str) -> StdResult<Option<String>> {
+        Ok(None)
+    }
+    /// The simulation query shape of an AMM pool.
+    fn query_kind(&self, pool: &Addr) -> StdResult<PoolQueryKind>;
+    /// The aggregator fee charged on a pool's output.
+    fn fee(&self, pool: &Addr) -> StdResult<Decimal>;
+    /// The trading rules of the orderbook market behind a swap contract, if registered.
+    fn market(&self, swap_contract: &Addr) -> StdResult<Option<MarketInfo>>;
+}
+
+/// The contract's own registries, as stored by the admin.
+impl PoolRegistry for dyn Storage + '_ {
+    fn adapter(&self) -> StdResult<Option<Addr>> {
+        Ok(CONFIG
+            .may_load(self)?
+            .map(|config| config.cw20_adapter_address))
+    }
+
+    fn wrapped_inj(&self) -> StdResult<Option<Addr>> {
+        WRAPPED_INJ.may_load(self)
+    }
+
+    fn canonical_denom(&self, denom: &str) -> StdResult<Option<String>> {
+        Ok(NATIVE_CONVERTERS
+            .may_load(self, denom)?
+            .map(|converter| converter.canonical_denom))
+    }
+
+    fn query_kind(&self, pool: &Addr) -> StdResult<PoolQueryKind> {
+        Ok(load_pool_config(self, pool)?.query_kind)
+    }
+
+    fn fee(&self, pool: &Addr) -> StdResult<Decimal> {
+        effective_fee(self, pool)
+    }
+
+    fn market(&self, swap_contract: &Addr) -> StdResult<Option<MarketInfo>> {
+        MARKETS.may_load(self, swap_contract)
+    }
+}
+
+/// `amount` rounded down to a whole number of ticks.
+pub fn round_to_tick(amount: Uint128, tick_size: Uint128) -> Uint128 {
+    amount / tick_size * tick_size
+}
+
+/// Rejects an orderbook swap of `offer_amount` for `output_amount` whose value in the market's
+/// quote denom is below its minimum notional. Swaps not involving the quote denom are not checked.
+pub fn check_min_notional(
+    swap_contract: &str,
+    market: &MarketInfo,
+    offer_denom: &str,
+    ask_denom: &str,
+    offer_amount: Uint128,
+    output_amount: Uint128,
+) -> Result<(), ContractError> {
+    let notional = if offer_denom == market.quote_denom {
+        offer_amount
+    } else if ask_denom == market.quote_denom {
+        output_amount
+    } else {
+        return Ok(());
+    };
+    if notional < market.min_notional {
+        return Err(ContractError::BelowMinNotional {
+            swap_contract: swap_contract.to_string(),
+            notional,
+            min_notional: market.min_notional,
+        });
+    }
+    Ok(())
+}
+
+/// The part of a zap's input swapped for the pool's other asset, so that its output and the
+/// input left over match the pool's ratio after the swap. A constant product pool is solved for
+/// exactly, with its commission read off a simulation of half the input; a stable pool, which
+/// trades near its peg, swaps half.
+pub fn zap_swap_amount(
+    querier: &QuerierWrapper,
+    op: &ProvideLiquidityOp,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let half = amount / Uint128::new(2);
+    if matches!(op.pair_type, PairType::Stable {}) || half.is_zero() {
+        return Ok(half);
+    }
+    let pool: amm::PoolResponse =
+        querier.query_wasm_smart(&op.pool_address, &amm::QueryMsg::Pool {})?;
+    let reserve = zap_pool_reserve(&pool, &op.offer_asset_info)?;
+    let probe: amm::SimulationResponse = querier.query_wasm_smart(
+        &op.pool_address,
+        &amm::QueryMsg::Simulation {
+            offer_asset: amm::Asset {
+                info: op.offer_asset_info.clone(),
+                amount: half,
+            },
+        },
+    )?;
+    let gross = probe.return_amount + probe.commission_amount;
+    let fee_bps = if gross.is_zero() {
+        Uint128::zero()
+    } else {
+        probe.commission_amount.multiply_ratio(10_000u128, gross)
+    };
+    optimal_xyk_swap_amount(amount, reserve, fee_bps)
+}
+
+/// Solves `s^2 + s * (r * (2 - f) - a * f) - a * r = 0` for the amount `s` of an input `a` to
+/// swap into a constant product pool holding `r` of it, whose commission `f` is taken from the
+/// output. Swapping `s` leaves `a - s` in the same ratio to the output as the pool's reserves.
+fn optimal_xyk_swap_amount(
+    amount: Uint128,
+    reserve: Uint128,
+    fee_bps: Uint128,
+) -> StdResult<Uint128> {
+    let scale = Uint256::from(10_000u128);
+    let (a, r, f) = (
+        Uint256::from(amount),
+        Uint256::from(reserve),
+        Uint256::from(fee_bps.min(Uint128::new(10_000))),
+    );
+    // Scaled by 10_000 throughout, so the commission stays a whole number of basis points.
+    let positive = r.checked_mul(scale + scale - f)?;
+    let negative = a.checked_mul(f)?;
+    let discriminant = r
+        .checked_mul(a)?
+        .checked_mul(scale * scale)?
+        .checked_mul(Uint256::from(4u128))?;
+    let root = positive
+        .abs_diff(negative)
+        .checked_pow(2)?
+        .checked_add(discriminant)?
+        .isqrt();
+    let numerator = if positive >= negative {
+        root - (positive - negative)
+    } else {
+        root + (negative - positive)
+    };
+    let swap_amount = Uint128::try_from(numerator / (scale + scale))?;
+    Ok(swap_amount.min(amount))
+}
+
+/// The amount of `info` a pool holds.
+pub fn zap_pool_reserve(pool: &amm::PoolResponse, info: &amm::AssetInfo) -> StdResult<Uint128> {
+    pool.assets
+        .iter()
+        .find(|asset| asset.info == *info)
+        .map(|asset| asset.amount)
+        .ok_or_else(|| StdError::generic_err("Zap pool does not hold the asset"))
+}
+
+/// Estimates the LP tokens minted for depositing `deposits` into a pool holding `reserves`, both
+/// as (offer asset, other asset), with `total_share` LP tokens outstanding. Constant product pools
+/// mint by the scarcer side; stable pools are valued at the peg, which holds near balance.
+pub fn estimate_lp_share(
+    pair_type: &PairType,
+    total_share: Uint128,
+    deposits: (Uint128, Uint128),
+    reserves: (Uint128, Uint128),
+) -> StdResult<Uint128> {
+    if total_share.is_zero() || reserves.0.is_zero() || reserves.1.is_zero() {
+        return Err(StdError::generic_err(
+            "Cannot zap into a pool without liquidity",
+        ));
+    }
+    Ok(match pair_type {
+        PairType::Xyk {} => std::cmp::min(
+            deposits.0.multiply_ratio(total_share, reserves.0),
+            deposits.1.multiply_ratio(total_share, reserves.1),
+        ),
+        PairType::Stable {} => {
+            (deposits.0 + deposits.1).multiply_ratio(total_share, reserves.0 + reserves.1)
+        }
+    })
+}
+
+/// Whether `a` and `b` are the native and CW20 forms of the same token under the cw20 adapter.
+pub fn is_adapter_counterpart(adapter: &Addr, a: &amm::AssetInfo, b: &amm::AssetInfo) -> bool {
+    let adapter_denom = |contract_addr: &str| format!("factory/{adapter}/{contract_addr}");
+    match (a, b) {
+        (amm::AssetInfo::Token { contract_addr }, amm::AssetInfo::NativeToken { denom })
+        | (amm::AssetInfo::NativeToken { denom }, amm::AssetInfo::Token { contract_addr }) => {
+            *denom == adapter_denom(contract_addr)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` are native INJ and the `wrapped_inj` CW20.
+pub fn is_wrapped_inj_counterpart(
+    wrapped_inj: &Addr,
+    a: &amm::AssetInfo,
+    b: &amm::AssetInfo,
+) -> bool {
+    match (a, b) {
+        (amm::AssetInfo::Token { contract_addr }, amm::AssetInfo::NativeToken { denom })
+        | (amm::AssetInfo::NativeToken { denom }, amm::AssetInfo::Token { contract_addr }) => {
+            denom == INJ_DENOM && contract_addr == wrapped_inj.as_str()
+        }
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` are native denoms one of which a registered native converter converts to
+/// and from the other.
+pub fn is_native_converter_counterpart<R: PoolRegistry + ?Sized>(
+    registry: &R,
+    a: &amm::AssetInfo,
+    b: &amm::AssetInfo,
+) -> StdResult<bool> {
+    let (amm::AssetInfo::NativeToken { denom: a }, amm::AssetInfo::NativeToken { denom: b }) =
+        (a, b)
+    else {
+        return Ok(false);
+    };
+    Ok(registry
+        .canonical_denom(a)?
+        .is_some_and(|canonical| canonical == *b)
+        || registry
+            .canonical_denom(b)?
+            .is_some_and(|canonical| canonical == *a))
+}
+
+/// Whether `a` and `b` are two forms of one token: native and CW20 under the cw20 adapter or, for
+/// INJ, the wrapped-INJ contract, or two native denoms with a registered converter between them.
+pub fn is_counterpart<R: PoolRegistry + ?Sized>(
+    registry: &R,
+    a: &amm::AssetInfo,
+    b: &amm::AssetInfo,
+) -> StdResult<bool> {
+    if registry
+        .adapter()?
+        .is_some_and(|adapter| is_adapter_counterpart(&adapter, a, b))
+        || registry
+            .wrapped_inj()?
+            .is_some_and(|wrapped_inj| is_wrapped_inj_counterpart(&wrapped_inj, a, b))
+    {
+        return Ok(true);
+    }
+    is_native_converter_counterpart(registry, a, b)
+}
+
+/// The other form of `info` under the cw20 adapter, with the CW20 address it is backed by. Native
+/// denoms outside the adapter's `factory/{adapter}/` namespace have none.
+pub fn adapter_counterpart(
+    adapter: &Addr,
+    info: &amm::AssetInfo,
+) -> Option<(amm::AssetInfo, String)> {
+    match info {
+        amm::AssetInfo::Token { contract_addr } => Some((
+            amm::AssetInfo::NativeToken {
+                denom: format!("factory/{adapter}/{contract_addr}"),
+            },
+            contract_addr.clone(),
+        )),
+        amm::AssetInfo::NativeToken { denom } => {
+            let prefix = format!("factory/{adapter}/");
+            let contract_addr = denom.strip_prefix(&prefix)?.to_string();
+            Some((
+                amm::AssetInfo::Token {
+                    contract_addr: contract_addr.clone(),
+                },
+                contract_addr,
+            ))
+        }
+    }
+}
+
+/// The native denom an orderbook swap into `ask` settles in. A CW20 ask settles in its adapter
+/// denom, which the reply converts to the CW20 once received.
+pub fn orderbook_settlement_denom<R: PoolRegistry + ?Sized>(
+    registry: &R,
+    ask: &amm::AssetInfo,
+) -> StdResult<String> {
+    match ask {
+        amm::AssetInfo::NativeToken { denom } => Ok(denom.clone()),
+        amm::AssetInfo::Token { contract_addr } => {
+            let adapter = registry.adapter()?.ok_or_else(|| {
+                StdError::generic_err("Orderbook swaps into a CW20 need the cw20 adapter")
+            })?;
+            Ok(format!("factory/{adapter}/{contract_addr}"))
+        }
+    }
+}
+
+/// Rejects routes where a stage requires an asset that neither the offers (for the first stage)
+/// nor any path of the previous stage can produce, directly or through a conversion between two
+/// forms of one token, and routes providing liquidity anywhere but at the end of a final-stage
+/// path.
+pub fn check_stage_inputs<R: PoolRegistry + ?Sized>(
+    registry: &R,
+    stages: &[Stage],
+    offer_infos: &[amm::AssetInfo],
+) -> Result<(), ContractError> {
+    let mut available = offer_infos.to_vec();
+    for (stage_index, stage) in stages.iter().enumerate() {
+        let mut produced = vec![];
+        for (split_index, split) in stage.splits.iter().enumerate() {
+            let (Some(first_op), Some(last_op)) = (split.path.first(), split.path.last()) else {
+                continue;
+            };
+            let is_zap = |op: &Operation| matches!(op, Operation::ProvideLiquidity(_));
+            let zap_mid_path = split.path[..split.path.len() - 1].iter().any(is_zap);
+            let zap_before_final_stage = stage_index + 1 < stages.len() && is_zap(last_op);
+            if zap_mid_path || zap_before_final_stage {
+                return Err(ContractError::ZapNotTerminal {
+                    stage_index,
+                    split_index,
+                });
+            }
+            let required = first_op.offer_asset_info()?;
+            let mut is_available = false;
+            for info in &available {
+                if *info == required || is_counterpart(registry, info, &required)? {
+                    is_available = true;
+                    break;
+                }
+            }
+            if !is_available {
+                return Err(ContractError::StageInputMismatch {
+                    stage_index,
+                    split_index,
+                });
+            }
+            produced.push(last_op.ask_asset_info()?);
+        }
+        available = produced;
+    }
+    Ok(())
+}
+
+/// Runs the route simulation. When `fee_overrides` is provided, the aggregator fee is deducted
+/// from each hop's output, preferring an override over the registry's fee for the hop's pool. The
+/// CW20 and native forms of an adapter token draw on one balance, as the adapter converts between
+/// them one to one.
+pub fn simulate_route_output<R: PoolRegistry + ?Sized>(
+    api: &dyn Api,
+    querier: &QuerierWrapper,
+    registry: &R,
+    stages: &[Stage],
+    offer_asset: amm::Asset,
+    fee_overrides: Option<&[(Addr, Decimal)]>,
+) -> StdResult<Uint128> {
+    simulate_route_detailed(
+        api,
+        querier,
+        registry,
+        stages,
+        offer_asset,
+        fee_overrides,
+        false,
+    )
+    .map(|simulation| simulation.output_amount)
+}
+
+/// A route simulation's output, with the input each split was allocated and, for a lenient
+/// simulation, the splits that failed to quote.
+#[derive(Debug, Default)]
+pub struct RouteSimulation {
+    pub output_amount: Uint128,
+    pub allocations: Vec<SplitAllocation>,
+    pub failed_splits: Vec<SplitFailure>,
+}
+
+/// Runs the route simulation like `simulate_route_output`. When `lenient`, a split whose path
+/// fails to simulate is returned as a failure and counted as producing nothing, and splits left
+/// with no input are not simulated at all.
+pub fn simulate_route_detailed<R: PoolRegistry + ?Sized>(
+    api: &dyn Api,
+    querier: &QuerierWrapper,
+    registry: &R,
+    stages: &[Stage],
+    offer_asset: amm::Asset,
+    fee_overrides: Option<&[(Addr, Decimal)]>,
+    lenient: bool,
+) -> StdResult<RouteSimulation> {
+    let mut simulation = RouteSimulation::default();
+    if stages.is_empty() {
+        return Ok(simulation);
+    }
+
+    let adapter = registry.adapter()?;
+    let wrapped_inj = registry.wrapped_inj()?;
+    let pile_id = |info: &amm::AssetInfo| {
+        form_pile_id(registry, adapter.as_ref(), wrapped_inj.as_ref(), info)
+    };
+
+    let mut current_assets: Vec<amm::Asset> = vec![offer_asset];
+    let mut quotes = QuoteCache::default();
+
+    for (stage_index, stage) in stages.iter().enumerate() {
+        let mut next_stage_outputs: Vec<amm::Asset> = vec![];
+
+        // Group the current assets by their type to get the total for each pile.
+        let mut grouped_inputs: Vec<(String, Uint128)> = vec![];
+        for asset in current_assets {
+            let id = pile_id(&asset.info)?;
+            if let Some((_, amount)) = grouped_inputs.iter_mut().find(|(pile, _)| *pile == id) {
+                *amount += asset.amount;
+            } else {
+                grouped_inputs.push((id, asset.amount));
+            }
+        }
+
+        // Each pile is apportioned across the splits drawing on it by their percentages, the same
+        // largest-remainder allocation the planner makes when the route executes.
+        let mut split_inputs = vec![];
+        for split in &stage.splits {
+            let path_input_info = path_start_info(&split.path)?;
+            split_inputs.push((pile_id(&path_input_info)?, path_input_info));
+        }
+        let mut amounts_for_splits = vec![Uint128::zero(); stage.splits.len()];
+        for (pile, total_amount_for_type) in &grouped_inputs {
+            let takers: Vec<usize> = (0..stage.splits.len())
+                .filter(|i| split_inputs[*i].0 == *pile)
+                .collect();
+            let percents: Vec<u8> = takers.iter().map(|i| stage.splits[*i].percent).collect();
+            let allocation = apportion(*total_amount_for_type, &percents)?;
+            for (i, amount) in takers.into_iter().zip(allocation) {
+                amounts_for_splits[i] = amount;
+            }
+        }
+
+        for (i, (split, (_, path_input_info))) in stage.splits.iter().zip(split_inputs).enumerate()
+        {
+            let path_input = amm::Asset {
+                info: path_input_info,
+                amount: amounts_for_splits[i],
+            };
+            simulation.allocations.push(SplitAllocation {
+                stage_index: stage_index as u64,
+                split_index: i as u64,
+                input: path_input.clone(),
+            });
+            if !lenient {
+                next_stage_outputs.push(simulate_path(
+                    api,
+                    querier,
+                    registry,
+                    &mut quotes,
+                    &split.path,
+                    path_input,
+                    fee_overrides,
+                )?);
+                continue;
+            }
+
+            let nothing = |info| amm::Asset {
+                info,
+                amount: Uint128::zero(),
+            };
+            let path_output_info = split
+                .path
+                .last()
+                .ok_or_else(|| StdError::generic_err("Split path cannot be empty"))?
+                .ask_asset_info()?;
+            if path_input.amount.is_zero() {
+                next_stage_outputs.push(nothing(path_output_info));
+                continue;
+            }
+            match simulate_path(
+                api,
+                querier,
+                registry,
+                &mut quotes,
+                &split.path,
+                path_input,
+                fee_overrides,
+            ) {
+                Ok(output) => next_stage_outputs.push(output),
+                Err(err) => {
+                    simulation.failed_splits.push(SplitFailure {
+                        stage_index: stage_index as u64,
+                        split_index: i as u64,
+                        error: err.to_string(),
+                    });
+                    next_stage_outputs.push(nothing(path_output_info));
+                }
+            }
+        }
+
+        current_assets = next_stage_outputs;
+    }
+
+    simulation.output_amount = current_assets.iter().map(|a| a.amount).sum();
+
+    Ok(simulation)
+}
+
+/// Outputs already quoted within one route simulation, keyed by the operation's contract and ask
+/// asset and by the offer. Splits offering a pool the same amount query it once. Sub-aggregators
+/// are always queried, as the same contract quotes whatever stages it is given.
+#[derive(Default)]
+struct QuoteCache {
+    quotes: HashMap<(String, String, String, Uint128), amm::Asset>,
+}
+
+impl QuoteCache {
+    fn simulate<R: PoolRegistry + ?Sized>(
+        &mut self,
+        querier: &QuerierWrapper,
+        registry: &R,
+        operation: &Operation,
+        offer_asset: &amm::Asset,
+    ) -> StdResult<amm::Asset> {
+        if matches!(operation, Operation::SubAggregator(_)) {
+            return simulate_single_operation(querier, registry, operation, offer_asset);
+        }
+        let key = (
+            get_operation_address(operation).clone(),
+            asset_id(&operation.ask_asset_info()?).to_string(),
+            asset_id(&offer_asset.info).to_string(),
+            offer_asset.amount,
+        );
+        if let Some(output) = self.quotes.get(&key) {
+            return Ok(output.clone());
+        }
+        let output = simulate_single_operation(querier, registry, operation, offer_asset)?;
+        self.quotes.insert(key, output.clone());
+        Ok(output)
+    }
+}
+
+/// Simulates one split's path hop by hop, less the aggregator fee when `fee_overrides` is given.
+fn simulate_path<R: PoolRegistry + ?Sized>(
+    api: &dyn Api,
+    querier: &QuerierWrapper,
+    registry: &R,
+    quotes: &mut QuoteCache,
+    path: &[Operation],
+    input: amm::Asset,
+    fee_overrides: Option<&[(Addr, Decimal)]>,
+) -> StdResult<amm::Asset> {
+    let mut current_path_asset = input;
+    for operation in path {
+        let mut output_asset =
+            quotes.simulate(querier, registry, operation, &current_path_asset)?;
+        if let Some(overrides) = fee_overrides {
+            (_, output_asset.amount) =
+                simulate_hop_fee(api, registry, operation, overrides, output_asset.amount)?;
+        }
+        current_path_asset = output_asset;
+    }
+    Ok(current_path_asset)
+}
+
+/// Identifies the balance an asset is drawn from: the CW20 address for either form of an adapter
+/// token or of wrapped INJ, the canonical denom for either form of a natively converted token,
+/// and the denom or address itself for anything else.
+fn form_pile_id<R: PoolRegistry + ?Sized>(
+    registry: &R,
+    adapter: Option<&Addr>,
+    wrapped_inj: Option<&Addr>,
+    info: &amm::AssetInfo,
+) -> StdResult<String> {
+    if let amm::AssetInfo::NativeToken { denom } = info {
+        if let Some(canonical) = registry.canonical_denom(denom)? {
+            return Ok(canonical);
+        }
+    }
+    Ok(match (adapter, wrapped_inj, info) {
+        (_, Some(wrapped_inj), amm::AssetInfo::NativeToken { denom }) if denom == INJ_DENOM => {
+            wrapped_inj.to_string()
+        }
+        (Some(adapter), _, amm::AssetInfo::NativeToken { denom }) => denom
+            .strip_prefix(&format!("factory/{adapter}/"))
+            .unwrap_or(denom)
+            .to_string(),
+        _ => asset_id(info).to_string(),
+    })
+}
+
+/// Splits `amount` into the aggregator fee at `fee_percent`, rounded down, and what is left after
+/// it. Execution and simulation both charge fees through this, so a quote deducts exactly what
+/// the route will. A fee above 100% is rejected rather than taking more than `amount`.
+pub fn deduct_fee(amount: Uint128, fee_percent: Decimal) -> StdResult<(Uint128, Uint128)> {
+    if fee_percent > Decimal::one() {
+        return Err(StdError::generic_err(format!(
+            "Fee percentage {fee_percent} exceeds 100%"
+        )));
+    }
+    let fee = amount.multiply_ratio(
+        fee_percent.atomics(),
+        Uint128::new(1_000_000_000_000_000_000u128),
+    );
+    Ok((fee, amount.checked_sub(fee)?))
+}
+
+/// The fee the aggregator would take on a hop's output and what is left after it, as the reply
+/// handler would deduct them.
+pub fn simulate_hop_fee<R: PoolRegistry + ?Sized>(
+    api: &dyn Api,
+    registry: &R,
+    operation: &Operation,
+    overrides: &[(Addr, Decimal)],
+    received_amount: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let pool_addr = api.addr_validate(get_operation_address(operation))?;
+
+    let fee_percent = match overrides.iter().find(|(addr, _)| *addr == pool_addr) {
+        Some((_, fee_percent)) => *fee_percent,
+        None => registry.fee(&pool_addr)?,
+    };
+
+    deduct_fee(received_amount, fee_percent)
+}
+
+/// Simulates a single swap operation, using the query shape registered for AMM pools.
+pub fn simulate_single_operation<R: PoolRegistry + ?Sized>(
+    querier: &QuerierWrapper,
+    registry: &R,
+    operation: &Operation,
+    offer_asset: &amm::Asset,
+) -> StdResult<amm::Asset> {
+    match operation {
+        Operation::AmmSwap(op) => {
+            let contract_addr = op.pool_address.to_string();
+            let query_kind = registry.query_kind(&Addr::unchecked(&op.pool_address))?;
+
+            let return_amount = match query_kind {
+                PoolQueryKind::Simulation => {
+                    let pair_query = amm::QueryMsg::Simulation {
+                        offer_asset: offer_asset.clone(),
+                    };
+                    let sim_response: amm::SimulationResponse = querier.query(
+                        &WasmQuery::Smart {
+                            contract_addr,
+                            msg: to_json_binary(&pair_query)?,
+                        }
+                        .into(),
+                    )?;
+                    sim_response.return_amount
+                }
+                PoolQueryKind::SimulateSwapOperations => {
+                    let pair_query = amm::SwapOperationsQueryMsg::SimulateSwapOperations {
+                        offer_amount: offer_asset.amount,
+                        operations: vec![amm::SwapOperation::AstroSwap {
+                            offer_asset_info: offer_asset.info.clone(),
+                            ask_asset_info: op.ask_asset_info.clone(),
+                        }],
+                    };
+                    let sim_response: amm::SimulateSwapOperationsResponse = querier.query(
+                        &WasmQuery::Smart {
+                            contract_addr,
+                            msg: to_json_binary(&pair_query)?,
+                        }
+                        .into(),
+                    )?;
+                    sim_response.amount
+                }
+            };
+
+            Ok(amm::Asset {
+                info: op.ask_asset_info.clone(),
+                amount: return_amount,
+            })
+        }
+        Operation::OrderbookSwap(op) => {
+            let source_denom = match &offer_asset.info {
+                amm::AssetInfo::NativeToken { denom } => denom.clone(),
+                _ => {
+                    return Err(StdError::generic_err(
+                        "Orderbook simulation only supports native token inputs",
+                    ))
+                }
+            };
+            let target_denom = orderbook_settlement_denom(registry, &op.ask_asset_info)?;
+
+            // A registered market is simulated as the swap will be dispatched: rounded to its
+            // tick size and checked against its minimum notional.
+            let market = registry.market(&Addr::unchecked(&op.swap_contract))?;
+            let mut from_quantity = offer_asset.amount;
+            if let Some(market) = &market {
+                from_quantity = round_to_tick(from_quantity, market.min_quantity_tick_size);
+                if from_quantity.is_zero() {
+                    return Ok(amm::Asset {
+                        info: op.ask_asset_info.clone(),
+                        amount: Uint128::zero(),
+                    });
+                }
+            }
+
+            let orderbook_query = orderbook::QueryMsg::GetOutputQuantity {
+                from_quantity: from_quantity.into(),
+                source_denom: source_denom.clone(),
+                target_denom: target_denom.clone(),
+            };
+            let contract_addr = op.swap_contract.to_string();
+
+            let sim_response: orderbook::SwapEstimationResult = querier.query(
+                &WasmQuery::Smart {
+                    contract_addr,
+                    msg: to_json_binary(&orderbook_query)?,
+                }
+                .into(),
+            )?;
+            let output_amount: Uint128 = sim_response.result_quantity.into();
+
+            if let Some(market) = &market {
+                check_min_notional(
+                    &op.swap_contract,
+                    market,
+                    &source_denom,
+                    &target_denom,
+                    from_quantity,
+                    output_amount,
+                )
+                .map_err(|err| StdError::generic_err(err.to_string()))?;
+            }
+
+            Ok(amm::Asset {
+                info: op.ask_asset_info.clone(),
+                amount: output_amount,
+            })
+        }
+        Operation::SubAggregator(op) => {
+            let sim_response: SimulateRouteResponse = querier.query_wasm_smart(
+                &op.contract,
+                &crate::msg::QueryMsg::SimulateRouteFromAsset {
+                    stages: op.stages.clone(),
+                    amount_in: offer_asset.clone(),
+                },
+            )?;
+
+            Ok(amm::Asset {
+                info: operation.ask_asset_info()?,
+                amount: sim_response.output_amount,
+            })
+        }
+        Operation::ProvideLiquidity(op) => {
+            let swap_amount = zap_swap_amount(querier, op, offer_asset.amount)?;
+            let swap_op = Operation::AmmSwap(AmmSwapOp {
+                pool_address: op.pool_address.clone(),
+                offer_asset_info: op.offer_asset_info.clone(),
+                ask_asset_info: op.other_asset_info.clone(),
+                min_out: None,
+            });
+            let swapped = simulate_single_operation(
+                querier,
+                registry,
+                &swap_op,
+                &amm::Asset {
+                    info: offer_asset.info.clone(),
+                    amount: swap_amount,
+                },
+            )?
+            .amount;
+
+            let pool: amm::PoolResponse =
+                querier.query_wasm_smart(&op.pool_address, &amm::QueryMsg::Pool {})?;
+            // Liquidity is provided into the pool as the swap leaves it. Whatever does not fit
+            // its ratio is refunded, so the scarcer side decides the share minted.
+            let reserves = (
+                zap_pool_reserve(&pool, &op.offer_asset_info)? + swap_amount,
+                zap_pool_reserve(&pool, &op.other_asset_info)?.checked_sub(swapped)?,
+            );
+            let share = estimate_lp_share(
+                &op.pair_type,
+                pool.total_share,
+                (offer_asset.amount - swap_amount, swapped),
+                reserves,
+            )?;
+
+            Ok(amm::Asset {
+                info: op.lp_token.clone(),
+                amount: share,
+            })
+        }
+    }
+}
+
+/// The contract an operation is executed against.
+pub fn get_operation_address(op: &Operation) -> &String {
+    match op {
+        Operation::AmmSwap(o) => &o.pool_address,
+        Operation::OrderbookSwap(o) => &o.swap_contract,
+        Operation::SubAggregator(o) => &o.contract,
+        Operation::ProvideLiquidity(o) => &o.pool_address,
+    }
+}
+
+/// The asset a path starts from.
+pub fn path_start_info(path: &[Operation]) -> StdResult<amm::AssetInfo> {
+    path.first()
+        .ok_or_else(|| StdError::generic_err("Path cannot be empty"))?
+        .offer_asset_info()
+}
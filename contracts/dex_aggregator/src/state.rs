@@ -1,52 +1,888 @@
-use crate::msg::{amm, Operation, PlannedSwap, Stage};
+use crate::msg::{
+    amm, FeeInfo, Operation, PlannedSwap, PoolHealthPolicy, PostAction, ReceiveHook,
+    SplitExpectations, Stage,
+};
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Decimal, Uint128};
+use cosmwasm_std::{
+    Addr, Attribute, Binary, Coin, Decimal, Empty, StdError, StdResult, Storage, Uint128,
+};
 use cw_storage_plus::{Item, Map};
+use injective_cosmwasm::OracleType;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::cell::OnceCell;
+use std::ops::Deref;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
+    pub admin: Addr,
+    pub cw20_adapter_address: Addr,
+    pub fee_collectors: Vec<FeeCollector>,
+}
+
+/// The `Config` layout of deployments from before fees were split between collectors, read once
+/// by `migrate`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyConfig {
     pub admin: Addr,
     pub cw20_adapter_address: Addr,
     pub fee_collector: Addr,
 }
 
+/// A recipient of protocol fees. The weights of all collectors sum to 1.
+#[cw_serde]
+pub struct FeeCollector {
+    pub address: Addr,
+    pub weight: Decimal,
+}
+
 #[cw_serde]
 pub enum Awaiting {
     Swaps,
     Conversions,
     FinalConversions,
     PathConversion,
+    /// The adapter conversion of an orderbook swap's output into the CW20 its path ends in.
+    OutputConversion,
+    LiquidityProvision,
+    Withdrawal,
+    Sweep,
+    Continuation,
+}
+
+/// Operational parameters tunable by the admin.
+#[cw_serde]
+pub struct Parameters {
+    /// Pool fees must be strictly below this cap.
+    pub max_fee_percent: Decimal,
+    /// Route complexity limits. Unset limits are not enforced.
+    pub max_stages: Option<u32>,
+    pub max_splits_per_stage: Option<u32>,
+    pub max_path_length: Option<u32>,
+    /// Slippage tolerance used for the minimum output of orderbook swaps.
+    pub orderbook_slippage: Decimal,
+    /// Leftover amounts below this are kept in the dust bucket instead of being refunded. Only
+    /// applies to leftovers, never to a route's output. Overridable per asset with
+    /// `DUST_THRESHOLDS`. Zero disables it.
+    pub dust_threshold: Uint128,
+    /// Debug mode: routes record the contract's balances of every involved asset and abort
+    /// with `InvariantViolation` if they end lower than that, less the fees collected.
+    pub invariant_checks: bool,
+    /// Largest multiple of its simulated output an operation may report before its reply is
+    /// rejected with `SuspiciousOutputAmount`, catching venues that report amounts in another
+    /// decimal convention. An operation that cannot be simulated when it is dispatched fails the
+    /// route with `OutputSimulationFailed`. Unset disables the check and the simulations it needs.
+    pub max_output_multiple: Option<Decimal>,
+    /// Largest deviation of a pool's marginal rate from its reference rate, as a fraction of the
+    /// reference, before the route is aborted with `PoolRateDeviation`. Unset disables the guard,
+    /// along with the reference rates and the canary simulations it needs.
+    pub max_rate_deviation: Option<Decimal>,
+    /// Debug mode: every reply a route processes is recorded in `TRACES`, queryable with
+    /// `QueryMsg::Trace`, at the cost of a storage write per reply.
+    pub tracing_enabled: bool,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            max_fee_percent: Decimal::one(),
+            max_stages: None,
+            max_splits_per_stage: None,
+            max_path_length: None,
+            orderbook_slippage: Decimal::permille(5),
+            dust_threshold: Uint128::zero(),
+            invariant_checks: false,
+            max_output_multiple: None,
+            max_rate_deviation: None,
+            tracing_enabled: false,
+        }
+    }
 }
 
 pub const CONFIG: Item<Config> = Item::new("config");
+pub const LEGACY_CONFIG: Item<LegacyConfig> = Item::new("config");
+pub const PARAMETERS: Item<Parameters> = Item::new("parameters");
 pub const FEE_MAP: Map<&Addr, Decimal> = Map::new("fee_map");
+/// The simulation query shape an AMM pool understands.
+#[cw_serde]
+#[derive(Default)]
+pub enum PoolQueryKind {
+    /// `Simulation { offer_asset }`
+    #[default]
+    Simulation,
+    /// `SimulateSwapOperations { offer_amount, operations }`
+    SimulateSwapOperations,
+}
+
+/// The event a pool reports its swap output in, for pools that do not emit the default `wasm`
+/// event with `return_amount`. Pools swapping through their own submessages may have it emitted
+/// by another contract.
+#[cw_serde]
+pub struct PoolEventFormat {
+    /// Type of the event, such as `wasm-swap`.
+    pub event_type: String,
+    /// Attributes that may hold the output amount, in order of preference.
+    pub amount_keys: Vec<String>,
+}
+
+/// AMM pools that leftover intermediate assets are swapped through into a route's output before
+/// it completes, keyed by the asset ids of the leftover and of the output.
+pub const SWEEP_POOLS: Map<(&str, &str), Addr> = Map::new("sweep_pools");
+
+/// The kind of venue behind a pool address.
+#[cw_serde]
+pub enum PoolProtocol {
+    Amm,
+    Orderbook,
+    Aggregator,
+}
+
+/// Display information for a pool, so front-ends can render routes without a separate registry.
+#[cw_serde]
+pub struct PoolMetadata {
+    pub label: String,
+    pub protocol: PoolProtocol,
+    /// Decimals of the tokens the pool trades.
+    pub token_decimals: Vec<TokenDecimals>,
+    /// Exchange market id, for orderbook pools.
+    pub market_id: Option<String>,
+    /// Router that trades through this pool for its asset pair, for AMM pools. Consecutive hops
+    /// of a path on pools of the same router are swapped in one router call.
+    pub router: Option<String>,
+}
+
+#[cw_serde]
+pub struct TokenDecimals {
+    pub asset_info: amm::AssetInfo,
+    pub decimals: u8,
+}
+
+/// The settings the admin registered for a pool. Each defaults to the pool behaving like a plain
+/// AMM pool with no limits.
+#[cw_serde]
+#[derive(Default)]
+pub struct PoolConfig {
+    /// Simulation query shape the pool understands.
+    pub query_kind: PoolQueryKind,
+    /// Event the pool's swap output is read from, when not the default.
+    pub event_format: Option<PoolEventFormat>,
+    /// The most a single swap may offer to the pool, for pools too shallow to take larger
+    /// trades.
+    pub max_input: Option<Uint128>,
+    pub metadata: Option<PoolMetadata>,
+}
+
+/// Pools with any setting registered. Pools with only defaults have no entry.
+pub const POOL_CONFIGS: Map<&Addr, PoolConfig> = Map::new("pool_configs");
+
+/// The settings registered for `pool`, or the defaults.
+pub fn load_pool_config(storage: &dyn Storage, pool: &Addr) -> StdResult<PoolConfig> {
+    Ok(POOL_CONFIGS.may_load(storage, pool)?.unwrap_or_default())
+}
+
+/// Applies `update` to the settings of `pool`, dropping its entry once none is left set.
+pub fn update_pool_config(
+    storage: &mut dyn Storage,
+    pool: &Addr,
+    update: impl FnOnce(&mut PoolConfig),
+) -> StdResult<()> {
+    let mut config = load_pool_config(storage, pool)?;
+    update(&mut config);
+    if config == PoolConfig::default() {
+        POOL_CONFIGS.remove(storage, pool);
+        Ok(())
+    } else {
+        POOL_CONFIGS.save(storage, pool, &config)
+    }
+}
+
+/// Trading rules of the orderbook market behind a swap contract, as enforced by the exchange.
+#[cw_serde]
+pub struct MarketInfo {
+    /// The market's quote denom, which `min_notional` is measured in.
+    pub quote_denom: String,
+    /// Quantity increment of orders. Replaces the route's `min_quantity_tick_size` for this swap
+    /// contract.
+    pub min_quantity_tick_size: Uint128,
+    /// Price increment of orders, for routers quoting the market off-chain.
+    pub min_price_tick_size: Decimal,
+    /// Smallest order value, in `quote_denom`, the exchange accepts.
+    pub min_notional: Uint128,
+    /// Taker fee rate the exchange charges on fills. Its quotes already net it, so it is kept for
+    /// routers quoting the market off-chain.
+    pub taker_fee_rate: Decimal,
+}
+
+/// Orderbook markets keyed by swap contract. Swaps on unlisted contracts use the route's tick size
+/// and are not checked against a minimum notional.
+pub const MARKETS: Map<&Addr, MarketInfo> = Map::new("markets");
+
+/// Where the USD price of an asset is read from.
+#[cw_serde]
+pub enum PriceSource {
+    /// A Pyth feed of the Injective oracle module, by its hex price id.
+    Pyth { price_id: String },
+    /// A price pair of the Injective oracle module, whose quote is USD.
+    Injective {
+        oracle_type: OracleType,
+        base: String,
+        quote: String,
+    },
+}
+
+/// How routes paying out an asset value it in USD, for their `minimum_receive_usd`.
+#[cw_serde]
+pub struct UsdPriceFeed {
+    pub source: PriceSource,
+    /// Decimals of the asset, by which its amounts are turned into whole units before pricing.
+    pub decimals: u8,
+    /// Age, in seconds, past which the feed's price is refused.
+    pub max_age_seconds: u64,
+}
+
+/// USD price feeds keyed by asset id. A feed also prices the other form of a CW20 output.
+pub const USD_PRICE_FEEDS: Map<&str, UsdPriceFeed> = Map::new("usd_price_feeds");
+
+/// A wallet or frontend routing through the aggregator under its own fee. Routes naming it pay
+/// `fee_bps` of their output to `collector`, on top of the aggregator fees charged per hop.
+#[cw_serde]
+pub struct Integrator {
+    pub address: Addr,
+    pub fee_bps: u16,
+    pub collector: Addr,
+}
+
+/// Registered integrators keyed by address.
+pub const INTEGRATORS: Map<&Addr, Integrator> = Map::new("integrators");
+
+/// An address paid `share_bps` basis points of a route's output.
+#[cw_serde]
+pub struct Recipient {
+    pub address: Addr,
+    pub share_bps: u16,
+}
+
+/// CW20 tokens whose received amounts are measured from the contract's balance instead of
+/// trusted from transfer amounts, for tokens that levy a tax on transfers.
+pub const BALANCE_CHECKED_TOKENS: Map<&Addr, Empty> = Map::new("balance_checked_tokens");
+
+/// Pools routes may use. While empty, every pool is allowed.
+pub const POOL_ALLOWLIST: Map<&Addr, Empty> = Map::new("pool_allowlist");
+
+/// Pools frozen by the admin. Routes through them are rejected until they are enabled again.
+pub const DISABLED_POOLS: Map<&Addr, Empty> = Map::new("disabled_pools");
+
+/// Terms on which pool operators may add their own AMM pools to the allowlist.
+#[cw_serde]
+pub struct PoolRegistrationTerms {
+    /// Anti-spam fee attached to `RegisterPool`, passed on to the fee collectors. A zero amount
+    /// takes no fee.
+    pub fee: Coin,
+    /// Amount of each pool asset the pool must quote a non-zero output for.
+    pub canary_amount: Uint128,
+    /// Code IDs the admin vetted, one of which a pool must be instantiated from to register.
+    pub code_ids: Vec<u64>,
+}
+
+/// Unset while pools can only be allowlisted by the admin.
+pub const POOL_REGISTRATION_TERMS: Item<PoolRegistrationTerms> =
+    Item::new("pool_registration_terms");
+/// Pools their operators added to the allowlist, with the operator that registered each. Their
+/// output is always measured from the contract's balance rather than taken from their events.
+pub const SELF_REGISTERED_POOLS: Map<&Addr, Addr> = Map::new("self_registered_pools");
+
+/// The native denom of INJ.
+pub const INJ_DENOM: &str = "inj";
+/// The wrapped-INJ CW20. Its contract wraps and unwraps native INJ one to one, so routes treat
+/// the two like the native and CW20 forms of an adapter token.
+pub const WRAPPED_INJ: Item<Addr> = Item::new("wrapped_inj");
+
+/// A contract converting a native denom one to one to and from the canonical denom of the same
+/// token, such as a bridged representation of USDT and the canonical one. Routes treat the two
+/// like the native and CW20 forms of an adapter token. Each message is a JSON template sent with
+/// the converted coins attached, with `{amount}` and `{recipient}` filled in.
+#[cw_serde]
+pub struct NativeConverter {
+    pub contract: Addr,
+    pub canonical_denom: String,
+    pub to_canonical_msg: String,
+    pub from_canonical_msg: String,
+}
+
+impl NativeConverter {
+    /// The message converting `amount` into the canonical denom, or out of it, paying
+    /// `recipient`. Fails if the filled-in template is not a JSON object.
+    pub fn conversion_msg(
+        &self,
+        to_canonical: bool,
+        amount: Uint128,
+        recipient: &Addr,
+    ) -> StdResult<Binary> {
+        let template = if to_canonical {
+            &self.to_canonical_msg
+        } else {
+            &self.from_canonical_msg
+        };
+        let msg = template
+            .replace("{amount}", &amount.to_string())
+            .replace("{recipient}", recipient.as_str());
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&msg).map_err(
+            |err| StdError::generic_err(format!("Converter message is not a JSON object: {err}")),
+        )?;
+        Ok(Binary::from(msg.into_bytes()))
+    }
+}
+
+/// Native converters, by the denom they convert to and from its canonical denom.
+pub const NATIVE_CONVERTERS: Map<&str, NativeConverter> = Map::new("native_converters");
+
+pub fn load_parameters(storage: &dyn Storage) -> StdResult<Parameters> {
+    Ok(PARAMETERS.may_load(storage)?.unwrap_or_default())
+}
+
+/// Per-asset dust thresholds, keyed by denom or CW20 contract address.
+pub const DUST_THRESHOLDS: Map<&str, Uint128> = Map::new("dust_thresholds");
+/// Leftover amounts accrued to the protocol, swept to the fee collectors by the admin.
+pub const DUST_BUCKET: Item<Vec<amm::Asset>> = Item::new("dust_bucket");
+
+/// Fees whose send to a collector failed, held in the contract until the collector claims them.
+pub const UNCLAIMED_FEES: Map<&Addr, Vec<amm::Asset>> = Map::new("unclaimed_fees");
+
+/// Reply id of fee sends, which only reply when the send fails. Route ids count up from 1 and
+/// never reach it.
+pub const FEE_DELIVERY_REPLY_ID: u64 = u64::MAX;
+
+/// The payload of a fee send: what to keep for the collector should it fail.
+#[cw_serde]
+pub struct FeeDelivery {
+    /// The route that took the fee, or `None` for swept dust and integrator fees.
+    pub route_id: Option<u64>,
+    pub collector: Addr,
+    pub asset: amm::Asset,
+}
+
+/// Identifies an asset by its denom or CW20 contract address.
+pub fn asset_id(info: &amm::AssetInfo) -> &str {
+    match info {
+        amm::AssetInfo::NativeToken { denom } => denom,
+        amm::AssetInfo::Token { contract_addr } => contract_addr,
+    }
+}
+
+/// Whether `asset` is below its dust threshold.
+pub fn is_dust(storage: &dyn Storage, asset: &amm::Asset) -> StdResult<bool> {
+    let threshold = match DUST_THRESHOLDS.may_load(storage, asset_id(&asset.info))? {
+        Some(threshold) => threshold,
+        None => load_parameters(storage)?.dust_threshold,
+    };
+    Ok(asset.amount < threshold)
+}
+
+/// Adds `asset` to the protocol dust bucket.
+pub fn accrue_dust(storage: &mut dyn Storage, asset: &amm::Asset) -> StdResult<()> {
+    let mut bucket = DUST_BUCKET.may_load(storage)?.unwrap_or_default();
+    add_asset_amount(&mut bucket, &asset.info, asset.amount);
+    DUST_BUCKET.save(storage, &bucket)
+}
+
+/// Adds `amount` of `info` to a running per-asset total, such as a route's collected fees.
+pub fn add_asset_amount(assets: &mut Vec<amm::Asset>, info: &amm::AssetInfo, amount: Uint128) {
+    if let Some(existing) = assets.iter_mut().find(|a| a.info == *info) {
+        existing.amount += amount;
+    } else {
+        assets.push(amm::Asset {
+            info: info.clone(),
+            amount,
+        });
+    }
+}
+
+/// Cumulative activity routed through one pool.
+#[cw_serde]
+#[derive(Default)]
+pub struct PoolStats {
+    /// Input sent to the pool, per offer asset.
+    pub volume_in: Vec<amm::Asset>,
+    /// Aggregator fees taken on the pool's output, per asset.
+    pub fees_collected: Vec<amm::Asset>,
+    pub swaps: u64,
+}
+
+pub const POOL_STATS: Map<&Addr, PoolStats> = Map::new("pool_stats");
+
+/// Recent marginal output per unit of input of a pool in one direction, moved towards each rate
+/// a swap through it was checked at.
+#[cw_serde]
+pub struct ReferenceRate {
+    pub rate: Decimal,
+    /// Block time, in seconds, of the swap that last moved it.
+    pub updated_at: u64,
+}
+
+/// Reference rates by pool and offer asset id.
+pub const POOL_REFERENCE_RATES: Map<(&Addr, &str), ReferenceRate> =
+    Map::new("pool_reference_rates");
+/// Weight of each checked rate in the moving reference rate.
+pub const REFERENCE_RATE_WEIGHT: Decimal = Decimal::percent(20);
+/// A reference rate not moved for this long is replaced by the next checked rate instead of
+/// being compared against it, so a pool whose price has moved on is not blocked for good.
+pub const REFERENCE_RATE_MAX_AGE_SECONDS: u64 = 3_600;
+/// Share of an operation's offer its pool is quoted for to read its marginal rate.
+pub const REFERENCE_RATE_CANARY_SHARE: Decimal = Decimal::permille(1);
+
+pub fn record_pool_swap(
+    storage: &mut dyn Storage,
+    pool: &Addr,
+    offer_info: &amm::AssetInfo,
+    amount: Uint128,
+) -> StdResult<()> {
+    let mut stats = POOL_STATS.may_load(storage, pool)?.unwrap_or_default();
+    add_asset_amount(&mut stats.volume_in, offer_info, amount);
+    stats.swaps += 1;
+    POOL_STATS.save(storage, pool, &stats)
+}
+
+pub fn record_pool_fee(
+    storage: &mut dyn Storage,
+    pool: &Addr,
+    fee_info: &amm::AssetInfo,
+    fee: Uint128,
+) -> StdResult<()> {
+    let mut stats = POOL_STATS.may_load(storage, pool)?.unwrap_or_default();
+    add_asset_amount(&mut stats.fees_collected, fee_info, fee);
+    POOL_STATS.save(storage, pool, &stats)
+}
+
+/// The fee charged on a pool: its `FEE_MAP` entry, or nothing for pools without one.
+pub fn effective_fee(storage: &dyn Storage, pool: &Addr) -> StdResult<Decimal> {
+    Ok(FEE_MAP.may_load(storage, pool)?.unwrap_or_default())
+}
+
+/// The fee a route is charged on `pool`: the one in its fee schedule, or the pool's current fee
+/// for pools outside it, such as sweep pools.
+pub fn route_fee(storage: &dyn Storage, plan: &RoutePlan, pool: &Addr) -> StdResult<Decimal> {
+    match plan
+        .fee_schedule
+        .iter()
+        .find(|entry| entry.pool_address == pool.as_str())
+    {
+        Some(entry) => Ok(entry.fee_percent),
+        None => effective_fee(storage, pool),
+    }
+}
+
+#[cw_serde]
+pub enum DenomPolicyMode {
+    /// Only the listed denoms may be a route's offer or ask asset.
+    Allow,
+    /// The listed denoms may not be a route's offer or ask asset.
+    Deny,
+}
+
+/// Restricts the terminal assets of a route. CW20 tokens are identified by contract address. A CW20
+/// registered with the cw20 adapter and its `factory/{adapter}/{cw20}` denom count as one token,
+/// so listing either form covers both.
+#[cw_serde]
+pub struct DenomPolicy {
+    pub mode: DenomPolicyMode,
+    pub denoms: Vec<String>,
+}
+
+impl DenomPolicy {
+    pub fn lists(&self, denom: &str) -> bool {
+        self.denoms.iter().any(|d| d == denom)
+    }
+
+    pub fn permits(&self, denom: &str) -> bool {
+        self.permits_listed(self.lists(denom))
+    }
+
+    /// Applies the policy's mode to whether a token is on its list.
+    pub fn permits_listed(&self, listed: bool) -> bool {
+        match self.mode {
+            DenomPolicyMode::Allow => listed,
+            DenomPolicyMode::Deny => !listed,
+        }
+    }
+}
+
+pub const DENOM_POLICY: Item<DenomPolicy> = Item::new("denom_policy");
+
+/// Per-sender limits on starting routes, for public deployments that subsidise execution.
+#[cw_serde]
+pub struct RateLimits {
+    /// Routes one address may start in a single block.
+    pub max_routes_per_block: Option<u32>,
+    /// Most of each listed offer asset one address may route within any hour. Assets not
+    /// listed are unlimited.
+    pub max_notional_per_hour: Vec<amm::Asset>,
+}
+
+/// Length of the sliding window `max_notional_per_hour` is enforced over.
+pub const RATE_LIMIT_WINDOW_SECONDS: u64 = 3_600;
+
+pub const RATE_LIMITS: Item<RateLimits> = Item::new("rate_limits");
+/// The block height each sender last started a route at, and how many it started there.
+pub const SENDER_BLOCK_ROUTES: Map<&Addr, (u64, u32)> = Map::new("sender_block_routes");
+/// Offers each sender routed within the window, per asset id, oldest first, as
+/// (block time in seconds, amount).
+pub const SENDER_NOTIONAL: Map<(&Addr, &str), Vec<(u64, Uint128)>> = Map::new("sender_notional");
+
+/// Addresses the admin allows to submit routes on behalf of their owners.
+pub const RELAYERS: Map<&Addr, Empty> = Map::new("relayers");
+/// The secp256k1 public key each owner signs relayed routes with.
+pub const RELAY_KEYS: Map<&Addr, Binary> = Map::new("relay_keys");
+/// The nonce the next route relayed for an owner must be signed with.
+pub const RELAY_NONCES: Map<&Addr, u64> = Map::new("relay_nonces");
+/// Native balances owners deposited for relayed routes to spend, per denom.
+pub const RELAY_DEPOSITS: Map<(&Addr, &str), Uint128> = Map::new("relay_deposits");
+
+/// A native offer escrowed to be routed in equal slices, one per interval, by its owner or a
+/// relayer.
+#[cw_serde]
+pub struct TwapOrder {
+    pub id: u64,
+    pub owner: Addr,
+    pub stages: Vec<Stage>,
+    /// What is left of the offer, escrowed in the contract's balance.
+    pub remaining: amm::Asset,
+    pub slices_left: u32,
+    /// Seconds between slices.
+    pub interval: u64,
+    /// Block time, in seconds, from which the next slice may execute.
+    pub next_slice_at: u64,
+    /// How far below its oracle value, or its simulated output where the route's assets have no
+    /// USD feeds, a slice may pay out.
+    pub max_slippage_bps: u16,
+}
+
+pub const TWAP_ORDERS: Map<u64, TwapOrder> = Map::new("twap_orders");
+pub const TWAP_ORDER_NEXT_ID: Item<u64> = Item::new("twap_order_next_id");
 
 #[cw_serde]
 pub struct PendingPathOp {
+    pub split_index: u64,
+    pub op_index: u64,
     pub operation: Operation,
     pub amount: Uint128,
 }
 
+/// The part of a zap's input held back while the rest is swapped, for the zap ending the path of
+/// split `split_index`.
+#[cw_serde]
+pub struct PendingZap {
+    pub split_index: u64,
+    pub retained: amm::Asset,
+}
+
+/// The input of an operation in flight, until its reply records its execution.
+#[cw_serde]
+pub struct PendingOffer {
+    /// Position of the operation in its stage, as (split, op) indices.
+    pub split_index: u64,
+    pub op_index: u64,
+    pub offer: amm::Asset,
+    /// Simulated output of the operation at dispatch, kept while `max_output_multiple` or
+    /// `max_rate_deviation` is set.
+    pub expected_output: Option<Uint128>,
+    /// Rate the pool quoted at dispatch for a canary-sized slice of the offer, kept while
+    /// `max_rate_deviation` is set.
+    pub marginal_rate: Option<Decimal>,
+}
+
+/// One operation of a route as executed, kept in its receipt for accounting.
+#[cw_serde]
+pub struct OperationExecution {
+    pub stage_index: u64,
+    pub split_index: u64,
+    pub op_index: u64,
+    /// The pool, swap contract or sub-aggregator that replied.
+    pub contract: Addr,
+    /// What the operation was offered. Hops swapped in one router call are recorded as the last
+    /// of them, with the first one's input.
+    pub offer: amm::Asset,
+    /// What the operation paid out, before the aggregator fee.
+    pub output: amm::Asset,
+    /// The aggregator fee taken from `output`.
+    pub fee: Uint128,
+    pub height: u64,
+    /// Block time in seconds.
+    pub time: u64,
+}
+
 #[cw_serde]
 pub struct RoutePlan {
     pub sender: Addr,
-    pub minimum_receive: Uint128,
+    pub minimum_receive: Option<amm::Asset>,
+    /// Empty once the plan is stored, as each stage is then kept under `ROUTE_STAGES`.
     pub stages: Vec<Stage>,
+    pub stage_count: u64,
+    pub offer_asset: amm::Asset,
+    pub max_stages_per_tx: Option<u64>,
+    pub tag: Option<String>,
+    pub pool_health_check: Option<PoolHealthPolicy>,
+    pub split_expectations: Option<SplitExpectations>,
+    /// Unix time in seconds forwarded to each AMM swap, which the pool rejects once passed.
+    pub deadline: Option<u64>,
+    /// Delivers the output to a contract's `Receive` hook instead of to the sender.
+    pub receive_hook: Option<ReceiveHook>,
+    /// Per-asset minimums of a multi-output route, whose final holdings are not normalized.
+    pub final_assets: Option<Vec<amm::Asset>>,
+    /// Taken with the output on the sender's behalf once it is paid out to them.
+    pub post_action: Option<PostAction>,
+    /// Highest aggregator fee, in basis points, any hop of the route may be charged.
+    pub max_fee_bps: Option<u16>,
+    /// Least the output must be worth in USD at its oracle price when the route settles.
+    pub minimum_receive_usd: Option<Decimal>,
+    /// The integrator the route was submitted through, as registered when it started.
+    pub integrator: Option<Integrator>,
+    /// The addresses the output is split between by share, in place of the sender.
+    pub recipients: Option<Vec<Recipient>>,
+    /// Pool the offered LP tokens are withdrawn from, whose assets seed the first stage.
+    pub withdraw_from: Option<String>,
+    /// Fee of every pool in the stages as the route started, so fee changes made while it is in
+    /// flight do not apply to it.
+    pub fee_schedule: Vec<FeeInfo>,
 }
 
 #[cw_serde]
 pub struct ExecutionState {
     pub awaiting: Awaiting,
     pub current_stage_index: u64,
+    /// Stage index at which the current transaction started executing the route.
+    pub tx_start_stage_index: u64,
     pub replies_expected: u64,
     pub accumulated_assets: Vec<amm::Asset>,
     pub pending_swaps: Vec<PlannedSwap>,
     pub pending_path_op: Option<PendingPathOp>,
+    /// Split whose output is being converted to the CW20 its path ends in.
+    pub pending_output_split: Option<u64>,
+    pub fees_collected: Vec<amm::Asset>,
+    pub conversions_performed: u64,
+    /// Inputs left unswapped by orderbook tick-size rounding and other leftovers, swept into the
+    /// output or refunded when the route completes.
+    pub skipped_assets: Vec<amm::Asset>,
+    /// Reply-tracked submessages dispatched over the route's lifetime.
+    pub submessages_dispatched: u64,
+    /// Deepest submessage nesting reached in any one transaction of the route.
+    pub reply_depth: u64,
+    /// Replies still outstanding at each nesting level of the current transaction, innermost last.
+    pub pending_replies_by_depth: Vec<u64>,
+    /// Contract balance of each balance-checked token the route touches as of the last reply,
+    /// less fees sent out since.
+    pub balance_snapshots: Vec<amm::Asset>,
+    /// Block time, in seconds, at which the route started or last checkpointed.
+    pub last_checkpoint_time: u64,
+    /// Balance-checked amounts the in-flight stage conversions were expected to deliver, less
+    /// what has been measured so far.
+    pub conversion_shortfalls: Vec<amm::Asset>,
+    /// Retained inputs of the zaps whose swaps are in flight, in dispatch order.
+    pub pending_zaps: Vec<PendingZap>,
+    /// Contract balances of the pool's assets before the route's liquidity withdrawal, while it
+    /// is in flight.
+    pub pending_withdrawal: Vec<amm::Asset>,
+    /// Inputs of the operations in flight, in dispatch order.
+    pub pending_offers: Vec<PendingOffer>,
+    /// The operations executed so far.
+    pub executions: Vec<OperationExecution>,
+    /// The asset the conversions in flight convert into. Only its CW20 contract may report their
+    /// result through the `InternalConversion` hook.
+    pub pending_conversion_target: Option<amm::AssetInfo>,
+    /// The contract's balances of the route's assets that the route does not own, as of the start
+    /// of the current transaction, plus the fees the route collected before it. Only recorded
+    /// while invariant checks are enabled.
+    pub balance_baseline: Option<Vec<amm::Asset>>,
 }
 
+/// Admin-registered stages that can be executed by name with `ExecuteTemplate`.
+pub const ROUTE_TEMPLATES: Map<&str, Vec<Stage>> = Map::new("route_templates");
+
 pub const ROUTE_PLANS: Map<u64, RoutePlan> = Map::new("route_plans");
+/// The stages of in-flight routes by route id and stage index. They are stored apart from the
+/// rest of the plan so that a reply reads only the stage it concerns, not the whole route.
+pub const ROUTE_STAGES: Map<(u64, u64), Stage> = Map::new("route_stages");
 pub const EXECUTION_STATES: Map<u64, ExecutionState> = Map::new("execution_states");
+/// Amounts delivered through the adapter's `InternalConversion` hook, awaiting the conversion reply.
+pub const CONVERSION_RESULTS: Map<u64, Uint128> = Map::new("conversion_results");
+
+/// An in-flight route's plan as it is executed: the plan without its stages, with each stage
+/// loaded from `ROUTE_STAGES` the first time it is asked for. The plan's own `stages` are empty.
+pub struct LoadedPlan {
+    route_id: u64,
+    plan: RoutePlan,
+    stages: Vec<OnceCell<Stage>>,
+}
+
+impl LoadedPlan {
+    pub fn load(storage: &dyn Storage, route_id: u64) -> StdResult<Self> {
+        let plan = ROUTE_PLANS.load(storage, route_id)?;
+        let stages = (0..plan.stage_count).map(|_| OnceCell::new()).collect();
+        Ok(LoadedPlan {
+            route_id,
+            plan,
+            stages,
+        })
+    }
+
+    /// The stage at `index`, or `None` past the route's last stage.
+    pub fn stage(&self, storage: &dyn Storage, index: u64) -> StdResult<Option<&Stage>> {
+        let Some(cell) = self.stages.get(index as usize) else {
+            return Ok(None);
+        };
+        if let Some(stage) = cell.get() {
+            return Ok(Some(stage));
+        }
+        let stage = ROUTE_STAGES.load(storage, (self.route_id, index))?;
+        Ok(Some(cell.get_or_init(|| stage)))
+    }
+
+    /// The route's final stage.
+    pub fn last_stage(&self, storage: &dyn Storage) -> StdResult<Option<&Stage>> {
+        match self.plan.stage_count.checked_sub(1) {
+            Some(index) => self.stage(storage, index),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Deref for LoadedPlan {
+    type Target = RoutePlan;
+
+    fn deref(&self) -> &RoutePlan {
+        &self.plan
+    }
+}
+
+/// Stores `plan` as `route_id`'s, its stages apart from the rest of it, and returns it as loaded.
+pub fn save_route_plan(
+    storage: &mut dyn Storage,
+    route_id: u64,
+    mut plan: RoutePlan,
+) -> StdResult<LoadedPlan> {
+    let stages = std::mem::take(&mut plan.stages);
+    for (index, stage) in stages.iter().enumerate() {
+        ROUTE_STAGES.save(storage, (route_id, index as u64), stage)?;
+    }
+    plan.stage_count = stages.len() as u64;
+    ROUTE_PLANS.save(storage, route_id, &plan)?;
+    Ok(LoadedPlan {
+        route_id,
+        plan,
+        stages: stages.into_iter().map(OnceCell::from).collect(),
+    })
+}
+
+/// Removes `route_id`'s plan and its stages.
+pub fn remove_route_plan(storage: &mut dyn Storage, route_id: u64, plan: &RoutePlan) {
+    for index in 0..plan.stage_count {
+        ROUTE_STAGES.remove(storage, (route_id, index));
+    }
+    ROUTE_PLANS.remove(storage, route_id);
+}
 
 pub const REPLY_ID_COUNTER: Item<u64> = Item::new("reply_id_counter");
+
+/// Most admin actions kept in the admin log. Older entries are dropped as new ones arrive.
+pub const ADMIN_LOG_CAPACITY: u64 = 500;
+
+/// A successful admin action, as recorded in the admin log.
+#[cw_serde]
+pub struct AdminLogEntry {
+    pub id: u64,
+    pub height: u64,
+    pub sender: Addr,
+    /// The executed message, in snake case, e.g. `set_fee`.
+    pub action: String,
+    /// The attributes of the action's response, other than `action` itself.
+    pub attributes: Vec<Attribute>,
+}
+
+/// Admin log entries keyed by id, holding the latest `ADMIN_LOG_CAPACITY`.
+pub const ADMIN_LOG: Map<u64, AdminLogEntry> = Map::new("admin_log");
+/// Id of the next admin log entry.
+pub const ADMIN_LOG_NEXT_ID: Item<u64> = Item::new("admin_log_next_id");
+
+pub fn record_admin_action(
+    storage: &mut dyn Storage,
+    height: u64,
+    sender: Addr,
+    action: &str,
+    attributes: &[Attribute],
+) -> StdResult<()> {
+    let id = ADMIN_LOG_NEXT_ID.may_load(storage)?.unwrap_or_default();
+    let entry = AdminLogEntry {
+        id,
+        height,
+        sender,
+        action: action.to_string(),
+        attributes: attributes
+            .iter()
+            .filter(|attr| attr.key != "action")
+            .cloned()
+            .collect(),
+    };
+    ADMIN_LOG.save(storage, id, &entry)?;
+    if id >= ADMIN_LOG_CAPACITY {
+        ADMIN_LOG.remove(storage, id - ADMIN_LOG_CAPACITY);
+    }
+    ADMIN_LOG_NEXT_ID.save(storage, &(id + 1))
+}
+
+/// Number of blocks a completed route's receipt remains queryable.
+pub const RECEIPT_RETENTION_BLOCKS: u64 = 100_000;
+
+#[cw_serde]
+pub struct Receipt {
+    pub input: amm::Asset,
+    pub output: amm::Asset,
+    /// Further assets paid out by a multi-output route, after `output`.
+    pub additional_outputs: Vec<amm::Asset>,
+    pub fees: Vec<amm::Asset>,
+    pub stages_executed: u64,
+    pub conversions: u64,
+    pub completed_at_height: u64,
+    /// Block time, in seconds, at which the route completed.
+    pub completed_at_time: u64,
+    pub expires_at_height: u64,
+    pub executions: Vec<OperationExecution>,
+    /// The fee schedule the route was charged by.
+    pub fee_schedule: Vec<FeeInfo>,
+}
+
+pub const RECEIPTS: Map<u64, Receipt> = Map::new("receipts");
+
+/// The last entry of each kind the previous `PruneHistory` call examined, which the next one
+/// resumes after. Unset once a kind was scanned to its end, to start it over.
+#[cw_serde]
+#[derive(Default)]
+pub struct PruneCursors {
+    pub receipts: Option<u64>,
+    pub routes: Option<u64>,
+    pub block_routes: Option<Addr>,
+    pub notional: Option<(Addr, String)>,
+}
+
+pub const PRUNE_CURSORS: Item<PruneCursors> = Item::new("prune_cursors");
+
+/// Where a route stood before or after a traced reply.
+#[cw_serde]
+pub struct TraceState {
+    pub awaiting: Awaiting,
+    pub stage_index: u64,
+}
+
+/// A reply processed for a route while `Parameters::tracing_enabled` was set.
+#[cw_serde]
+pub struct TraceEntry {
+    pub height: u64,
+    pub before: TraceState,
+    /// `None` once the reply completed the route.
+    pub after: Option<TraceState>,
+    /// The operations whose output the reply accounted for, with the amounts read from it.
+    /// Empty for conversions, withdrawals and other replies outside a path.
+    pub executions: Vec<OperationExecution>,
+}
+
+/// The replies of each traced route in the order they were processed, kept while the route is in
+/// flight and pruned with its receipt.
+pub const TRACES: Map<u64, Vec<TraceEntry>> = Map::new("traces");
@@ -0,0 +1,464 @@
+//! In-crate harness for unit-testing the reply flows without a chain: dependencies whose querier
+//! answers bank, CW20 and pool queries per contract, seeded route state, and builders for the
+//! replies that pools and the cw20 adapter send back.
+//!
+//! Compiled for the crate's own tests and, with the `testing` feature, for downstream crates.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage};
+use cosmwasm_std::{
+    from_json, to_json_binary, Addr, Binary, Coin, ContractResult, Decimal, Event, Order,
+    OwnedDeps, Record, Reply, StdError, StdResult, Storage, SubMsgResponse, SubMsgResult,
+    SystemError, SystemResult, Uint128, WasmQuery,
+};
+use cw20::{BalanceResponse, Cw20QueryMsg};
+use injective_cosmwasm::oracle::types::{PriceState, PythPriceState};
+use injective_cosmwasm::{InjectiveQuery, InjectiveQueryWrapper, PythPriceResponse};
+use injective_math::FPDecimal;
+
+use crate::error::ContractError;
+use crate::msg::{amm, Stage};
+use crate::planner::StagePlan;
+use crate::state::{
+    save_route_plan, Awaiting, Config, ExecutionState, FeeCollector, RoutePlan, CONFIG,
+    CONVERSION_RESULTS, EXECUTION_STATES,
+};
+
+pub type MockDeps =
+    OwnedDeps<MockStorage, MockApi, MockQuerier<InjectiveQueryWrapper>, InjectiveQueryWrapper>;
+
+type ContractHandler = Box<dyn Fn(&Binary) -> StdResult<Binary>>;
+
+/// Smart-query handlers keyed by contract address. Queries to any other contract fail with
+/// `NoSuchContract`, as they would on chain.
+#[derive(Default)]
+pub struct MockContracts {
+    handlers: HashMap<String, ContractHandler>,
+}
+
+impl MockContracts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_contract(
+        mut self,
+        address: impl Into<String>,
+        handler: impl Fn(&Binary) -> StdResult<Binary> + 'static,
+    ) -> Self {
+        self.handlers.insert(address.into(), Box::new(handler));
+        self
+    }
+
+    /// A CW20 token answering `Balance` from a fixed table; unlisted holders have none.
+    pub fn with_cw20(self, token: impl Into<String>, balances: &[(&str, u128)]) -> Self {
+        let balances: HashMap<String, Uint128> = balances
+            .iter()
+            .map(|(holder, amount)| (holder.to_string(), Uint128::new(*amount)))
+            .collect();
+        self.with_contract(token, move |msg| match from_json(msg)? {
+            Cw20QueryMsg::Balance { address } => to_json_binary(&BalanceResponse {
+                balance: balances.get(&address).copied().unwrap_or_default(),
+            }),
+            _ => Err(StdError::generic_err("mock cw20 only answers Balance")),
+        })
+    }
+
+    /// An AMM pool answering `Simulation` at a fixed return amount.
+    pub fn with_amm_pool(self, pool: impl Into<String>, return_amount: u128) -> Self {
+        self.with_contract(pool, move |_| {
+            to_json_binary(&amm::SimulationResponse {
+                return_amount: Uint128::new(return_amount),
+                spread_amount: Uint128::zero(),
+                commission_amount: Uint128::zero(),
+            })
+        })
+    }
+
+    fn install(self, querier: &mut MockQuerier<InjectiveQueryWrapper>) {
+        querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { contract_addr, msg } => match self.handlers.get(contract_addr) {
+                Some(handler) => SystemResult::Ok(ContractResult::from(handler(msg))),
+                None => SystemResult::Err(SystemError::NoSuchContract {
+                    addr: contract_addr.clone(),
+                }),
+            },
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "non-smart wasm query".to_string(),
+            }),
+        });
+    }
+}
+
+/// Dependencies with the given bank balances and contracts, and a stored `Config` whose admin,
+/// adapter and single fee collector are `addr_make("admin")`, `addr_make("adapter")` and
+/// `addr_make("collector")`.
+pub fn mock_deps(balances: &[(&str, &[Coin])], contracts: MockContracts) -> MockDeps {
+    let mut querier = MockQuerier::<InjectiveQueryWrapper>::new(balances);
+    contracts.install(&mut querier);
+    let api = MockApi::default();
+    let mut deps = OwnedDeps {
+        storage: MockStorage::default(),
+        api,
+        querier,
+        custom_query_type: PhantomData,
+    };
+    let config = Config {
+        admin: api.addr_make("admin"),
+        cw20_adapter_address: api.addr_make("adapter"),
+        fee_collectors: vec![FeeCollector {
+            address: api.addr_make("collector"),
+            weight: Decimal::one(),
+        }],
+    };
+    CONFIG.save(&mut deps.storage, &config).unwrap();
+    deps
+}
+
+/// Answers the Injective oracle's Pyth price queries from `prices`, as (price id, price, publish
+/// time in seconds). Unlisted feeds have no price.
+pub fn set_pyth_prices(deps: &mut MockDeps, prices: &[(&str, &str, i64)]) {
+    let prices: HashMap<String, (FPDecimal, i64)> = prices
+        .iter()
+        .map(|(price_id, price, publish_time)| {
+            (
+                price_id.to_string(),
+                (FPDecimal::must_from_str(price), *publish_time),
+            )
+        })
+        .collect();
+    let querier = std::mem::replace(&mut deps.querier, MockQuerier::new(&[]));
+    deps.querier = querier.with_custom_handler(move |query| match &query.query_data {
+        InjectiveQuery::PythPrice { price_id } => {
+            let price_state = prices
+                .get(price_id)
+                .map(|(price, publish_time)| PythPriceState {
+                    price_id: price_id.clone(),
+                    ema_price: *price,
+                    ema_conf: FPDecimal::ZERO,
+                    conf: FPDecimal::ZERO,
+                    publish_time: *publish_time,
+                    price_state: PriceState {
+                        price: *price,
+                        cumulative_price: FPDecimal::ZERO,
+                        timestamp: *publish_time,
+                    },
+                });
+            SystemResult::Ok(ContractResult::from(to_json_binary(&PythPriceResponse {
+                price_state,
+            })))
+        }
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "injective query other than PythPrice".to_string(),
+        }),
+    });
+}
+
+/// A route plan for `stages` with no optional behaviour enabled.
+pub fn route_plan(
+    sender: Addr,
+    stages: Vec<Stage>,
+    offer_asset: amm::Asset,
+    minimum_receive: Option<amm::Asset>,
+) -> RoutePlan {
+    RoutePlan {
+        sender,
+        minimum_receive,
+        stage_count: stages.len() as u64,
+        stages,
+        offer_asset,
+        max_stages_per_tx: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+        withdraw_from: None,
+        fee_schedule: vec![],
+    }
+}
+
+/// Execution state at `stage_index`, waiting on `replies_expected` replies of kind `awaiting`.
+pub fn execution_state(
+    awaiting: Awaiting,
+    stage_index: u64,
+    replies_expected: u64,
+) -> ExecutionState {
+    ExecutionState {
+        awaiting,
+        current_stage_index: stage_index,
+        tx_start_stage_index: 0,
+        replies_expected,
+        accumulated_assets: vec![],
+        pending_swaps: vec![],
+        pending_path_op: None,
+        pending_output_split: None,
+        fees_collected: vec![],
+        conversions_performed: 0,
+        skipped_assets: vec![],
+        submessages_dispatched: replies_expected,
+        reply_depth: 1,
+        pending_replies_by_depth: vec![replies_expected],
+        balance_snapshots: vec![],
+        conversion_shortfalls: vec![],
+        last_checkpoint_time: 0,
+        pending_zaps: vec![],
+        pending_withdrawal: vec![],
+        pending_offers: vec![],
+        executions: vec![],
+        pending_conversion_target: None,
+        balance_baseline: None,
+    }
+}
+
+/// Stores a route as if `route_id` had been dispatched and was waiting on replies.
+pub fn seed_route(
+    storage: &mut dyn Storage,
+    route_id: u64,
+    plan: &RoutePlan,
+    exec_state: &ExecutionState,
+) -> StdResult<()> {
+    save_route_plan(storage, route_id, plan.clone())?;
+    EXECUTION_STATES.save(storage, route_id, exec_state)
+}
+
+/// Storage that records the keys read through it, so a test can assert what a handler loads.
+pub struct CountingStorage<'a> {
+    inner: &'a mut dyn Storage,
+    reads: RefCell<Vec<Vec<u8>>>,
+}
+
+impl<'a> CountingStorage<'a> {
+    pub fn new(inner: &'a mut dyn Storage) -> Self {
+        CountingStorage {
+            inner,
+            reads: RefCell::new(vec![]),
+        }
+    }
+
+    /// Reads of the entries of the map stored under `namespace`.
+    pub fn reads_of(&self, namespace: &str) -> usize {
+        let prefix = [
+            &(namespace.len() as u16).to_be_bytes()[..],
+            namespace.as_bytes(),
+        ]
+        .concat();
+        self.reads
+            .borrow()
+            .iter()
+            .filter(|key| key.starts_with(&prefix))
+            .count()
+    }
+}
+
+impl Storage for CountingStorage<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.reads.borrow_mut().push(key.to_vec());
+        self.inner.get(key)
+    }
+
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        self.inner.range(start, end, order)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.inner.set(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.inner.remove(key)
+    }
+}
+
+fn reply_with_events(route_id: u64, events: Vec<Event>) -> Reply {
+    #[allow(deprecated)]
+    let response = SubMsgResponse {
+        events,
+        data: None,
+        msg_responses: vec![],
+    };
+    Reply {
+        id: route_id,
+        payload: Binary::default(),
+        gas_used: 0,
+        result: SubMsgResult::Ok(response),
+    }
+}
+
+fn amm_swap_event(pool: &str, return_amount: u128) -> Event {
+    Event::new("wasm")
+        .add_attribute("_contract_address", pool)
+        .add_attribute("action", "swap")
+        .add_attribute("return_amount", return_amount.to_string())
+}
+
+/// The reply to an AMM swap that returned `return_amount`.
+pub fn amm_swap_reply(route_id: u64, pool: &str, return_amount: u128) -> Reply {
+    reply_with_events(route_id, vec![amm_swap_event(pool, return_amount)])
+}
+
+/// The reply to a router call, with a swap event from each pool it swapped through, in order.
+pub fn router_swap_reply(route_id: u64, hops: &[(&str, u128)]) -> Reply {
+    reply_with_events(
+        route_id,
+        hops.iter()
+            .map(|(pool, return_amount)| amm_swap_event(pool, *return_amount))
+            .collect(),
+    )
+}
+
+/// The reply to a swap on an Astroport pair that returned `return_amount`.
+pub fn astroport_swap_reply(route_id: u64, pool: &str, return_amount: u128) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![Event::new("wasm-astro_swap")
+            .add_attribute("_contract_address", pool)
+            .add_attribute("return_amount", return_amount.to_string())],
+    )
+}
+
+/// The reply to an orderbook swap that filled `final_amount`.
+pub fn orderbook_swap_reply(route_id: u64, contract: &str, final_amount: u128) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![Event::new("wasm-atomic_swap_execution")
+            .add_attribute("_contract_address", contract)
+            .add_attribute("swap_final_amount", final_amount.to_string())],
+    )
+}
+
+/// The reply to a swap `pool` executed through a submessage to `engine`, which reported its output
+/// as the `key` attribute of an `event_type` event.
+pub fn nested_swap_reply(
+    route_id: u64,
+    pool: &str,
+    engine: &str,
+    event_type: &str,
+    key: &str,
+    amount: u128,
+) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![
+            Event::new("execute").add_attribute("_contract_address", pool),
+            Event::new("execute").add_attribute("_contract_address", engine),
+            Event::new(event_type)
+                .add_attribute("_contract_address", engine)
+                .add_attribute(key, amount.to_string()),
+        ],
+    )
+}
+
+/// The reply to a sub-aggregator operation. With `final_received` its nested route completed and
+/// paid that out; without it, the nested route checkpointed.
+pub fn sub_aggregator_reply(route_id: u64, contract: &str, final_received: Option<u128>) -> Reply {
+    let completion = Event::new("wasm").add_attribute("_contract_address", contract);
+    let completion = match final_received {
+        Some(amount) => completion
+            .add_attribute("action", "aggregate_swap_complete")
+            .add_attribute("final_received", amount.to_string()),
+        None => completion.add_attribute("action", "route_checkpointed"),
+    };
+    reply_with_events(route_id, vec![completion])
+}
+
+/// The reply to a liquidity provision that minted `share` LP tokens.
+pub fn provide_liquidity_reply(route_id: u64, pool: &str, share: u128) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![Event::new("wasm")
+            .add_attribute("_contract_address", pool)
+            .add_attribute("action", "provide_liquidity")
+            .add_attribute("share", share.to_string())],
+    )
+}
+
+/// The reply to a swap whose pool emitted no swap event, which the contract reads as zero output.
+pub fn empty_reply(route_id: u64) -> Reply {
+    reply_with_events(route_id, vec![])
+}
+
+/// The reply to a CW20 -> native adapter conversion, which pays out by bank transfer.
+pub fn adapter_bank_transfer_reply(
+    route_id: u64,
+    recipient: &Addr,
+    amount: u128,
+    denom: &str,
+) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![Event::new("transfer")
+            .add_attribute("recipient", recipient)
+            .add_attribute("amount", format!("{amount}{denom}"))],
+    )
+}
+
+/// The reply to wrapping INJ, whose CW20 is minted straight to `recipient`.
+pub fn wrapped_inj_mint_reply(
+    route_id: u64,
+    wrapper: &Addr,
+    recipient: &Addr,
+    amount: u128,
+) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![Event::new("wasm")
+            .add_attribute("_contract_address", wrapper)
+            .add_attribute("action", "mint")
+            .add_attribute("to", recipient)
+            .add_attribute("amount", amount.to_string())],
+    )
+}
+
+/// The reply to a swap on a venue that pays out by bank send from `sender`, with no swap event.
+/// `amount` is the transfer's coin list, e.g. `"300usdt,5inj"`.
+pub fn bank_settlement_reply(
+    route_id: u64,
+    sender: &Addr,
+    recipient: &Addr,
+    amount: &str,
+) -> Reply {
+    reply_with_events(
+        route_id,
+        vec![Event::new("transfer")
+            .add_attribute("recipient", recipient)
+            .add_attribute("sender", sender)
+            .add_attribute("amount", amount)],
+    )
+}
+
+/// The reply to a native -> CW20 adapter conversion. The adapter reports the amount through the
+/// `InternalConversion` hook, which runs before the reply, so it is recorded here too.
+pub fn adapter_cw20_reply(storage: &mut dyn Storage, route_id: u64, amount: u128) -> Reply {
+    CONVERSION_RESULTS
+        .save(storage, route_id, &Uint128::new(amount))
+        .unwrap();
+    reply_with_events(route_id, vec![])
+}
+
+/// The stage planner, which splits holdings across a stage and sizes the adapter conversions.
+pub fn plan_next_stage(
+    accumulated_assets: &[amm::Asset],
+    next_stage: &Stage,
+) -> Result<StagePlan, ContractError> {
+    crate::planner::plan_next_stage(accumulated_assets, next_stage)
+}
+
+/// The form final outputs are normalized into when no minimum names one.
+pub fn select_normalization_target(assets: &[amm::Asset]) -> amm::AssetInfo {
+    crate::planner::select_normalization_target(assets)
+}
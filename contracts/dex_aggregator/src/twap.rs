@@ -0,0 +1,215 @@
+//! Time-weighted orders: a native offer escrowed and routed in equal slices, one per interval.
+//!
+//! Each slice runs through `execute_aggregate_swaps_internal` like any other route, on behalf of
+//! the order's owner, who receives its output. Its minimum is derived when it executes: from the
+//! oracle when both the offer and the output have USD price feeds, and otherwise from a fresh
+//! simulation of the slice. Only the owner and the relayers the admin allows may execute slices,
+//! as a simulated minimum cannot protect a slice from the party executing it. A slice that fails
+//! leaves the order as it was, so it can be retried.
+
+use cosmwasm_std::{
+    BankMsg, Coin, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError, Uint128,
+};
+use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQueryWrapper};
+
+use crate::error::ContractError;
+use crate::execute::execute_aggregate_swaps_internal;
+use crate::msg::{amm, RouteOptions, Stage};
+use crate::oracle::{load_usd_price_feed, usd_value};
+use crate::routing::{check_stage_inputs, simulate_route_output};
+use crate::state::{TwapOrder, RELAYERS, TWAP_ORDERS, TWAP_ORDER_NEXT_ID};
+
+/// Escrows the attached native funds as a TWAP order of `n_slices` slices, the first of which
+/// may execute at once.
+#[allow(clippy::too_many_arguments)]
+pub fn place_twap_order(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    stages: Vec<Stage>,
+    total_amount: Uint128,
+    n_slices: u32,
+    interval: u64,
+    max_slippage_bps: u16,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let invalid = |reason: &str| ContractError::InvalidTwapOrder {
+        reason: reason.to_string(),
+    };
+    if info.funds.len() != 1 {
+        return Err(ContractError::InvalidFunds {});
+    }
+    let offer: amm::Asset = (&info.funds[0]).into();
+    if offer.amount != total_amount {
+        return Err(invalid("the attached funds must equal total_amount"));
+    }
+    if n_slices == 0 {
+        return Err(invalid("n_slices must be at least 1"));
+    }
+    if total_amount < Uint128::from(n_slices) {
+        return Err(invalid("every slice must offer at least one unit"));
+    }
+    if max_slippage_bps > 10_000 {
+        return Err(invalid("max_slippage_bps must be at most 10000"));
+    }
+    if stages.is_empty() {
+        return Err(ContractError::NoStages {});
+    }
+    check_stage_inputs(deps.storage, &stages, &[offer.info.clone()])?;
+
+    let id = TWAP_ORDER_NEXT_ID.may_load(deps.storage)?.unwrap_or(0) + 1;
+    TWAP_ORDER_NEXT_ID.save(deps.storage, &id)?;
+    let order = TwapOrder {
+        id,
+        owner: info.sender.clone(),
+        stages,
+        remaining: offer,
+        slices_left: n_slices,
+        interval,
+        next_slice_at: env.block.time.seconds(),
+        max_slippage_bps,
+    };
+    TWAP_ORDERS.save(deps.storage, id, &order)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "place_twap_order")
+        .add_attribute("twap_order_id", id.to_string())
+        .add_attribute("owner", info.sender)
+        .add_attribute("total_amount", total_amount.to_string())
+        .add_attribute("n_slices", n_slices.to_string())
+        .add_attribute("interval", interval.to_string()))
+}
+
+/// Routes the next slice of an order, once it is due. Every slice but the last offers the
+/// remaining amount divided by the slices left, so the last takes up the rounding.
+pub fn execute_twap_slice(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    info: MessageInfo,
+    order_id: u64,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let mut order = TWAP_ORDERS
+        .may_load(deps.storage, order_id)?
+        .ok_or(ContractError::TwapOrderNotFound { order_id })?;
+    if info.sender != order.owner && !RELAYERS.has(deps.storage, &info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !info.funds.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err(
+            "ExecuteTwapSlice does not accept funds; the slice spends the order's escrow",
+        )));
+    }
+    let now = env.block.time.seconds();
+    if now < order.next_slice_at {
+        return Err(ContractError::TwapSliceNotDue {
+            order_id,
+            next_slice_at: order.next_slice_at,
+        });
+    }
+
+    let slice = amm::Asset {
+        info: order.remaining.info.clone(),
+        amount: order.remaining.amount / Uint128::from(order.slices_left),
+    };
+    let (minimum_receive, minimum_receive_usd) =
+        slice_minimums(deps.as_ref(), &env, &order, &slice)?;
+
+    order.remaining.amount -= slice.amount;
+    order.slices_left -= 1;
+    order.next_slice_at = now + order.interval;
+    if order.slices_left == 0 {
+        TWAP_ORDERS.remove(deps.storage, order_id);
+    } else {
+        TWAP_ORDERS.save(deps.storage, order_id, &order)?;
+    }
+
+    let options = RouteOptions {
+        minimum_receive,
+        minimum_receive_usd,
+        ..Default::default()
+    };
+    let response = execute_aggregate_swaps_internal(
+        deps,
+        env,
+        info,
+        order.stages,
+        options,
+        None,
+        slice.clone(),
+        order.owner,
+    )?;
+
+    Ok(response
+        .add_attribute("twap_order_id", order_id.to_string())
+        .add_attribute("twap_slice", slice.amount.to_string())
+        .add_attribute("twap_slices_left", order.slices_left.to_string()))
+}
+
+/// The minimum a slice must pay out: its oracle value less the order's slippage when the offer
+/// and the output are both priced in USD, and otherwise its simulated output less the slippage.
+fn slice_minimums(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    order: &TwapOrder,
+    slice: &amm::Asset,
+) -> Result<(Option<amm::Asset>, Option<Decimal>), ContractError> {
+    let output_info = order
+        .stages
+        .last()
+        .and_then(|stage| stage.splits.first())
+        .and_then(|split| split.path.last())
+        .ok_or(ContractError::NoStages {})?
+        .ask_asset_info()?;
+    let kept = 10_000 - order.max_slippage_bps as u128;
+
+    if load_usd_price_feed(deps, &slice.info).is_ok()
+        && load_usd_price_feed(deps, &output_info).is_ok()
+    {
+        let value = usd_value(deps, env, slice)?;
+        return Ok((None, Some(value * Decimal::from_ratio(kept, 10_000u128))));
+    }
+
+    let querier_deps = deps.into_empty();
+    let simulated = simulate_route_output(
+        querier_deps.api,
+        &querier_deps.querier,
+        querier_deps.storage,
+        &order.stages,
+        slice.clone(),
+        None,
+    )?;
+    Ok((
+        Some(amm::Asset {
+            info: output_info,
+            amount: simulated.multiply_ratio(kept, 10_000u128),
+        }),
+        None,
+    ))
+}
+
+/// Closes an order and refunds what is left of its offer to its owner.
+pub fn cancel_twap_order(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    info: MessageInfo,
+    order_id: u64,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let order = TWAP_ORDERS
+        .may_load(deps.storage, order_id)?
+        .ok_or(ContractError::TwapOrderNotFound { order_id })?;
+    if info.sender != order.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    TWAP_ORDERS.remove(deps.storage, order_id);
+
+    let amm::AssetInfo::NativeToken { denom } = &order.remaining.info else {
+        return Err(ContractError::InvalidFunds {});
+    };
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: order.owner.to_string(),
+            amount: vec![Coin::new(order.remaining.amount, denom.clone())],
+        })
+        .add_attribute("action", "cancel_twap_order")
+        .add_attribute("twap_order_id", order_id.to_string())
+        .add_attribute("refunded", order.remaining.amount.to_string())
+        .add_attribute("slices_cancelled", order.slices_left.to_string()))
+}
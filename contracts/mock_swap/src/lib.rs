@@ -1,28 +1,18 @@
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{
-    entry_point, from_json, to_json_binary, BankMsg, Binary, Coin, CosmosMsg, Decimal, Deps,
-    DepsMut, Env, Event, MessageInfo, Response, StdError, StdResult, Uint128, WasmMsg,
+    entry_point, from_json, to_json_binary, Addr, BankMsg, Binary, Coin, CosmosMsg, Decimal, Deps,
+    DepsMut, Env, Event, MessageInfo, Order, Response, StdError, StdResult, Storage, Uint128,
+    WasmMsg,
 };
 use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
-use cw_storage_plus::Item;
+use cw_storage_plus::{Item, Map};
 use injective_cosmwasm::InjectiveQueryWrapper;
 use injective_math::FPDecimal;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
-pub enum AssetInfo {
-    Token { contract_addr: String },
-    NativeToken { denom: String },
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct Asset {
-    pub info: AssetInfo,
-    pub amount: Uint128,
-}
+pub use aggregation_types::{Asset, AssetInfo, MAX_DECIMALS};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct SwapEstimationResult {
@@ -52,6 +42,53 @@ pub enum ExecuteMsg {
         min_output_quantity: String,
     },
     Receive(Cw20ReceiveMsg),
+    // Admin-only. Adds or replaces the pair keyed by the config's (input, output) assets.
+    AddPair {
+        config: SwapConfig,
+    },
+    // Admin-only. `None` restores normal behaviour.
+    SetFailureMode {
+        failure_mode: Option<FailureMode>,
+    },
+    // Admin-only. Lets the pool accept liquidity, paying out LP tokens it has been funded with.
+    SetLiquidityPool {
+        pool: LiquidityPool,
+    },
+    // AMM pair interface. Mints by the scarcer side against the pool's reserves, x * y = k style.
+    ProvideLiquidity {
+        assets: Vec<Asset>,
+        slippage_tolerance: Option<Decimal>,
+        receiver: Option<String>,
+        deadline: Option<u64>,
+    },
+}
+
+// Sent along with LP tokens, as to a real pair.
+#[cw_serde]
+pub enum MockPairHookMsg {
+    WithdrawLiquidity {},
+}
+
+#[cw_serde]
+pub struct LiquidityPool {
+    // CW20 paid out as LP tokens, from the pool's own balance.
+    pub lp_token: String,
+    pub reserves: Vec<Asset>,
+    pub total_share: Uint128,
+}
+
+#[cw_serde]
+pub enum FailureMode {
+    // Every swap reverts.
+    AlwaysFail,
+    // Swaps with an offer amount above the threshold revert.
+    FailAboveAmount(Uint128),
+    // Output is paid, but the swap event uses attribute keys the aggregator does not recognise.
+    WrongEventFormat,
+    // Output is paid, but no swap event is emitted.
+    NoEvent,
+    // Only the given percentage of the computed output, from 1 to 100, is paid out.
+    PartialOutput(u8),
 }
 
 #[cw_serde]
@@ -60,6 +97,15 @@ pub enum ProtocolType {
     Orderbook,
 }
 
+#[cw_serde]
+pub enum SpreadModel {
+    // x * y = k pricing against fixed virtual reserves, in atomic units. `rate` is ignored.
+    ConstantProduct {
+        offer_reserve: Uint128,
+        ask_reserve: Uint128,
+    },
+}
+
 #[cw_serde]
 pub struct SwapConfig {
     pub input_asset_info: AssetInfo,
@@ -68,6 +114,21 @@ pub struct SwapConfig {
     pub protocol_type: ProtocolType,
     pub input_decimals: u8,
     pub output_decimals: u8,
+    // Commission taken from the gross output, in basis points.
+    #[serde(default)]
+    pub commission_bps: u16,
+    // When unset, the linear `rate` is used and there is no spread.
+    #[serde(default)]
+    pub spread_model: Option<SpreadModel>,
+    // Remaining output liquidity, drawn down by every swap. Swaps that would exceed it revert.
+    #[serde(default)]
+    pub virtual_reserve: Option<Uint128>,
+}
+
+pub struct SwapOutcome {
+    pub return_amount: Uint128,
+    pub spread_amount: Uint128,
+    pub commission_amount: Uint128,
 }
 
 #[cw_serde]
@@ -96,53 +157,96 @@ pub enum QueryMsg {
         source_denom: String,
         target_denom: String,
     },
+    // AMM pair interface, as queried by the aggregator's route simulation.
+    Simulation {
+        offer_asset: Asset,
+    },
+    Pool {},
 }
 
-pub const CONFIG: Item<SwapConfig> = Item::new("config");
+#[cw_serde]
+pub struct PoolResponse {
+    pub assets: Vec<Asset>,
+    pub total_share: Uint128,
+}
+
+#[cw_serde]
+pub struct SimulationResponse {
+    pub return_amount: Uint128,
+    pub spread_amount: Uint128,
+    pub commission_amount: Uint128,
+}
+
+// Pair configs keyed by (input, output) denom or contract address.
+pub const PAIRS: Map<(&str, &str), SwapConfig> = Map::new("pairs");
+pub const ADMIN: Item<Addr> = Item::new("admin");
+pub const FAILURE_MODE: Item<FailureMode> = Item::new("failure_mode");
+pub const LIQUIDITY_POOL: Item<LiquidityPool> = Item::new("liquidity_pool");
 const DECIMAL_PRECISION: u32 = 18;
 
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
-    CONFIG.save(deps.storage, &msg.config)?;
+    save_pair(deps.storage, &msg.config)?;
+    ADMIN.save(deps.storage, &info.sender)?;
     Ok(Response::new())
 }
 
 #[entry_point]
-pub fn execute(
-    deps: DepsMut,
-    _env: Env,
-    info: MessageInfo,
-    msg: ExecuteMsg,
-) -> StdResult<Response> {
-    let config = CONFIG.load(deps.storage)?;
+pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
     let mut recipient = info.sender.to_string();
+    let mut target_denom = None;
 
     let (offer_amount, offer_info) = match msg {
+        ExecuteMsg::AddPair { config } => return add_pair(deps, info, config),
+        ExecuteMsg::SetFailureMode { failure_mode } => {
+            return set_failure_mode(deps, info, failure_mode)
+        }
+        ExecuteMsg::SetLiquidityPool { pool } => return set_liquidity_pool(deps, info, pool),
+        ExecuteMsg::ProvideLiquidity {
+            assets,
+            receiver,
+            deadline,
+            ..
+        } => return provide_liquidity(deps, env, info, assets, receiver, deadline),
         ExecuteMsg::Swap {
-            offer_asset, to, ..
+            offer_asset,
+            to,
+            deadline,
+            ..
         } => {
+            check_deadline(&env, deadline)?;
             if let Some(to_addr) = to {
                 recipient = to_addr;
             }
             (offer_asset.amount, offer_asset.info)
         }
-        ExecuteMsg::SwapMinOutput { .. } => (
-            info.funds[0].amount,
-            AssetInfo::NativeToken {
-                denom: info.funds[0].denom.clone(),
-            },
-        ),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: target,
+            ..
+        } => {
+            target_denom = Some(target);
+            (
+                info.funds[0].amount,
+                AssetInfo::NativeToken {
+                    denom: info.funds[0].denom.clone(),
+                },
+            )
+        }
         ExecuteMsg::Receive(Cw20ReceiveMsg {
             sender,
             amount,
             msg,
         }) => {
+            if let Ok(MockPairHookMsg::WithdrawLiquidity {}) = from_json::<MockPairHookMsg>(&msg) {
+                return withdraw_liquidity(deps, info, sender, amount);
+            }
             if let Ok(hook) = from_json::<MockSwapHookMsg>(&msg) {
+                check_deadline(&env, hook.swap.deadline)?;
                 recipient = hook.swap.to.unwrap_or(sender);
             } else {
                 recipient = sender;
@@ -156,27 +260,36 @@ pub fn execute(
         }
     };
 
-    let final_return_amount = if offer_info == config.input_asset_info {
-        let offer_decimal = Decimal::from_atomics(offer_amount, config.input_decimals as u32)
-            .map_err(|_| StdError::generic_err("Failed to create decimal from offer amount"))?;
-
-        let rate_decimal = Decimal::from_str(&config.rate)?;
-        let return_decimal = offer_decimal * rate_decimal;
-        let decimal_diff = DECIMAL_PRECISION.saturating_sub(config.output_decimals as u32);
-        let scaling_factor = Uint128::from(10u128.pow(decimal_diff));
+    let config = match find_pair(deps.storage, &offer_info, target_denom.as_deref())? {
+        Some(config) => config,
+        None => return Ok(Response::new().add_attribute("action", "swap_skipped_or_zero_amount")),
+    };
 
-        return_decimal
-            .atomics()
-            .checked_div(scaling_factor)
-            .unwrap_or_default()
-    } else {
-        Uint128::zero()
+    let outcome = compute_swap(&config, offer_amount)?;
+    let failure_mode = FAILURE_MODE.may_load(deps.storage)?;
+    let final_return_amount = match &failure_mode {
+        Some(FailureMode::AlwaysFail) => {
+            return Err(StdError::generic_err("Mock swap failure: always_fail"))
+        }
+        Some(FailureMode::FailAboveAmount(max_amount)) if offer_amount > *max_amount => {
+            return Err(StdError::generic_err(format!(
+                "Mock swap failure: offer amount {} exceeds {}",
+                offer_amount, max_amount
+            )))
+        }
+        Some(FailureMode::PartialOutput(percent)) => outcome
+            .return_amount
+            .multiply_ratio(*percent as u128, 100u128),
+        _ => outcome.return_amount,
     };
 
     if final_return_amount.is_zero() {
         return Ok(Response::new().add_attribute("action", "swap_skipped_or_zero_amount"));
     }
 
+    deplete_liquidity(deps.storage, &config, offer_amount, final_return_amount)?;
+    move_pool_reserves(deps.storage, &config, offer_amount, final_return_amount)?;
+
     let send_msg: CosmosMsg = match &config.output_asset_info {
         AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
             contract_addr: contract_addr.clone(),
@@ -198,10 +311,24 @@ pub fn execute(
     let (input_denom_str, _) = get_denom_and_addr(&config.input_asset_info);
     let (output_denom_str, _) = get_denom_and_addr(&config.output_asset_info);
 
+    match failure_mode {
+        Some(FailureMode::NoEvent) => return Ok(Response::new().add_message(send_msg)),
+        Some(FailureMode::WrongEventFormat) => {
+            return Ok(Response::new().add_message(send_msg).add_event(
+                Event::new("wasm")
+                    .add_attribute("action", "swap")
+                    .add_attribute("output_amount", final_return_amount.to_string()),
+            ))
+        }
+        _ => {}
+    }
+
     let event = match config.protocol_type {
         ProtocolType::Amm => Event::new("wasm")
             .add_attribute("action", "swap")
-            .add_attribute("return_amount", final_return_amount.to_string()),
+            .add_attribute("return_amount", final_return_amount.to_string())
+            .add_attribute("spread_amount", outcome.spread_amount.to_string())
+            .add_attribute("commission_amount", outcome.commission_amount.to_string()),
         ProtocolType::Orderbook => Event::new("atomic_swap_execution")
             .add_attribute("sender", info.sender.to_string())
             .add_attribute("swap_input_amount", offer_amount)
@@ -214,6 +341,361 @@ pub fn execute(
     Ok(Response::new().add_message(send_msg).add_event(event))
 }
 
+fn save_pair(storage: &mut dyn Storage, config: &SwapConfig) -> StdResult<()> {
+    for decimals in [config.input_decimals, config.output_decimals] {
+        if decimals > MAX_DECIMALS {
+            return Err(StdError::generic_err(format!(
+                "Unsupported decimals: {} is above the maximum of {}",
+                decimals, MAX_DECIMALS
+            )));
+        }
+    }
+    let (input_key, _) = get_denom_and_addr(&config.input_asset_info);
+    let (output_key, _) = get_denom_and_addr(&config.output_asset_info);
+    PAIRS.save(storage, (input_key.as_str(), output_key.as_str()), config)
+}
+
+// Finds the pair for an offer asset. Without an explicit target the input must map to a single pair.
+fn find_pair(
+    storage: &dyn Storage,
+    offer_info: &AssetInfo,
+    target_denom: Option<&str>,
+) -> StdResult<Option<SwapConfig>> {
+    let (input_key, _) = get_denom_and_addr(offer_info);
+    if let Some(target) = target_denom {
+        return PAIRS.may_load(storage, (input_key.as_str(), target));
+    }
+
+    let mut candidates = PAIRS
+        .prefix(input_key.as_str())
+        .range(storage, None, None, Order::Ascending)
+        .take(2)
+        .collect::<StdResult<Vec<_>>>()?;
+    if candidates.len() > 1 {
+        return Err(StdError::generic_err(format!(
+            "Ambiguous pair: multiple outputs configured for input {}",
+            input_key
+        )));
+    }
+    Ok(candidates.pop().map(|(_, config)| config))
+}
+
+fn add_pair(deps: DepsMut, info: MessageInfo, config: SwapConfig) -> StdResult<Response> {
+    if info.sender != ADMIN.load(deps.storage)? {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    save_pair(deps.storage, &config)?;
+
+    let (input_key, _) = get_denom_and_addr(&config.input_asset_info);
+    let (output_key, _) = get_denom_and_addr(&config.output_asset_info);
+    Ok(Response::new()
+        .add_attribute("action", "add_pair")
+        .add_attribute("input", input_key)
+        .add_attribute("output", output_key))
+}
+
+fn set_failure_mode(
+    deps: DepsMut,
+    info: MessageInfo,
+    failure_mode: Option<FailureMode>,
+) -> StdResult<Response> {
+    if info.sender != ADMIN.load(deps.storage)? {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    if let Some(FailureMode::PartialOutput(percent)) = failure_mode {
+        if percent == 0 || percent > 100 {
+            return Err(StdError::generic_err(format!(
+                "PartialOutput percentage must be between 1 and 100, got {}",
+                percent
+            )));
+        }
+    }
+
+    match &failure_mode {
+        Some(mode) => FAILURE_MODE.save(deps.storage, mode)?,
+        None => FAILURE_MODE.remove(deps.storage),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_failure_mode")
+        .add_attribute("failure_mode", format!("{:?}", failure_mode)))
+}
+
+fn set_liquidity_pool(
+    deps: DepsMut,
+    info: MessageInfo,
+    pool: LiquidityPool,
+) -> StdResult<Response> {
+    if info.sender != ADMIN.load(deps.storage)? {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    LIQUIDITY_POOL.save(deps.storage, &pool)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_liquidity_pool")
+        .add_attribute("lp_token", pool.lp_token))
+}
+
+fn provide_liquidity(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    assets: Vec<Asset>,
+    receiver: Option<String>,
+    deadline: Option<u64>,
+) -> StdResult<Response> {
+    check_deadline(&env, deadline)?;
+    let mut pool = LIQUIDITY_POOL
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("Mock pool does not accept liquidity"))?;
+
+    let mut share: Option<Uint128> = None;
+    let mut messages: Vec<CosmosMsg> = vec![];
+    for asset in &assets {
+        let reserve = pool
+            .reserves
+            .iter_mut()
+            .find(|reserve| reserve.info == asset.info)
+            .ok_or_else(|| StdError::generic_err("Asset is not in the mock pool"))?;
+        match &asset.info {
+            AssetInfo::NativeToken { denom } => {
+                let sent = info
+                    .funds
+                    .iter()
+                    .find(|coin| coin.denom == *denom)
+                    .map(|coin| coin.amount)
+                    .unwrap_or_default();
+                if sent != asset.amount {
+                    return Err(StdError::generic_err(format!(
+                        "Expected {} {} in funds, got {}",
+                        asset.amount, denom, sent
+                    )));
+                }
+            }
+            AssetInfo::Token { contract_addr } => {
+                messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: contract_addr.clone(),
+                    msg: to_json_binary(&Cw20ExecuteMsg::TransferFrom {
+                        owner: info.sender.to_string(),
+                        recipient: env.contract.address.to_string(),
+                        amount: asset.amount,
+                    })?,
+                    funds: vec![],
+                }));
+            }
+        }
+        let minted = asset
+            .amount
+            .checked_multiply_ratio(pool.total_share, reserve.amount)
+            .map_err(|_| StdError::generic_err("Mock pool has no liquidity"))?;
+        share = Some(share.map_or(minted, |share| share.min(minted)));
+        reserve.amount += asset.amount;
+    }
+    let share = share.unwrap_or_default();
+    pool.total_share += share;
+    LIQUIDITY_POOL.save(deps.storage, &pool)?;
+
+    let receiver = receiver.unwrap_or_else(|| info.sender.to_string());
+    if !share.is_zero() {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pool.lp_token.clone(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: receiver.clone(),
+                amount: share,
+            })?,
+            funds: vec![],
+        }));
+    }
+
+    Ok(Response::new().add_messages(messages).add_event(
+        Event::new("wasm")
+            .add_attribute("action", "provide_liquidity")
+            .add_attribute("receiver", receiver)
+            .add_attribute("share", share.to_string()),
+    ))
+}
+
+// Pays out the LP tokens' share of every reserve. The LP tokens stay with the pool, which pays
+// them out again on later provisions.
+fn withdraw_liquidity(
+    deps: DepsMut,
+    info: MessageInfo,
+    sender: String,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let mut pool = LIQUIDITY_POOL
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("Mock pool does not accept liquidity"))?;
+    if info.sender != pool.lp_token {
+        return Err(StdError::generic_err(
+            "Only the pool's LP token can be withdrawn",
+        ));
+    }
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    let mut refunds = vec![];
+    for reserve in pool.reserves.iter_mut() {
+        let refund = reserve
+            .amount
+            .checked_multiply_ratio(amount, pool.total_share)
+            .map_err(|_| StdError::generic_err("Mock pool has no liquidity"))?;
+        reserve.amount -= refund;
+        let (key, _) = get_denom_and_addr(&reserve.info);
+        refunds.push(format!("{refund}{key}"));
+        if refund.is_zero() {
+            continue;
+        }
+        messages.push(match &reserve.info {
+            AssetInfo::NativeToken { denom } => CosmosMsg::Bank(BankMsg::Send {
+                to_address: sender.clone(),
+                amount: vec![Coin {
+                    denom: denom.clone(),
+                    amount: refund,
+                }],
+            }),
+            AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: contract_addr.clone(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: sender.clone(),
+                    amount: refund,
+                })?,
+                funds: vec![],
+            }),
+        });
+    }
+    pool.total_share = pool.total_share.checked_sub(amount)?;
+    LIQUIDITY_POOL.save(deps.storage, &pool)?;
+
+    Ok(Response::new().add_messages(messages).add_event(
+        Event::new("wasm")
+            .add_attribute("action", "withdraw_liquidity")
+            .add_attribute("withdrawn_share", amount.to_string())
+            .add_attribute("refund_assets", refunds.join(", ")),
+    ))
+}
+
+// Moves the liquidity pool's reserves by an executed swap, so liquidity is provided at the
+// post-swap ratio, as on a real pair.
+fn move_pool_reserves(
+    storage: &mut dyn Storage,
+    config: &SwapConfig,
+    offer_amount: Uint128,
+    paid_amount: Uint128,
+) -> StdResult<()> {
+    let Some(mut pool) = LIQUIDITY_POOL.may_load(storage)? else {
+        return Ok(());
+    };
+    for reserve in pool.reserves.iter_mut() {
+        if reserve.info == config.input_asset_info {
+            reserve.amount += offer_amount;
+        } else if reserve.info == config.output_asset_info {
+            reserve.amount = reserve.amount.checked_sub(paid_amount)?;
+        }
+    }
+    LIQUIDITY_POOL.save(storage, &pool)
+}
+
+// Rejects a swap after its deadline, as the real AMM pairs do.
+fn check_deadline(env: &Env, deadline: Option<u64>) -> StdResult<()> {
+    match deadline {
+        Some(deadline) if env.block.time.seconds() > deadline => {
+            Err(StdError::generic_err("Mock swap failure: deadline expired"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Linear pricing at `rate`, scaled between the configured input and output decimals. The
+// return is computed at 18 decimals and scaled down, or up for tokens of more than 18.
+fn linear_return(config: &SwapConfig, offer_amount: Uint128) -> StdResult<Uint128> {
+    let offer_decimal = Decimal::from_atomics(offer_amount, config.input_decimals as u32)
+        .map_err(|_| StdError::generic_err("Failed to create decimal from offer amount"))?;
+
+    let rate_decimal = Decimal::from_str(&config.rate)?;
+    let return_atomics = offer_decimal
+        .checked_mul(rate_decimal)
+        .map_err(|_| StdError::generic_err("Return amount overflows"))?
+        .atomics();
+    let output_decimals = config.output_decimals as u32;
+    if output_decimals <= DECIMAL_PRECISION {
+        let scaling_factor = Uint128::from(10u128.pow(DECIMAL_PRECISION - output_decimals));
+        Ok(return_atomics / scaling_factor)
+    } else {
+        let scaling_factor = Uint128::from(10u128.pow(output_decimals - DECIMAL_PRECISION));
+        return_atomics
+            .checked_mul(scaling_factor)
+            .map_err(|_| StdError::generic_err("Return amount overflows"))
+    }
+}
+
+pub fn compute_swap(config: &SwapConfig, offer_amount: Uint128) -> StdResult<SwapOutcome> {
+    let (gross_return, spread_amount) = match &config.spread_model {
+        None => (linear_return(config, offer_amount)?, Uint128::zero()),
+        Some(SpreadModel::ConstantProduct {
+            offer_reserve,
+            ask_reserve,
+        }) => {
+            let ideal_return = offer_amount
+                .checked_multiply_ratio(*ask_reserve, *offer_reserve)
+                .map_err(|_| StdError::generic_err("Invalid constant product reserves"))?;
+            let gross_return = ask_reserve
+                .checked_multiply_ratio(offer_amount, *offer_reserve + offer_amount)
+                .map_err(|_| StdError::generic_err("Invalid constant product reserves"))?;
+            (gross_return, ideal_return.saturating_sub(gross_return))
+        }
+    };
+
+    let commission_amount = gross_return.multiply_ratio(config.commission_bps as u128, 10_000u128);
+    let return_amount = gross_return - commission_amount;
+
+    if let Some(reserve) = config.virtual_reserve {
+        if return_amount > reserve {
+            return Err(StdError::generic_err(format!(
+                "Insufficient liquidity: return amount {} exceeds remaining reserve {}",
+                return_amount, reserve
+            )));
+        }
+    }
+
+    Ok(SwapOutcome {
+        return_amount,
+        spread_amount,
+        commission_amount,
+    })
+}
+
+// Moves the pair's reserves by an executed swap, so later swaps see worse rates or revert.
+fn deplete_liquidity(
+    storage: &mut dyn Storage,
+    config: &SwapConfig,
+    offer_amount: Uint128,
+    paid_amount: Uint128,
+) -> StdResult<()> {
+    let mut updated = config.clone();
+    if let Some(SpreadModel::ConstantProduct {
+        offer_reserve,
+        ask_reserve,
+    }) = &mut updated.spread_model
+    {
+        *offer_reserve += offer_amount;
+        *ask_reserve = ask_reserve.checked_sub(paid_amount)?;
+    }
+    if let Some(reserve) = &mut updated.virtual_reserve {
+        *reserve = reserve.checked_sub(paid_amount)?;
+    }
+
+    if updated != *config {
+        save_pair(storage, &updated)?;
+    }
+    Ok(())
+}
+
+// Whether the pair prices with more than its linear `rate`.
+fn has_pricing_model(config: &SwapConfig) -> bool {
+    config.commission_bps != 0 || config.spread_model.is_some() || config.virtual_reserve.is_some()
+}
+
 fn get_denom_and_addr(asset_info: &AssetInfo) -> (String, String) {
     match asset_info {
         AssetInfo::NativeToken { denom } => (denom.clone(), "".to_string()),
@@ -227,46 +709,76 @@ pub fn query(
     _env: Env,
     msg: QueryMsg,
 ) -> Result<Binary, StdError> {
+    // A pool that always fails cannot quote either.
+    if let Some(FailureMode::AlwaysFail) = FAILURE_MODE.may_load(deps.storage)? {
+        return Err(StdError::generic_err("Mock query failure: always_fail"));
+    }
     match msg {
         QueryMsg::GetOutputQuantity {
             from_quantity,
             source_denom,
             target_denom,
         } => {
-            let config = CONFIG.load(deps.storage)?;
+            // 1. Validation: Ensure the query matches one of the contract's configured pairs.
+            let config = PAIRS
+                .may_load(deps.storage, (source_denom.as_str(), target_denom.as_str()))?
+                .ok_or_else(|| {
+                    StdError::generic_err(format!(
+                        "Invalid trading pair for this mock contract: {} -> {}",
+                        source_denom, target_denom
+                    ))
+                })?;
 
-            // 1. Validation: Ensure the query matches the contract's configured trading pair.
-            let config_source_denom = match config.input_asset_info {
-                AssetInfo::NativeToken { denom } => denom,
-                AssetInfo::Token { contract_addr } => contract_addr,
-            };
-            let config_target_denom = match config.output_asset_info {
-                AssetInfo::NativeToken { denom } => denom,
-                AssetInfo::Token { contract_addr } => contract_addr,
+            // 2. The Core Mock Logic: a plain pair quotes the simple rate calculation, while a
+            // pair with commission, spread or reserves configured quotes its execution pricing.
+            let (result_quantity, fee) = if has_pricing_model(&config) {
+                let outcome = compute_swap(&config, from_quantity.into())?;
+                (
+                    FPDecimal::from(outcome.return_amount),
+                    FPDecimal::from(outcome.commission_amount),
+                )
+            } else {
+                (
+                    from_quantity * FPDecimal::from_str(&config.rate)?,
+                    FPDecimal::ZERO,
+                )
             };
 
-            if source_denom != config_source_denom || target_denom != config_target_denom {
-                return Err(StdError::generic_err(format!(
-                    "Invalid trading pair for this mock contract. Expected {} -> {}, got {} -> {}",
-                    config_source_denom, config_target_denom, source_denom, target_denom
-                )));
-            }
-
-            // 2. The Core Mock Logic: Perform the simple rate calculation.
-            let rate = FPDecimal::from_str(&config.rate)?;
-            let result_quantity = from_quantity * rate;
-
             // 3. Construct the response object that the aggregator expects.
             let response = SwapEstimationResult {
                 result_quantity,
-                // For a mock, we can return an empty or zero fee.
                 expected_fees: vec![FPCoin {
-                    amount: FPDecimal::ZERO,
+                    amount: fee,
                     denom: target_denom, // The fee is in the output currency
                 }],
             };
 
             to_json_binary(&response)
         }
+        QueryMsg::Simulation { offer_asset } => {
+            let config = find_pair(deps.storage, &offer_asset.info, None)?.ok_or_else(|| {
+                StdError::generic_err(format!(
+                    "No pair configured for offer asset {:?}",
+                    offer_asset.info
+                ))
+            })?;
+
+            let outcome = compute_swap(&config, offer_asset.amount)?;
+
+            to_json_binary(&SimulationResponse {
+                return_amount: outcome.return_amount,
+                spread_amount: outcome.spread_amount,
+                commission_amount: outcome.commission_amount,
+            })
+        }
+        QueryMsg::Pool {} => {
+            let pool = LIQUIDITY_POOL
+                .may_load(deps.storage)?
+                .ok_or_else(|| StdError::generic_err("Mock pool does not accept liquidity"))?;
+            to_json_binary(&PoolResponse {
+                assets: pool.reserves,
+                total_share: pool.total_share,
+            })
+        }
     }
 }
@@ -0,0 +1,52 @@
+//! Asset types shared by the aggregator and the mock pools.
+//!
+//! Both sides speak the AMM pool wire format, so the definitions live here
+//! once instead of drifting apart in each contract.
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Coin, Uint128};
+
+/// Most decimals a token may have. One whole token of 30 decimals still leaves room for
+/// supplies of a few hundred million in a `Uint128`.
+pub const MAX_DECIMALS: u8 = 30;
+
+#[cw_serde]
+pub enum AssetInfo {
+    Token { contract_addr: String },
+    NativeToken { denom: String },
+}
+
+impl AssetInfo {
+    pub fn native(denom: impl Into<String>) -> Self {
+        AssetInfo::NativeToken {
+            denom: denom.into(),
+        }
+    }
+
+    pub fn token(contract_addr: impl Into<String>) -> Self {
+        AssetInfo::Token {
+            contract_addr: contract_addr.into(),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct Asset {
+    pub info: AssetInfo,
+    pub amount: Uint128,
+}
+
+impl From<Coin> for Asset {
+    fn from(coin: Coin) -> Self {
+        Asset {
+            info: AssetInfo::NativeToken { denom: coin.denom },
+            amount: coin.amount,
+        }
+    }
+}
+
+impl From<&Coin> for Asset {
+    fn from(coin: &Coin) -> Self {
+        coin.clone().into()
+    }
+}
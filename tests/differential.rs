@@ -0,0 +1,294 @@
+//! Differential test of route simulation against execution.
+//!
+//! Each case deploys fresh mock pools with generated rates, commissions and constant-product
+//! spreads, charges generated aggregator fees on some of them, and executes a generated route
+//! through them. The output `SimulateRoute` predicted must be exactly what the route paid out, so
+//! a refactor that changes how either side prices, allocates or charges fees shows up here. Every
+//! pool is used once per route, so an execution's depletion of a pool cannot make it diverge from
+//! the simulation, which quotes all splits against the same state.
+
+use super::*;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, RngAlgorithm, TestRng, TestRunner};
+
+/// Routes executed per run. Each case deploys its own pools, so this is kept small.
+const DIFFERENTIAL_CASES: u32 = 16;
+
+/// A generated pool, priced from INJ to USDT or the other way round.
+#[derive(Debug, Clone)]
+struct PoolSpec {
+    /// USDT per INJ, in thousandths.
+    price_milli: u128,
+    commission_bps: u16,
+    /// Whole INJ on the INJ side of a constant-product pool, or `None` for linear pricing.
+    depth_inj: Option<u128>,
+    /// The aggregator's fee on the pool, in tenths of a percent.
+    fee_permille: u64,
+}
+
+#[derive(Debug, Clone)]
+struct SplitSpec {
+    percent: u8,
+    pool: PoolSpec,
+}
+
+#[derive(Debug, Clone)]
+struct RouteSpec {
+    /// Whole INJ offered.
+    offer_inj: u128,
+    /// Stages alternate between INJ -> USDT and USDT -> INJ, starting with INJ -> USDT.
+    stages: Vec<Vec<SplitSpec>>,
+}
+
+fn pool_spec() -> impl Strategy<Value = PoolSpec> {
+    (
+        5_000u128..=20_000,
+        0u16..=100,
+        prop::option::of(1_000u128..=100_000),
+        prop::option::weighted(0.5, 1u64..=10),
+    )
+        .prop_map(
+            |(price_milli, commission_bps, depth_inj, fee_permille)| PoolSpec {
+                price_milli,
+                commission_bps,
+                depth_inj,
+                fee_permille: fee_permille.unwrap_or(0),
+            },
+        )
+}
+
+/// One to four splits of at least 1% each, summing to 100.
+fn stage_spec() -> impl Strategy<Value = Vec<SplitSpec>> {
+    prop::collection::vec((1u8..=25, pool_spec()), 1..=4).prop_map(|splits| {
+        let last = splits.len() - 1;
+        let assigned: u8 = splits[..last].iter().map(|(percent, _)| percent).sum();
+        splits
+            .into_iter()
+            .enumerate()
+            .map(|(index, (percent, pool))| SplitSpec {
+                percent: if index == last {
+                    100 - assigned
+                } else {
+                    percent
+                },
+                pool,
+            })
+            .collect()
+    })
+}
+
+fn route_spec() -> impl Strategy<Value = RouteSpec> {
+    (1u128..=100, prop::collection::vec(stage_spec(), 1..=3))
+        .prop_map(|(offer_inj, stages)| RouteSpec { offer_inj, stages })
+}
+
+/// Deploys a pool swapping `offer` for `ask` as `spec` describes, funds it with both assets and
+/// sets its aggregator fee.
+fn deploy_pool(
+    env: &TestEnv,
+    code_id: u64,
+    spec: &PoolSpec,
+    inj_to_usdt: bool,
+) -> Result<String, TestCaseError> {
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let (offer, ask, input_decimals, output_decimals) = if inj_to_usdt {
+        ("inj", "usdt", 18, 6)
+    } else {
+        ("usdt", "inj", 6, 18)
+    };
+    let rate = if inj_to_usdt {
+        Decimal::from_ratio(spec.price_milli, 1_000u128)
+    } else {
+        Decimal::from_ratio(1_000u128, spec.price_milli)
+    };
+    let spread_model = spec.depth_inj.map(|depth| {
+        let inj_reserve = Uint128::new(depth * 1_000_000_000_000_000_000);
+        let usdt_reserve = Uint128::new(depth * spec.price_milli * 1_000);
+        let (offer_reserve, ask_reserve) = if inj_to_usdt {
+            (inj_reserve, usdt_reserve)
+        } else {
+            (usdt_reserve, inj_reserve)
+        };
+        SpreadModel::ConstantProduct {
+            offer_reserve,
+            ask_reserve,
+        }
+    });
+
+    let pool_addr = wasm
+        .instantiate(
+            code_id,
+            &MockInstantiateMsg {
+                config: SwapConfig {
+                    input_asset_info: AssetInfo::NativeToken {
+                        denom: offer.to_string(),
+                    },
+                    output_asset_info: AssetInfo::NativeToken {
+                        denom: ask.to_string(),
+                    },
+                    rate: rate.to_string(),
+                    protocol_type: ProtocolType::Amm,
+                    input_decimals,
+                    output_decimals,
+                    commission_bps: spec.commission_bps,
+                    virtual_reserve: None,
+                    spread_model,
+                },
+            },
+            Some(&env.admin.address()),
+            Some("mock-differential"),
+            &[],
+            &env.admin,
+        )
+        .map_err(|e| TestCaseError::fail(format!("pool instantiation failed: {e}")))?
+        .data
+        .address;
+
+    bank.send(
+        MsgSend {
+            from_address: env.admin.address(),
+            to_address: pool_addr.clone(),
+            amount: vec![
+                ProtoCoin {
+                    denom: "inj".to_string(),
+                    amount: "10000000000000000000000".to_string(),
+                },
+                ProtoCoin {
+                    denom: "usdt".to_string(),
+                    amount: "1000000000000".to_string(),
+                },
+            ],
+        },
+        &env.admin,
+    )
+    .map_err(|e| TestCaseError::fail(format!("pool funding failed: {e}")))?;
+
+    if spec.fee_permille > 0 {
+        wasm.execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::SetFee {
+                pool_address: pool_addr.clone(),
+                fee_percent: Decimal::permille(spec.fee_permille),
+            },
+            &[],
+            &env.admin,
+        )
+        .map_err(|e| TestCaseError::fail(format!("setting the pool fee failed: {e}")))?;
+    }
+    Ok(pool_addr)
+}
+
+fn check_simulation_matches_execution(
+    env: &TestEnv,
+    code_id: u64,
+    spec: &RouteSpec,
+) -> Result<(), TestCaseError> {
+    let wasm = Wasm::new(&env.app);
+    let native = |denom: &str| amm::AssetInfo::NativeToken {
+        denom: denom.to_string(),
+    };
+
+    let mut stages = vec![];
+    for (index, splits) in spec.stages.iter().enumerate() {
+        let inj_to_usdt = index % 2 == 0;
+        let (offer, ask) = if inj_to_usdt {
+            ("inj", "usdt")
+        } else {
+            ("usdt", "inj")
+        };
+        let mut stage = Stage { splits: vec![] };
+        for split in splits {
+            let pool_address = deploy_pool(env, code_id, &split.pool, inj_to_usdt)?;
+            stage.splits.push(Split {
+                percent: split.percent,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address,
+                    offer_asset_info: native(offer),
+                    ask_asset_info: native(ask),
+                    min_out: None,
+                })],
+            });
+        }
+        stages.push(stage);
+    }
+    let output_denom = if spec.stages.len() % 2 == 1 {
+        "usdt"
+    } else {
+        "inj"
+    };
+    let amount_in = Coin::new(spec.offer_inj * 1_000_000_000_000_000_000, "inj");
+
+    let simulation: SimulateRouteResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::SimulateRoute {
+                stages: stages.clone(),
+                amount_in: amount_in.clone(),
+                lenient: false,
+            },
+        )
+        .map_err(|e| TestCaseError::fail(format!("simulation failed: {e}")))?;
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages,
+                minimum_receive: native_minimum(output_denom, 1),
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[amount_in],
+            &env.user,
+        )
+        .map_err(|e| TestCaseError::fail(format!("execution failed: {e}")))?;
+    let final_received = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "final_received")
+        .map(|a| a.value.clone())
+        .ok_or_else(|| TestCaseError::fail("route did not report final_received"))?;
+
+    prop_assert_eq!(final_received, simulation.output_amount.to_string());
+    Ok(())
+}
+
+#[test]
+fn simulated_output_matches_executed_output() {
+    let env = setup();
+    let code_id = Wasm::new(&env.app)
+        .store_code(get_wasm_byte_code("mock_swap.wasm"), None, &env.admin)
+        .unwrap()
+        .data
+        .code_id;
+
+    // A fixed seed keeps runs reproducible; shrinking is capped as every attempt redeploys pools.
+    let config = Config {
+        cases: DIFFERENTIAL_CASES,
+        max_shrink_iters: 32,
+        ..Config::default()
+    };
+    let mut runner =
+        TestRunner::new_with_rng(config, TestRng::deterministic_rng(RngAlgorithm::ChaCha));
+    let result = runner.run(&route_spec(), |spec| {
+        check_simulation_matches_execution(&env, code_id, &spec)
+    });
+    if let Err(failure) = result {
+        panic!("simulation diverged from execution: {failure}");
+    }
+}
@@ -0,0 +1,225 @@
+//! Gas benchmarks for representative routes.
+//!
+//! Each benchmark executes a route against the mocks and fails when it burns more gas than
+//! its recorded baseline plus `GAS_HEADROOM_PERCENT`, so a planner or state change that makes
+//! routes more expensive shows up in a local `cargo test` run. A baseline is the `gas_used` of
+//! the benchmark when it was recorded. When a change legitimately costs more gas, re-record it
+//! from the `gas_used` in the failing assertion's message and say why in the commit.
+//!
+//! The baselines below are estimates that have not been measured yet: they were written without
+//! running the benchmarks, and the committed `artifacts/dex_aggregator.wasm` predates the routes
+//! they cover. Replace each with the `gas_used` of a run against artifacts rebuilt with
+//! `build_release.sh`, as CI does, before relying on them to catch regressions.
+
+use super::*;
+
+/// How far above its baseline a benchmark may go, absorbing small changes in the mocks.
+const GAS_HEADROOM_PERCENT: u64 = 5;
+
+/// A single AMM swap of 1 stage and 1 split. Estimated, not measured.
+const SINGLE_SWAP_GAS_BASELINE: u64 = 900_000;
+
+/// Three stages of three splits each, mixing AMM and orderbook pools. Estimated, not measured.
+const THREE_BY_THREE_GAS_BASELINE: u64 = 3_500_000;
+
+/// Two splits of two CW20-paying hops each, ending in a CW20 payout. Estimated, not measured.
+const CW20_HEAVY_GAS_BASELINE: u64 = 2_500_000;
+
+fn route(stages: Vec<Stage>, minimum_receive: Option<amm::Asset>) -> ExecuteMsg {
+    ExecuteMsg::ExecuteRoute {
+        stages,
+        minimum_receive,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    }
+}
+
+fn native(denom: &str) -> amm::AssetInfo {
+    amm::AssetInfo::NativeToken {
+        denom: denom.to_string(),
+    }
+}
+
+fn amm_split(percent: u8, pool: &str, offer: amm::AssetInfo, ask: amm::AssetInfo) -> Split {
+    Split {
+        percent,
+        path: vec![Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: offer,
+            ask_asset_info: ask,
+            min_out: None,
+        })],
+    }
+}
+
+fn orderbook_split(percent: u8, contract: &str, offer: &str, ask: &str, tick: u128) -> Split {
+    Split {
+        percent,
+        path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+            swap_contract: contract.to_string(),
+            offer_asset_info: native(offer),
+            ask_asset_info: native(ask),
+            min_quantity_tick_size: Uint128::new(tick),
+            min_out: None,
+        })],
+    }
+}
+
+fn assert_gas_within(benchmark: &str, gas_used: u64, baseline: u64) {
+    let limit = baseline + baseline * GAS_HEADROOM_PERCENT / 100;
+    assert!(
+        gas_used <= limit,
+        "{benchmark}: gas_used = {gas_used}, above its baseline of {baseline} \
+         plus {GAS_HEADROOM_PERCENT}% ({limit})"
+    );
+}
+
+#[test]
+fn bench_single_stage_single_split() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let msg = route(
+        vec![Stage {
+            splits: vec![amm_split(
+                100,
+                &env.mock_amm_1_addr,
+                native("inj"),
+                native("usdt"),
+            )],
+        }],
+        native_minimum("usdt", 100_000_000),
+    );
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &msg,
+            &[Coin::new(10_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    assert_gas_within(
+        "1 stage / 1 split",
+        response.gas_info.gas_used,
+        SINGLE_SWAP_GAS_BASELINE,
+    );
+}
+
+#[test]
+fn bench_three_stages_three_splits() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let inj_to_usdt = || Stage {
+        splits: vec![
+            amm_split(33, &env.mock_amm_1_addr, native("inj"), native("usdt")),
+            amm_split(33, &env.mock_amm_2_addr, native("inj"), native("usdt")),
+            orderbook_split(
+                34,
+                &env.mock_ob_inj_usdt_addr,
+                "inj",
+                "usdt",
+                1_000_000_000_000_000,
+            ),
+        ],
+    };
+    // Only one mock quotes USDT -> INJ, so the middle stage splits across it three times.
+    let usdt_to_inj = Stage {
+        splits: [33, 33, 34]
+            .into_iter()
+            .map(|percent| {
+                orderbook_split(percent, &env.mock_ob_usdt_inj_addr, "usdt", "inj", 10_000)
+            })
+            .collect(),
+    };
+
+    let msg = route(
+        vec![inj_to_usdt(), usdt_to_inj, inj_to_usdt()],
+        native_minimum("usdt", 1),
+    );
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &msg,
+            &[Coin::new(10_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    assert_gas_within(
+        "3 stages / 3 splits",
+        response.gas_info.gas_used,
+        THREE_BY_THREE_GAS_BASELINE,
+    );
+}
+
+#[test]
+fn bench_cw20_heavy_route() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let user = &setup.env.user;
+
+    let shroom = amm::AssetInfo::Token {
+        contract_addr: setup.shroom_cw20_addr.clone(),
+    };
+    let sai = amm::AssetInfo::Token {
+        contract_addr: setup.sai_cw20_addr.clone(),
+    };
+
+    // Each split buys CW20 SHROOM and swaps it for CW20 SAI, so every hop moves a CW20.
+    let cw20_path = |percent| Split {
+        percent,
+        path: vec![
+            Operation::AmmSwap(AmmSwapOp {
+                pool_address: setup.mock_inj_to_cw20_shroom_amm.clone(),
+                offer_asset_info: native("inj"),
+                ask_asset_info: shroom.clone(),
+                min_out: None,
+            }),
+            Operation::AmmSwap(AmmSwapOp {
+                pool_address: setup.mock_cw20_shroom_to_cw20_sai_amm.clone(),
+                offer_asset_info: shroom.clone(),
+                ask_asset_info: sai.clone(),
+                min_out: None,
+            }),
+        ],
+    };
+
+    // 10 INJ -> 1,000 SHROOM -> 100 SAI
+    let msg = route(
+        vec![Stage {
+            splits: vec![cw20_path(50), cw20_path(50)],
+        }],
+        cw20_minimum(&setup.sai_cw20_addr, 99_000_000),
+    );
+
+    let response = wasm
+        .execute(
+            &setup.env.aggregator_addr,
+            &msg,
+            &[Coin::new(10_000_000_000_000_000_000u128, "inj")],
+            user,
+        )
+        .unwrap();
+
+    assert_gas_within(
+        "CW20-heavy",
+        response.gas_info.gas_used,
+        CW20_HEAVY_GAS_BASELINE,
+    );
+}
@@ -2,22 +2,45 @@
 
 use std::str::FromStr;
 
-use cosmwasm_std::{to_json_binary, Addr, Coin, Decimal, Uint128};
+use cosmwasm_std::{to_json_binary, Addr, Binary, Coin, Decimal, Uint128};
 use cw20::{BalanceResponse, Cw20QueryMsg};
 use cw20_base::msg::InstantiateMsg as Cw20InstantiateMsg;
 use dex_aggregator::msg::{
-    amm, cw20_adapter, AmmSwapOp, Cw20HookMsg, ExecuteMsg, InstantiateMsg, Operation,
-    OrderbookSwapOp, QueryMsg, Split, Stage,
+    amm, cw20_adapter, domain_separated_hash, AdminLogResponse, AllPoolStatsResponse,
+    AllPoolsResponse, AmmSwapOp, BalanceCheckedTokensResponse, Cw20HookMsg, DenomPolicyResponse,
+    DisabledPoolsResponse, DustBucketResponse, ExecuteMsg, FeeCollectorInfo, FeeCollectorsResponse,
+    FeeInfo, FeeResponse, InstantiateMsg, IntegratorResponse, MarketResponse, MarketsResponse,
+    NativeConverterInfo, NativeConverterResponse, Operation, OrderbookSwapOp, PairType,
+    ParametersResponse, PoolAllowlistResponse, PoolHealthPolicy, PoolStatsResponse, PostAction,
+    ProvideLiquidityOp, QueryMsg, QuoteAge, RateLimitsResponse, ReceiptResponse, ReceiveHook,
+    RecipientInfo, RelayAccountResponse, RelayNonceResponse, RelayedRoute, RouteOptions,
+    RouteResult, RouteTemplateResponse, RouteTemplatesResponse, SimulateRouteResponse, Split,
+    SplitExpectations, Stage, SubAggregatorOp, TwapOrderResponse, TwapOrdersResponse,
+    RELAYED_ROUTE_DOMAIN,
+};
+use dex_aggregator::packed::encode_route;
+use dex_aggregator::state::{
+    Config as AggregatorConfig, DenomPolicyMode, MarketInfo, Parameters, PoolMetadata,
+    PoolProtocol, PoolRegistrationTerms, RateLimits, TokenDecimals,
 };
-use dex_aggregator::state::Config as AggregatorConfig;
 use injective_test_tube::{
+    injective_std::shim::Any,
     injective_std::types::cosmos::{
+        authz::v1beta1::{GenericAuthorization, Grant, MsgGrant, MsgGrantResponse},
         bank::v1beta1::{MsgSend, QueryBalanceRequest},
         base::v1beta1::Coin as ProtoCoin,
+        staking::v1beta1::{QueryDelegationRequest, QueryDelegationResponse},
     },
-    Account, Bank, InjectiveTestApp, Module, SigningAccount, Wasm,
+    Account, Bank, InjectiveTestApp, Module, Runner, SigningAccount, Wasm,
+};
+use mock_swap::{
+    Asset as MockAsset, AssetInfo, ExecuteMsg as MockExecuteMsg, FailureMode,
+    InstantiateMsg as MockInstantiateMsg, LiquidityPool, MockSwapHookMsg, MockSwapHookSwapField,
+    ProtocolType, SpreadModel, SwapConfig,
 };
-use mock_swap::{AssetInfo, InstantiateMsg as MockInstantiateMsg, ProtocolType, SwapConfig};
+
+mod differential;
+mod gas_benchmarks;
 
 fn get_wasm_byte_code(filename: &str) -> &'static [u8] {
     match filename {
@@ -29,12 +52,33 @@ fn get_wasm_byte_code(filename: &str) -> &'static [u8] {
     }
 }
 
+/// A `minimum_receive` declared in a native denom.
+fn native_minimum(denom: &str, amount: u128) -> Option<amm::Asset> {
+    Some(amm::Asset {
+        info: amm::AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        },
+        amount: Uint128::new(amount),
+    })
+}
+
+/// A `minimum_receive` declared in a CW20 token.
+fn cw20_minimum(contract_addr: &str, amount: u128) -> Option<amm::Asset> {
+    Some(amm::Asset {
+        info: amm::AssetInfo::Token {
+            contract_addr: contract_addr.to_string(),
+        },
+        amount: Uint128::new(amount),
+    })
+}
+
 pub struct TestEnv {
     pub app: InjectiveTestApp,
     pub admin: SigningAccount,
     pub user: SigningAccount,
     pub fee_collector: SigningAccount,
     pub aggregator_addr: String,
+    pub mock_swap_code_id: u64,
     pub mock_amm_1_addr: String,
     pub mock_amm_2_addr: String,
     pub mock_ob_inj_usdt_addr: String,
@@ -112,7 +156,13 @@ fn setup() -> TestEnv {
             &InstantiateMsg {
                 admin: admin.address(),
                 cw20_adapter_address: adapter_addr,
-                fee_collector_address: fee_collector_account.address(),
+                fee_collectors: vec![FeeCollectorInfo {
+                    address: fee_collector_account.address(),
+                    weight: Decimal::one(),
+                }],
+                initial_fees: None,
+                allowed_pools: None,
+                parameters: None,
             },
             Some(&admin.address()),
             Some("dex-aggregator"),
@@ -139,6 +189,9 @@ fn setup() -> TestEnv {
                     protocol_type: ProtocolType::Amm, // This is an AMM
                     input_decimals: 18,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -165,6 +218,9 @@ fn setup() -> TestEnv {
                     protocol_type: ProtocolType::Amm, // This is an AMM
                     input_decimals: 18,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -191,6 +247,9 @@ fn setup() -> TestEnv {
                     protocol_type: ProtocolType::Orderbook, // This is an Orderbook
                     input_decimals: 18,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -217,6 +276,9 @@ fn setup() -> TestEnv {
                     protocol_type: ProtocolType::Orderbook, // This is an Orderbook
                     input_decimals: 6,
                     output_decimals: 18,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -264,6 +326,7 @@ fn setup() -> TestEnv {
         user: user,
         fee_collector: fee_collector_account,
         aggregator_addr,
+        mock_swap_code_id,
         mock_amm_1_addr,
         mock_amm_2_addr,
         mock_ob_inj_usdt_addr,
@@ -297,6 +360,7 @@ fn test_aggregate_swap_success() {
                         offer_asset_info: amm::AssetInfo::NativeToken {
                             denom: "inj".to_string(),
                         },
+                        min_out: None,
                     })],
                 },
                 Split {
@@ -309,6 +373,7 @@ fn test_aggregate_swap_success() {
                         offer_asset_info: amm::AssetInfo::NativeToken {
                             denom: "inj".to_string(),
                         },
+                        min_out: None,
                     })],
                 },
                 Split {
@@ -322,11 +387,26 @@ fn test_aggregate_swap_success() {
                             denom: "inj".to_string(),
                         },
                         min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                        min_out: None,
                     })],
                 },
             ],
         }],
-        minimum_receive: Some("1910000000".to_string()), // Min 1910 USDT
+        minimum_receive: native_minimum("usdt", 1910000000), // Min 1910 USDT
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let res = wasm.execute(
@@ -409,6 +489,7 @@ fn test_multi_stage_aggregate_swap_success() {
                             denom: "usdt".to_string(),
                         },
                         min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
                     })],
                 }],
             },
@@ -425,6 +506,7 @@ fn test_multi_stage_aggregate_swap_success() {
                             offer_asset_info: amm::AssetInfo::NativeToken {
                                 denom: "inj".to_string(),
                             },
+                            min_out: None,
                         })],
                     },
                     Split {
@@ -437,13 +519,28 @@ fn test_multi_stage_aggregate_swap_success() {
                             offer_asset_info: amm::AssetInfo::NativeToken {
                                 denom: "inj".to_string(),
                             },
+                            min_out: None,
                         })],
                     },
                 ],
             },
         ],
         // The minimum we expect from summing the Stage 2 outputs.
-        minimum_receive: Some("1500000000000".to_string()), // 1,500,000 USDT
+        minimum_receive: native_minimum("usdt", 1500000000000), // 1,500,000 USDT
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     // The initial funds for this route are 1,000,000 USDT
@@ -582,7 +679,13 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
             &InstantiateMsg {
                 admin: admin.address(),
                 cw20_adapter_address: adapter_addr.clone(),
-                fee_collector_address: fee_collector_account.address(),
+                fee_collectors: vec![FeeCollectorInfo {
+                    address: fee_collector_account.address(),
+                    weight: Decimal::one(),
+                }],
+                initial_fees: None,
+                allowed_pools: None,
+                parameters: None,
             },
             Some(&admin.address()),
             Some("aggregator"),
@@ -679,6 +782,9 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
                     protocol_type: ProtocolType::Orderbook,
                     input_decimals: 18,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -706,6 +812,9 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
                     protocol_type: ProtocolType::Amm,
                     input_decimals: 18,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -733,6 +842,9 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
                     protocol_type: ProtocolType::Amm,
                     input_decimals: 6,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -759,6 +871,9 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
                     protocol_type: ProtocolType::Orderbook,
                     input_decimals: 6,
                     output_decimals: 18,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -785,6 +900,9 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
                     protocol_type: ProtocolType::Orderbook,
                     input_decimals: 6,
                     output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -811,6 +929,9 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
                     protocol_type: ProtocolType::Amm,
                     input_decimals: 6,  // SHROOM decimals
                     output_decimals: 6, // USDT decimals
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
                 },
             },
             Some(&admin.address()),
@@ -943,6 +1064,7 @@ fn setup_for_conversion_test() -> ConversionTestSetup {
             user,
             fee_collector: fee_collector_account,
             aggregator_addr,
+            mock_swap_code_id,
             mock_amm_1_addr: "".to_string(),
             mock_amm_2_addr: "".to_string(),
             mock_ob_inj_usdt_addr: "".to_string(),
@@ -988,6 +1110,7 @@ fn test_full_normalization_route() {
                                 denom: native_shroom_denom.clone(),
                             },
                             min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                            min_out: None,
                         })],
                     },
                     Split {
@@ -1000,6 +1123,7 @@ fn test_full_normalization_route() {
                             ask_asset_info: amm::AssetInfo::Token {
                                 contract_addr: setup.shroom_cw20_addr.clone(),
                             },
+                            min_out: None,
                         })],
                     },
                 ],
@@ -1016,11 +1140,26 @@ fn test_full_normalization_route() {
                         ask_asset_info: amm::AssetInfo::Token {
                             contract_addr: setup.sai_cw20_addr.clone(),
                         },
+                        min_out: None,
                     })],
                 }],
             },
         ],
-        minimum_receive: Some("97000000".to_string()), // 97 SAI
+        minimum_receive: cw20_minimum(&setup.sai_cw20_addr, 97000000), // 97 SAI
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let res = wasm.execute(
@@ -1054,8 +1193,8 @@ fn test_multi_stage_with_final_normalization() {
     // Stage 2: 100 INJ is split:
     //   - 10% (10 INJ) -> AMM @ 100.0 = 1,000 CW20 SHROOM
     //   - 90% (90 INJ) -> OB  @ 100.0 = 9,000 Native SHROOM
-    // Final Result: The aggregator normalizes the 9,000 Native SHROOM and sends the
-    // total 10,000 CW20 SHROOM to the user.
+    // Final Result: The aggregator normalizes into the larger holding, converting the 1,000
+    // CW20 SHROOM and sending the total 10,000 Native SHROOM to the user.
 
     let native_shroom_denom = format!("factory/{}/{}", setup.adapter_addr, setup.shroom_cw20_addr);
 
@@ -1074,6 +1213,7 @@ fn test_multi_stage_with_final_normalization() {
                             denom: "inj".to_string(),
                         },
                         min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
                     })],
                 }],
             },
@@ -1090,6 +1230,7 @@ fn test_multi_stage_with_final_normalization() {
                             ask_asset_info: amm::AssetInfo::Token {
                                 contract_addr: setup.shroom_cw20_addr.clone(),
                             },
+                            min_out: None,
                         })],
                     },
                     Split {
@@ -1103,13 +1244,28 @@ fn test_multi_stage_with_final_normalization() {
                                 denom: native_shroom_denom.clone(),
                             },
                             min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                            min_out: None,
                         })],
                     },
                 ],
             },
         ],
-        // The final expected output is unified CW20 SHROOM
-        minimum_receive: Some("9900000000".to_string()), // Min 9,900 CW20 SHROOM
+        // The final expected output is unified Native SHROOM
+        minimum_receive: native_minimum(&native_shroom_denom, 9900000000), // Min 9,900 Native SHROOM
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     // The user initiates the swap with 1,000 USDT
@@ -1117,11 +1273,33 @@ fn test_multi_stage_with_final_normalization() {
 
     let res = wasm.execute(&setup.env.aggregator_addr, &msg, &[initial_funds], user);
     assert!(res.is_ok(), "Execution failed: {:?}", res.unwrap_err());
+    let response = res.unwrap();
+    let target = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "normalization_target")
+        .map(|a| a.value.clone());
+    assert_eq!(target.as_deref(), Some(native_shroom_denom.as_str()));
 
     // Assert the final outcome.
     // The aggregator should have performed the swaps, normalized the assets, and sent
-    // the final unified CW20 SHROOM to the user.
-    let balance: BalanceResponse = wasm
+    // the final unified Native SHROOM to the user.
+    let balance = Bank::new(&setup.env.app)
+        .query_balance(&QueryBalanceRequest {
+            address: user.address(),
+            denom: native_shroom_denom,
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+
+    // Expected final amount: 10,000 SHROOM (with 6 decimals)
+    assert_eq!(balance.amount, "10000000000");
+
+    // No CW20 SHROOM is left with the user.
+    let cw20_balance: BalanceResponse = wasm
         .query(
             &setup.shroom_cw20_addr,
             &Cw20QueryMsg::Balance {
@@ -1129,10 +1307,7 @@ fn test_multi_stage_with_final_normalization() {
             },
         )
         .unwrap();
-
-    // Expected final amount: 10,000 SHROOM (with 6 decimals)
-    let expected_final_balance = Uint128::new(10_000_000_000u128);
-    assert_eq!(balance.balance, expected_final_balance);
+    assert_eq!(cw20_balance.balance, Uint128::zero());
 }
 
 #[test]
@@ -1190,10 +1365,25 @@ fn test_cw20_entry_point_swap_success() {
                     ask_asset_info: amm::AssetInfo::Token {
                         contract_addr: setup.sai_cw20_addr.clone(),
                     },
+                    min_out: None,
                 })],
             }],
         }],
-        minimum_receive: Some("99000000".to_string()), // Min 99 SAI
+        minimum_receive: cw20_minimum(&setup.sai_cw20_addr, 99000000), // Min 99 SAI
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let res = wasm.execute(
@@ -1267,6 +1457,7 @@ fn test_reverse_normalization_route() {
                         ask_asset_info: amm::AssetInfo::Token {
                             contract_addr: setup.shroom_cw20_addr.clone(),
                         },
+                        min_out: None,
                     })],
                 }],
             },
@@ -1284,11 +1475,26 @@ fn test_reverse_normalization_route() {
                             denom: "usdt".to_string(),
                         },
                         min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
                     })],
                 }],
             },
         ],
-        minimum_receive: Some("495000000".to_string()), // Min 495 USDT
+        minimum_receive: native_minimum("usdt", 495000000), // Min 495 USDT
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let initial_balance = bank
@@ -1359,6 +1565,7 @@ fn test_failure_if_minimum_receive_not_met() {
                         offer_asset_info: amm::AssetInfo::NativeToken {
                             denom: "inj".to_string(),
                         },
+                        min_out: None,
                     })],
                 },
                 Split {
@@ -1371,6 +1578,7 @@ fn test_failure_if_minimum_receive_not_met() {
                         offer_asset_info: amm::AssetInfo::NativeToken {
                             denom: "inj".to_string(),
                         },
+                        min_out: None,
                     })],
                 },
                 Split {
@@ -1384,12 +1592,30 @@ fn test_failure_if_minimum_receive_not_met() {
                             denom: "inj".to_string(),
                         },
                         min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                        min_out: None,
                     })],
                 },
             ],
         }],
 
-        minimum_receive: Some("1920000001".to_string()),
+        minimum_receive: native_minimum("usdt", 1920000001),
+
+        max_stages_per_tx: None,
+
+        max_quote_age: None,
+
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let funds_to_send = Coin::new(100_000_000_000_000_000_000u128, "inj");
@@ -1402,7 +1628,9 @@ fn test_failure_if_minimum_receive_not_met() {
 
     let error = res.unwrap_err();
     assert!(
-        error.to_string().contains("Minimum receive amount not met"),
+        error.to_string().contains(
+            "Minimum receive amount not met: expected at least 1920000001, received 1920000000"
+        ),
         "Error message was not the expected 'MinimumReceiveNotMet'. Got: {}",
         error
     );
@@ -1453,6 +1681,7 @@ fn test_failure_on_invalid_percentage_sum() {
                         offer_asset_info: amm::AssetInfo::NativeToken {
                             denom: "inj".to_string(),
                         },
+                        min_out: None,
                     })],
                 },
                 Split {
@@ -1465,11 +1694,26 @@ fn test_failure_on_invalid_percentage_sum() {
                         offer_asset_info: amm::AssetInfo::NativeToken {
                             denom: "inj".to_string(),
                         },
+                        min_out: None,
                     })],
                 },
             ],
         }],
         minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let res = wasm.execute(
@@ -1546,6 +1790,7 @@ fn test_mixed_input_unified_output_reconciliation() {
                     denom: "inj".to_string(),
                 },
                 ask_asset_info: cw20_shroom_info.clone(),
+                min_out: None,
             })],
         }],
     };
@@ -1561,6 +1806,7 @@ fn test_mixed_input_unified_output_reconciliation() {
                     offer_asset_info: native_shroom_info.clone(),
                     ask_asset_info: usdt_info.clone(),
                     min_quantity_tick_size: Uint128::new(10000),
+                    min_out: None,
                 })],
             },
             Split {
@@ -1570,13 +1816,28 @@ fn test_mixed_input_unified_output_reconciliation() {
                     pool_address: setup.mock_cw20_shroom_to_usdt_amm.clone(),
                     offer_asset_info: cw20_shroom_info.clone(),
                     ask_asset_info: usdt_info.clone(),
+                    min_out: None,
                 })],
             },
         ],
     };
 
     let msg = ExecuteMsg::ExecuteRoute {
-        minimum_receive: Some("459000000".to_string()), // Min 459 USDT (Target is 460)
+        minimum_receive: native_minimum("usdt", 459000000), // Min 459 USDT (Target is 460)
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
         stages: vec![stage1, stage2],
     };
 
@@ -1670,6 +1931,7 @@ fn test_cw20_input_with_initial_reconciliation() {
                     offer_asset_info: native_shroom_info.clone(),
                     ask_asset_info: usdt_info.clone(),
                     min_quantity_tick_size: Uint128::new(10000),
+                    min_out: None,
                 })],
             },
             Split {
@@ -1679,6 +1941,7 @@ fn test_cw20_input_with_initial_reconciliation() {
                     pool_address: setup.mock_cw20_shroom_to_usdt_amm.clone(),
                     offer_asset_info: cw20_shroom_info.clone(),
                     ask_asset_info: usdt_info.clone(),
+                    min_out: None,
                 })],
             },
         ],
@@ -1686,7 +1949,21 @@ fn test_cw20_input_with_initial_reconciliation() {
 
     // The hook message sent with the CW20 token
     let hook_msg = Cw20HookMsg::ExecuteRoute {
-        minimum_receive: Some("469000000".to_string()), // Min 469 USDT (Target is 470)
+        minimum_receive: native_minimum("usdt", 469000000), // Min 469 USDT (Target is 470)
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
         stages: vec![stage1],
     };
 
@@ -1775,6 +2052,7 @@ fn test_complex_reconciliation_mixed_to_mixed() {
                     offer_asset_info: inj_info.clone(),
                     ask_asset_info: native_shroom_info.clone(),
                     min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                    min_out: None,
                 })],
             },
             Split {
@@ -1784,6 +2062,7 @@ fn test_complex_reconciliation_mixed_to_mixed() {
                     pool_address: setup.mock_inj_to_cw20_shroom_amm.clone(),
                     offer_asset_info: inj_info.clone(),
                     ask_asset_info: cw20_shroom_info.clone(),
+                    min_out: None,
                 })],
             },
         ],
@@ -1800,6 +2079,7 @@ fn test_complex_reconciliation_mixed_to_mixed() {
                     offer_asset_info: native_shroom_info.clone(),
                     ask_asset_info: usdt_info.clone(),
                     min_quantity_tick_size: Uint128::new(10000),
+                    min_out: None,
                 })],
             },
             Split {
@@ -1809,13 +2089,28 @@ fn test_complex_reconciliation_mixed_to_mixed() {
                     pool_address: setup.mock_cw20_shroom_to_usdt_amm.clone(),
                     offer_asset_info: cw20_shroom_info.clone(),
                     ask_asset_info: usdt_info.clone(),
+                    min_out: None,
                 })],
             },
         ],
     };
 
     let msg = ExecuteMsg::ExecuteRoute {
-        minimum_receive: Some("424000000".to_string()), // Min 424 USDT (Target is 425)
+        minimum_receive: native_minimum("usdt", 424000000), // Min 424 USDT (Target is 425)
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
         stages: vec![stage1, stage2],
     };
 
@@ -1886,6 +2181,7 @@ fn test_final_output_is_cw20_token() {
                 pool_address: setup.mock_inj_to_cw20_shroom_amm.clone(),
                 offer_asset_info: inj_info.clone(),
                 ask_asset_info: cw20_shroom_info.clone(),
+                min_out: None,
             })],
         }],
     };
@@ -1897,12 +2193,27 @@ fn test_final_output_is_cw20_token() {
                 pool_address: setup.mock_cw20_shroom_to_cw20_sai_amm.clone(),
                 offer_asset_info: cw20_shroom_info.clone(),
                 ask_asset_info: cw20_sai_info.clone(),
+                min_out: None,
             })],
         }],
     };
 
     let msg = ExecuteMsg::ExecuteRoute {
-        minimum_receive: Some("99000000".to_string()), // Min 99 SAI (Target is 100)
+        minimum_receive: cw20_minimum(&setup.sai_cw20_addr, 99000000), // Min 99 SAI (Target is 100)
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
         stages: vec![stage1, stage2],
     };
 
@@ -2014,12 +2325,27 @@ fn test_native_input_with_initial_cw20_requirement() {
                 pool_address: setup.mock_cw20_shroom_to_cw20_sai_amm.clone(),
                 offer_asset_info: cw20_shroom_info.clone(),
                 ask_asset_info: cw20_sai_info.clone(),
+                min_out: None,
             })],
         }],
     };
 
     let msg = ExecuteMsg::ExecuteRoute {
-        minimum_receive: Some("99000000".to_string()), // Min 99 SAI (Target is 100)
+        minimum_receive: cw20_minimum(&setup.sai_cw20_addr, 99000000), // Min 99 SAI (Target is 100)
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
         stages: vec![stage1],
     };
 
@@ -2078,6 +2404,7 @@ fn test_zero_amount_from_split_is_handled_gracefully() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             },
             Split {
@@ -2090,6 +2417,7 @@ fn test_zero_amount_from_split_is_handled_gracefully() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             },
         ],
@@ -2098,6 +2426,20 @@ fn test_zero_amount_from_split_is_handled_gracefully() {
     let msg = ExecuteMsg::ExecuteRoute {
         stages: vec![stage1],
         minimum_receive: None, // We don't care about the output amount, only that it doesn't fail.
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let initial_usdt_balance = bank
@@ -2157,6 +2499,7 @@ fn test_stage_with_single_hundred_percent_split() {
                 offer_asset_info: amm::AssetInfo::NativeToken {
                     denom: "inj".to_string(),
                 },
+                min_out: None,
             })],
         }],
     };
@@ -2173,13 +2516,28 @@ fn test_stage_with_single_hundred_percent_split() {
                     denom: "usdt".to_string(),
                 },
                 min_quantity_tick_size: Uint128::new(10000),
+                min_out: None,
             })],
         }],
     };
 
     let msg = ExecuteMsg::ExecuteRoute {
         stages: vec![stage1, stage2],
-        minimum_receive: Some("99000000000000000000".to_string()), // Min 99 INJ
+        minimum_receive: native_minimum("inj", 99000000000000000000), // Min 99 INJ
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let funds_to_send = Coin::new(100_000_000_000_000_000_000u128, "inj"); // 100 INJ
@@ -2252,6 +2610,7 @@ fn test_intermediate_swap_failure_reverts_transaction() {
                     denom: "usdt".to_string(),
                 },
                 min_quantity_tick_size: Uint128::new(10000),
+                min_out: None,
             })],
         }],
     };
@@ -2270,6 +2629,7 @@ fn test_intermediate_swap_failure_reverts_transaction() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             },
             Split {
@@ -2283,6 +2643,7 @@ fn test_intermediate_swap_failure_reverts_transaction() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             },
         ],
@@ -2291,6 +2652,20 @@ fn test_intermediate_swap_failure_reverts_transaction() {
     let msg = ExecuteMsg::ExecuteRoute {
         stages: vec![stage1, stage2],
         minimum_receive: None, // Not relevant, as the transaction should fail.
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     // Execute the transaction
@@ -2363,10 +2738,25 @@ fn test_fee_collection_on_single_swap() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             }],
         }],
-        minimum_receive: Some("996000000".to_string()), // Min 996 USDT
+        minimum_receive: native_minimum("usdt", 996000000), // Min 996 USDT
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let initial_collector_balance_res = bank
@@ -2496,13 +2886,28 @@ fn test_fee_collection_on_cw20_output() {
                 offer_asset_info: amm::AssetInfo::NativeToken {
                     denom: "inj".to_string(),
                 },
+                min_out: None,
             })],
         }],
     };
 
     let msg = ExecuteMsg::ExecuteRoute {
         stages: vec![stage1],
-        minimum_receive: Some("984000000".to_string()), // Min 984 SHROOM
+        minimum_receive: cw20_minimum(&setup.shroom_cw20_addr, 984000000), // Min 984 SHROOM
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     // Execute the transaction
@@ -2584,18 +2989,21 @@ fn test_admin_functions_fail_for_unauthorized_user() {
         .to_string()
         .contains("Unauthorized"));
 
-    // --- UpdateFeeCollector ---
+    // --- UpdateFeeCollectors ---
     let res_update_collector = wasm.execute(
         &env.aggregator_addr,
-        &ExecuteMsg::UpdateFeeCollector {
-            new_fee_collector: unauthorized_user.address(),
+        &ExecuteMsg::UpdateFeeCollectors {
+            collectors: vec![FeeCollectorInfo {
+                address: unauthorized_user.address(),
+                weight: Decimal::one(),
+            }],
         },
         &[],
         unauthorized_user,
     );
     assert!(
         res_update_collector.is_err(),
-        "UpdateFeeCollector should fail for unauthorized user"
+        "UpdateFeeCollectors should fail for unauthorized user"
     );
     assert!(res_update_collector
         .unwrap_err()
@@ -2641,10 +3049,25 @@ fn test_full_admin_fee_lifecycle() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             }],
         }],
         minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
     wasm.execute(
         &env.aggregator_addr,
@@ -2713,8 +3136,11 @@ fn test_full_admin_fee_lifecycle() {
     .unwrap();
     wasm.execute(
         &env.aggregator_addr,
-        &ExecuteMsg::UpdateFeeCollector {
-            new_fee_collector: new_collector.address(),
+        &ExecuteMsg::UpdateFeeCollectors {
+            collectors: vec![FeeCollectorInfo {
+                address: new_collector.address(),
+                weight: Decimal::one(),
+            }],
         },
         &[],
         admin,
@@ -2792,6 +3218,7 @@ fn test_multi_split_with_mixed_fees() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             },
             Split {
@@ -2805,6 +3232,7 @@ fn test_multi_split_with_mixed_fees() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             },
         ],
@@ -2812,7 +3240,21 @@ fn test_multi_split_with_mixed_fees() {
 
     let msg = ExecuteMsg::ExecuteRoute {
         stages: vec![stage1],
-        minimum_receive: Some("1595000000".to_string()), // Min 1595 USDT
+        minimum_receive: native_minimum("usdt", 1595000000), // Min 1595 USDT
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     // Execute the transaction
@@ -2910,10 +3352,25 @@ fn test_fee_truncates_to_zero() {
                     offer_asset_info: amm::AssetInfo::NativeToken {
                         denom: "inj".to_string(),
                     },
+                    min_out: None,
                 })],
             }],
         }],
         minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     // Execute the transaction
@@ -3083,6 +3540,7 @@ fn test_multi_hop_path_with_mid_path_conversion() {
             pool_address: setup.mock_inj_to_cw20_shroom_amm.clone(),
             offer_asset_info: inj_info.clone(),
             ask_asset_info: cw20_shroom_info.clone(),
+            min_out: None,
         }),
         // Hop 2: Native SHROOM -> USDT (INPUT MISMATCH HERE)
         Operation::OrderbookSwap(OrderbookSwapOp {
@@ -3090,6 +3548,7 @@ fn test_multi_hop_path_with_mid_path_conversion() {
             offer_asset_info: native_shroom_info.clone(),
             ask_asset_info: usdt_info.clone(),
             min_quantity_tick_size: Uint128::new(10000),
+            min_out: None,
         }),
         // Hop 3: USDT -> INJ
         Operation::OrderbookSwap(OrderbookSwapOp {
@@ -3097,6 +3556,7 @@ fn test_multi_hop_path_with_mid_path_conversion() {
             offer_asset_info: usdt_info.clone(),
             ask_asset_info: inj_info.clone(),
             min_quantity_tick_size: Uint128::new(10000),
+            min_out: None,
         }),
     ];
 
@@ -3107,7 +3567,21 @@ fn test_multi_hop_path_with_mid_path_conversion() {
                 path, // Use the complex path
             }],
         }],
-        minimum_receive: Some("49000000000000000000".to_string()), // Min 49 INJ
+        minimum_receive: native_minimum("inj", 49000000000000000000), // Min 49 INJ
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
     };
 
     let funds_to_send = Coin::new(10_000_000_000_000_000_000u128, "inj"); // 10 INJ
@@ -3136,7 +3610,22 @@ fn test_multi_hop_path_with_mid_path_conversion() {
         "Execution with mid-path conversion failed: {:?}",
         res.unwrap_err()
     );
-    println!("Gas Used: {}", res.unwrap().gas_info.gas_used);
+    let response = res.unwrap();
+    println!("Gas Used: {}", response.gas_info.gas_used);
+
+    // Swap, conversion, swap, swap: each dispatched from the previous one's reply.
+    let counter = |key: &str| {
+        response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    };
+    assert_eq!(counter("submessages_dispatched"), Some("4".to_string()));
+    assert_eq!(counter("conversions_performed"), Some("1".to_string()));
+    assert_eq!(counter("reply_depth"), Some("4".to_string()));
 
     // --- ASSERT FINAL BALANCE ---
     let final_inj_balance_response = bank
@@ -3310,3 +3799,6312 @@ fn test_emergency_withdraw() {
         .unwrap();
     assert_eq!(contract_shroom_balance.balance, Uint128::zero());
 }
+
+#[test]
+fn test_route_receipt_is_queryable_by_route_id() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let admin = &env.admin;
+    let user = &env.user;
+
+    // 1% fee on AMM1 so the receipt records a non-empty fee list.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::from_str("0.01").unwrap(),
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+
+    // 100 INJ -> AMM1 @ 10.0 = 1,000 USDT gross, 10 USDT fee, 990 USDT net.
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &msg,
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            user,
+        )
+        .unwrap();
+
+    let route_id: u64 = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "route_id")
+        .expect("Did not find route_id attribute")
+        .value
+        .parse()
+        .unwrap();
+
+    let res: ReceiptResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::Receipt { route_id })
+        .unwrap();
+    let receipt = res
+        .receipt
+        .expect("Receipt should be stored for a completed route");
+
+    assert_eq!(
+        receipt.input,
+        amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            amount: Uint128::new(100_000_000_000_000_000_000u128),
+        }
+    );
+    assert_eq!(
+        receipt.output,
+        amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            amount: Uint128::new(990_000_000u128),
+        }
+    );
+    assert_eq!(
+        receipt.fees,
+        vec![amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            amount: Uint128::new(10_000_000u128),
+        }]
+    );
+    assert_eq!(receipt.stages_executed, 1);
+    assert_eq!(receipt.conversions, 0);
+
+    // An unknown route id has no receipt.
+    let res: ReceiptResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::Receipt {
+                route_id: route_id + 1,
+            },
+        )
+        .unwrap();
+    assert!(res.receipt.is_none());
+}
+
+#[test]
+fn test_aggregator_fee_on_pool_with_commission_and_spread() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let admin = &env.admin;
+    let user = &env.user;
+
+    // A constant-product pool with 1,000 INJ / 10,000 USDT virtual reserves and a 0.3% commission.
+    let mock_swap_code_id = wasm
+        .store_code(get_wasm_byte_code("mock_swap.wasm"), None, admin)
+        .unwrap()
+        .data
+        .code_id;
+    let cp_pool_addr = wasm
+        .instantiate(
+            mock_swap_code_id,
+            &MockInstantiateMsg {
+                config: SwapConfig {
+                    input_asset_info: AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    output_asset_info: AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    rate: "10.0".to_string(),
+                    protocol_type: ProtocolType::Amm,
+                    input_decimals: 18,
+                    output_decimals: 6,
+                    commission_bps: 30,
+                    virtual_reserve: None,
+                    spread_model: Some(SpreadModel::ConstantProduct {
+                        offer_reserve: Uint128::new(1_000_000_000_000_000_000_000u128),
+                        ask_reserve: Uint128::new(10_000_000_000u128),
+                    }),
+                },
+            },
+            Some(&admin.address()),
+            Some("mock-cp-inj-usdt"),
+            &[],
+            admin,
+        )
+        .unwrap()
+        .data
+        .address;
+    bank.send(
+        MsgSend {
+            from_address: admin.address(),
+            to_address: cp_pool_addr.clone(),
+            amount: vec![ProtoCoin {
+                denom: "usdt".to_string(),
+                amount: "10000000000".to_string(),
+            }],
+        },
+        admin,
+    )
+    .unwrap();
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: cp_pool_addr.clone(),
+            fee_percent: Decimal::from_str("0.01").unwrap(),
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+
+    // 100 INJ in:
+    // Gross:      10,000 * 100 / (1,000 + 100) = 909.090909 USDT
+    // Commission: 909.090909 * 0.3%            =   2.727272 USDT
+    // Pool out:                                  906.363637 USDT
+    // Agg. fee:   906.363637 * 1%              =   9.063636 USDT
+    // Net:                                       897.300001 USDT
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: cp_pool_addr.clone(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: native_minimum("usdt", 897300001),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &msg,
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            user,
+        )
+        .unwrap();
+
+    let attr = |key: &str| {
+        response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+            .unwrap_or_else(|| panic!("Did not find {} attribute", key))
+    };
+
+    assert_eq!(attr("commission_amount"), "2727272");
+    assert_eq!(attr("fee_collected"), "9063636");
+    assert_eq!(attr("final_received"), "897300001");
+
+    let collector_balance = bank
+        .query_balance(&QueryBalanceRequest {
+            address: env.fee_collector.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+    assert_eq!(collector_balance.amount, "9063636");
+}
+
+#[test]
+fn test_mock_failure_modes_drive_aggregator_error_paths() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let admin = &env.admin;
+    let user = &env.user;
+
+    let route = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")]; // 100 INJ -> 1,000 USDT
+    let set_mode = |failure_mode: Option<FailureMode>| {
+        wasm.execute(
+            &env.mock_amm_1_addr,
+            &MockExecuteMsg::SetFailureMode { failure_mode },
+            &[],
+            admin,
+        )
+        .unwrap();
+    };
+
+    // Only the mock's admin may inject failures.
+    let res = wasm.execute(
+        &env.mock_amm_1_addr,
+        &MockExecuteMsg::SetFailureMode {
+            failure_mode: Some(FailureMode::AlwaysFail),
+        },
+        &[],
+        user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    // A partial output must pay out between 1% and 100%.
+    for percent in [0, 101] {
+        let res = wasm.execute(
+            &env.mock_amm_1_addr,
+            &MockExecuteMsg::SetFailureMode {
+                failure_mode: Some(FailureMode::PartialOutput(percent)),
+            },
+            &[],
+            admin,
+        );
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("must be between 1 and 100"));
+    }
+
+    // AlwaysFail: the pool reverts, so the whole route reverts.
+    set_mode(Some(FailureMode::AlwaysFail));
+    let res = wasm.execute(&env.aggregator_addr, &route, &funds, user);
+    assert!(res.unwrap_err().to_string().contains("always_fail"));
+
+    // FailAboveAmount: 100 INJ is above the 50 INJ threshold.
+    set_mode(Some(FailureMode::FailAboveAmount(Uint128::new(
+        50_000_000_000_000_000_000u128,
+    ))));
+    let res = wasm.execute(&env.aggregator_addr, &route, &funds, user);
+    assert!(res.unwrap_err().to_string().contains("exceeds"));
+
+    // PartialOutput: only half of the quoted 1,000 USDT is paid out.
+    set_mode(Some(FailureMode::PartialOutput(50)));
+    let response = wasm
+        .execute(&env.aggregator_addr, &route, &funds, user)
+        .unwrap();
+    let final_received = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "final_received")
+        .unwrap();
+    assert_eq!(final_received.value, "500000000");
+
+    // NoEvent / WrongEventFormat: there is no swap event to read, so the aggregator falls back to
+    // the pool's bank transfer and still sees the full 1,000 USDT.
+    for mode in [FailureMode::NoEvent, FailureMode::WrongEventFormat] {
+        set_mode(Some(mode));
+        let guarded_route = ExecuteMsg::ExecuteRoute {
+            stages: match &route {
+                ExecuteMsg::ExecuteRoute { stages, .. } => stages.clone(),
+                _ => unreachable!(),
+            },
+            minimum_receive: native_minimum("usdt", 1),
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook: None,
+            final_assets: None,
+            post_action: None,
+            max_fee_bps: None,
+            minimum_receive_usd: None,
+            integrator: None,
+            recipients: None,
+        };
+        let response = wasm
+            .execute(&env.aggregator_addr, &guarded_route, &funds, user)
+            .unwrap();
+        let final_received = response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == "final_received")
+            .unwrap();
+        assert_eq!(final_received.value, "1000000000");
+    }
+
+    // Clearing the failure mode restores normal behaviour.
+    set_mode(None);
+    let res = wasm.execute(&env.aggregator_addr, &route, &funds, user);
+    assert!(res.is_ok(), "Execution failed: {:?}", res.unwrap_err());
+}
+
+#[test]
+fn test_multi_pair_mock_serves_both_directions() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let admin = &env.admin;
+    let user = &env.user;
+
+    let inj = AssetInfo::NativeToken {
+        denom: "inj".to_string(),
+    };
+    let usdt = AssetInfo::NativeToken {
+        denom: "usdt".to_string(),
+    };
+
+    // One mock instance serving INJ -> USDT (AMM) and USDT -> INJ (orderbook).
+    let mock_swap_code_id = wasm
+        .store_code(get_wasm_byte_code("mock_swap.wasm"), None, admin)
+        .unwrap()
+        .data
+        .code_id;
+    let multi_pair_addr = wasm
+        .instantiate(
+            mock_swap_code_id,
+            &MockInstantiateMsg {
+                config: SwapConfig {
+                    input_asset_info: inj.clone(),
+                    output_asset_info: usdt.clone(),
+                    rate: "10.0".to_string(),
+                    protocol_type: ProtocolType::Amm,
+                    input_decimals: 18,
+                    output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: None,
+                },
+            },
+            Some(&admin.address()),
+            Some("mock-multi-pair"),
+            &[],
+            admin,
+        )
+        .unwrap()
+        .data
+        .address;
+    wasm.execute(
+        &multi_pair_addr,
+        &MockExecuteMsg::AddPair {
+            config: SwapConfig {
+                input_asset_info: usdt.clone(),
+                output_asset_info: inj.clone(),
+                rate: "0.1".to_string(),
+                protocol_type: ProtocolType::Orderbook,
+                input_decimals: 6,
+                output_decimals: 18,
+                commission_bps: 0,
+                virtual_reserve: None,
+                spread_model: None,
+            },
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+    bank.send(
+        MsgSend {
+            from_address: admin.address(),
+            to_address: multi_pair_addr.clone(),
+            amount: vec![
+                ProtoCoin {
+                    denom: "inj".to_string(),
+                    amount: "1000000000000000000000".to_string(),
+                },
+                ProtoCoin {
+                    denom: "usdt".to_string(),
+                    amount: "10000000000".to_string(),
+                },
+            ],
+        },
+        admin,
+    )
+    .unwrap();
+
+    // Stage 1: 100 INJ -> 1,000 USDT. Stage 2: 1,000 USDT -> 100 INJ. Same contract both times.
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: multi_pair_addr.clone(),
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                        swap_contract: multi_pair_addr.clone(),
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
+                    })],
+                }],
+            },
+        ],
+        minimum_receive: native_minimum("inj", 100000000000000000000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &msg,
+        &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+        user,
+    );
+    assert!(res.is_ok(), "Execution failed: {:?}", res.unwrap_err());
+
+    // Only the admin can add pairs.
+    let res = wasm.execute(
+        &multi_pair_addr,
+        &MockExecuteMsg::AddPair {
+            config: SwapConfig {
+                input_asset_info: usdt,
+                output_asset_info: inj,
+                rate: "1000.0".to_string(),
+                protocol_type: ProtocolType::Orderbook,
+                input_decimals: 6,
+                output_decimals: 18,
+                commission_bps: 0,
+                virtual_reserve: None,
+                spread_model: None,
+            },
+        },
+        &[],
+        user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+}
+
+#[test]
+fn test_simulate_route_against_mocks_matches_execution() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    // Same route as test_aggregate_swap_success: 100 INJ -> 330 + 840 + 750 = 1920 USDT.
+    let stages = vec![Stage {
+        splits: vec![
+            Split {
+                percent: 33,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            },
+            Split {
+                percent: 42,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_2_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            },
+            Split {
+                percent: 25,
+                path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                    swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                    min_out: None,
+                })],
+            },
+        ],
+    }];
+    let amount_in = Coin::new(100_000_000_000_000_000_000u128, "inj");
+
+    let simulation: SimulateRouteResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::SimulateRoute {
+                stages: stages.clone(),
+                amount_in: amount_in.clone(),
+                lenient: false,
+            },
+        )
+        .unwrap();
+    assert_eq!(simulation.output_amount, Uint128::new(1_920_000_000u128));
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages,
+                minimum_receive: native_minimum("usdt", simulation.output_amount.u128()),
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[amount_in],
+            &env.user,
+        )
+        .unwrap();
+    let final_received = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "final_received")
+        .unwrap();
+    assert_eq!(final_received.value, simulation.output_amount.to_string());
+}
+
+#[test]
+fn test_mock_liquidity_depletion() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let admin = &env.admin;
+    let user = &env.user;
+
+    let mock_swap_code_id = wasm
+        .store_code(get_wasm_byte_code("mock_swap.wasm"), None, admin)
+        .unwrap()
+        .data
+        .code_id;
+    let instantiate_pool = |label: &str, config: SwapConfig| {
+        let addr = wasm
+            .instantiate(
+                mock_swap_code_id,
+                &MockInstantiateMsg { config },
+                Some(&admin.address()),
+                Some(label),
+                &[],
+                admin,
+            )
+            .unwrap()
+            .data
+            .address;
+        bank.send(
+            MsgSend {
+                from_address: admin.address(),
+                to_address: addr.clone(),
+                amount: vec![ProtoCoin {
+                    denom: "usdt".to_string(),
+                    amount: "100000000000".to_string(),
+                }],
+            },
+            admin,
+        )
+        .unwrap();
+        addr
+    };
+    let route_through = |pool_address: &str| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: pool_address.to_string(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let final_received = |events: &[cosmwasm_std::Event]| {
+        events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == "final_received")
+            .map(|a| Uint128::from_str(&a.value).unwrap())
+            .unwrap()
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")]; // 100 INJ
+
+    // Linear pool at 10 USDT/INJ with 1,500 USDT of liquidity: the first 1,000 USDT swap
+    // succeeds, the second exceeds the remaining 500 USDT and reverts.
+    let linear_pool = instantiate_pool(
+        "mock-linear-limited",
+        SwapConfig {
+            input_asset_info: AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            output_asset_info: AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            rate: "10.0".to_string(),
+            protocol_type: ProtocolType::Amm,
+            input_decimals: 18,
+            output_decimals: 6,
+            commission_bps: 0,
+            virtual_reserve: Some(Uint128::new(1_500_000_000)),
+            spread_model: None,
+        },
+    );
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route_through(&linear_pool),
+            &funds,
+            user,
+        )
+        .unwrap();
+    assert_eq!(
+        final_received(&response.events),
+        Uint128::new(1_000_000_000)
+    );
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &route_through(&linear_pool),
+        &funds,
+        user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Insufficient liquidity"));
+
+    // Constant-product pool: each swap moves the reserves, so the same input gets less output.
+    let cp_pool = instantiate_pool(
+        "mock-cp-depleting",
+        SwapConfig {
+            input_asset_info: AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            output_asset_info: AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            rate: "10.0".to_string(),
+            protocol_type: ProtocolType::Amm,
+            input_decimals: 18,
+            output_decimals: 6,
+            commission_bps: 0,
+            virtual_reserve: None,
+            spread_model: Some(SpreadModel::ConstantProduct {
+                offer_reserve: Uint128::new(1_000_000_000_000_000_000_000u128),
+                ask_reserve: Uint128::new(10_000_000_000u128),
+            }),
+        },
+    );
+    let first = final_received(
+        &wasm
+            .execute(&env.aggregator_addr, &route_through(&cp_pool), &funds, user)
+            .unwrap()
+            .events,
+    );
+    let second = final_received(
+        &wasm
+            .execute(&env.aggregator_addr, &route_through(&cp_pool), &funds, user)
+            .unwrap()
+            .events,
+    );
+    // 10,000 * 100 / 1,100 = 909.090909; then 9,090.909091 * 100 / 1,200 = 757.575757
+    assert_eq!(first, Uint128::new(909_090_909));
+    assert_eq!(second, Uint128::new(757_575_757));
+}
+
+#[test]
+fn test_internal_conversion_hook_rejects_non_adapter_senders() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let admin = &setup.env.admin;
+    let user = &setup.env.user;
+
+    wasm.execute(
+        &setup.shroom_cw20_addr,
+        &cw20_base::msg::ExecuteMsg::Mint {
+            recipient: user.address(),
+            amount: Uint128::new(1_000_000),
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+
+    // A user cannot spoof a conversion result for a route.
+    let res = wasm.execute(
+        &setup.shroom_cw20_addr,
+        &cw20::Cw20ExecuteMsg::Send {
+            contract: setup.env.aggregator_addr.clone(),
+            amount: Uint128::new(1_000_000),
+            msg: to_json_binary(&Cw20HookMsg::InternalConversion { route_id: 1 }).unwrap(),
+        },
+        &[],
+        user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+}
+
+#[test]
+fn test_route_checkpoints_and_continues_across_transactions() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let user = &env.user;
+
+    // Same two-stage route as test_multi_stage_aggregate_swap_success, but executed one stage
+    // per transaction.
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                        swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
+                    })],
+                }],
+            },
+            Stage {
+                splits: vec![
+                    Split {
+                        percent: 49,
+                        path: vec![Operation::AmmSwap(AmmSwapOp {
+                            pool_address: env.mock_amm_1_addr.clone(),
+                            ask_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "usdt".to_string(),
+                            },
+                            offer_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "inj".to_string(),
+                            },
+                            min_out: None,
+                        })],
+                    },
+                    Split {
+                        percent: 51,
+                        path: vec![Operation::AmmSwap(AmmSwapOp {
+                            pool_address: env.mock_amm_2_addr.clone(),
+                            ask_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "usdt".to_string(),
+                            },
+                            offer_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "inj".to_string(),
+                            },
+                            min_out: None,
+                        })],
+                    },
+                ],
+            },
+        ],
+        minimum_receive: native_minimum("usdt", 1500000000000),
+        max_stages_per_tx: Some(1),
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &msg,
+            &[Coin::new(1_000_000_000_000u128, "usdt")],
+            user,
+        )
+        .unwrap();
+    let find_attr = |events: &[cosmwasm_std::Event], key: &str| {
+        events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    };
+    let has_action = |events: &[cosmwasm_std::Event], action: &str| {
+        events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .any(|a| a.key == "action" && a.value == action)
+    };
+    assert!(has_action(&response.events, "route_checkpointed"));
+    assert_eq!(
+        find_attr(&response.events, "next_stage_index").as_deref(),
+        Some("1")
+    );
+    assert!(find_attr(&response.events, "final_received").is_none());
+    let route_id: u64 = find_attr(&response.events, "route_id")
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // The intermediate INJ is held by the aggregator between transactions.
+    let held_inj = bank
+        .query_balance(&QueryBalanceRequest {
+            address: env.aggregator_addr.clone(),
+            denom: "inj".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+    assert_ne!(held_inj.amount, "0");
+
+    // Only the initiator may continue the route.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ContinueRoute { route_id },
+        &[],
+        &env.admin,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ContinueRoute { route_id },
+            &[],
+            user,
+        )
+        .unwrap();
+    assert!(has_action(&response.events, "aggregate_swap_complete"));
+    assert_eq!(
+        find_attr(&response.events, "final_received").as_deref(),
+        Some("1510000000000")
+    );
+
+    // The route is complete and cannot be continued again.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ContinueRoute { route_id },
+        &[],
+        user,
+    );
+    assert!(res.unwrap_err().to_string().contains("No in-flight route"));
+}
+
+#[test]
+fn test_denom_policy_restricts_route_assets() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let route = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(1_000_000_000_000_000_000u128, "inj")];
+
+    // Only the admin may set a policy.
+    let deny_usdt = ExecuteMsg::SetDenomPolicy {
+        mode: DenomPolicyMode::Deny,
+        denoms: vec!["usdt".to_string()],
+    };
+    let res = wasm.execute(&env.aggregator_addr, &deny_usdt, &[], &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    wasm.execute(&env.aggregator_addr, &deny_usdt, &[], &env.admin)
+        .unwrap();
+    let policy: DenomPolicyResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::DenomPolicy {})
+        .unwrap();
+    let policy = policy.policy.unwrap();
+    assert_eq!(policy.mode, DenomPolicyMode::Deny);
+    assert_eq!(policy.denoms, vec!["usdt".to_string()]);
+
+    // The route's ask asset is denied.
+    let res = wasm.execute(&env.aggregator_addr, &route, &funds, &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Denom usdt is not permitted by the denom policy"));
+
+    // An allow list covering both terminal assets lets the route through.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetDenomPolicy {
+            mode: DenomPolicyMode::Allow,
+            denoms: vec!["inj".to_string(), "usdt".to_string()],
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    wasm.execute(&env.aggregator_addr, &route, &funds, &env.user)
+        .unwrap();
+
+    // An allow list missing the offer asset rejects the route.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetDenomPolicy {
+            mode: DenomPolicyMode::Allow,
+            denoms: vec!["usdt".to_string()],
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    let res = wasm.execute(&env.aggregator_addr, &route, &funds, &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Denom inj is not permitted by the denom policy"));
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::RemoveDenomPolicy {},
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    let policy: DenomPolicyResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::DenomPolicy {})
+        .unwrap();
+    assert!(policy.policy.is_none());
+    wasm.execute(&env.aggregator_addr, &route, &funds, &env.user)
+        .unwrap();
+}
+
+#[test]
+fn test_fee_split_across_weighted_collectors() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let treasury = env.app.init_account(&[]).unwrap();
+    let staking = env.app.init_account(&[]).unwrap();
+
+    // Weights that do not sum to 100% are rejected.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::UpdateFeeCollectors {
+            collectors: vec![
+                FeeCollectorInfo {
+                    address: treasury.address(),
+                    weight: Decimal::percent(70),
+                },
+                FeeCollectorInfo {
+                    address: staking.address(),
+                    weight: Decimal::percent(20),
+                },
+            ],
+        },
+        &[],
+        &env.admin,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Fee collector weights must be positive and sum to 100%"));
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::UpdateFeeCollectors {
+            collectors: vec![
+                FeeCollectorInfo {
+                    address: treasury.address(),
+                    weight: Decimal::percent(70),
+                },
+                FeeCollectorInfo {
+                    address: staking.address(),
+                    weight: Decimal::percent(30),
+                },
+            ],
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    let split: FeeCollectorsResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::FeeCollectors {})
+        .unwrap();
+    assert_eq!(split.collectors.len(), 2);
+    assert_eq!(split.collectors[0].address.to_string(), treasury.address());
+    assert_eq!(split.collectors[0].weight, Decimal::percent(70));
+    assert_eq!(split.collectors[1].address.to_string(), staking.address());
+    assert_eq!(split.collectors[1].weight, Decimal::percent(30));
+
+    // 1% fee on 100 INJ -> AMM1 @ 10.0 = 1000 USDT, i.e. a 10 USDT fee split 7 / 3.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ExecuteRoute {
+            stages: vec![Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: env.mock_amm_1_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            }],
+            minimum_receive: None,
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook: None,
+            final_assets: None,
+            post_action: None,
+            max_fee_bps: None,
+            minimum_receive_usd: None,
+            integrator: None,
+            recipients: None,
+        },
+        &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+        &env.user,
+    )
+    .unwrap();
+
+    let usdt_balance = |address: String| {
+        bank.query_balance(&QueryBalanceRequest {
+            address,
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap()
+        .amount
+    };
+    assert_eq!(usdt_balance(treasury.address()), "7000000");
+    assert_eq!(usdt_balance(staking.address()), "3000000");
+    assert_eq!(usdt_balance(env.fee_collector.address()), "0");
+}
+
+#[test]
+fn test_execute_route_with_allowance() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let user = &setup.env.user;
+    let admin = &setup.env.admin;
+
+    let shroom_amount = Uint128::new(1_000_000_000u128); // 1,000 SHROOM
+    wasm.execute(
+        &setup.shroom_cw20_addr,
+        &cw20_base::msg::ExecuteMsg::Mint {
+            recipient: user.address(),
+            amount: shroom_amount,
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+
+    // Same route as test_cw20_entry_point_swap_success: 1,000 SHROOM -> 100 SAI.
+    let msg = ExecuteMsg::ExecuteRouteWithAllowance {
+        token: setup.shroom_cw20_addr.clone(),
+        amount: shroom_amount,
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: setup.mock_cw20_shroom_to_cw20_sai_amm.clone(),
+                    offer_asset_info: amm::AssetInfo::Token {
+                        contract_addr: setup.shroom_cw20_addr.clone(),
+                    },
+                    ask_asset_info: amm::AssetInfo::Token {
+                        contract_addr: setup.sai_cw20_addr.clone(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: cw20_minimum(&setup.sai_cw20_addr, 99000000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    // Without an allowance the TransferFrom fails and the whole route reverts.
+    let res = wasm.execute(&setup.env.aggregator_addr, &msg, &[], user);
+    assert!(res.is_err());
+
+    wasm.execute(
+        &setup.shroom_cw20_addr,
+        &cw20::Cw20ExecuteMsg::IncreaseAllowance {
+            spender: setup.env.aggregator_addr.clone(),
+            amount: shroom_amount,
+            expires: None,
+        },
+        &[],
+        user,
+    )
+    .unwrap();
+    wasm.execute(&setup.env.aggregator_addr, &msg, &[], user)
+        .unwrap();
+
+    let balance = |token: &String| -> Uint128 {
+        let res: BalanceResponse = wasm
+            .query(
+                token,
+                &Cw20QueryMsg::Balance {
+                    address: user.address(),
+                },
+            )
+            .unwrap();
+        res.balance
+    };
+    assert_eq!(balance(&setup.shroom_cw20_addr), Uint128::zero());
+    assert_eq!(balance(&setup.sai_cw20_addr), Uint128::new(100_000_000u128));
+}
+
+#[test]
+fn test_tick_size_remainder_is_refunded() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    // 10.5 INJ into an orderbook with a 1 INJ tick size: 10 INJ @ 30.0 = 300 USDT is swapped and
+    // the 0.5 INJ remainder is returned to the user.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                            swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+                            ask_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "usdt".to_string(),
+                            },
+                            offer_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "inj".to_string(),
+                            },
+                            min_quantity_tick_size: Uint128::new(1_000_000_000_000_000_000),
+                            min_out: None,
+                        })],
+                    }],
+                }],
+                minimum_receive: None,
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(10_500_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    let attrs: Vec<_> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .collect();
+    let find = |key: &str| {
+        attrs
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.as_str())
+    };
+    assert_eq!(find("final_received"), Some("300000000"));
+    assert_eq!(find("refunded_asset"), Some("inj"));
+    assert_eq!(find("refunded_amount"), Some("500000000000000000"));
+
+    let bank_sends_to_user = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "transfer")
+        .filter(|e| {
+            e.attributes
+                .iter()
+                .any(|a| a.key == "recipient" && a.value == env.user.address())
+        })
+        .flat_map(|e| e.attributes.iter())
+        .any(|a| a.key == "amount" && a.value == "500000000000000000inj");
+    assert!(bank_sends_to_user);
+}
+
+#[test]
+fn test_dust_remainder_accrues_and_is_swept() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let inj = amm::AssetInfo::NativeToken {
+        denom: "inj".to_string(),
+    };
+
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetDustThreshold {
+            asset_info: inj.clone(),
+            threshold: Some(Uint128::new(1_000_000_000_000_000_000)),
+        },
+        &[],
+        &env.user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetDustThreshold {
+            asset_info: inj.clone(),
+            threshold: Some(Uint128::new(1_000_000_000_000_000_000)),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // The 0.5 INJ tick-size remainder is below the 1 INJ threshold, so it is kept as dust
+    // instead of being refunded.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                            swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+                            ask_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "usdt".to_string(),
+                            },
+                            offer_asset_info: inj.clone(),
+                            min_quantity_tick_size: Uint128::new(1_000_000_000_000_000_000),
+                            min_out: None,
+                        })],
+                    }],
+                }],
+                minimum_receive: None,
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(10_500_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    let attrs: Vec<_> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .collect();
+    let find = |key: &str| {
+        attrs
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.as_str())
+    };
+    assert_eq!(find("final_received"), Some("300000000"));
+    assert_eq!(find("refunded_amount"), None);
+    assert_eq!(find("dust_asset"), Some("inj"));
+    assert_eq!(find("dust_amount"), Some("500000000000000000"));
+
+    let bucket: DustBucketResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::DustBucket {})
+        .unwrap();
+    assert_eq!(
+        bucket.assets,
+        vec![amm::Asset {
+            info: inj.clone(),
+            amount: Uint128::new(500_000_000_000_000_000),
+        }]
+    );
+
+    // Sweeping sends the bucket to the fee collector and empties it.
+    let collector_inj = || {
+        let balance = bank
+            .query_balance(&QueryBalanceRequest {
+                address: env.fee_collector.address(),
+                denom: "inj".to_string(),
+            })
+            .unwrap()
+            .balance
+            .unwrap();
+        Uint128::from_str(&balance.amount).unwrap()
+    };
+    let before = collector_inj();
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SweepDust {},
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    assert_eq!(
+        collector_inj() - before,
+        Uint128::new(500_000_000_000_000_000)
+    );
+    let bucket: DustBucketResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::DustBucket {})
+        .unwrap();
+    assert!(bucket.assets.is_empty());
+}
+
+#[test]
+fn test_zero_rounded_orderbook_split_does_not_abort_route() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    // Split 1 (99%): 99 INJ -> AMM1 @ 10.0 = 990 USDT
+    // Split 2 (1%): 1 INJ -> OB with a 10 INJ tick size, which rounds down to nothing. The
+    // split completes with zero output and the 1 INJ is refunded.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: vec![Stage {
+                    splits: vec![
+                        Split {
+                            percent: 99,
+                            path: vec![Operation::AmmSwap(AmmSwapOp {
+                                pool_address: env.mock_amm_1_addr.clone(),
+                                ask_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "usdt".to_string(),
+                                },
+                                offer_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "inj".to_string(),
+                                },
+                                min_out: None,
+                            })],
+                        },
+                        Split {
+                            percent: 1,
+                            path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                                swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+                                ask_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "usdt".to_string(),
+                                },
+                                offer_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "inj".to_string(),
+                                },
+                                min_quantity_tick_size: Uint128::new(10_000_000_000_000_000_000),
+                                min_out: None,
+                            })],
+                        },
+                    ],
+                }],
+                minimum_receive: native_minimum("usdt", 990000000),
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    let attrs: Vec<_> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .collect();
+    let find = |key: &str| {
+        attrs
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.as_str())
+    };
+    assert_eq!(find("final_received"), Some("990000000"));
+    assert_eq!(find("refunded_asset"), Some("inj"));
+    assert_eq!(find("refunded_amount"), Some("1000000000000000000"));
+
+    // Noop is only callable by the aggregator itself.
+    let res = wasm.execute(&env.aggregator_addr, &ExecuteMsg::Noop {}, &[], &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+}
+
+#[test]
+fn test_route_template_registry_and_execution() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                min_out: None,
+            })],
+        }],
+    }];
+    let register = ExecuteMsg::RegisterRouteTemplate {
+        name: "inj_usdt".to_string(),
+        stages: stages.clone(),
+    };
+
+    let res = wasm.execute(&env.aggregator_addr, &register, &[], &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    wasm.execute(&env.aggregator_addr, &register, &[], &env.admin)
+        .unwrap();
+
+    let template: RouteTemplateResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::RouteTemplate {
+                name: "inj_usdt".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(template.stages, stages);
+    let templates: RouteTemplatesResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::RouteTemplates {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(templates.templates, vec![template]);
+
+    // 100 INJ -> AMM1 @ 10.0 = 1000 USDT
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteTemplate {
+                name: "inj_usdt".to_string(),
+                minimum_receive: native_minimum("usdt", 1000000000),
+            },
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+    let final_received = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "final_received")
+        .map(|a| a.value.clone());
+    assert_eq!(final_received.as_deref(), Some("1000000000"));
+
+    // The template starts from INJ, so it cannot be executed with USDT.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ExecuteTemplate {
+            name: "inj_usdt".to_string(),
+            minimum_receive: None,
+        },
+        &[Coin::new(1_000_000u128, "usdt")],
+        &env.user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Route template inj_usdt takes inj, not usdt"));
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::RemoveRouteTemplate {
+            name: "inj_usdt".to_string(),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ExecuteTemplate {
+            name: "inj_usdt".to_string(),
+            minimum_receive: None,
+        },
+        &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+        &env.user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("No route template named inj_usdt"));
+}
+
+#[test]
+fn test_fee_is_charged_on_mid_path_hops() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // Hop 1: 10 INJ -> AMM1 @ 10.0 = 100 USDT, taxed 1% -> 99 USDT continues.
+    // Hop 2: 99 USDT -> OB @ 0.1 = 9.9 INJ.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![
+                            Operation::AmmSwap(AmmSwapOp {
+                                pool_address: env.mock_amm_1_addr.clone(),
+                                ask_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "usdt".to_string(),
+                                },
+                                offer_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "inj".to_string(),
+                                },
+                                min_out: None,
+                            }),
+                            Operation::OrderbookSwap(OrderbookSwapOp {
+                                swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                                ask_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "inj".to_string(),
+                                },
+                                offer_asset_info: amm::AssetInfo::NativeToken {
+                                    denom: "usdt".to_string(),
+                                },
+                                min_quantity_tick_size: Uint128::new(10000),
+                                min_out: None,
+                            }),
+                        ],
+                    }],
+                }],
+                minimum_receive: None,
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(10_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    let attrs: Vec<_> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .collect();
+    let find = |key: &str| {
+        attrs
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.as_str())
+    };
+    assert_eq!(find("fee_collected"), Some("1000000"));
+    assert_eq!(find("final_received"), Some("9900000000000000000"));
+
+    let collector_balance = bank
+        .query_balance(&QueryBalanceRequest {
+            address: env.fee_collector.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+    assert_eq!(collector_balance.amount, "1000000");
+}
+
+#[test]
+fn test_stale_quote_is_rejected() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                min_out: None,
+            })],
+        }],
+    }];
+    let quote: SimulateRouteResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::SimulateRoute {
+                stages: stages.clone(),
+                amount_in: Coin::new(1_000_000_000_000_000_000u128, "inj"),
+                lenient: false,
+            },
+        )
+        .unwrap();
+    assert!(quote.block_height > 0);
+
+    let route = |max_blocks: u64| ExecuteMsg::ExecuteRoute {
+        stages: stages.clone(),
+        minimum_receive: native_minimum("usdt", quote.output_amount.u128()),
+        max_stages_per_tx: None,
+        max_quote_age: Some(QuoteAge {
+            quote_height: quote.block_height,
+            max_blocks,
+        }),
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    // Every transaction lands in a new block, so a zero-block allowance is already stale.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &route(0),
+        &[Coin::new(1_000_000_000_000_000_000u128, "inj")],
+        &env.user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("is too old at height"));
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &route(100),
+        &[Coin::new(1_000_000_000_000_000_000u128, "inj")],
+        &env.user,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_parameters_query_and_enforcement() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+
+    let params: ParametersResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::Parameters {})
+        .unwrap();
+    assert_eq!(params.parameters, Parameters::default());
+    assert_eq!(params.config.admin.to_string(), env.admin.address());
+    assert!(params.denom_policy.is_none());
+
+    let inj_to_usdt = |pool: &String| Split {
+        percent: 100,
+        path: vec![Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.clone(),
+            ask_asset_info: amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            offer_asset_info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            min_out: None,
+        })],
+    };
+    let route = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![inj_to_usdt(&env.mock_amm_2_addr)],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+    let update = |parameters: Parameters| {
+        wasm.execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::UpdateParameters { parameters },
+            &[],
+            &env.admin,
+        )
+    };
+
+    // Only the admin may update parameters, and they are validated.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::UpdateParameters {
+            parameters: Parameters::default(),
+        },
+        &[],
+        &env.user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    let res = update(Parameters {
+        max_stages: Some(0),
+        ..Parameters::default()
+    });
+    assert!(res.unwrap_err().to_string().contains("Invalid parameters"));
+
+    // Route complexity limits.
+    update(Parameters {
+        max_splits_per_stage: Some(1),
+        ..Parameters::default()
+    })
+    .unwrap();
+    let mut half = inj_to_usdt(&env.mock_amm_1_addr);
+    half.percent = 50;
+    let mut other_half = inj_to_usdt(&env.mock_amm_2_addr);
+    other_half.percent = 50;
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ExecuteRoute {
+            stages: vec![Stage {
+                splits: vec![half, other_half],
+            }],
+            minimum_receive: None,
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook: None,
+            final_assets: None,
+            post_action: None,
+            max_fee_bps: None,
+            minimum_receive_usd: None,
+            integrator: None,
+            recipients: None,
+        },
+        &funds,
+        &env.user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Route exceeds the complexity limits"));
+
+    // The fee cap bounds SetFee.
+    update(Parameters {
+        max_fee_percent: Decimal::percent(5),
+        ..Parameters::default()
+    })
+    .unwrap();
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::percent(5),
+        },
+        &[],
+        &env.admin,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Fee percentage must be less than 5%"));
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_2_addr.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // 100 INJ -> AMM2 @ 20.0 = 2000 USDT, 1% fee = 20 USDT.
+    wasm.execute(&env.aggregator_addr, &route, &funds, &env.user)
+        .unwrap();
+    let collector_balance = bank
+        .query_balance(&QueryBalanceRequest {
+            address: env.fee_collector.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+    assert_eq!(collector_balance.amount, "20000000");
+}
+
+#[test]
+fn test_instantiate_seeds_fees_allowlist_and_parameters() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let code_id = wasm
+        .store_code(&get_wasm_byte_code("dex_aggregator.wasm"), None, &env.admin)
+        .unwrap()
+        .data
+        .code_id;
+    let config: AggregatorConfig = wasm
+        .query(&env.aggregator_addr, &QueryMsg::Config {})
+        .unwrap();
+    let parameters = Parameters {
+        max_fee_percent: Decimal::percent(5),
+        ..Parameters::default()
+    };
+    let instantiate = |fee_percent: Decimal| {
+        wasm.instantiate(
+            code_id,
+            &InstantiateMsg {
+                admin: env.admin.address(),
+                cw20_adapter_address: config.cw20_adapter_address.to_string(),
+                fee_collectors: vec![FeeCollectorInfo {
+                    address: env.fee_collector.address(),
+                    weight: Decimal::one(),
+                }],
+                initial_fees: Some(vec![FeeInfo {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    fee_percent,
+                }]),
+                allowed_pools: Some(vec![env.mock_amm_1_addr.clone()]),
+                parameters: Some(parameters.clone()),
+            },
+            None,
+            Some("seeded-aggregator"),
+            &[],
+            &env.admin,
+        )
+    };
+
+    // Seeded fees are held to the seeded fee cap.
+    let res = instantiate(Decimal::percent(5));
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Fee percentage must be less than 5%"));
+    let aggregator_addr = instantiate(Decimal::percent(1)).unwrap().data.address;
+
+    let fee: FeeResponse = wasm
+        .query(
+            &aggregator_addr,
+            &QueryMsg::FeeForPool {
+                pool_address: env.mock_amm_1_addr.clone(),
+            },
+        )
+        .unwrap();
+    assert_eq!(fee.fee, Some(Decimal::percent(1)));
+    let allowlist: PoolAllowlistResponse = wasm
+        .query(
+            &aggregator_addr,
+            &QueryMsg::PoolAllowlist {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(allowlist.pools, vec![Addr::unchecked(&env.mock_amm_1_addr)]);
+    let params: ParametersResponse = wasm
+        .query(&aggregator_addr, &QueryMsg::Parameters {})
+        .unwrap();
+    assert_eq!(params.parameters, parameters);
+
+    let route = |pool: &String| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: pool.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+
+    let res = wasm.execute(
+        &aggregator_addr,
+        &route(&env.mock_amm_2_addr),
+        &funds,
+        &env.user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("is not on the allowlist"));
+
+    // 100 INJ -> AMM1 @ 10.0 = 1000 USDT, less the seeded 1% fee.
+    let response = wasm
+        .execute(
+            &aggregator_addr,
+            &route(&env.mock_amm_1_addr),
+            &funds,
+            &env.user,
+        )
+        .unwrap();
+    let final_received = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "final_received")
+        .map(|a| a.value.clone());
+    assert_eq!(final_received, Some("990000000".to_string()));
+
+    // Emptying the allowlist allows every pool again.
+    wasm.execute(
+        &aggregator_addr,
+        &ExecuteMsg::UpdatePoolAllowlist {
+            add: vec![],
+            remove: vec![env.mock_amm_1_addr.clone()],
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    wasm.execute(
+        &aggregator_addr,
+        &route(&env.mock_amm_2_addr),
+        &funds,
+        &env.user,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_operators_register_and_admin_revokes_pools() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let fee = Coin::new(1_000_000u128, "usdt");
+    let register = ExecuteMsg::RegisterPool {
+        pool_address: env.mock_amm_2_addr.clone(),
+    };
+    let allowlist = || -> Vec<Addr> {
+        wasm.query::<_, PoolAllowlistResponse>(
+            &env.aggregator_addr,
+            &QueryMsg::PoolAllowlist {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap()
+        .pools
+    };
+
+    let set_terms = |code_id: u64| {
+        wasm.execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::SetPoolRegistrationTerms {
+                terms: Some(PoolRegistrationTerms {
+                    fee: fee.clone(),
+                    canary_amount: Uint128::new(1_000_000),
+                    code_ids: vec![code_id],
+                }),
+            },
+            &[],
+            &env.admin,
+        )
+        .unwrap();
+    };
+    // At first only the aggregator's code, not the mock pools', is open to registration.
+    set_terms(env.mock_swap_code_id - 1);
+
+    // With an empty allowlist every pool is already allowed.
+    let res = wasm.execute(&env.aggregator_addr, &register, &[fee.clone()], &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("registration is closed"));
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::UpdatePoolAllowlist {
+            add: vec![env.mock_amm_1_addr.clone()],
+            remove: vec![],
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    let res = wasm.execute(&env.aggregator_addr, &register, &[], &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("requires a fee of exactly 1000000usdt"));
+
+    let res = wasm.execute(&env.aggregator_addr, &register, &[fee.clone()], &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("is not open to registration"));
+    set_terms(env.mock_swap_code_id);
+
+    // AMM2 reports its reserves but only quotes INJ -> USDT.
+    wasm.execute(
+        &env.mock_amm_2_addr,
+        &MockExecuteMsg::SetLiquidityPool {
+            pool: LiquidityPool {
+                // Never paid out, as nothing provides liquidity here.
+                lp_token: env.admin.address(),
+                reserves: vec![
+                    MockAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        amount: Uint128::new(1_000_000_000_000_000_000_000),
+                    },
+                    MockAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        amount: Uint128::new(10_000_000_000),
+                    },
+                ],
+                total_share: Uint128::new(1_000_000_000),
+            },
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    let res = wasm.execute(&env.aggregator_addr, &register, &[fee.clone()], &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("failed its registration check"));
+
+    wasm.execute(
+        &env.mock_amm_2_addr,
+        &MockExecuteMsg::AddPair {
+            config: SwapConfig {
+                input_asset_info: AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                output_asset_info: AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                rate: "0.1".to_string(),
+                protocol_type: ProtocolType::Amm,
+                input_decimals: 6,
+                output_decimals: 18,
+                commission_bps: 0,
+                virtual_reserve: None,
+                spread_model: None,
+            },
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    wasm.execute(&env.aggregator_addr, &register, &[fee.clone()], &env.user)
+        .unwrap();
+    assert!(allowlist().contains(&Addr::unchecked(&env.mock_amm_2_addr)));
+    let collector_balance = bank
+        .query_balance(&QueryBalanceRequest {
+            address: env.fee_collector.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+    assert_eq!(collector_balance.amount, fee.amount.to_string());
+
+    let res = wasm.execute(&env.aggregator_addr, &register, &[fee], &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("already on the allowlist"));
+
+    let revoke = |pool: &String| ExecuteMsg::RevokePoolRegistration {
+        pool_address: pool.clone(),
+    };
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &revoke(&env.mock_amm_2_addr),
+        &[],
+        &env.user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &revoke(&env.mock_amm_1_addr),
+        &[],
+        &env.admin,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("was not registered by its operator"));
+    wasm.execute(
+        &env.aggregator_addr,
+        &revoke(&env.mock_amm_2_addr),
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    assert_eq!(allowlist(), vec![Addr::unchecked(&env.mock_amm_1_addr)]);
+}
+
+#[test]
+fn test_bulk_fee_updates() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let fee_for = |pool: &String| -> Option<Decimal> {
+        wasm.query::<_, FeeResponse>(
+            &env.aggregator_addr,
+            &QueryMsg::FeeForPool {
+                pool_address: pool.clone(),
+            },
+        )
+        .unwrap()
+        .fee
+    };
+    let set_fees = |all_or_nothing: bool| ExecuteMsg::SetFees {
+        entries: vec![
+            FeeInfo {
+                pool_address: env.mock_amm_1_addr.clone(),
+                fee_percent: Decimal::permille(3),
+            },
+            FeeInfo {
+                pool_address: env.mock_amm_2_addr.clone(),
+                fee_percent: Decimal::one(),
+            },
+            FeeInfo {
+                pool_address: "not-an-address".to_string(),
+                fee_percent: Decimal::permille(3),
+            },
+        ],
+        all_or_nothing,
+    };
+
+    let res = wasm.execute(&env.aggregator_addr, &set_fees(true), &[], &env.admin);
+    assert!(res.unwrap_err().to_string().contains("Fee percentage"));
+    assert_eq!(fee_for(&env.mock_amm_1_addr), None);
+
+    // Without all-or-nothing, the valid entry is applied and the others reported.
+    let res = wasm
+        .execute(&env.aggregator_addr, &set_fees(false), &[], &env.admin)
+        .unwrap();
+    let attrs: Vec<_> = res
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .collect();
+    assert_eq!(attrs.iter().filter(|a| a.key == "skipped").count(), 2);
+    assert!(attrs.iter().any(|a| a.key == "set" && a.value == "1"));
+    assert_eq!(fee_for(&env.mock_amm_1_addr), Some(Decimal::permille(3)));
+    assert_eq!(fee_for(&env.mock_amm_2_addr), None);
+
+    let remove_fees = |all_or_nothing: bool| ExecuteMsg::RemoveFees {
+        pool_addresses: vec![env.mock_amm_1_addr.clone(), "not-an-address".to_string()],
+        all_or_nothing,
+    };
+    let res = wasm.execute(&env.aggregator_addr, &remove_fees(true), &[], &env.admin);
+    assert!(res.is_err());
+    assert_eq!(fee_for(&env.mock_amm_1_addr), Some(Decimal::permille(3)));
+    let res = wasm.execute(&env.aggregator_addr, &remove_fees(false), &[], &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    wasm.execute(&env.aggregator_addr, &remove_fees(false), &[], &env.admin)
+        .unwrap();
+    assert_eq!(fee_for(&env.mock_amm_1_addr), None);
+}
+
+#[test]
+fn test_invariant_checks_verify_balances_after_route() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::UpdateParameters {
+            parameters: Parameters {
+                invariant_checks: true,
+                ..Parameters::default()
+            },
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![Operation::AmmSwap(AmmSwapOp {
+                            pool_address: env.mock_amm_1_addr.clone(),
+                            ask_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "usdt".to_string(),
+                            },
+                            offer_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "inj".to_string(),
+                            },
+                            min_out: None,
+                        })],
+                    }],
+                }],
+                minimum_receive: None,
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    let attrs: Vec<_> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .collect();
+    let find = |key: &str| {
+        attrs
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.as_str())
+    };
+    assert_eq!(find("final_received"), Some("990000000"));
+    assert!(attrs
+        .iter()
+        .any(|a| a.key == "action" && a.value == "invariants_verified"));
+    assert_eq!(find("assets_checked"), Some("2"));
+
+    // The check is an internal self-call.
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::VerifyInvariants {
+            minimum_balances: vec![],
+        },
+        &[],
+        &env.user,
+    );
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+}
+
+#[test]
+fn test_pool_stats_track_volume_and_fees() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // Two routes of 100 INJ -> AMM1 @ 10.0 = 1000 USDT, 10 USDT fee each.
+    let route = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    for _ in 0..2 {
+        wasm.execute(
+            &env.aggregator_addr,
+            &route,
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+    }
+
+    let stats: PoolStatsResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::PoolStats {
+                pool_address: env.mock_amm_1_addr.clone(),
+            },
+        )
+        .unwrap();
+    assert_eq!(stats.stats.swaps, 2);
+    assert_eq!(
+        stats.stats.volume_in,
+        vec![amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            amount: Uint128::new(200_000_000_000_000_000_000),
+        }]
+    );
+    assert_eq!(
+        stats.stats.fees_collected,
+        vec![amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            amount: Uint128::new(20_000_000),
+        }]
+    );
+
+    let all: AllPoolStatsResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::AllPoolStats {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(all.pools, vec![stats]);
+}
+
+#[test]
+fn test_route_tag_is_echoed_on_completion() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let route = |tag: &str| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: Some(tag.to_string()),
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+
+    let response = wasm
+        .execute(&env.aggregator_addr, &route("wallet-x"), &funds, &env.user)
+        .unwrap();
+    let tag = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "tag")
+        .map(|a| a.value.clone());
+    assert_eq!(tag, Some("wallet-x".to_string()));
+
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &route(&"x".repeat(65)),
+        &funds,
+        &env.user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Tag exceeds 64 characters"));
+}
+
+#[test]
+fn test_sub_aggregator_operation_forwards_to_nested_aggregator() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let code_id = wasm
+        .store_code(&get_wasm_byte_code("dex_aggregator.wasm"), None, &env.admin)
+        .unwrap()
+        .data
+        .code_id;
+    let config: AggregatorConfig = wasm
+        .query(&env.aggregator_addr, &QueryMsg::Config {})
+        .unwrap();
+    let sub_aggregator_addr = wasm
+        .instantiate(
+            code_id,
+            &InstantiateMsg {
+                admin: env.admin.address(),
+                cw20_adapter_address: config.cw20_adapter_address.to_string(),
+                fee_collectors: vec![FeeCollectorInfo {
+                    address: env.fee_collector.address(),
+                    weight: Decimal::one(),
+                }],
+                initial_fees: None,
+                allowed_pools: None,
+                parameters: None,
+            },
+            None,
+            Some("sub-aggregator"),
+            &[],
+            &env.admin,
+        )
+        .unwrap()
+        .data
+        .address;
+
+    // 100 INJ is forwarded to the sub-aggregator, which swaps it on AMM1 @ 10.0 and pays the
+    // 1000 USDT back to the outer aggregator.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: vec![Stage {
+                    splits: vec![Split {
+                        percent: 100,
+                        path: vec![Operation::SubAggregator(SubAggregatorOp {
+                            contract: sub_aggregator_addr.clone(),
+                            stages: vec![Stage {
+                                splits: vec![Split {
+                                    percent: 100,
+                                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                                        pool_address: env.mock_amm_1_addr.clone(),
+                                        ask_asset_info: amm::AssetInfo::NativeToken {
+                                            denom: "usdt".to_string(),
+                                        },
+                                        offer_asset_info: amm::AssetInfo::NativeToken {
+                                            denom: "inj".to_string(),
+                                        },
+                                        min_out: None,
+                                    })],
+                                }],
+                            }],
+                            min_out: None,
+                        })],
+                    }],
+                }],
+                minimum_receive: native_minimum("usdt", 1000000000),
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            &env.user,
+        )
+        .unwrap();
+
+    let final_received_by = |contract: &str| {
+        response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .filter(|e| {
+                e.attributes
+                    .iter()
+                    .any(|a| a.key == "_contract_address" && a.value == contract)
+            })
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == "final_received")
+            .map(|a| a.value.clone())
+    };
+    assert_eq!(
+        final_received_by(&sub_aggregator_addr),
+        Some("1000000000".to_string())
+    );
+    assert_eq!(
+        final_received_by(&env.aggregator_addr),
+        Some("1000000000".to_string())
+    );
+}
+
+#[test]
+fn test_minimum_receive_in_wrong_asset_is_rejected() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        }],
+    }];
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+
+    // The route pays out USDT, so a minimum declared in INJ is refused before any swap runs,
+    // even though the amount alone would be satisfied.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages: stages.clone(),
+                minimum_receive: native_minimum("inj", 1),
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Minimum receive is declared in inj but the route pays out usdt"));
+
+    // Declared in the payout asset, the same route succeeds.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ExecuteRoute {
+            stages,
+            minimum_receive: native_minimum("usdt", 1_000_000_000),
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook: None,
+            final_assets: None,
+            post_action: None,
+            max_fee_bps: None,
+            minimum_receive_usd: None,
+            integrator: None,
+            recipients: None,
+        },
+        &funds,
+        &env.user,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_stage_input_that_cannot_be_produced_is_rejected() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let inj_to_usdt = Operation::AmmSwap(AmmSwapOp {
+        pool_address: env.mock_amm_1_addr.clone(),
+        offer_asset_info: amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        },
+        ask_asset_info: amm::AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        },
+        min_out: None,
+    });
+    // Stage 1 produces USDT, but the second stage asks for ATOM.
+    let atom_to_inj = Operation::AmmSwap(AmmSwapOp {
+        pool_address: env.mock_amm_2_addr.clone(),
+        offer_asset_info: amm::AssetInfo::NativeToken {
+            denom: "atom".to_string(),
+        },
+        ask_asset_info: amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        },
+        min_out: None,
+    });
+    let route = |stages: Vec<Vec<Operation>>| ExecuteMsg::ExecuteRoute {
+        stages: stages
+            .into_iter()
+            .map(|path| Stage {
+                splits: vec![Split { percent: 100, path }],
+            })
+            .collect(),
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![vec![inj_to_usdt.clone()], vec![atom_to_inj.clone()]]),
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Stage 1 requires an input asset the route cannot produce at that point"));
+
+    // The first stage is checked against the offer itself.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![vec![atom_to_inj]]),
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Stage 0 requires an input asset the route cannot produce at that point"));
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &route(vec![vec![inj_to_usdt]]),
+        &funds,
+        &env.user,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_pool_health_check_policies() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let split = |pool: &str| Split {
+        percent: 50,
+        path: vec![Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            ask_asset_info: amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            min_out: None,
+        })],
+    };
+    let route = |pool_health_check: Option<PoolHealthPolicy>| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![split(&env.mock_amm_1_addr), split(&env.mock_amm_2_addr)],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+    // Executes the route and returns the aggregator's attributes.
+    let run = |policy: PoolHealthPolicy| -> Vec<(String, String)> {
+        wasm.execute(
+            &env.aggregator_addr,
+            &route(Some(policy)),
+            &funds,
+            &env.user,
+        )
+        .unwrap()
+        .events
+        .into_iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes)
+        .map(|a| (a.key, a.value))
+        .collect()
+    };
+    let attribute = |attributes: &[(String, String)], key: &str| {
+        attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+
+    // AMM2 reverts on both swaps and quotes.
+    wasm.execute(
+        &env.mock_amm_2_addr,
+        &MockExecuteMsg::SetFailureMode {
+            failure_mode: Some(FailureMode::AlwaysFail),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // Without a check the route fails inside the swap submessage.
+    let err = wasm
+        .execute(&env.aggregator_addr, &route(None), &funds, &env.user)
+        .unwrap_err();
+    assert!(err.to_string().contains("always_fail"));
+
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(Some(PoolHealthPolicy::Abort)),
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains(&format!(
+        "Pool {} in stage 0 failed its health check",
+        env.mock_amm_2_addr
+    )));
+
+    // Skip: 50 INJ -> AMM1 @ 10.0 = 500 USDT, and the other 50 INJ is refunded.
+    let attributes = run(PoolHealthPolicy::Skip);
+    assert_eq!(
+        attribute(&attributes, "unhealthy_pool"),
+        Some(env.mock_amm_2_addr.clone())
+    );
+    assert_eq!(
+        attribute(&attributes, "final_received"),
+        Some("500000000".to_string())
+    );
+    assert_eq!(
+        attribute(&attributes, "refunded_amount"),
+        Some("50000000000000000000".to_string())
+    );
+
+    // Fallback: AMM1 takes the whole 100 INJ for 1,000 USDT.
+    let attributes = run(PoolHealthPolicy::Fallback);
+    assert_eq!(
+        attribute(&attributes, "unhealthy_pool"),
+        Some(env.mock_amm_2_addr.clone())
+    );
+    assert_eq!(
+        attribute(&attributes, "final_received"),
+        Some("1000000000".to_string())
+    );
+}
+
+#[test]
+fn test_pool_metadata_is_listed_in_all_pools() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let amm_metadata = PoolMetadata {
+        label: "INJ/USDT AMM".to_string(),
+        protocol: PoolProtocol::Amm,
+        token_decimals: vec![
+            TokenDecimals {
+                asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                decimals: 18,
+            },
+            TokenDecimals {
+                asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                decimals: 6,
+            },
+        ],
+        market_id: None,
+        router: None,
+    };
+    let ob_metadata = PoolMetadata {
+        label: "INJ/USDT spot".to_string(),
+        protocol: PoolProtocol::Orderbook,
+        token_decimals: vec![],
+        market_id: Some(
+            "0xa508cb32923323679f29a032c70342c147c17d0145625922b0ef22e955c844c0".to_string(),
+        ),
+        router: None,
+    };
+    let set = |pool: &str, metadata: Option<PoolMetadata>, sender: &SigningAccount| {
+        wasm.execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::SetPoolMetadata {
+                pool_address: pool.to_string(),
+                metadata,
+            },
+            &[],
+            sender,
+        )
+    };
+
+    let res = set(&env.mock_amm_1_addr, Some(amm_metadata.clone()), &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    // A market id only makes sense for an orderbook.
+    let res = set(
+        &env.mock_amm_1_addr,
+        Some(PoolMetadata {
+            market_id: ob_metadata.market_id.clone(),
+            ..amm_metadata.clone()
+        }),
+        &env.admin,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("market_id is only valid for orderbook pools"));
+
+    // Orderbooks are not traded through routers.
+    let res = set(
+        &env.mock_ob_inj_usdt_addr,
+        Some(PoolMetadata {
+            router: Some(env.mock_amm_2_addr.clone()),
+            ..ob_metadata.clone()
+        }),
+        &env.admin,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("router is only valid for AMM pools"));
+
+    // A token of more decimals than a Uint128 can sensibly carry.
+    let mut too_precise = amm_metadata.clone();
+    too_precise.token_decimals[0].decimals = 31;
+    let res = set(&env.mock_amm_1_addr, Some(too_precise), &env.admin);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Token inj has 31 decimals, above the supported maximum of 30"));
+
+    set(&env.mock_amm_1_addr, Some(amm_metadata.clone()), &env.admin).unwrap();
+    set(
+        &env.mock_ob_inj_usdt_addr,
+        Some(ob_metadata.clone()),
+        &env.admin,
+    )
+    .unwrap();
+
+    let all_pools = |start_after: Option<String>| -> AllPoolsResponse {
+        wasm.query(
+            &env.aggregator_addr,
+            &QueryMsg::AllPools {
+                start_after,
+                limit: None,
+            },
+        )
+        .unwrap()
+    };
+    let mut expected = vec![
+        (env.mock_amm_1_addr.clone(), amm_metadata),
+        (env.mock_ob_inj_usdt_addr.clone(), ob_metadata),
+    ];
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    let listed: Vec<_> = all_pools(None)
+        .pools
+        .into_iter()
+        .map(|pool| (pool.pool_address, pool.metadata))
+        .collect();
+    assert_eq!(listed, expected);
+    assert_eq!(all_pools(Some(expected[0].0.clone())).pools.len(), 1);
+
+    set(&env.mock_amm_1_addr, None, &env.admin).unwrap();
+    let remaining = all_pools(None).pools;
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].pool_address, env.mock_ob_inj_usdt_addr);
+}
+
+#[test]
+fn test_split_expectations_catch_a_single_deviating_split() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let split = |pool: &str| Split {
+        percent: 50,
+        path: vec![Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            ask_asset_info: amm::AssetInfo::NativeToken {
+                denom: "usdt".to_string(),
+            },
+            min_out: None,
+        })],
+    };
+    // 50 INJ -> AMM1 @ 10.0 = 500 USDT, 50 INJ -> AMM2 @ 20.0 = 1,000 USDT.
+    let route = |expected_outputs: Vec<Vec<u128>>| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![split(&env.mock_amm_1_addr), split(&env.mock_amm_2_addr)],
+        }],
+        minimum_receive: native_minimum("usdt", 1_400_000_000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: Some(SplitExpectations {
+            expected_outputs: expected_outputs
+                .into_iter()
+                .map(|outputs| outputs.into_iter().map(Uint128::new).collect())
+                .collect(),
+            tolerance: Decimal::percent(5),
+        }),
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![vec![500_000_000]]),
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Split expectations need one output per split"));
+
+    // AMM2 pays 1,000 against a quoted 1,100: over 5% short, although the route as a whole
+    // still clears its 1,400 minimum.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![vec![500_000_000, 1_100_000_000]]),
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains(
+        "Stage 0 split 1 paid out 1000000000, short of the expected 1100000000 beyond the tolerance"
+    ));
+
+    // Within 5% of 1,040 is fine.
+    wasm.execute(
+        &env.aggregator_addr,
+        &route(vec![vec![500_000_000, 1_040_000_000]]),
+        &funds,
+        &env.user,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_balance_checked_tokens_are_measured_from_balances() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let user = &setup.env.user;
+
+    let set = |token: &str, enabled: bool, sender: &SigningAccount| {
+        wasm.execute(
+            &setup.env.aggregator_addr,
+            &ExecuteMsg::SetBalanceCheckedToken {
+                token: token.to_string(),
+                enabled,
+            },
+            &[],
+            sender,
+        )
+    };
+    let res = set(&setup.shroom_cw20_addr, true, user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    set(&setup.shroom_cw20_addr, true, &setup.env.admin).unwrap();
+    set(&setup.sai_cw20_addr, true, &setup.env.admin).unwrap();
+
+    let checked: BalanceCheckedTokensResponse = wasm
+        .query(
+            &setup.env.aggregator_addr,
+            &QueryMsg::BalanceCheckedTokens {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    let mut expected = vec![setup.shroom_cw20_addr.clone(), setup.sai_cw20_addr.clone()];
+    expected.sort();
+    let listed: Vec<String> = checked.tokens.into_iter().map(String::from).collect();
+    assert_eq!(listed, expected);
+
+    // 10 INJ -> 500 native + 500 CW20 SHROOM, the native half converted through the adapter,
+    // then 1000 CW20 SHROOM -> 100 SAI. Without a transfer tax the measured amounts match the
+    // reported ones, so the route pays out exactly as it does unflagged.
+    let native_shroom_denom = format!("factory/{}/{}", setup.adapter_addr, setup.shroom_cw20_addr);
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![
+                    Split {
+                        percent: 50,
+                        path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                            swap_contract: setup.mock_inj_to_native_shroom_ob.clone(),
+                            offer_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "inj".to_string(),
+                            },
+                            ask_asset_info: amm::AssetInfo::NativeToken {
+                                denom: native_shroom_denom.clone(),
+                            },
+                            min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                            min_out: None,
+                        })],
+                    },
+                    Split {
+                        percent: 50,
+                        path: vec![Operation::AmmSwap(AmmSwapOp {
+                            pool_address: setup.mock_inj_to_cw20_shroom_amm.clone(),
+                            offer_asset_info: amm::AssetInfo::NativeToken {
+                                denom: "inj".to_string(),
+                            },
+                            ask_asset_info: amm::AssetInfo::Token {
+                                contract_addr: setup.shroom_cw20_addr.clone(),
+                            },
+                            min_out: None,
+                        })],
+                    },
+                ],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: setup.mock_cw20_shroom_to_cw20_sai_amm.clone(),
+                        offer_asset_info: amm::AssetInfo::Token {
+                            contract_addr: setup.shroom_cw20_addr.clone(),
+                        },
+                        ask_asset_info: amm::AssetInfo::Token {
+                            contract_addr: setup.sai_cw20_addr.clone(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            },
+        ],
+        minimum_receive: cw20_minimum(&setup.sai_cw20_addr, 97000000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    wasm.execute(
+        &setup.env.aggregator_addr,
+        &msg,
+        &[Coin::new(10_000_000_000_000_000_000u128, "inj")],
+        user,
+    )
+    .unwrap();
+
+    let balance: BalanceResponse = wasm
+        .query(
+            &setup.sai_cw20_addr,
+            &Cw20QueryMsg::Balance {
+                address: user.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance.balance, Uint128::new(100_000_000));
+}
+
+#[test]
+fn test_max_price_impact_rejects_routes_through_thin_pools() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let admin = &env.admin;
+    let user = &env.user;
+
+    let mock_swap_code_id = wasm
+        .store_code(get_wasm_byte_code("mock_swap.wasm"), None, admin)
+        .unwrap()
+        .data
+        .code_id;
+    // 1,000 INJ against 10,000 USDT: swapping 100 INJ moves the price by about 9%.
+    let thin_pool = wasm
+        .instantiate(
+            mock_swap_code_id,
+            &MockInstantiateMsg {
+                config: SwapConfig {
+                    input_asset_info: AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    output_asset_info: AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    rate: "10.0".to_string(),
+                    protocol_type: ProtocolType::Amm,
+                    input_decimals: 18,
+                    output_decimals: 6,
+                    commission_bps: 0,
+                    virtual_reserve: None,
+                    spread_model: Some(SpreadModel::ConstantProduct {
+                        offer_reserve: Uint128::new(1_000_000_000_000_000_000_000u128),
+                        ask_reserve: Uint128::new(10_000_000_000u128),
+                    }),
+                },
+            },
+            Some(&admin.address()),
+            Some("mock-cp-thin"),
+            &[],
+            admin,
+        )
+        .unwrap()
+        .data
+        .address;
+    bank.send(
+        MsgSend {
+            from_address: admin.address(),
+            to_address: thin_pool.clone(),
+            amount: vec![ProtoCoin {
+                denom: "usdt".to_string(),
+                amount: "100000000000".to_string(),
+            }],
+        },
+        admin,
+    )
+    .unwrap();
+
+    let route = |max_price_impact_bps: Option<u16>| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: thin_pool.clone(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")]; // 100 INJ
+
+    let err = wasm
+        .execute(&env.aggregator_addr, &route(Some(10_001)), &funds, user)
+        .unwrap_err();
+    assert!(err.to_string().contains("exceeds 10000 bps"));
+
+    // 909.09 USDT for 100 INJ against 0.9999 USDT for 0.1 INJ: about 908 bps.
+    let err = wasm
+        .execute(&env.aggregator_addr, &route(Some(500)), &funds, user)
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Simulated price impact of 908 bps exceeds the maximum of 500 bps"),
+        "unexpected error: {err}"
+    );
+
+    wasm.execute(&env.aggregator_addr, &route(Some(1_000)), &funds, user)
+        .unwrap();
+}
+
+#[test]
+fn test_adapter_registered_token_forms_are_one_token() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let admin = &setup.env.admin;
+    let user = &setup.env.user;
+
+    let native_shroom_denom = format!("factory/{}/{}", setup.adapter_addr, setup.shroom_cw20_addr);
+    let set_policy = |denoms: Vec<String>| {
+        wasm.execute(
+            &setup.env.aggregator_addr,
+            &ExecuteMsg::SetDenomPolicy {
+                mode: DenomPolicyMode::Allow,
+                denoms,
+            },
+            &[],
+            admin,
+        )
+        .unwrap();
+    };
+    // 10 INJ -> 1,000 native SHROOM, with the minimum declared in CW20 SHROOM.
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                    swap_contract: setup.mock_inj_to_native_shroom_ob.clone(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: native_shroom_denom.clone(),
+                    },
+                    min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: cw20_minimum(&setup.shroom_cw20_addr, 990_000_000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(10_000_000_000_000_000_000u128, "inj")];
+
+    set_policy(vec!["inj".to_string(), setup.sai_cw20_addr.clone()]);
+    let res = wasm.execute(&setup.env.aggregator_addr, &msg, &funds, user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains(&format!("Denom {native_shroom_denom} is not permitted")));
+
+    // Listing the registered CW20 also permits its factory denom.
+    set_policy(vec!["inj".to_string(), setup.shroom_cw20_addr.clone()]);
+    let response = wasm
+        .execute(&setup.env.aggregator_addr, &msg, &funds, user)
+        .unwrap();
+    let target = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "normalization_target")
+        .map(|a| a.value.clone());
+    assert_eq!(target.as_deref(), Some(setup.shroom_cw20_addr.as_str()));
+
+    // The native output was normalized into the CW20 form the minimum is declared in.
+    let balance: BalanceResponse = wasm
+        .query(
+            &setup.shroom_cw20_addr,
+            &Cw20QueryMsg::Balance {
+                address: user.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance.balance, Uint128::new(1_000_000_000));
+
+    // The forms are converted one to one, so decimals registered differently for them are
+    // rejected before anything is swapped.
+    wasm.execute(
+        &setup.env.aggregator_addr,
+        &ExecuteMsg::SetPoolMetadata {
+            pool_address: setup.mock_inj_to_native_shroom_ob.clone(),
+            metadata: Some(PoolMetadata {
+                label: "INJ/SHROOM spot".to_string(),
+                protocol: PoolProtocol::Orderbook,
+                token_decimals: vec![
+                    TokenDecimals {
+                        asset_info: amm::AssetInfo::NativeToken {
+                            denom: native_shroom_denom.clone(),
+                        },
+                        decimals: 18,
+                    },
+                    TokenDecimals {
+                        asset_info: amm::AssetInfo::Token {
+                            contract_addr: setup.shroom_cw20_addr.clone(),
+                        },
+                        decimals: 6,
+                    },
+                ],
+                market_id: None,
+                router: None,
+            }),
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+    let res = wasm.execute(&setup.env.aggregator_addr, &msg, &funds, user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("registered with 6 decimals where 18 were expected"));
+}
+
+#[test]
+fn test_rate_limits_cap_routes_per_sender() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let route = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(1_000_000_000_000_000_000u128, "inj")];
+
+    // Only the admin may set limits.
+    let limits = RateLimits {
+        max_routes_per_block: Some(1),
+        max_notional_per_hour: vec![amm::Asset {
+            info: amm::AssetInfo::NativeToken {
+                denom: "inj".to_string(),
+            },
+            amount: Uint128::new(1_500_000_000_000_000_000),
+        }],
+    };
+    let set_limits = ExecuteMsg::SetRateLimits {
+        limits: limits.clone(),
+    };
+    let res = wasm.execute(&env.aggregator_addr, &set_limits, &[], &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    wasm.execute(&env.aggregator_addr, &set_limits, &[], &env.admin)
+        .unwrap();
+    let stored: RateLimitsResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::RateLimits {})
+        .unwrap();
+    assert_eq!(stored.limits, Some(limits));
+
+    // The first route fits within the hourly notional; a second 1 INJ route would not.
+    wasm.execute(&env.aggregator_addr, &route, &funds, &env.user)
+        .unwrap();
+    let res = wasm.execute(&env.aggregator_addr, &route, &funds, &env.user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("would exceed the hourly limit"));
+
+    // Once the first route leaves the window, the sender may route again.
+    env.app.increase_time(3_600);
+    wasm.execute(&env.aggregator_addr, &route, &funds, &env.user)
+        .unwrap();
+
+    // Without limits the sender is unrestricted.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::RemoveRateLimits {},
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    let stored: RateLimitsResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::RateLimits {})
+        .unwrap();
+    assert!(stored.limits.is_none());
+    wasm.execute(&env.aggregator_addr, &route, &funds, &env.user)
+        .unwrap();
+}
+
+#[test]
+fn test_deadline_is_forwarded_to_amm_pools() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+
+    // An orderbook stage then an AMM stage, one stage per transaction, so the AMM swap only
+    // runs when the route is continued.
+    let route = |deadline: u64| ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                        swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
+                    })],
+                }],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: env.mock_amm_1_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            },
+        ],
+        minimum_receive: None,
+        max_stages_per_tx: Some(1),
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: Some(deadline),
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(1_000_000_000_000u128, "usdt")];
+
+    // The aggregator rejects a route whose deadline has already passed.
+    let now = env.app.get_block_time_seconds() as u64;
+    let res = wasm.execute(&env.aggregator_addr, &route(now - 1), &funds, user);
+    assert!(res.unwrap_err().to_string().contains("Route deadline"));
+
+    let response = wasm
+        .execute(&env.aggregator_addr, &route(now + 100), &funds, user)
+        .unwrap();
+    let route_id: u64 = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "route_id")
+        .unwrap()
+        .value
+        .parse()
+        .unwrap();
+
+    // By the time the route is continued the deadline has passed, and the pool rejects the swap.
+    env.app.increase_time(200);
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ContinueRoute { route_id },
+        &[],
+        user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Mock swap failure: deadline expired"));
+}
+
+#[test]
+fn test_receive_hook_delivers_output_to_a_contract() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let user = &setup.env.user;
+    let native_shroom_denom = format!("factory/{}/{}", setup.adapter_addr, setup.shroom_cw20_addr);
+
+    // The SHROOM -> SAI pool stands in for a vault: its `Receive` hook swaps whatever arrives
+    // and pays the SAI to the user.
+    let receive_hook = ReceiveHook {
+        contract: setup.mock_cw20_shroom_to_cw20_sai_amm.clone(),
+        msg: to_json_binary(&MockSwapHookMsg {
+            swap: MockSwapHookSwapField {
+                offer_asset: None,
+                belief_price: None,
+                max_spread: None,
+                to: Some(user.address()),
+                deadline: None,
+            },
+        })
+        .unwrap(),
+    };
+    let route =
+        |operation: Operation, receive_hook: Option<ReceiveHook>| ExecuteMsg::ExecuteRoute {
+            stages: vec![Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![operation],
+                }],
+            }],
+            minimum_receive: None,
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook,
+            final_assets: None,
+            post_action: None,
+            max_fee_bps: None,
+            minimum_receive_usd: None,
+            integrator: None,
+            recipients: None,
+        };
+    let to_cw20_shroom = Operation::AmmSwap(AmmSwapOp {
+        pool_address: setup.mock_inj_to_cw20_shroom_amm.clone(),
+        offer_asset_info: amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        },
+        ask_asset_info: amm::AssetInfo::Token {
+            contract_addr: setup.shroom_cw20_addr.clone(),
+        },
+        min_out: None,
+    });
+    let to_native_shroom = Operation::OrderbookSwap(OrderbookSwapOp {
+        swap_contract: setup.mock_inj_to_native_shroom_ob.clone(),
+        offer_asset_info: amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        },
+        ask_asset_info: amm::AssetInfo::NativeToken {
+            denom: native_shroom_denom,
+        },
+        min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+        min_out: None,
+    });
+    let funds = [Coin::new(5_000_000_000_000_000_000u128, "inj")];
+    let balance = |token: &str| {
+        let response: BalanceResponse = wasm
+            .query(
+                token,
+                &Cw20QueryMsg::Balance {
+                    address: user.address(),
+                },
+            )
+            .unwrap();
+        response.balance
+    };
+
+    // A plain native output has no `Receive` hook to call. The route is rejected before the
+    // pool is reached.
+    let to_usdt = Operation::OrderbookSwap(OrderbookSwapOp {
+        swap_contract: setup.mock_inj_to_native_shroom_ob.clone(),
+        offer_asset_info: amm::AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        },
+        ask_asset_info: amm::AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        },
+        min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+        min_out: None,
+    });
+    let res = wasm.execute(
+        &setup.env.aggregator_addr,
+        &route(to_usdt, Some(receive_hook.clone())),
+        &funds,
+        user,
+    );
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Receive hooks need a CW20 output"));
+
+    // A CW20 output is delivered with `Send`.
+    wasm.execute(
+        &setup.env.aggregator_addr,
+        &route(to_cw20_shroom, Some(receive_hook.clone())),
+        &funds,
+        user,
+    )
+    .unwrap();
+    let sai_after_send = balance(&setup.sai_cw20_addr);
+    assert!(!sai_after_send.is_zero());
+    assert!(balance(&setup.shroom_cw20_addr).is_zero());
+
+    // An output held in native form goes through the adapter's `RedeemAndSend`.
+    wasm.execute(
+        &setup.env.aggregator_addr,
+        &route(to_native_shroom, Some(receive_hook)),
+        &funds,
+        user,
+    )
+    .unwrap();
+    assert!(balance(&setup.sai_cw20_addr) > sai_after_send);
+    assert!(balance(&setup.shroom_cw20_addr).is_zero());
+}
+
+#[test]
+fn test_admin_actions_are_logged() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let set_fee = ExecuteMsg::SetFee {
+        pool_address: env.mock_amm_1_addr.clone(),
+        fee_percent: Decimal::permille(3),
+    };
+
+    // Rejected attempts are not logged.
+    let res = wasm.execute(&env.aggregator_addr, &set_fee, &[], &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    wasm.execute(&env.aggregator_addr, &set_fee, &[], &env.admin)
+        .unwrap();
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::RemoveFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // Routes are not admin actions.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ExecuteRoute {
+            stages: vec![Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: env.mock_amm_1_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            }],
+            minimum_receive: None,
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook: None,
+            final_assets: None,
+            post_action: None,
+            max_fee_bps: None,
+            minimum_receive_usd: None,
+            integrator: None,
+            recipients: None,
+        },
+        &[Coin::new(1_000_000_000_000_000_000u128, "inj")],
+        &env.user,
+    )
+    .unwrap();
+
+    let log: AdminLogResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::AdminLog {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    let actions: Vec<&str> = log
+        .entries
+        .iter()
+        .map(|entry| entry.action.as_str())
+        .collect();
+    assert_eq!(actions, vec!["set_fee", "remove_fee"]);
+    let first = &log.entries[0];
+    assert_eq!(first.sender.as_str(), env.admin.address());
+    assert!(first.height < log.entries[1].height);
+    assert!(first
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "pool_address" && attr.value == env.mock_amm_1_addr));
+
+    let rest: AdminLogResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::AdminLog {
+                start_after: Some(first.id),
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(rest.entries, log.entries[1..].to_vec());
+}
+
+#[test]
+fn test_market_registry_rounds_and_enforces_min_notional() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let market = |min_notional: u128| MarketInfo {
+        quote_denom: "usdt".to_string(),
+        min_quantity_tick_size: Uint128::new(1_000_000_000_000_000_000), // 1 INJ
+        min_price_tick_size: Decimal::permille(1),
+        min_notional: Uint128::new(min_notional),
+        taker_fee_rate: Decimal::permille(1),
+    };
+    let set_market = |market: Option<MarketInfo>, sender: &SigningAccount| {
+        wasm.execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::SetMarket {
+                swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+                market,
+            },
+            &[],
+            sender,
+        )
+    };
+
+    let res = set_market(Some(market(500_000_000)), &env.user);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+    let mut zero_tick = market(500_000_000);
+    zero_tick.min_quantity_tick_size = Uint128::zero();
+    let res = set_market(Some(zero_tick), &env.admin);
+    assert!(res.unwrap_err().to_string().contains("Invalid market"));
+    set_market(Some(market(500_000_000)), &env.admin).unwrap();
+
+    let markets: MarketsResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::Markets {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        markets.markets,
+        vec![MarketResponse {
+            swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+            market: market(500_000_000),
+        }]
+    );
+
+    // 10.5 INJ rounds down to the market's 1 INJ tick, not the route's finer one.
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                swap_contract: env.mock_ob_inj_usdt_addr.clone(),
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                min_out: None,
+            })],
+        }],
+    }];
+    let offer = Coin::new(10_500_000_000_000_000_000u128, "inj");
+    let route = ExecuteMsg::ExecuteRoute {
+        stages: stages.clone(),
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    // 10 INJ at 30 USDT is 300 USDT, below the 500 USDT minimum: both the quote and the route
+    // are rejected before reaching the exchange.
+    let res = wasm.query::<QueryMsg, SimulateRouteResponse>(
+        &env.aggregator_addr,
+        &QueryMsg::SimulateRoute {
+            stages: stages.clone(),
+            amount_in: offer.clone(),
+            lenient: false,
+        },
+    );
+    assert!(res.unwrap_err().to_string().contains("minimum notional"));
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &route,
+        std::slice::from_ref(&offer),
+        &env.user,
+    );
+    assert!(res.unwrap_err().to_string().contains("minimum notional"));
+
+    set_market(Some(market(100_000_000)), &env.admin).unwrap();
+    let simulation: SimulateRouteResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::SimulateRoute {
+                stages,
+                amount_in: offer.clone(),
+                lenient: false,
+            },
+        )
+        .unwrap();
+    assert_eq!(simulation.output_amount, Uint128::new(300_000_000));
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route,
+            std::slice::from_ref(&offer),
+            &env.user,
+        )
+        .unwrap();
+    let final_received = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .find(|a| a.key == "final_received")
+        .unwrap();
+    assert_eq!(final_received.value, "300000000");
+
+    set_market(None, &env.admin).unwrap();
+    let markets: MarketsResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::Markets {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(markets.markets.is_empty());
+}
+
+#[test]
+fn test_multi_output_route_enforces_each_declared_minimum() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let native = |denom: &str| amm::AssetInfo::NativeToken {
+        denom: denom.to_string(),
+    };
+
+    // Half the INJ becomes USDT, the other half goes through USDT and back into INJ, so the final
+    // stage holds both.
+    let inj_to_usdt = Operation::AmmSwap(AmmSwapOp {
+        pool_address: env.mock_amm_1_addr.clone(),
+        offer_asset_info: native("inj"),
+        ask_asset_info: native("usdt"),
+        min_out: None,
+    });
+    let usdt_to_inj = Operation::OrderbookSwap(OrderbookSwapOp {
+        swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+        ask_asset_info: native("inj"),
+        offer_asset_info: native("usdt"),
+        min_quantity_tick_size: Uint128::new(10000),
+        min_out: None,
+    });
+    let stages = vec![Stage {
+        splits: vec![
+            Split {
+                percent: 50,
+                path: vec![inj_to_usdt.clone()],
+            },
+            Split {
+                percent: 50,
+                path: vec![inj_to_usdt, usdt_to_inj],
+            },
+        ],
+    }];
+    let route = |minimum_receive: Option<amm::Asset>, final_assets: Vec<(&str, u128)>| {
+        ExecuteMsg::ExecuteRoute {
+            stages: stages.clone(),
+            minimum_receive,
+            max_stages_per_tx: None,
+            max_quote_age: None,
+            tag: None,
+            pool_health_check: None,
+            split_expectations: None,
+            max_price_impact_bps: None,
+            deadline: None,
+            receive_hook: None,
+            final_assets: Some(
+                final_assets
+                    .into_iter()
+                    .map(|(denom, amount)| amm::Asset {
+                        info: native(denom),
+                        amount: Uint128::new(amount),
+                    })
+                    .collect(),
+            ),
+        }
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+    let execute = |msg: &ExecuteMsg| wasm.execute(&env.aggregator_addr, msg, &funds, &env.user);
+
+    let res = execute(&route(
+        native_minimum("usdt", 1),
+        vec![("usdt", 0), ("inj", 0)],
+    ));
+    assert!(res.unwrap_err().to_string().contains("cannot be combined"));
+
+    // Every asset the final stage produces must be declared, and nothing else.
+    let res = execute(&route(None, vec![("usdt", 0)]));
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("do not match the route's final assets"));
+    let res = execute(&route(None, vec![("usdt", 0), ("inj", 0), ("atom", 0)]));
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("do not match the route's final assets"));
+
+    // 500 USDT and 50 INJ come out; each minimum applies to its own asset only.
+    let res = execute(&route(
+        None,
+        vec![("usdt", 500_000_000), ("inj", 50_000_000_000_000_000_001)],
+    ));
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("Minimum receive for inj not met"));
+
+    let bank = Bank::new(&env.app);
+    let usdt_balance = || {
+        bank.query_balance(&QueryBalanceRequest {
+            address: env.user.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap()
+        .amount
+        .parse::<u128>()
+        .unwrap()
+    };
+    let usdt_before = usdt_balance();
+    let response = execute(&route(
+        None,
+        vec![("usdt", 500_000_000), ("inj", 50_000_000_000_000_000_000)],
+    ))
+    .unwrap();
+    let received: Vec<(String, String)> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm")
+        .flat_map(|e| e.attributes.iter())
+        .filter(|a| a.key == "final_asset" || a.key == "final_received")
+        .map(|a| (a.key.clone(), a.value.clone()))
+        .collect();
+    assert_eq!(
+        received,
+        vec![
+            ("final_asset".to_string(), "usdt".to_string()),
+            ("final_received".to_string(), "500000000".to_string()),
+            ("final_asset".to_string(), "inj".to_string()),
+            (
+                "final_received".to_string(),
+                "50000000000000000000".to_string()
+            ),
+        ]
+    );
+    assert_eq!(usdt_balance() - usdt_before, 500_000_000);
+}
+
+#[test]
+fn test_abort_execution_refunds_a_checkpointed_route() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let user = &env.user;
+
+    // USDT -> INJ -> USDT, one stage per transaction, so the route rests holding INJ.
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                        swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
+                    })],
+                }],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: env.mock_amm_1_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            },
+        ],
+        minimum_receive: None,
+        max_stages_per_tx: Some(1),
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let start_route = || -> u64 {
+        let response = wasm
+            .execute(
+                &env.aggregator_addr,
+                &msg,
+                &[Coin::new(1_000_000_000u128, "usdt")],
+                user,
+            )
+            .unwrap();
+        response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == "route_id")
+            .unwrap()
+            .value
+            .parse()
+            .unwrap()
+    };
+    let abort = |route_id: u64, sender: &SigningAccount| {
+        wasm.execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::AbortExecution { route_id },
+            &[],
+            sender,
+        )
+    };
+    let aggregator_inj = || {
+        bank.query_balance(&QueryBalanceRequest {
+            address: env.aggregator_addr.clone(),
+            denom: "inj".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap()
+        .amount
+    };
+
+    // The sender has to wait out the TTL; anyone else is turned away.
+    let route_id = start_route();
+    let res = abort(route_id, user);
+    assert!(res
+        .unwrap_err()
+        .to_string()
+        .contains("cannot be aborted by its sender before"));
+    let res = abort(route_id, &env.fee_collector);
+    assert!(res.unwrap_err().to_string().contains("Unauthorized"));
+
+    env.app.increase_time(86_400);
+    let response = abort(route_id, user).unwrap();
+    let aborted = response
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-execution_aborted")
+        .expect("missing execution_aborted event");
+    let refund: Vec<(String, String)> = aborted
+        .attributes
+        .iter()
+        .filter(|a| a.key.starts_with("refunded_"))
+        .map(|a| (a.key.clone(), a.value.clone()))
+        .collect();
+    // 1,000 USDT at 0.1 INJ per USDT.
+    assert_eq!(
+        refund,
+        vec![
+            ("refunded_asset".to_string(), "inj".to_string()),
+            (
+                "refunded_amount".to_string(),
+                "100000000000000000000".to_string()
+            ),
+        ]
+    );
+    assert_eq!(aggregator_inj(), "0");
+    let res = wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::ContinueRoute { route_id },
+        &[],
+        user,
+    );
+    assert!(res.unwrap_err().to_string().contains("No in-flight route"));
+
+    // The admin may abort straight away.
+    let route_id = start_route();
+    abort(route_id, &env.admin).unwrap();
+    assert_eq!(aggregator_inj(), "0");
+    let res = abort(route_id, &env.admin);
+    assert!(res.unwrap_err().to_string().contains("No in-flight route"));
+}
+
+#[test]
+fn test_prune_history_refunds_idle_routes() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+
+    // USDT -> INJ -> USDT, one stage per transaction, so the route rests holding INJ.
+    let usdt = amm::AssetInfo::NativeToken {
+        denom: "usdt".to_string(),
+    };
+    let inj = amm::AssetInfo::NativeToken {
+        denom: "inj".to_string(),
+    };
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                        swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                        ask_asset_info: inj.clone(),
+                        offer_asset_info: usdt.clone(),
+                        min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
+                    })],
+                }],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: env.mock_amm_1_addr.clone(),
+                        ask_asset_info: usdt,
+                        offer_asset_info: inj,
+                        min_out: None,
+                    })],
+                }],
+            },
+        ],
+        minimum_receive: None,
+        max_stages_per_tx: Some(1),
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    wasm.execute(
+        &env.aggregator_addr,
+        &msg,
+        &[Coin::new(1_000_000_000u128, "usdt")],
+        user,
+    )
+    .unwrap();
+
+    let prune = || {
+        let response = wasm
+            .execute(
+                &env.aggregator_addr,
+                &ExecuteMsg::PruneHistory {
+                    older_than_blocks: 0,
+                    limit: Some(10),
+                },
+                &[],
+                &env.fee_collector,
+            )
+            .unwrap();
+        let refunded = response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == "routes_refunded")
+            .map(|a| a.value.clone());
+        (response, refunded)
+    };
+
+    // A route resting within its TTL is left for its sender to continue.
+    let (_, refunded) = prune();
+    assert_eq!(refunded.as_deref(), Some("0"));
+
+    env.app.increase_time(86_400);
+    let (response, refunded) = prune();
+    assert_eq!(refunded.as_deref(), Some("1"));
+    let aborted = response
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-execution_aborted")
+        .expect("missing execution_aborted event");
+    // 1,000 USDT at 0.1 INJ per USDT, refunded to the route's sender.
+    assert!(aborted
+        .attributes
+        .iter()
+        .any(|a| a.key == "sender" && a.value == user.address()));
+    assert!(aborted
+        .attributes
+        .iter()
+        .any(|a| a.key == "refunded_amount" && a.value == "100000000000000000000"));
+}
+
+#[test]
+fn test_route_result_is_returned_as_response_data() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+    let usdt = amm::AssetInfo::NativeToken {
+        denom: "usdt".to_string(),
+    };
+
+    // USDT -> INJ -> USDT, which returns the input at the mock rates.
+    let route = |max_stages_per_tx: Option<u64>| ExecuteMsg::ExecuteRoute {
+        stages: vec![
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                        swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                        ask_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        offer_asset_info: usdt.clone(),
+                        min_quantity_tick_size: Uint128::new(10000),
+                        min_out: None,
+                    })],
+                }],
+            },
+            Stage {
+                splits: vec![Split {
+                    percent: 100,
+                    path: vec![Operation::AmmSwap(AmmSwapOp {
+                        pool_address: env.mock_amm_1_addr.clone(),
+                        ask_asset_info: usdt.clone(),
+                        offer_asset_info: amm::AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        min_out: None,
+                    })],
+                }],
+            },
+        ],
+        minimum_receive: None,
+        max_stages_per_tx,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(1_000_000_000u128, "usdt")];
+    let completed = |route_id: u64| RouteResult {
+        route_id,
+        output: Some(amm::Asset {
+            info: usdt.clone(),
+            amount: Uint128::new(1_000_000_000),
+        }),
+        additional_outputs: vec![],
+    };
+
+    // A route completing in one transaction returns its output.
+    let response = wasm
+        .execute(&env.aggregator_addr, &route(None), &funds, user)
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(result, completed(result.route_id));
+
+    // A checkpointed route returns only its id, and the continuation the output.
+    let response = wasm
+        .execute(&env.aggregator_addr, &route(Some(1)), &funds, user)
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(result.output, None);
+    let route_id = result.route_id;
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ContinueRoute { route_id },
+            &[],
+            user,
+        )
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(result, completed(route_id));
+}
+
+#[test]
+fn test_zap_provides_liquidity_and_pays_out_lp_tokens() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+
+    // mock_amm_1 doubles as an INJ/USDT pair holding 1000 INJ and 10000 USDT, with 1e9 LP
+    // tokens outstanding, and pays LP tokens out of a balance funded up front.
+    let cw20_code_id = wasm
+        .store_code(get_wasm_byte_code("cw20_base.wasm"), None, &env.admin)
+        .unwrap()
+        .data
+        .code_id;
+    let lp_token_addr = wasm
+        .instantiate(
+            cw20_code_id,
+            &Cw20InstantiateMsg {
+                name: "INJ-USDT LP".to_string(),
+                symbol: "INJUSDTLP".to_string(),
+                decimals: 6,
+                initial_balances: vec![cw20::Cw20Coin {
+                    address: env.mock_amm_1_addr.clone(),
+                    amount: Uint128::new(1_000_000_000_000),
+                }],
+                mint: None,
+                marketing: None,
+            },
+            Some(&env.admin.address()),
+            Some("inj-usdt-lp"),
+            &[],
+            &env.admin,
+        )
+        .unwrap()
+        .data
+        .address;
+    wasm.execute(
+        &env.mock_amm_1_addr,
+        &MockExecuteMsg::SetLiquidityPool {
+            pool: LiquidityPool {
+                lp_token: lp_token_addr.clone(),
+                reserves: vec![
+                    MockAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        amount: Uint128::new(1_000_000_000_000_000_000_000),
+                    },
+                    MockAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        amount: Uint128::new(10_000_000_000),
+                    },
+                ],
+                total_share: Uint128::new(1_000_000_000),
+            },
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    let lp_token = amm::AssetInfo::Token {
+        contract_addr: lp_token_addr.clone(),
+    };
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::ProvideLiquidity(ProvideLiquidityOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                pair_type: PairType::Xyk {},
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                other_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                lp_token: lp_token.clone(),
+                min_out: None,
+            })],
+        }],
+    }];
+
+    // With no commission, sqrt(1000 * 1100) - 1000 = 48.8088... INJ is swapped for 488.088481
+    // USDT, leaving 1048.8088... INJ and 9511.911519 USDT in the pool. The retained 51.1911...
+    // INJ is the scarcer side: it mints 51.1911 / 1048.8088 of the outstanding LP tokens and
+    // takes 464.265445 USDT, and the other 23.823036 USDT is refunded.
+    let expected_lp = Uint128::new(48_808_848);
+    let simulation: SimulateRouteResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::SimulateRoute {
+                stages: stages.clone(),
+                amount_in: Coin::new(100_000_000_000_000_000_000u128, "inj"),
+                lenient: false,
+            },
+        )
+        .unwrap();
+    assert_eq!(simulation.output_amount, expected_lp);
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoute {
+                stages,
+                minimum_receive: Some(amm::Asset {
+                    info: lp_token.clone(),
+                    amount: expected_lp,
+                }),
+                max_stages_per_tx: None,
+                max_quote_age: None,
+                tag: None,
+                pool_health_check: None,
+                split_expectations: None,
+                max_price_impact_bps: None,
+                deadline: None,
+                receive_hook: None,
+                final_assets: None,
+                post_action: None,
+                max_fee_bps: None,
+                minimum_receive_usd: None,
+                integrator: None,
+                recipients: None,
+            },
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            user,
+        )
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output,
+        Some(amm::Asset {
+            info: lp_token,
+            amount: expected_lp,
+        })
+    );
+
+    let lp_balance: BalanceResponse = wasm
+        .query(
+            &lp_token_addr,
+            &Cw20QueryMsg::Balance {
+                address: user.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(lp_balance.balance, expected_lp);
+
+    let usdt_balance = Bank::new(&env.app)
+        .query_balance(&QueryBalanceRequest {
+            address: user.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        usdt_balance.balance.unwrap().amount,
+        (1_000_000_000_000u128 + 23_823_036).to_string()
+    );
+}
+
+#[test]
+fn test_lp_tokens_are_withdrawn_and_routed_into_one_asset() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+
+    // mock_amm_1 doubles as an INJ/USDT pair holding 1000 INJ and 10000 USDT for 1e9 LP tokens,
+    // a tenth of which the user holds.
+    let cw20_code_id = wasm
+        .store_code(get_wasm_byte_code("cw20_base.wasm"), None, &env.admin)
+        .unwrap()
+        .data
+        .code_id;
+    let lp_token_addr = wasm
+        .instantiate(
+            cw20_code_id,
+            &Cw20InstantiateMsg {
+                name: "INJ-USDT LP".to_string(),
+                symbol: "INJUSDTLP".to_string(),
+                decimals: 6,
+                initial_balances: vec![cw20::Cw20Coin {
+                    address: user.address(),
+                    amount: Uint128::new(100_000_000),
+                }],
+                mint: None,
+                marketing: None,
+            },
+            Some(&env.admin.address()),
+            Some("inj-usdt-lp"),
+            &[],
+            &env.admin,
+        )
+        .unwrap()
+        .data
+        .address;
+    wasm.execute(
+        &env.mock_amm_1_addr,
+        &MockExecuteMsg::SetLiquidityPool {
+            pool: LiquidityPool {
+                lp_token: lp_token_addr.clone(),
+                reserves: vec![
+                    MockAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "inj".to_string(),
+                        },
+                        amount: Uint128::new(1_000_000_000_000_000_000_000),
+                    },
+                    MockAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "usdt".to_string(),
+                        },
+                        amount: Uint128::new(10_000_000_000),
+                    },
+                ],
+                total_share: Uint128::new(1_000_000_000),
+            },
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    let usdt = amm::AssetInfo::NativeToken {
+        denom: "usdt".to_string(),
+    };
+    let withdraw_and_route = |stages: Vec<Stage>, minimum: u128| Cw20HookMsg::WithdrawAndRoute {
+        pool_address: env.mock_amm_1_addr.clone(),
+        stages,
+        minimum_receive: native_minimum("usdt", minimum),
+        max_stages_per_tx: None,
+        tag: None,
+        deadline: None,
+    };
+    let send = |hook_msg: &Cw20HookMsg| {
+        wasm.execute(
+            &lp_token_addr,
+            &cw20::Cw20ExecuteMsg::Send {
+                contract: env.aggregator_addr.clone(),
+                amount: Uint128::new(100_000_000),
+                msg: to_json_binary(hook_msg).unwrap(),
+            },
+            &[],
+            user,
+        )
+    };
+    let inj_to_usdt = Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: usdt.clone(),
+                min_out: None,
+            })],
+        }],
+    };
+
+    // Each withdrawn asset a first-stage split takes must be allocated in full.
+    let mut partial = inj_to_usdt.clone();
+    partial.splits[0].percent = 60;
+    let err = send(&withdraw_and_route(vec![partial], 0)).unwrap_err();
+    assert!(
+        err.to_string().contains("stage 0 sums to 60"),
+        "unexpected error: {err}"
+    );
+
+    // 100 INJ and 1000 USDT are withdrawn. The INJ is swapped for another 1000 USDT and the
+    // withdrawn USDT joins it.
+    let response = send(&withdraw_and_route(vec![inj_to_usdt], 2_000_000_000)).unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output,
+        Some(amm::Asset {
+            info: usdt,
+            amount: Uint128::new(2_000_000_000),
+        })
+    );
+
+    let lp_balance: BalanceResponse = wasm
+        .query(
+            &lp_token_addr,
+            &Cw20QueryMsg::Balance {
+                address: user.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(lp_balance.balance, Uint128::zero());
+}
+
+#[test]
+fn test_post_action_delegates_inj_output_for_the_sender() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+    let validator = env.app.get_first_validator_address().unwrap();
+
+    let usdt_to_inj = Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                swap_contract: env.mock_ob_usdt_inj_addr.clone(),
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                min_quantity_tick_size: Uint128::new(10000),
+                min_out: None,
+            })],
+        }],
+    };
+    let route = |stages: Vec<Stage>| ExecuteMsg::ExecuteRoute {
+        stages,
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: Some(PostAction::Delegate {
+            validator: validator.clone(),
+        }),
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    // Only an output in the bond denom can be delegated.
+    let inj_to_usdt = Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        }],
+    };
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![inj_to_usdt]),
+            &[Coin::new(100_000_000_000_000_000_000u128, "inj")],
+            user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Delegating needs the route to pay out the bond denom inj"),
+        "unexpected error: {err}"
+    );
+
+    // The delegation runs as the sender, so the route is rejected until they authorize the
+    // aggregator.
+    let usdt_in = Coin::new(1_000_000_000u128, "usdt");
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![usdt_to_inj.clone()]),
+            &[usdt_in.clone()],
+            user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains(
+            "has not granted the aggregator an authz authorization for \
+             /cosmos.staking.v1beta1.MsgDelegate"
+        ),
+        "unexpected error: {err}"
+    );
+
+    env.app
+        .execute::<MsgGrant, MsgGrantResponse>(
+            MsgGrant {
+                granter: user.address(),
+                grantee: env.aggregator_addr.clone(),
+                grant: Some(Grant {
+                    authorization: Some(Any {
+                        type_url: GenericAuthorization::TYPE_URL.to_string(),
+                        value: GenericAuthorization {
+                            msg: "/cosmos.staking.v1beta1.MsgDelegate".to_string(),
+                        }
+                        .to_proto_bytes(),
+                    }),
+                    expiration: None,
+                }),
+            },
+            MsgGrant::TYPE_URL,
+            user,
+        )
+        .unwrap();
+
+    // 1000 USDT buys 100 INJ, which ends up staked with the validator by the user.
+    wasm.execute(
+        &env.aggregator_addr,
+        &route(vec![usdt_to_inj]),
+        &[usdt_in],
+        user,
+    )
+    .unwrap();
+
+    let delegation: QueryDelegationResponse = env
+        .app
+        .query(
+            "/cosmos.staking.v1beta1.Query/Delegation",
+            &QueryDelegationRequest {
+                delegator_addr: user.address(),
+                validator_addr: validator,
+            },
+        )
+        .unwrap();
+    let balance = delegation
+        .delegation_response
+        .and_then(|response| response.balance)
+        .unwrap();
+    assert_eq!(balance.denom, "inj");
+    assert_eq!(balance.amount, "100000000000000000000");
+}
+
+#[test]
+fn test_route_at_quote_reverts_when_the_fresh_quote_is_worse() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+
+    // 100 INJ swaps for 1000 USDT on mock_amm_1.
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        }],
+    }];
+    let at_quote = |quoted_out: u128| ExecuteMsg::ExecuteRouteAtQuote {
+        stages: stages.clone(),
+        quoted_out: Uint128::new(quoted_out),
+        max_deviation_bps: 500,
+    };
+    let funds = [Coin::new(100_000_000_000_000_000_000u128, "inj")];
+
+    // A quote of 1100 USDT tolerates no less than 1045.
+    let err = wasm
+        .execute(&env.aggregator_addr, &at_quote(1_100_000_000), &funds, user)
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Fresh quote of 1000000000 is below the quoted 1100000000"),
+        "unexpected error: {err}"
+    );
+
+    // A quote of 1020 USDT tolerates down to 969.
+    let response = wasm
+        .execute(&env.aggregator_addr, &at_quote(1_020_000_000), &funds, user)
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output.map(|output| output.amount),
+        Some(Uint128::new(1_000_000_000))
+    );
+
+    // The fresh quote is net of the aggregator fee: with 1% charged on the pool, a quote of 1050
+    // USDT, tolerating down to 997, is short of the 990 the route would pay out.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: env.mock_amm_1_addr.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    let err = wasm
+        .execute(&env.aggregator_addr, &at_quote(1_050_000_000), &funds, user)
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Fresh quote of 990000000 is below the quoted 1050000000"),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn test_relayer_runs_a_signed_route_from_the_owners_deposit() {
+    use k256::ecdsa::{signature::hazmat::PrehashSigner, Signature, SigningKey};
+
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let owner = &env.user;
+    let relayer = env
+        .app
+        .init_account(&[Coin::new(1_000_000_000_000_000_000_000u128, "inj")])
+        .unwrap();
+
+    let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+    let pubkey = signing_key.verifying_key().to_encoded_point(true);
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetRelayKey {
+            pubkey: Some(pubkey.as_bytes().to_vec().into()),
+        },
+        &[],
+        owner,
+    )
+    .unwrap();
+    let offer = Coin::new(100_000_000_000_000_000_000u128, "inj");
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::DepositForRelay {},
+        &[offer.clone()],
+        owner,
+    )
+    .unwrap();
+
+    // 100 INJ swaps for 1000 USDT on mock_amm_1.
+    let now = env.app.get_block_time_seconds() as u64;
+    let relayed = |nonce: u64| RelayedRoute {
+        chain_id: "injective-777".to_string(),
+        contract: env.aggregator_addr.clone(),
+        owner: owner.address(),
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        offer: offer.clone(),
+        minimum_receive: native_minimum("usdt", 1_000_000_000),
+        nonce,
+        expires_at: now + 3_600,
+    };
+    let sign = |route: &RelayedRoute| -> Signature {
+        signing_key.sign_prehash(&route.hash().unwrap()).unwrap()
+    };
+    let submit = |route: RelayedRoute, signature: Signature| ExecuteMsg::ExecuteRouteFor {
+        owner: route.owner,
+        stages: route.stages,
+        offer: route.offer,
+        minimum_receive: route.minimum_receive,
+        nonce: route.nonce,
+        expires_at: route.expires_at,
+        signature: signature.to_bytes().to_vec().into(),
+    };
+
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(relayed(0), sign(&relayed(0))),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("is not allowed to relay routes"),
+        "unexpected error: {err}"
+    );
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetRelayer {
+            relayer: relayer.address(),
+            allowed: true,
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+
+    // A relayer loosening the signed minimum invalidates the signature.
+    let mut loosened = relayed(0);
+    loosened.minimum_receive = native_minimum("usdt", 1);
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(loosened, sign(&relayed(0))),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Signature verification failed"),
+        "unexpected error: {err}"
+    );
+
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(relayed(1), sign(&relayed(1))),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid nonce: expected 0, received 1"),
+        "unexpected error: {err}"
+    );
+
+    // A signature past its expiry is rejected, and moving the expiry invalidates it.
+    let mut expired = relayed(0);
+    expired.expires_at = now - 1;
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(expired.clone(), sign(&expired)),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Relayed route expired"),
+        "unexpected error: {err}"
+    );
+    let signature = sign(&expired);
+    expired.expires_at = now + 3_600;
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(expired, signature),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Signature verification failed"),
+        "unexpected error: {err}"
+    );
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(relayed(0), sign(&relayed(0))),
+            &[],
+            &relayer,
+        )
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output.map(|output| output.amount),
+        Some(Uint128::new(1_000_000_000))
+    );
+
+    let account: RelayAccountResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::RelayAccount {
+                owner: owner.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(account.nonce, 1);
+    assert!(account.deposits.is_empty());
+    let nonce: RelayNonceResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::RelayNonce {
+                owner: owner.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(nonce.nonce, 1);
+
+    // Replaying the executed route fails on its spent nonce.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(relayed(0), sign(&relayed(0))),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid nonce: expected 1, received 0"),
+        "unexpected error: {err}"
+    );
+
+    // The owner cancels the routes they signed ahead, with nonces 1 and 2.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::CancelRelayedRoutes { next_nonce: 1 },
+            &[],
+            owner,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid nonce: expected 2, received 1"),
+        "unexpected error: {err}"
+    );
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::CancelRelayedRoutes { next_nonce: 3 },
+        &[],
+        owner,
+    )
+    .unwrap();
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &submit(relayed(1), sign(&relayed(1))),
+            &[],
+            &relayer,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid nonce: expected 3, received 1"),
+        "unexpected error: {err}"
+    );
+    let nonce: RelayNonceResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::RelayNonce {
+                owner: owner.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(nonce.nonce, 3);
+}
+
+/// Pins the byte layout client SDKs reproduce when hashing a relayed route.
+#[test]
+fn test_domain_separated_hash_layout() {
+    assert_eq!(
+        hex::encode(domain_separated_hash(RELAYED_ROUTE_DOMAIN, b"{}")),
+        "81488eb0e9ca6c757386adc990a69e10967da069257dfbe70422fcb9715ce5f4"
+    );
+}
+
+#[test]
+fn test_zero_and_many_decimal_tokens_through_a_route() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let admin = &env.admin;
+
+    let mock_swap_code_id = wasm
+        .store_code(get_wasm_byte_code("mock_swap.wasm"), None, admin)
+        .unwrap()
+        .data
+        .code_id;
+    // USDT is treated as a token of 0 decimals and INJ as one of 24, two units of it per unit.
+    let config = |output_decimals: u8| SwapConfig {
+        input_asset_info: AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        },
+        output_asset_info: AssetInfo::NativeToken {
+            denom: "inj".to_string(),
+        },
+        rate: "2.0".to_string(),
+        protocol_type: ProtocolType::Amm,
+        input_decimals: 0,
+        output_decimals,
+        commission_bps: 0,
+        virtual_reserve: None,
+        spread_model: None,
+    };
+    let instantiate = |output_decimals: u8| {
+        wasm.instantiate(
+            mock_swap_code_id,
+            &MockInstantiateMsg {
+                config: config(output_decimals),
+            },
+            Some(&admin.address()),
+            Some("mock-extreme-decimals"),
+            &[],
+            admin,
+        )
+    };
+
+    let err = instantiate(31).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Unsupported decimals: 31 is above the maximum of 30"),
+        "unexpected error: {err}"
+    );
+
+    let pool = instantiate(24).unwrap().data.address;
+    bank.send(
+        MsgSend {
+            from_address: admin.address(),
+            to_address: pool.clone(),
+            amount: vec![ProtoCoin {
+                denom: "inj".to_string(),
+                amount: "100000000000000000000000000".to_string(),
+            }],
+        },
+        admin,
+    )
+    .unwrap();
+
+    // 5 whole USDT of 0 decimals are 10 whole INJ of 24 decimals.
+    let simulation: amm::SimulationResponse = wasm
+        .query(
+            &pool,
+            &amm::QueryMsg::Simulation {
+                offer_asset: amm::Asset {
+                    info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    amount: Uint128::new(5),
+                },
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        simulation.return_amount,
+        Uint128::new(10_000_000_000_000_000_000_000_000)
+    );
+
+    // Through the aggregator, at a 1% fee.
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetFee {
+            pool_address: pool.clone(),
+            fee_percent: Decimal::percent(1),
+        },
+        &[],
+        admin,
+    )
+    .unwrap();
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: pool.clone(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: native_minimum("inj", 9_900_000_000_000_000_000_000_000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &msg,
+            &[Coin::new(5u128, "usdt")],
+            &env.user,
+        )
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output.map(|output| output.amount),
+        Some(Uint128::new(9_900_000_000_000_000_000_000_000))
+    );
+}
+
+#[test]
+fn test_orderbook_swap_into_a_cw20_is_converted_after_settling() {
+    let setup = setup_for_conversion_test();
+    let wasm = Wasm::new(&setup.env.app);
+    let user = &setup.env.user;
+
+    // The orderbook trades native SHROOM, but the route asks it for the CW20.
+    let msg = ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::OrderbookSwap(OrderbookSwapOp {
+                    swap_contract: setup.mock_inj_to_native_shroom_ob.clone(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::Token {
+                        contract_addr: setup.shroom_cw20_addr.clone(),
+                    },
+                    min_quantity_tick_size: Uint128::new(1_000_000_000_000_000),
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: cw20_minimum(&setup.shroom_cw20_addr, 990_000_000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+
+    // 10 INJ -> 1,000 native SHROOM, redeemed for 1,000 CW20 SHROOM.
+    let response = wasm
+        .execute(
+            &setup.env.aggregator_addr,
+            &msg,
+            &[Coin::new(10_000_000_000_000_000_000u128, "inj")],
+            user,
+        )
+        .unwrap();
+    let counter = |key: &str| {
+        response
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    };
+    assert_eq!(counter("conversions_performed"), Some("1".to_string()));
+
+    let balance: BalanceResponse = wasm
+        .query(
+            &setup.shroom_cw20_addr,
+            &Cw20QueryMsg::Balance {
+                address: user.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance.balance, Uint128::new(1_000_000_000));
+}
+
+#[test]
+fn test_packed_route_executes_like_its_json_form() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let user = &env.user;
+
+    let inj = amm::AssetInfo::native("inj");
+    let usdt = amm::AssetInfo::native("usdt");
+    let inj_to_usdt = |pool: &str, percent| Split {
+        percent,
+        path: vec![Operation::AmmSwap(AmmSwapOp {
+            pool_address: pool.to_string(),
+            offer_asset_info: inj.clone(),
+            ask_asset_info: usdt.clone(),
+            min_out: None,
+        })],
+    };
+    let stages = vec![Stage {
+        splits: vec![
+            inj_to_usdt(&env.mock_amm_1_addr, 50),
+            inj_to_usdt(&env.mock_amm_2_addr, 50),
+        ],
+    }];
+    let options = RouteOptions {
+        minimum_receive: native_minimum("usdt", 1_000_000_000),
+        tag: Some("packed".to_string()),
+        ..Default::default()
+    };
+    let packed = |stages: &[Stage]| ExecuteMsg::ExecuteRoutePacked {
+        route: encode_route(stages, &options).unwrap(),
+    };
+
+    // 50 INJ -> 500 USDT through the first pool and 50 INJ -> 1000 USDT through the second.
+    let offer = Coin::new(100_000_000_000_000_000_000u128, "inj");
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &packed(&stages),
+            &[offer.clone()],
+            user,
+        )
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output,
+        Some(amm::Asset {
+            info: usdt.clone(),
+            amount: Uint128::new(1_500_000_000),
+        })
+    );
+    assert!(response.events.iter().any(|event| event.ty == "wasm"
+        && event
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "tag" && attr.value == "packed")));
+
+    // The decoded route is validated like any other.
+    let mut uneven = stages.clone();
+    uneven[0].splits[1].percent = 40;
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &packed(&uneven),
+            &[offer.clone()],
+            user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Percentages in a stage must sum to 100"),
+        "unexpected error: {err}"
+    );
+
+    // Bytes that are not a packed route are rejected before anything runs.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &ExecuteMsg::ExecuteRoutePacked {
+                route: Binary::from(vec![1u8, 0]),
+            },
+            &[offer],
+            user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid packed route: unexpected end of input"),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn test_integrator_fee_is_paid_to_its_collector() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let wallet = env.app.init_account(&[]).unwrap();
+    let wallet_collector = env.app.init_account(&[]).unwrap();
+
+    let register = ExecuteMsg::RegisterIntegrator {
+        addr: wallet.address(),
+        fee_bps: 50,
+        collector: wallet_collector.address(),
+    };
+    let err = wasm
+        .execute(&env.aggregator_addr, &register, &[], &env.user)
+        .unwrap_err();
+    assert!(err.to_string().contains("Unauthorized"), "{err}");
+    wasm.execute(&env.aggregator_addr, &register, &[], &env.admin)
+        .unwrap();
+
+    let registered: IntegratorResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::Integrator {
+                addr: wallet.address(),
+            },
+        )
+        .unwrap();
+    assert_eq!(registered.integrator.map(|i| i.fee_bps), Some(50));
+
+    let route = |integrator: String| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    offer_asset_info: amm::AssetInfo::native("inj"),
+                    ask_asset_info: amm::AssetInfo::native("usdt"),
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: native_minimum("usdt", 995_000_000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: Some(integrator),
+        recipients: None,
+    };
+    let offer = Coin::new(100_000_000_000_000_000_000u128, "inj");
+
+    // Routes may only name a registered integrator.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(wallet_collector.address()),
+            &[offer.clone()],
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("is not a registered integrator"),
+        "{err}"
+    );
+
+    // 100 INJ -> 1000 USDT, of which 0.5% goes to the wallet's collector.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(wallet.address()),
+            &[offer],
+            &env.user,
+        )
+        .unwrap();
+    let result: RouteResult = cosmwasm_std::from_json(&response.data.data).unwrap();
+    assert_eq!(
+        result.output.map(|output| output.amount),
+        Some(Uint128::new(995_000_000))
+    );
+    let collected = bank
+        .query_balance(&QueryBalanceRequest {
+            address: wallet_collector.address(),
+            denom: "usdt".to_string(),
+        })
+        .unwrap()
+        .balance
+        .unwrap();
+    assert_eq!(collected.amount, "5000000");
+}
+
+#[test]
+fn test_output_is_split_between_recipients() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let bank = Bank::new(&env.app);
+    let dao = env.app.init_account(&[]).unwrap();
+    let contributor = env.app.init_account(&[]).unwrap();
+
+    let route = |recipients: Vec<RecipientInfo>| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: env.mock_amm_1_addr.clone(),
+                    offer_asset_info: amm::AssetInfo::native("inj"),
+                    ask_asset_info: amm::AssetInfo::native("usdt"),
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: native_minimum("usdt", 1_000_000_000),
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: Some(recipients),
+    };
+    let share = |account: &SigningAccount, share_bps: u16| RecipientInfo {
+        address: account.address(),
+        share_bps,
+    };
+    let offer = Coin::new(100_000_000_000_000_000_000u128, "inj");
+
+    // The shares must add up to the whole output.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![share(&dao, 3_000), share(&contributor, 6_999)]),
+            &[offer.clone()],
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Invalid output recipients"),
+        "{err}"
+    );
+
+    // 100 INJ -> 1000 USDT, paid 30% to the DAO and 70% to the contributor.
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(vec![share(&dao, 3_000), share(&contributor, 7_000)]),
+            &[offer],
+            &env.user,
+        )
+        .unwrap();
+    let payouts: Vec<_> = response
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm-route_payout")
+        .map(|e| {
+            let value = |key: &str| {
+                e.attributes
+                    .iter()
+                    .find(|a| a.key == key)
+                    .map(|a| a.value.clone())
+                    .unwrap()
+            };
+            (value("recipient"), value("amount"))
+        })
+        .collect();
+    assert_eq!(
+        payouts,
+        vec![
+            (dao.address(), "300000000".to_string()),
+            (contributor.address(), "700000000".to_string()),
+        ]
+    );
+    for (account, expected) in [(&dao, "300000000"), (&contributor, "700000000")] {
+        let balance = bank
+            .query_balance(&QueryBalanceRequest {
+                address: account.address(),
+                denom: "usdt".to_string(),
+            })
+            .unwrap()
+            .balance
+            .unwrap();
+        assert_eq!(balance.amount, expected);
+    }
+}
+
+#[test]
+fn test_native_converter_registration() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let converter = env.app.init_account(&[]).unwrap();
+
+    let set = |denom: &str, canonical_denom: &str, to_canonical_msg: &str| {
+        ExecuteMsg::SetNativeConverter {
+            denom: denom.to_string(),
+            converter: Some(NativeConverterInfo {
+                contract: converter.address(),
+                canonical_denom: canonical_denom.to_string(),
+                to_canonical_msg: to_canonical_msg.to_string(),
+                from_canonical_msg: r#"{"redeem":{"recipient":"{recipient}"}}"#.to_string(),
+            }),
+        }
+    };
+    let valid = set(
+        "peggy0xusdt",
+        "usdt",
+        r#"{"convert":{"amount":"{amount}"}}"#,
+    );
+    let err = wasm
+        .execute(&env.aggregator_addr, &valid, &[], &env.user)
+        .unwrap_err();
+    assert!(err.to_string().contains("Unauthorized"), "{err}");
+
+    // Templates must be JSON objects once filled in, and INJ has its own converter.
+    for invalid in [
+        set("peggy0xusdt", "usdt", "{amount}"),
+        set("peggy0xusdt", "peggy0xusdt", "{}"),
+        set("peggy0xinj", "inj", "{}"),
+    ] {
+        let err = wasm
+            .execute(&env.aggregator_addr, &invalid, &[], &env.admin)
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid parameters"), "{err}");
+    }
+
+    wasm.execute(&env.aggregator_addr, &valid, &[], &env.admin)
+        .unwrap();
+    let query = |denom: &str| -> NativeConverterResponse {
+        wasm.query(
+            &env.aggregator_addr,
+            &QueryMsg::NativeConverter {
+                denom: denom.to_string(),
+            },
+        )
+        .unwrap()
+    };
+    let registered = query("peggy0xusdt").converter.unwrap();
+    assert_eq!(registered.contract.as_str(), converter.address());
+    assert_eq!(registered.canonical_denom, "usdt");
+
+    // Each token has at most two forms.
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &set("wormhole/usdt", "usdt", "{}"),
+            &[],
+            &env.admin,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("converted from another denom"),
+        "{err}"
+    );
+
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetNativeConverter {
+            denom: "peggy0xusdt".to_string(),
+            converter: None,
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    assert_eq!(query("peggy0xusdt").converter, None);
+}
+
+#[test]
+fn test_disabled_pool_rejects_routes_through_it() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+
+    let route = |pool: &str| ExecuteMsg::ExecuteRoute {
+        stages: vec![Stage {
+            splits: vec![Split {
+                percent: 100,
+                path: vec![Operation::AmmSwap(AmmSwapOp {
+                    pool_address: pool.to_string(),
+                    offer_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "inj".to_string(),
+                    },
+                    ask_asset_info: amm::AssetInfo::NativeToken {
+                        denom: "usdt".to_string(),
+                    },
+                    min_out: None,
+                })],
+            }],
+        }],
+        minimum_receive: None,
+        max_stages_per_tx: None,
+        max_quote_age: None,
+        tag: None,
+        pool_health_check: None,
+        split_expectations: None,
+        max_price_impact_bps: None,
+        deadline: None,
+        receive_hook: None,
+        final_assets: None,
+        post_action: None,
+        max_fee_bps: None,
+        minimum_receive_usd: None,
+        integrator: None,
+        recipients: None,
+    };
+    let funds = [Coin::new(1_000_000_000_000_000_000u128, "inj")];
+    let set_disabled = |disabled: bool| ExecuteMsg::SetPoolDisabled {
+        pool_address: env.mock_amm_1_addr.clone(),
+        disabled,
+    };
+
+    let err = wasm
+        .execute(&env.aggregator_addr, &set_disabled(true), &[], &env.user)
+        .unwrap_err();
+    assert!(err.to_string().contains("Unauthorized"), "{err}");
+
+    wasm.execute(&env.aggregator_addr, &set_disabled(true), &[], &env.admin)
+        .unwrap();
+    let disabled: DisabledPoolsResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::DisabledPools {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(disabled.pools, vec![Addr::unchecked(&env.mock_amm_1_addr)]);
+
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &route(&env.mock_amm_1_addr),
+            &funds,
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains(&format!("Pool {} is disabled", env.mock_amm_1_addr)),
+        "{err}"
+    );
+
+    // Other pools keep routing while one is frozen.
+    wasm.execute(
+        &env.aggregator_addr,
+        &route(&env.mock_amm_2_addr),
+        &funds,
+        &env.user,
+    )
+    .unwrap();
+
+    wasm.execute(&env.aggregator_addr, &set_disabled(false), &[], &env.admin)
+        .unwrap();
+    wasm.execute(
+        &env.aggregator_addr,
+        &route(&env.mock_amm_1_addr),
+        &funds,
+        &env.user,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_twap_order_slices_and_cancellation() {
+    let env = setup();
+    let wasm = Wasm::new(&env.app);
+    let stranger = env
+        .app
+        .init_account(&[Coin::new(1_000_000_000_000_000_000u128, "inj")])
+        .unwrap();
+
+    let stages = vec![Stage {
+        splits: vec![Split {
+            percent: 100,
+            path: vec![Operation::AmmSwap(AmmSwapOp {
+                pool_address: env.mock_amm_1_addr.clone(),
+                offer_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "inj".to_string(),
+                },
+                ask_asset_info: amm::AssetInfo::NativeToken {
+                    denom: "usdt".to_string(),
+                },
+                min_out: None,
+            })],
+        }],
+    }];
+    let total = 3_000_000_000_000_000_000u128;
+
+    // The attached funds must be the order's total.
+    let place = |total_amount: u128| ExecuteMsg::PlaceTwapOrder {
+        stages: stages.clone(),
+        total_amount: Uint128::new(total_amount),
+        n_slices: 3,
+        interval: 60,
+        max_slippage_bps: 100,
+    };
+    let err = wasm
+        .execute(
+            &env.aggregator_addr,
+            &place(total + 1),
+            &[Coin::new(total, "inj")],
+            &env.user,
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("Invalid TWAP order"), "{err}");
+
+    let response = wasm
+        .execute(
+            &env.aggregator_addr,
+            &place(total),
+            &[Coin::new(total, "inj")],
+            &env.user,
+        )
+        .unwrap();
+    let find_attr = |events: &[cosmwasm_std::Event], key: &str| {
+        events
+            .iter()
+            .filter(|e| e.ty == "wasm")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+    };
+    let order_id: u64 = find_attr(&response.events, "twap_order_id")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let slice = ExecuteMsg::ExecuteTwapSlice { order_id };
+
+    // The first slice is due at once: 1 INJ -> AMM1 @ 10.0 = 10 USDT.
+    let response = wasm
+        .execute(&env.aggregator_addr, &slice, &[], &env.user)
+        .unwrap();
+    assert_eq!(
+        find_attr(&response.events, "twap_slice"),
+        Some("1000000000000000000".to_string())
+    );
+    assert_eq!(
+        find_attr(&response.events, "final_received"),
+        Some("10000000".to_string())
+    );
+    let order: TwapOrderResponse = wasm
+        .query(&env.aggregator_addr, &QueryMsg::TwapOrder { order_id })
+        .unwrap();
+    let order = order.order.unwrap();
+    assert_eq!(order.slices_left, 2);
+    assert_eq!(
+        order.remaining.amount,
+        Uint128::new(2_000_000_000_000_000_000)
+    );
+
+    let err = wasm
+        .execute(&env.aggregator_addr, &slice, &[], &env.user)
+        .unwrap_err();
+    assert!(err.to_string().contains("is not due until"), "{err}");
+
+    // Only the owner and allowed relayers execute slices.
+    env.app.increase_time(60);
+    let err = wasm
+        .execute(&env.aggregator_addr, &slice, &[], &stranger)
+        .unwrap_err();
+    assert!(err.to_string().contains("Unauthorized"), "{err}");
+    wasm.execute(
+        &env.aggregator_addr,
+        &ExecuteMsg::SetRelayer {
+            relayer: stranger.address(),
+            allowed: true,
+        },
+        &[],
+        &env.admin,
+    )
+    .unwrap();
+    wasm.execute(&env.aggregator_addr, &slice, &[], &stranger)
+        .unwrap();
+
+    // Cancelling refunds the last, unrouted slice to the owner.
+    let cancel = ExecuteMsg::CancelTwapOrder { order_id };
+    let err = wasm
+        .execute(&env.aggregator_addr, &cancel, &[], &stranger)
+        .unwrap_err();
+    assert!(err.to_string().contains("Unauthorized"), "{err}");
+    let response = wasm
+        .execute(&env.aggregator_addr, &cancel, &[], &env.user)
+        .unwrap();
+    assert_eq!(
+        find_attr(&response.events, "refunded"),
+        Some("1000000000000000000".to_string())
+    );
+    let orders: TwapOrdersResponse = wasm
+        .query(
+            &env.aggregator_addr,
+            &QueryMsg::TwapOrders {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(orders.orders.is_empty());
+}